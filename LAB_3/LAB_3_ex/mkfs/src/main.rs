@@ -0,0 +1,672 @@
+// Explicit returns are the style of the kernel this tool belongs to
+#![allow(clippy::needless_return)]
+
+use std::env;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Has to match unios/src/ramfs.rs
+const SECTOR_SIZE:u64 = 512;
+const MAGIC:&[u8; 8] = b"UNIOSFS1";
+const VERSION:u32 = 1;
+const INODE_SIZE:usize = 32;
+const ENTRIES_SIZE:usize = 64;
+const NO_ENTRY:u16 = 0xFFFF;
+const KIND_FREE:u8 = 0;
+const KIND_FILE:u8 = 1;
+const KIND_DIRECTORY:u8 = 2;
+const ROOT_INDEX:usize = 0;
+
+// The table sizes the kernel is built with, for new images
+const DEFAULT_GEOMETRY:Geometry = Geometry
+{
+    dirs: 20,
+    children_per_dir: 10,
+    files_per_dir: 10,
+    files: 20,
+    file_capacity: 2048,
+    name_length: 10,
+};
+
+const USAGE:&str = "\
+usage: unios-mkfs create <image> [size in KiB]
+       unios-mkfs info <image>
+       unios-mkfs ls <image> [path]
+       unios-mkfs mkdir <image> <path>
+       unios-mkfs put <image> <host file> <path>
+       unios-mkfs get <image> <path>
+       unios-mkfs rm <image> <path>";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Geometry
+{
+    dirs:usize,
+    children_per_dir:usize,
+    files_per_dir:usize,
+    files:usize,
+    file_capacity:usize,
+    name_length:usize,
+}
+
+impl Geometry
+{
+    fn sectors(bytes:usize) -> u64
+    {
+        (bytes as u64).div_ceil(SECTOR_SIZE)
+    }
+
+    fn inodes_start(&self) -> u64
+    {
+        1
+    }
+
+    fn entries_start(&self) -> u64
+    {
+        self.inodes_start() + Geometry::sectors((self.dirs + self.files) * INODE_SIZE)
+    }
+
+    fn data_start(&self) -> u64
+    {
+        self.entries_start() + Geometry::sectors(self.dirs * ENTRIES_SIZE)
+    }
+
+    fn file_sectors(&self) -> u64
+    {
+        Geometry::sectors(self.file_capacity)
+    }
+
+    fn image_sectors(&self) -> u64
+    {
+        self.data_start() + self.files as u64 * self.file_sectors()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Inode
+{
+    kind:u8,
+    name:Vec<u8>,
+    parent:usize,
+    size:usize,
+    // seconds since 1970
+    created:u32,
+    modified:u32,
+    owner:u16,
+    // 0 for the kernel's default
+    mode:u16,
+}
+
+// A directory's slots, None where free
+#[derive(Debug, Clone)]
+struct Entries
+{
+    children:Vec<Option<usize>>,
+    files:Vec<Option<usize>>,
+}
+
+/// An open image: the tables are read whole, file data is read and written
+/// in place.
+struct Image
+{
+    file:File,
+    geometry:Geometry,
+    // directories first, then files, like on disk
+    inodes:Vec<Inode>,
+    entries:Vec<Entries>,
+}
+
+fn get_u32(buf:&[u8], offset:usize) -> u32
+{
+    u32::from_le_bytes([buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]])
+}
+
+fn get_u16(buf:&[u8], offset:usize) -> u16
+{
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn free_inode() -> Inode
+{
+    Inode { kind: KIND_FREE, name: Vec::new(), parent: 0, size: 0, created: 0, modified: 0, owner: 0, mode: 0 }
+}
+
+fn now() -> u32
+{
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs() as u32)
+}
+
+fn new_inode(kind:u8, name:&str, parent:usize, size:usize) -> Inode
+{
+    let now = now();
+    Inode { kind, name: name.as_bytes().to_vec(), parent, size, created: now, modified: now, ..free_inode() }
+}
+
+fn components(path:&str) -> Vec<&str>
+{
+    path.split('/').filter(|component| !component.is_empty() && *component != ".").collect()
+}
+
+impl Image
+{
+    fn create(path:&str, size_kib:u64) -> Result<Image, String>
+    {
+        let geometry = DEFAULT_GEOMETRY;
+        let needed = geometry.image_sectors() * SECTOR_SIZE;
+        if size_kib * 1024 < needed
+        {
+            return Err(format!("the image needs at least {} KiB", needed.div_ceil(1024)));
+        }
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path)
+            .map_err(|error| format!("{}: {}", path, error))?;
+        file.set_len(size_kib * 1024).map_err(|error| error.to_string())?;
+
+        let mut inodes = vec![free_inode(); geometry.dirs + geometry.files];
+        inodes[ROOT_INDEX] = new_inode(KIND_DIRECTORY, "", ROOT_INDEX, 0);
+        let entries = vec![
+            Entries { children: vec![None; geometry.children_per_dir], files: vec![None; geometry.files_per_dir] };
+            geometry.dirs
+        ];
+        let mut image = Image { file, geometry, inodes, entries };
+        image.save()?;
+        return Ok(image);
+    }
+
+    fn open(path:&str) -> Result<Image, String>
+    {
+        let mut file = OpenOptions::new().read(true).write(true).open(path)
+            .map_err(|error| format!("{}: {}", path, error))?;
+        let mut superblock = [0u8; SECTOR_SIZE as usize];
+        file.read_exact(&mut superblock).map_err(|_| format!("{}: too small for an image", path))?;
+        if &superblock[..8] != MAGIC || get_u32(&superblock, 8) != VERSION
+        {
+            return Err(format!("{}: no unios filesystem", path));
+        }
+        let field = |i:usize| get_u32(&superblock, 12 + 4 * i) as usize;
+        let geometry = Geometry
+        {
+            dirs: field(0),
+            children_per_dir: field(1),
+            files_per_dir: field(2),
+            files: field(3),
+            file_capacity: field(4),
+            name_length: field(5),
+        };
+        if field(6) as u64 != geometry.inodes_start() || field(7) as u64 != geometry.entries_start()
+            || field(8) as u64 != geometry.data_start() || ENTRIES_SIZE < 2 * (geometry.children_per_dir + geometry.files_per_dir)
+            || geometry.dirs == 0 || geometry.dirs + geometry.files >= NO_ENTRY as usize || 1 + geometry.name_length > 12
+        {
+            return Err(format!("{}: damaged superblock", path));
+        }
+
+        let mut inode_table = vec![0u8; (geometry.dirs + geometry.files) * INODE_SIZE];
+        file.seek(SeekFrom::Start(geometry.inodes_start() * SECTOR_SIZE)).map_err(|error| error.to_string())?;
+        file.read_exact(&mut inode_table).map_err(|error| error.to_string())?;
+        let mut entry_table = vec![0u8; geometry.dirs * ENTRIES_SIZE];
+        file.seek(SeekFrom::Start(geometry.entries_start() * SECTOR_SIZE)).map_err(|error| error.to_string())?;
+        file.read_exact(&mut entry_table).map_err(|error| error.to_string())?;
+
+        let inodes = inode_table.chunks(INODE_SIZE).map(|record| {
+            let name = &record[1..1 + geometry.name_length];
+            let length = name.iter().position(|&byte| byte == 0).unwrap_or(name.len());
+            Inode
+            {
+                kind: record[0],
+                name: name[..length].to_vec(),
+                parent: get_u32(record, 12) as usize,
+                size: get_u32(record, 16) as usize,
+                created: get_u32(record, 20),
+                modified: get_u32(record, 24),
+                owner: get_u16(record, 28),
+                mode: get_u16(record, 30),
+            }
+        }).collect();
+        let decode = |record:&[u8], slot:usize| match get_u16(record, 2 * slot)
+        {
+            NO_ENTRY => None,
+            index => Some(index as usize),
+        };
+        let entries = entry_table.chunks(ENTRIES_SIZE).map(|record| Entries
+        {
+            children: (0..geometry.children_per_dir).map(|slot| decode(record, slot)).collect(),
+            files: (0..geometry.files_per_dir).map(|slot| decode(record, geometry.children_per_dir + slot)).collect(),
+        }).collect();
+        let image = Image { file, geometry, inodes, entries };
+        if !image.tables_agree()
+        {
+            return Err(format!("{}: damaged tables", path));
+        }
+        return Ok(image);
+    }
+
+    // Every index is in range and every listed entry points back at its
+    // directory, so walking the tree can neither panic nor loop
+    fn tables_agree(&self) -> bool
+    {
+        let geometry = self.geometry;
+        let is_dir = |index:usize| self.inodes.get(index).is_some_and(|inode| inode.kind == KIND_DIRECTORY);
+        if !is_dir(ROOT_INDEX) || self.inodes[ROOT_INDEX].parent != ROOT_INDEX
+        {
+            return false;
+        }
+        for (i, inode) in self.inodes.iter().enumerate().filter(|(_, inode)| inode.kind != KIND_FREE)
+        {
+            let wrong_kind = if i < geometry.dirs { KIND_FILE } else { KIND_DIRECTORY };
+            if inode.kind == wrong_kind || inode.kind > KIND_DIRECTORY || !is_dir(inode.parent) || inode.parent >= geometry.dirs
+                || inode.size > geometry.file_capacity
+            {
+                return false;
+            }
+        }
+        for (i, entries) in self.entries.iter().enumerate()
+        {
+            let bad_child = |&child:&usize| child == ROOT_INDEX || child == i || child >= geometry.dirs || !is_dir(child)
+                || self.inodes[child].parent != i;
+            let bad_file = |&file:&usize| file >= geometry.files || self.file_inode(file).kind != KIND_FILE
+                || self.file_inode(file).parent != i;
+            if entries.children.iter().flatten().any(bad_child) || entries.files.iter().flatten().any(bad_file)
+            {
+                return false;
+            }
+        }
+        // Every directory reaches the root by its parents
+        return (0..geometry.dirs).filter(|&i| is_dir(i)).all(|i| {
+            let mut dir = i;
+            (0..geometry.dirs).any(|_| {
+                dir = self.inodes[dir].parent;
+                dir == ROOT_INDEX
+            }) || i == ROOT_INDEX
+        });
+    }
+
+    // Writes the superblock and the tables back
+    fn save(&mut self) -> Result<(), String>
+    {
+        let geometry = self.geometry;
+        let mut superblock = vec![0u8; SECTOR_SIZE as usize];
+        superblock[..8].copy_from_slice(MAGIC);
+        let fields = [
+            VERSION,
+            geometry.dirs as u32,
+            geometry.children_per_dir as u32,
+            geometry.files_per_dir as u32,
+            geometry.files as u32,
+            geometry.file_capacity as u32,
+            geometry.name_length as u32,
+            geometry.inodes_start() as u32,
+            geometry.entries_start() as u32,
+            geometry.data_start() as u32,
+        ];
+        for (i, field) in fields.iter().enumerate()
+        {
+            superblock[8 + 4 * i..12 + 4 * i].copy_from_slice(&field.to_le_bytes());
+        }
+
+        let mut inode_table = vec![0u8; Geometry::sectors(self.inodes.len() * INODE_SIZE) as usize * SECTOR_SIZE as usize];
+        for (inode, record) in self.inodes.iter().zip(inode_table.chunks_mut(INODE_SIZE))
+        {
+            if inode.kind == KIND_FREE
+            {
+                continue;
+            }
+            record[0] = inode.kind;
+            record[1..1 + inode.name.len()].copy_from_slice(&inode.name);
+            record[12..16].copy_from_slice(&(inode.parent as u32).to_le_bytes());
+            record[16..20].copy_from_slice(&(inode.size as u32).to_le_bytes());
+            record[20..24].copy_from_slice(&inode.created.to_le_bytes());
+            record[24..28].copy_from_slice(&inode.modified.to_le_bytes());
+            record[28..30].copy_from_slice(&inode.owner.to_le_bytes());
+            record[30..32].copy_from_slice(&inode.mode.to_le_bytes());
+        }
+
+        let mut entry_table = vec![0xFFu8; Geometry::sectors(self.entries.len() * ENTRIES_SIZE) as usize * SECTOR_SIZE as usize];
+        for (entries, record) in self.entries.iter().zip(entry_table.chunks_mut(ENTRIES_SIZE))
+        {
+            for (slot, index) in entries.children.iter().chain(entries.files.iter()).enumerate()
+            {
+                if let Some(index) = index
+                {
+                    record[2 * slot..2 * slot + 2].copy_from_slice(&(*index as u16).to_le_bytes());
+                }
+            }
+        }
+
+        self.write_at(0, &superblock)?;
+        self.write_at(geometry.inodes_start() * SECTOR_SIZE, &inode_table)?;
+        self.write_at(geometry.entries_start() * SECTOR_SIZE, &entry_table)?;
+        return Ok(());
+    }
+
+    fn write_at(&mut self, offset:u64, data:&[u8]) -> Result<(), String>
+    {
+        self.file.seek(SeekFrom::Start(offset)).map_err(|error| error.to_string())?;
+        self.file.write_all(data).map_err(|error| error.to_string())
+    }
+
+    fn file_inode(&self, file:usize) -> &Inode
+    {
+        &self.inodes[self.geometry.dirs + file]
+    }
+
+    // The directory or file called `name` in the directory `dir`
+    fn find(&self, dir:usize, name:&str) -> Option<(u8, usize)>
+    {
+        let entries = &self.entries[dir];
+        if let Some(child) = entries.children.iter().flatten().find(|&&child| self.inodes[child].name == name.as_bytes())
+        {
+            return Some((KIND_DIRECTORY, *child));
+        }
+        entries.files.iter().flatten()
+            .find(|&&file| self.file_inode(file).name == name.as_bytes())
+            .map(|&file| (KIND_FILE, file))
+    }
+
+    fn resolve(&self, path:&str) -> Result<(u8, usize), String>
+    {
+        let mut found = (KIND_DIRECTORY, ROOT_INDEX);
+        for component in components(path)
+        {
+            if found.0 != KIND_DIRECTORY
+            {
+                return Err(format!("{}: not a directory", path));
+            }
+            found = match component
+            {
+                ".." => (KIND_DIRECTORY, self.inodes[found.1].parent),
+                _ => self.find(found.1, component).ok_or_else(|| format!("{}: no such file or directory", path))?,
+            };
+        }
+        return Ok(found);
+    }
+
+    // The directory `path` is in and its last component
+    fn resolve_parent<'a>(&self, path:&'a str) -> Result<(usize, &'a str), String>
+    {
+        let mut parts = components(path);
+        let name = parts.pop().ok_or_else(|| format!("{}: no name", path))?;
+        match self.resolve(&parts.join("/"))?
+        {
+            (KIND_DIRECTORY, dir) => Ok((dir, name)),
+            _ => Err(format!("{}: not a directory", path)),
+        }
+    }
+
+    fn check_new_entry(&self, dir:usize, name:&str) -> Result<(), String>
+    {
+        if name == ".." || name.len() > self.geometry.name_length
+        {
+            return Err(format!("{}: invalid name, at most {} bytes", name, self.geometry.name_length));
+        }
+        if self.find(dir, name).is_some()
+        {
+            return Err(format!("{}: already exists", name));
+        }
+        return Ok(());
+    }
+
+    fn mkdir(&mut self, path:&str) -> Result<(), String>
+    {
+        let (parent, name) = self.resolve_parent(path)?;
+        self.check_new_entry(parent, name)?;
+        let index = (0..self.geometry.dirs).find(|&i| self.inodes[i].kind == KIND_FREE)
+            .ok_or("no free directory left")?;
+        let slot = self.entries[parent].children.iter().position(Option::is_none)
+            .ok_or_else(|| format!("{}: the parent directory is full", path))?;
+        self.inodes[index] = new_inode(KIND_DIRECTORY, name, parent, 0);
+        self.entries[parent].children[slot] = Some(index);
+        self.inodes[parent].modified = now();
+        return self.save();
+    }
+
+    fn put(&mut self, data:&[u8], path:&str) -> Result<(), String>
+    {
+        if data.len() > self.geometry.file_capacity
+        {
+            return Err(format!("a file holds at most {} bytes", self.geometry.file_capacity));
+        }
+        let (parent, name) = self.resolve_parent(path)?;
+        let mut inode = new_inode(KIND_FILE, name, parent, data.len());
+        let file = match self.find(parent, name)
+        {
+            // An overwritten file keeps its creation time
+            Some((KIND_FILE, file)) => {
+                inode.created = self.file_inode(file).created;
+                file
+            }
+            Some(_) => return Err(format!("{}: is a directory", path)),
+            None => {
+                self.check_new_entry(parent, name)?;
+                let file = (0..self.geometry.files).find(|&i| self.file_inode(i).kind == KIND_FREE)
+                    .ok_or("no free file left")?;
+                let slot = self.entries[parent].files.iter().position(Option::is_none)
+                    .ok_or_else(|| format!("{}: the directory is full", path))?;
+                self.entries[parent].files[slot] = Some(file);
+                self.inodes[parent].modified = inode.modified;
+                file
+            }
+        };
+        self.inodes[self.geometry.dirs + file] = inode;
+        // The rest of the slot reads as zeros, like in the kernel
+        let mut contents = vec![0u8; self.geometry.file_sectors() as usize * SECTOR_SIZE as usize];
+        contents[..data.len()].copy_from_slice(data);
+        let offset = (self.geometry.data_start() + file as u64 * self.geometry.file_sectors()) * SECTOR_SIZE;
+        self.write_at(offset, &contents)?;
+        return self.save();
+    }
+
+    fn get(&mut self, path:&str) -> Result<Vec<u8>, String>
+    {
+        let file = match self.resolve(path)?
+        {
+            (KIND_FILE, file) => file,
+            _ => return Err(format!("{}: is a directory", path)),
+        };
+        let mut data = vec![0u8; self.file_inode(file).size.min(self.geometry.file_capacity)];
+        let offset = (self.geometry.data_start() + file as u64 * self.geometry.file_sectors()) * SECTOR_SIZE;
+        self.file.seek(SeekFrom::Start(offset)).map_err(|error| error.to_string())?;
+        self.file.read_exact(&mut data).map_err(|error| error.to_string())?;
+        return Ok(data);
+    }
+
+    fn rm(&mut self, path:&str) -> Result<(), String>
+    {
+        let (parent, name) = self.resolve_parent(path)?;
+        match self.find(parent, name)
+        {
+            Some((KIND_FILE, file)) => {
+                self.entries[parent].files.retain(|&slot| slot != Some(file));
+                self.entries[parent].files.resize(self.geometry.files_per_dir, None);
+                self.inodes[self.geometry.dirs + file] = free_inode();
+            }
+            Some((_, dir)) => {
+                let entries = &self.entries[dir];
+                if entries.children.iter().chain(entries.files.iter()).any(Option::is_some)
+                {
+                    return Err(format!("{}: directory is not empty", path));
+                }
+                self.entries[parent].children.retain(|&slot| slot != Some(dir));
+                self.entries[parent].children.resize(self.geometry.children_per_dir, None);
+                self.inodes[dir] = free_inode();
+            }
+            None => return Err(format!("{}: no such file or directory", path)),
+        }
+        self.inodes[parent].modified = now();
+        return self.save();
+    }
+
+    fn print_tree(&self, dir:usize, depth:usize)
+    {
+        let entries = &self.entries[dir];
+        for &child in entries.children.iter().flatten()
+        {
+            println!("{}{}/", "  ".repeat(depth), String::from_utf8_lossy(&self.inodes[child].name));
+            self.print_tree(child, depth + 1);
+        }
+        for &file in entries.files.iter().flatten()
+        {
+            let inode = self.file_inode(file);
+            println!("{}{}  {} bytes", "  ".repeat(depth), String::from_utf8_lossy(&inode.name), inode.size);
+        }
+    }
+
+    fn print_info(&self)
+    {
+        let geometry = self.geometry;
+        let used = |range:std::ops::Range<usize>| range.filter(|&i| self.inodes[i].kind != KIND_FREE).count();
+        println!("directories  {} of {} used, {} subdirectories and {} files each",
+            used(0..geometry.dirs), geometry.dirs, geometry.children_per_dir, geometry.files_per_dir);
+        println!("files        {} of {} used, up to {} bytes each",
+            used(geometry.dirs..geometry.dirs + geometry.files), geometry.files, geometry.file_capacity);
+        println!("names        up to {} bytes", geometry.name_length);
+        println!("sectors      inodes at {}, entries at {}, data at {}, {} in all",
+            geometry.inodes_start(), geometry.entries_start(), geometry.data_start(), geometry.image_sectors());
+    }
+}
+
+fn run(args:&[String]) -> Result<(), String>
+{
+    let arg = |i:usize| args.get(i).map(String::as_str).ok_or_else(|| USAGE.to_string());
+    match arg(0)?
+    {
+        "create" => {
+            let size = match args.get(2)
+            {
+                Some(size) => size.parse::<u64>().map_err(|_| format!("{}: not a size in KiB", size))?,
+                None => 1024,
+            };
+            Image::create(arg(1)?, size)?;
+        }
+        "info" => Image::open(arg(1)?)?.print_info(),
+        "ls" => {
+            let image = Image::open(arg(1)?)?;
+            let path = args.get(2).map_or("/", String::as_str);
+            match image.resolve(path)?
+            {
+                (KIND_DIRECTORY, dir) => image.print_tree(dir, 0),
+                (_, file) => println!("{}  {} bytes", path, image.file_inode(file).size),
+            }
+        }
+        "mkdir" => Image::open(arg(1)?)?.mkdir(arg(2)?)?,
+        "put" => {
+            let data = std::fs::read(arg(2)?).map_err(|error| format!("{}: {}", arg(2).unwrap_or(""), error))?;
+            Image::open(arg(1)?)?.put(&data, arg(3)?)?;
+        }
+        "get" => {
+            let data = Image::open(arg(1)?)?.get(arg(2)?)?;
+            io::stdout().write_all(&data).map_err(|error| error.to_string())?;
+        }
+        "rm" => Image::open(arg(1)?)?.rm(arg(2)?)?,
+        _ => return Err(USAGE.to_string()),
+    }
+    return Ok(());
+}
+
+fn main()
+{
+    let args:Vec<String> = env::args().skip(1).collect();
+    if let Err(message) = run(&args)
+    {
+        eprintln!("{}", message);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    // A fresh path per test, removed when dropped
+    struct TempImage(String);
+
+    impl TempImage
+    {
+        fn new(name:&str) -> TempImage
+        {
+            let path = env::temp_dir().join(format!("unios-mkfs-{}-{}.img", name, process::id()));
+            return TempImage(path.to_string_lossy().into_owned());
+        }
+    }
+
+    impl Drop for TempImage
+    {
+        fn drop(&mut self)
+        {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn write_u16(path:&str, offset:u64, value:u16)
+    {
+        let mut file = OpenOptions::new().write(true).open(path).unwrap();
+        file.seek(SeekFrom::Start(offset)).unwrap();
+        file.write_all(&value.to_le_bytes()).unwrap();
+    }
+
+    #[test]
+    fn create_then_open_round_trips()
+    {
+        let image = TempImage::new("round-trip");
+        {
+            let mut created = Image::create(&image.0, 1024).unwrap();
+            created.mkdir("/docs").unwrap();
+            created.mkdir("/docs/notes").unwrap();
+            created.put(b"hello", "/docs/notes/a.txt").unwrap();
+            created.put(b"top", "/b.txt").unwrap();
+        }
+        let mut opened = Image::open(&image.0).unwrap();
+        assert_eq!(opened.geometry, DEFAULT_GEOMETRY);
+        assert_eq!(opened.get("/docs/notes/a.txt").unwrap(), b"hello");
+        assert_eq!(opened.get("/b.txt").unwrap(), b"top");
+        assert_eq!(opened.resolve("/docs/notes/..").unwrap(), opened.resolve("/docs").unwrap());
+
+        opened.rm("/docs/notes/a.txt").unwrap();
+        opened.rm("/docs/notes").unwrap();
+        let reopened = Image::open(&image.0).unwrap();
+        assert!(reopened.resolve("/docs/notes").is_err());
+        assert!(reopened.resolve("/docs").is_ok());
+    }
+
+    #[test]
+    fn create_rejects_a_small_image()
+    {
+        let image = TempImage::new("small");
+        assert!(Image::create(&image.0, 1).is_err());
+    }
+
+    #[test]
+    fn open_rejects_an_index_out_of_range()
+    {
+        let image = TempImage::new("out-of-range");
+        Image::create(&image.0, 1024).unwrap();
+        // The root's first child slot
+        write_u16(&image.0, DEFAULT_GEOMETRY.entries_start() * SECTOR_SIZE, 500);
+        assert!(Image::open(&image.0).is_err());
+    }
+
+    #[test]
+    fn open_rejects_a_parent_cycle()
+    {
+        let image = TempImage::new("cycle");
+        {
+            let mut created = Image::create(&image.0, 1024).unwrap();
+            created.mkdir("/a").unwrap();
+            created.mkdir("/a/b").unwrap();
+        }
+        // b lists a, whose parent stays the root
+        let b_entries = DEFAULT_GEOMETRY.entries_start() * SECTOR_SIZE + 2 * ENTRIES_SIZE as u64;
+        write_u16(&image.0, b_entries, 1);
+        assert!(Image::open(&image.0).is_err());
+    }
+
+    #[test]
+    fn open_rejects_a_long_name_length()
+    {
+        let image = TempImage::new("name-length");
+        Image::create(&image.0, 1024).unwrap();
+        // The name length field of the superblock
+        write_u16(&image.0, 12 + 4 * 5, 40);
+        assert!(Image::open(&image.0).is_err());
+    }
+}
@@ -0,0 +1,93 @@
+// Packs the `initrd` directory into a ustar archive that the kernel embeds
+// with include_bytes!, see src/initrd.rs.
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const BLOCK:usize = 512;
+
+fn octal(field:&mut [u8], value:u64)
+{
+    // Digits right-aligned, then a NUL
+    let text = format!("{:0width$o}", value, width = field.len() - 1);
+    field[..text.len()].copy_from_slice(text.as_bytes());
+}
+
+fn header(path:&str, size:u64, directory:bool) -> [u8; BLOCK]
+{
+    let mut block = [0u8; BLOCK];
+    let bytes = path.as_bytes();
+    // Long paths go into the prefix field, split at a '/'
+    let (prefix, name) = if bytes.len() <= 100
+    {
+        (&bytes[..0], bytes)
+    }
+    else
+    {
+        // Cut on a character boundary, the '/' looked for is one anyway
+        let mut end = path.len().min(156);
+        while !path.is_char_boundary(end)
+        {
+            end -= 1;
+        }
+        let split = path[..end].rfind('/').expect("initrd path too long");
+        (&bytes[..split], &bytes[split + 1..])
+    };
+    assert!(name.len() <= 100 && prefix.len() <= 155, "initrd path too long: {}", path);
+    block[..name.len()].copy_from_slice(name);
+    octal(&mut block[100..108], if directory { 0o755 } else { 0o644 });
+    octal(&mut block[108..116], 0);
+    octal(&mut block[116..124], 0);
+    octal(&mut block[124..136], size);
+    octal(&mut block[136..148], 0);
+    block[156] = if directory { b'5' } else { b'0' };
+    block[257..263].copy_from_slice(b"ustar\0");
+    block[263..265].copy_from_slice(b"00");
+    block[345..345 + prefix.len()].copy_from_slice(prefix);
+    // The checksum is taken with its own field full of spaces
+    block[148..156].fill(b' ');
+    let sum:u32 = block.iter().map(|&byte| byte as u32).sum();
+    octal(&mut block[148..155], sum as u64);
+    block[155] = b' ';
+    return block;
+}
+
+fn pack(root:&Path, dir:&Path, archive:&mut Vec<u8>) -> io::Result<()>
+{
+    let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    // Sorted, so the same tree always gives the same archive
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries
+    {
+        let path = entry.path();
+        let name = path.strip_prefix(root).unwrap().to_str().expect("initrd names must be UTF-8").replace('\\', "/");
+        if entry.file_type()?.is_dir()
+        {
+            archive.extend_from_slice(&header(&format!("{}/", name), 0, true));
+            pack(root, &path, archive)?;
+        }
+        else
+        {
+            let data = fs::read(&path)?;
+            archive.extend_from_slice(&header(&name, data.len() as u64, false));
+            archive.extend_from_slice(&data);
+            archive.resize(archive.len().div_ceil(BLOCK) * BLOCK, 0);
+        }
+    }
+    return Ok(());
+}
+
+fn main()
+{
+    let root = Path::new("initrd");
+    println!("cargo:rerun-if-changed=initrd");
+    let mut archive = Vec::new();
+    if root.is_dir()
+    {
+        pack(root, root, &mut archive).expect("can not read initrd");
+    }
+    // Two zero blocks end an archive
+    archive.resize(archive.len() + 2 * BLOCK, 0);
+    let out = PathBuf::from(std::env::var("OUT_DIR").unwrap()).join("initrd.tar");
+    fs::write(out, archive).expect("can not write initrd.tar");
+}
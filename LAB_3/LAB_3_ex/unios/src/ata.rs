@@ -0,0 +1,317 @@
+use core::hint::spin_loop;
+use lazy_static::lazy_static;
+use x86_64::instructions::port::Port;
+use crate::block::{self, BlockDevice, BlockError, SECTOR_SIZE};
+use crate::println;
+use crate::sync::Mutex;
+
+/// Drives are numbered like QEMU's `-drive index=N`: 0 and 1 are the master
+/// and slave of the primary channel, 2 and 3 those of the secondary one.
+pub const MAX_DRIVES:usize = 4;
+
+// Registers from the I/O base of a channel
+const REG_DATA:u16 = 0;
+const REG_SECTOR_COUNT:u16 = 2;
+const REG_LBA_LOW:u16 = 3;
+const REG_LBA_MID:u16 = 4;
+const REG_LBA_HIGH:u16 = 5;
+const REG_DRIVE:u16 = 6;
+const REG_STATUS:u16 = 7;
+const REG_COMMAND:u16 = 7;
+
+const STATUS_ERR:u8 = 0x01;
+const STATUS_DRQ:u8 = 0x08;
+const STATUS_DF:u8 = 0x20;
+const STATUS_BSY:u8 = 0x80;
+
+// Device control register: no interrupts, we poll
+const CONTROL_NO_INTERRUPTS:u8 = 0x02;
+
+const COMMAND_READ:u8 = 0x20;
+const COMMAND_READ_EXT:u8 = 0x24;
+const COMMAND_WRITE:u8 = 0x30;
+const COMMAND_WRITE_EXT:u8 = 0x34;
+const COMMAND_FLUSH:u8 = 0xE7;
+const COMMAND_FLUSH_EXT:u8 = 0xEA;
+const COMMAND_IDENTIFY:u8 = 0xEC;
+
+// LBA28 addresses 2^28 sectors, and a command moves at most 256 of them
+const LBA28_LIMIT:u64 = 1 << 28;
+const MAX_SECTORS_PER_COMMAND:u64 = 128;
+
+/// Status polls before giving up on a drive.
+const POLL_LIMIT:u32 = 1_000_000;
+
+struct Channel
+{
+    io_base:u16,
+    control_base:u16,
+    // one command at a time on the wires of a channel
+    lock:Mutex<()>,
+}
+
+static CHANNELS: [Channel; 2] = [
+    Channel { io_base: 0x1F0, control_base: 0x3F6, lock: Mutex::new(()) },
+    Channel { io_base: 0x170, control_base: 0x376, lock: Mutex::new(()) },
+];
+
+/// A drive found by IDENTIFY. It is copied around freely, commands to it are
+/// serialized by the lock of its channel.
+#[derive(Debug, Clone, Copy)]
+pub struct Drive
+{
+    index:usize,
+    sectors:u64,
+    lba48:bool,
+    model:[u8; 40],
+}
+
+lazy_static!
+{
+    static ref DRIVES: [Option<Drive>; MAX_DRIVES] = {
+        let mut drives = [None; MAX_DRIVES];
+        for (index, drive) in drives.iter_mut().enumerate()
+        {
+            *drive = identify(index);
+        }
+        drives
+    };
+}
+
+/// Looks for drives on both channels and lists them. Call once at boot.
+pub fn init()
+{
+    for drive in DRIVES.iter().flatten()
+    {
+        println!("ata{}: {}, {} KiB{}", drive.index, drive.model(), drive.sectors * SECTOR_SIZE as u64 / 1024,
+            if drive.lba48 { ", LBA48" } else { "" });
+    }
+}
+
+/// The drive number `index`, if there is one.
+pub fn drive(index:usize) -> Option<Drive>
+{
+    DRIVES.get(index).copied().flatten()
+}
+
+fn read_register(channel:&Channel, register:u16) -> u8
+{
+    unsafe { Port::<u8>::new(channel.io_base + register).read() }
+}
+
+fn write_register(channel:&Channel, register:u16, value:u8)
+{
+    unsafe { Port::<u8>::new(channel.io_base + register).write(value) }
+}
+
+// Reading the alternate status four times takes the 400ns a drive needs to
+// put its status up after a command or a drive select
+fn delay_400ns(channel:&Channel)
+{
+    for _ in 0..4
+    {
+        unsafe { Port::<u8>::new(channel.control_base).read() };
+    }
+}
+
+fn wait_not_busy(channel:&Channel) -> Result<u8, BlockError>
+{
+    for _ in 0..POLL_LIMIT
+    {
+        let status = read_register(channel, REG_STATUS);
+        if status & STATUS_BSY == 0
+        {
+            return Ok(status);
+        }
+        spin_loop();
+    }
+    return Err(BlockError::Timeout);
+}
+
+// Waits until the drive wants the next sector moved through the data register
+fn wait_for_data(channel:&Channel) -> Result<(), BlockError>
+{
+    let status = wait_not_busy(channel)?;
+    if status & (STATUS_ERR | STATUS_DF) != 0 || status & STATUS_DRQ == 0
+    {
+        return Err(BlockError::DeviceError);
+    }
+    return Ok(());
+}
+
+// Sends IDENTIFY to drive `index` and reads what it says about itself
+fn identify(index:usize) -> Option<Drive>
+{
+    let channel = &CHANNELS[index / 2];
+    let slave = (index % 2) as u8;
+    unsafe { Port::<u8>::new(channel.control_base).write(CONTROL_NO_INTERRUPTS) };
+
+    // Nothing is attached to a floating bus
+    if read_register(channel, REG_STATUS) == 0xFF
+    {
+        return None;
+    }
+    write_register(channel, REG_DRIVE, 0xA0 | slave << 4);
+    delay_400ns(channel);
+    for register in REG_SECTOR_COUNT..=REG_LBA_HIGH
+    {
+        write_register(channel, register, 0);
+    }
+    write_register(channel, REG_COMMAND, COMMAND_IDENTIFY);
+    delay_400ns(channel);
+    if read_register(channel, REG_STATUS) == 0
+    {
+        return None;
+    }
+    wait_not_busy(channel).ok()?;
+    // ATAPI and SATA devices put a signature here and do not take ATA commands
+    if read_register(channel, REG_LBA_MID) != 0 || read_register(channel, REG_LBA_HIGH) != 0
+    {
+        return None;
+    }
+    wait_for_data(channel).ok()?;
+
+    let mut words = [0u16; 256];
+    let mut data = Port::<u16>::new(channel.io_base + REG_DATA);
+    for word in words.iter_mut()
+    {
+        *word = unsafe { data.read() };
+    }
+
+    let lba48 = words[83] & (1 << 10) != 0;
+    let sectors = if lba48
+    {
+        (words[100] as u64) | (words[101] as u64) << 16 | (words[102] as u64) << 32 | (words[103] as u64) << 48
+    }
+    else
+    {
+        (words[60] as u64) | (words[61] as u64) << 16
+    };
+    // The model name is stored with the two bytes of every word swapped
+    let mut model = [b' '; 40];
+    for i in 0..20
+    {
+        model[2 * i] = (words[27 + i] >> 8) as u8;
+        model[2 * i + 1] = words[27 + i] as u8;
+    }
+    return Some(Drive { index, sectors, lba48, model });
+}
+
+impl Drive
+{
+    pub fn index(&self) -> usize
+    {
+        self.index
+    }
+
+    pub fn model(&self) -> &str
+    {
+        core::str::from_utf8(&self.model).unwrap_or("?").trim()
+    }
+
+    fn channel(&self) -> &'static Channel
+    {
+        &CHANNELS[self.index / 2]
+    }
+
+    // Selects the drive and sends a read or write command for `count`
+    // sectors at `lba`, LBA48 only when the sectors are out of LBA28's reach
+    fn send_command(&self, lba:u64, count:u64, command28:u8, command48:u8)
+    {
+        let channel = self.channel();
+        let slave = (self.index % 2) as u8;
+        if lba + count <= LBA28_LIMIT
+        {
+            write_register(channel, REG_DRIVE, 0xE0 | slave << 4 | (lba >> 24) as u8 & 0x0F);
+            delay_400ns(channel);
+            write_register(channel, REG_SECTOR_COUNT, count as u8);
+            write_register(channel, REG_LBA_LOW, lba as u8);
+            write_register(channel, REG_LBA_MID, (lba >> 8) as u8);
+            write_register(channel, REG_LBA_HIGH, (lba >> 16) as u8);
+            write_register(channel, REG_COMMAND, command28);
+        }
+        else
+        {
+            write_register(channel, REG_DRIVE, 0x40 | slave << 4);
+            delay_400ns(channel);
+            // High bytes first, each register keeps the two last values written
+            write_register(channel, REG_SECTOR_COUNT, (count >> 8) as u8);
+            write_register(channel, REG_LBA_LOW, (lba >> 24) as u8);
+            write_register(channel, REG_LBA_MID, (lba >> 32) as u8);
+            write_register(channel, REG_LBA_HIGH, (lba >> 40) as u8);
+            write_register(channel, REG_SECTOR_COUNT, count as u8);
+            write_register(channel, REG_LBA_LOW, lba as u8);
+            write_register(channel, REG_LBA_MID, (lba >> 8) as u8);
+            write_register(channel, REG_LBA_HIGH, (lba >> 16) as u8);
+            write_register(channel, REG_COMMAND, command48);
+        }
+        delay_400ns(channel);
+    }
+
+    fn flush(&self) -> Result<(), BlockError>
+    {
+        let channel = self.channel();
+        write_register(channel, REG_COMMAND, if self.lba48 { COMMAND_FLUSH_EXT } else { COMMAND_FLUSH });
+        delay_400ns(channel);
+        let status = wait_not_busy(channel)?;
+        if status & (STATUS_ERR | STATUS_DF) != 0
+        {
+            return Err(BlockError::DeviceError);
+        }
+        return Ok(());
+    }
+}
+
+impl BlockDevice for Drive
+{
+    fn sector_count(&self) -> u64
+    {
+        self.sectors
+    }
+
+    fn read_sectors(&mut self, lba:u64, buf:&mut [u8]) -> Result<(), BlockError>
+    {
+        block::check_transfer(self.sectors, lba, buf.len())?;
+        let channel = self.channel();
+        let _guard = channel.lock.lock();
+        let mut data = Port::<u16>::new(channel.io_base + REG_DATA);
+        for (number, chunk) in buf.chunks_mut(MAX_SECTORS_PER_COMMAND as usize * SECTOR_SIZE).enumerate()
+        {
+            let count = (chunk.len() / SECTOR_SIZE) as u64;
+            self.send_command(lba + number as u64 * MAX_SECTORS_PER_COMMAND, count, COMMAND_READ, COMMAND_READ_EXT);
+            for sector in chunk.chunks_mut(SECTOR_SIZE)
+            {
+                wait_for_data(channel)?;
+                for bytes in sector.chunks_mut(2)
+                {
+                    let word = unsafe { data.read() };
+                    bytes.copy_from_slice(&word.to_le_bytes());
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    fn write_sectors(&mut self, lba:u64, data:&[u8]) -> Result<(), BlockError>
+    {
+        block::check_transfer(self.sectors, lba, data.len())?;
+        let channel = self.channel();
+        let _guard = channel.lock.lock();
+        let mut port = Port::<u16>::new(channel.io_base + REG_DATA);
+        for (number, chunk) in data.chunks(MAX_SECTORS_PER_COMMAND as usize * SECTOR_SIZE).enumerate()
+        {
+            let count = (chunk.len() / SECTOR_SIZE) as u64;
+            self.send_command(lba + number as u64 * MAX_SECTORS_PER_COMMAND, count, COMMAND_WRITE, COMMAND_WRITE_EXT);
+            for sector in chunk.chunks(SECTOR_SIZE)
+            {
+                wait_for_data(channel)?;
+                for bytes in sector.chunks(2)
+                {
+                    unsafe { port.write(u16::from_le_bytes([bytes[0], bytes[1]])) };
+                }
+            }
+        }
+        // Without it the data may sit in the drive's cache at power-off
+        return self.flush();
+    }
+}
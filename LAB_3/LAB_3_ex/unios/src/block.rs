@@ -0,0 +1,56 @@
+/// Size of the blocks every device is read and written in.
+pub const SECTOR_SIZE:usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockError
+{
+    // past the last sector of the device
+    OutOfRange,
+    // not a whole number of sectors
+    BadBuffer,
+    // the device reported an error
+    DeviceError,
+    // the device did not answer in time
+    Timeout,
+}
+
+impl BlockError
+{
+    pub fn message(&self) -> &'static str
+    {
+        match self
+        {
+            BlockError::OutOfRange => "Sector out of range",
+            BlockError::BadBuffer => "Buffer is not a whole number of sectors",
+            BlockError::DeviceError => "Device error",
+            BlockError::Timeout => "Device does not answer",
+        }
+    }
+}
+
+/// A disk a filesystem can live on, read and written in whole sectors.
+pub trait BlockDevice
+{
+    /// How many sectors the device has.
+    fn sector_count(&self) -> u64;
+    /// Reads `buf.len() / SECTOR_SIZE` sectors starting at sector `lba`.
+    fn read_sectors(&mut self, lba:u64, buf:&mut [u8]) -> Result<(), BlockError>;
+    /// Writes `data.len() / SECTOR_SIZE` sectors starting at sector `lba`.
+    fn write_sectors(&mut self, lba:u64, data:&[u8]) -> Result<(), BlockError>;
+}
+
+/// Checks a transfer of `length` bytes at `lba` against a device of
+/// `sector_count` sectors and returns how many sectors it covers.
+pub fn check_transfer(sector_count:u64, lba:u64, length:usize) -> Result<u64, BlockError>
+{
+    if length % SECTOR_SIZE != 0
+    {
+        return Err(BlockError::BadBuffer);
+    }
+    let count = (length / SECTOR_SIZE) as u64;
+    if lba.checked_add(count).map_or(true, |end| end > sector_count)
+    {
+        return Err(BlockError::OutOfRange);
+    }
+    return Ok(count);
+}
@@ -0,0 +1,237 @@
+use x86_64::structures::paging::PageTableFlags;
+use crate::memory::{AddressSpace, USER_BASE};
+use crate::process::{self, ExecError};
+use crate::sync::Mutex;
+use crate::usermode::{self, USER_STACK_SIZE, USER_STACK_TOP};
+use crate::vfs::{self, Inode};
+
+const ELF_MAGIC:[u8; 4] = [0x7f, b'E', b'L', b'F'];
+const ELFCLASS64:u8 = 2;
+const ELFDATA2LSB:u8 = 1;
+const ET_EXEC:u16 = 2;
+const EM_X86_64:u16 = 0x3e;
+
+const ELF_HEADER_SIZE:usize = 64;
+const PROGRAM_HEADER_SIZE:usize = 56;
+
+const PT_LOAD:u32 = 1;
+const PF_X:u32 = 1;
+const PF_W:u32 = 2;
+
+/// Longest executable `exec_elf_file` runs, it is read whole before loading.
+pub const MAX_FILE_SIZE:usize = 64 * 1024;
+
+// Where `exec_elf_file` reads an executable, one at a time. Reading a file
+// may sleep, so it is a sleeping lock.
+static FILE_IMAGE:Mutex<[u8; MAX_FILE_SIZE]> = Mutex::new([0; MAX_FILE_SIZE]);
+
+/// The fields of the ELF header the loader needs.
+pub struct ElfHeader
+{
+    pub entry:u64,
+    pub program_header_offset:u64,
+    pub program_header_count:u16,
+}
+
+/// One entry of the program header table.
+pub struct ProgramHeader
+{
+    pub kind:u32,
+    pub flags:u32,
+    pub offset:u64,
+    pub virtual_address:u64,
+    pub file_size:u64,
+    pub memory_size:u64,
+}
+
+fn read_u16(image:&[u8], offset:usize) -> u16
+{
+    u16::from_le_bytes([image[offset], image[offset + 1]])
+}
+
+fn read_u32(image:&[u8], offset:usize) -> u32
+{
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&image[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn read_u64(image:&[u8], offset:usize) -> u64
+{
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&image[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+/// Checks that `image` is a little endian x86_64 ELF64 executable.
+pub fn parse_header(image:&[u8]) -> Result<ElfHeader, ExecError>
+{
+    if image.len() < ELF_HEADER_SIZE
+        || image[0..4] != ELF_MAGIC
+        || image[4] != ELFCLASS64
+        || image[5] != ELFDATA2LSB
+        || read_u16(image, 16) != ET_EXEC
+        || read_u16(image, 18) != EM_X86_64
+        || read_u16(image, 54) as usize != PROGRAM_HEADER_SIZE
+    {
+        return Err(ExecError::BadImage);
+    }
+
+    let header = ElfHeader
+    {
+        entry: read_u64(image, 24),
+        program_header_offset: read_u64(image, 32),
+        program_header_count: read_u16(image, 56),
+    };
+    let table_end = header.program_header_offset
+        .checked_add(header.program_header_count as u64 * PROGRAM_HEADER_SIZE as u64);
+    match table_end
+    {
+        Some(end) if end <= image.len() as u64 => Ok(header),
+        _ => Err(ExecError::BadImage),
+    }
+}
+
+pub fn program_header(image:&[u8], header:&ElfHeader, index:u16) -> ProgramHeader
+{
+    let offset = header.program_header_offset as usize + index as usize * PROGRAM_HEADER_SIZE;
+    ProgramHeader
+    {
+        kind: read_u32(image, offset),
+        flags: read_u32(image, offset + 4),
+        offset: read_u64(image, offset + 8),
+        virtual_address: read_u64(image, offset + 16),
+        file_size: read_u64(image, offset + 32),
+        memory_size: read_u64(image, offset + 40),
+    }
+}
+
+fn load_segment(space:&mut AddressSpace, image:&[u8], segment:&ProgramHeader) -> Result<(), ExecError>
+{
+    let file_end = segment.offset.checked_add(segment.file_size);
+    let memory_end = segment.virtual_address.checked_add(segment.memory_size);
+    let fits = match (file_end, memory_end)
+    {
+        (Some(file_end), Some(memory_end)) => file_end <= image.len() as u64
+            && segment.file_size <= segment.memory_size
+            && segment.virtual_address >= USER_BASE
+            && memory_end <= USER_STACK_TOP - USER_STACK_SIZE,
+        _ => false,
+    };
+    if !fits
+    {
+        return Err(ExecError::BadImage);
+    }
+
+    let mut flags = PageTableFlags::empty();
+    if segment.flags & PF_W != 0
+    {
+        flags |= PageTableFlags::WRITABLE;
+    }
+    if segment.flags & PF_X == 0
+    {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+    if !space.map(segment.virtual_address, segment.memory_size, flags)
+    {
+        return Err(ExecError::OutOfMemory);
+    }
+
+    let data = &image[segment.offset as usize..(segment.offset + segment.file_size) as usize];
+    if !space.write(segment.virtual_address, data)
+    {
+        return Err(ExecError::OutOfMemory);
+    }
+
+    // .bss: the frames come zeroed, but the page may be shared with another segment
+    let zeros = [0u8; 256];
+    let mut address = segment.virtual_address + segment.file_size;
+    while address < memory_end.unwrap()
+    {
+        let count = (memory_end.unwrap() - address).min(zeros.len() as u64);
+        if !space.write(address, &zeros[..count as usize])
+        {
+            return Err(ExecError::OutOfMemory);
+        }
+        address += count;
+    }
+    return Ok(());
+}
+
+fn load(space:&mut AddressSpace, image:&[u8], argv:&[&str]) -> Result<(u64, u64), ExecError>
+{
+    let header = parse_header(image)?;
+    for i in 0..header.program_header_count
+    {
+        let segment = program_header(image, &header, i);
+        if segment.kind == PT_LOAD
+        {
+            load_segment(space, image, &segment)?;
+        }
+    }
+
+    let stack_pointer = usermode::setup_stack(space, argv).ok_or(ExecError::OutOfMemory)?;
+    return Ok((header.entry, stack_pointer));
+}
+
+/// Loads the PT_LOAD segments of an ELF64 executable into a fresh address
+/// space and starts it with `argv`. Only returns if it could not be loaded.
+pub fn exec_elf(image:&[u8], argv:&[&str]) -> ExecError
+{
+    exec_with(argv, |space| load(space, image, argv))
+}
+
+/// Like `exec_elf` for the executable in `file`, which may be up to
+/// MAX_FILE_SIZE bytes long.
+pub fn exec_elf_file(file:Inode, argv:&[&str]) -> ExecError
+{
+    exec_with(argv, |space| {
+        // Given back before the program starts, it never returns here
+        let mut image = FILE_IMAGE.lock();
+        let size = read_file(file, &mut image[..])?;
+        load(space, &image[..size], argv)
+    })
+}
+
+// Reads all of `file` into `buf` and returns its size
+fn read_file(file:Inode, buf:&mut [u8]) -> Result<usize, ExecError>
+{
+    let size = vfs::stat(file).map_err(|_| ExecError::NotFound)?.size;
+    if size > buf.len()
+    {
+        return Err(ExecError::TooLarge);
+    }
+    let mut done = 0;
+    while done < size
+    {
+        match vfs::read_at(file, done, &mut buf[done..size])
+        {
+            Ok(0) | Err(_) => return Err(ExecError::BadImage),
+            Ok(count) => done += count,
+        }
+    }
+    return Ok(size);
+}
+
+// Starts what `load_into` puts in a fresh address space. The process is
+// only renamed once that worked, a failed exec leaves it as it was.
+fn exec_with(argv:&[&str], load_into:impl FnOnce(&mut AddressSpace) -> Result<(u64, u64), ExecError>) -> ExecError
+{
+    let mut space = match AddressSpace::new()
+    {
+        Some(space) => space,
+        None => return ExecError::OutOfMemory,
+    };
+
+    match load_into(&mut space)
+    {
+        Ok((entry, stack_pointer)) => {
+            process::become_program(argv);
+            usermode::start(space, entry, stack_pointer)
+        }
+        Err(error) => {
+            space.destroy();
+            error
+        }
+    }
+}
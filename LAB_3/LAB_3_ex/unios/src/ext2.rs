@@ -0,0 +1,500 @@
+use crate::ata::Drive;
+use crate::block::{BlockDevice, SECTOR_SIZE};
+use crate::vfs::{DirEntry, FileSystem, FileType, FsError, Inode, Metadata, MAX_NAME_LENGTH};
+
+const SUPERBLOCK_OFFSET:u64 = 1024;
+const MAGIC:u16 = 0xEF53;
+const ROOT_INODE:u32 = 2;
+// Only the type byte in directory entries is understood, anything else
+// incompatible (extents, 64 bit, compression, a journal to replay) is refused
+const INCOMPAT_FILETYPE:u32 = 0x0002;
+const MAX_BLOCK_SIZE:usize = 4096;
+
+const MODE_TYPE:u16 = 0xF000;
+const MODE_DIRECTORY:u16 = 0x4000;
+const MODE_SYMLINK:u16 = 0xA000;
+
+const DIRECT_BLOCKS:u64 = 12;
+// Links short enough are kept in the block pointers themselves
+const FAST_LINK_LENGTH:usize = 60;
+const MAX_LINK_LENGTH:usize = 256;
+const MAX_LINK_DEPTH:usize = 8;
+
+/// A read-only ext2 volume, on a whole disk or on its first partition that
+/// has one. Inode numbers are ext2's own. `lookup` follows symbolic links;
+/// one that leads nowhere shows up as a file holding its target.
+pub struct Ext2Fs
+{
+    device:Drive,
+    // where the filesystem starts on the disk, in bytes
+    start:u64,
+    block_size:usize,
+    inode_count:u32,
+    inodes_per_group:u32,
+    inode_size:u64,
+    // byte offset of the block group descriptor table
+    descriptors:u64,
+}
+
+// The parts of an inode that are used
+struct RawInode
+{
+    mode:u16,
+    size:u64,
+    // 512 byte units, 0 for a link kept in `block`
+    sectors:u32,
+    block:[u32; 15],
+    uid:u16,
+    // ext2 keeps no creation time; the last change of the inode stands in
+    ctime:u32,
+    mtime:u32,
+}
+
+fn get_u16(buf:&[u8], offset:usize) -> u16
+{
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn get_u32(buf:&[u8], offset:usize) -> u32
+{
+    u32::from_le_bytes([buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]])
+}
+
+impl RawInode
+{
+    fn kind(&self) -> FileType
+    {
+        if self.mode & MODE_TYPE == MODE_DIRECTORY { FileType::Directory } else { FileType::File }
+    }
+
+    fn is_symlink(&self) -> bool
+    {
+        self.mode & MODE_TYPE == MODE_SYMLINK
+    }
+
+    fn is_fast_symlink(&self) -> bool
+    {
+        self.is_symlink() && self.sectors == 0 && (self.size as usize) < FAST_LINK_LENGTH
+    }
+}
+
+// Whether `readdir` shows an entry. A name longer than a DirEntry holds
+// would come out cut and lead nowhere, so it is left out like "." and "..".
+fn is_listed(name:&[u8]) -> bool
+{
+    name != b"." && name != b".." && name.len() <= MAX_NAME_LENGTH
+}
+
+impl Ext2Fs
+{
+    /// Reads the superblock of `device`, or of the first partition in its
+    /// partition table that has one.
+    pub fn new(mut device:Drive) -> Result<Ext2Fs, FsError>
+    {
+        if let Some(fs) = Ext2Fs::parse(device, 0)?
+        {
+            return Ok(fs);
+        }
+        let mut sector = [0u8; SECTOR_SIZE];
+        device.read_sectors(0, &mut sector)?;
+        if get_u16(&sector, 510) != 0xAA55
+        {
+            return Err(FsError::NotAFileSystem);
+        }
+        for partition in 0..4
+        {
+            let start = get_u32(&sector, 446 + 16 * partition + 8) as u64;
+            if sector[446 + 16 * partition + 4] == 0 || start == 0
+            {
+                continue;
+            }
+            if let Ok(Some(fs)) = Ext2Fs::parse(device, start * SECTOR_SIZE as u64)
+            {
+                return Ok(fs);
+            }
+        }
+        return Err(FsError::NotAFileSystem);
+    }
+
+    // The filesystem starting at byte `start` of `device`, if there is one
+    fn parse(device:Drive, start:u64) -> Result<Option<Ext2Fs>, FsError>
+    {
+        let mut fs = Ext2Fs { device, start, block_size: 1024, inode_count: 0, inodes_per_group: 0, inode_size: 128, descriptors: 0 };
+        let mut superblock = [0u8; 1024];
+        fs.read_bytes(SUPERBLOCK_OFFSET, &mut superblock)?;
+        let log_block_size = get_u32(&superblock, 24);
+        if get_u16(&superblock, 56) != MAGIC || log_block_size > 2
+            || get_u32(&superblock, 96) & !INCOMPAT_FILETYPE != 0
+        {
+            return Ok(None);
+        }
+        fs.block_size = 1024 << log_block_size;
+        fs.inode_count = get_u32(&superblock, 0);
+        fs.inodes_per_group = get_u32(&superblock, 40);
+        // Revision 0 has fixed 128 byte inodes
+        if get_u32(&superblock, 76) >= 1
+        {
+            fs.inode_size = get_u16(&superblock, 88) as u64;
+        }
+        fs.descriptors = (get_u32(&superblock, 20) as u64 + 1) * fs.block_size as u64;
+        if fs.inodes_per_group == 0 || fs.inode_size < 128
+        {
+            return Ok(None);
+        }
+        return Ok(Some(fs));
+    }
+
+    /// Always "ext2".
+    pub fn kind(&self) -> &'static str
+    {
+        "ext2"
+    }
+
+    pub fn drive(&self) -> Drive
+    {
+        self.device
+    }
+
+    // Reads `buf.len()` bytes from byte `offset` of the filesystem
+    fn read_bytes(&self, offset:u64, buf:&mut [u8]) -> Result<(), FsError>
+    {
+        let mut device = self.device;
+        let mut sector = [0u8; SECTOR_SIZE];
+        let mut done = 0;
+        while done < buf.len()
+        {
+            let position = self.start + offset + done as u64;
+            device.read_sectors(position / SECTOR_SIZE as u64, &mut sector)?;
+            let start = (position % SECTOR_SIZE as u64) as usize;
+            let count = (SECTOR_SIZE - start).min(buf.len() - done);
+            buf[done..done + count].copy_from_slice(&sector[start..start + count]);
+            done += count;
+        }
+        return Ok(());
+    }
+
+    fn read_u32(&self, offset:u64) -> Result<u32, FsError>
+    {
+        let mut bytes = [0u8; 4];
+        self.read_bytes(offset, &mut bytes)?;
+        return Ok(u32::from_le_bytes(bytes));
+    }
+
+    fn inode(&self, number:u32) -> Result<RawInode, FsError>
+    {
+        if number == 0 || number > self.inode_count
+        {
+            return Err(FsError::Corrupted);
+        }
+        let group = ((number - 1) / self.inodes_per_group) as u64;
+        let index = ((number - 1) % self.inodes_per_group) as u64;
+        // The inode table's block is the third field of the group descriptor
+        let table = self.read_u32(self.descriptors + group * 32 + 8)? as u64;
+        let mut raw = [0u8; 128];
+        self.read_bytes(table * self.block_size as u64 + index * self.inode_size, &mut raw)?;
+        let mode = get_u16(&raw, 0);
+        let mut size = get_u32(&raw, 4) as u64;
+        // Regular files keep the high half of their size where directories
+        // keep their ACL
+        if mode & MODE_TYPE != MODE_DIRECTORY
+        {
+            size |= (get_u32(&raw, 108) as u64) << 32;
+        }
+        let mut block = [0u32; 15];
+        for (i, pointer) in block.iter_mut().enumerate()
+        {
+            *pointer = get_u32(&raw, 40 + 4 * i);
+        }
+        return Ok(RawInode
+        {
+            mode,
+            size,
+            sectors: get_u32(&raw, 28),
+            block,
+            uid: get_u16(&raw, 2),
+            ctime: get_u32(&raw, 12),
+            mtime: get_u32(&raw, 16),
+        });
+    }
+
+    // Entry `index` of the block of pointers `table`, 0 being a hole
+    fn indirect(&self, table:u32, index:u64) -> Result<u32, FsError>
+    {
+        if table == 0
+        {
+            return Ok(0);
+        }
+        return self.read_u32(table as u64 * self.block_size as u64 + index * 4);
+    }
+
+    // The disk block holding block `n` of the inode's data
+    fn block_of(&self, inode:&RawInode, n:u64) -> Result<u32, FsError>
+    {
+        let per_block = (self.block_size / 4) as u64;
+        if n < DIRECT_BLOCKS
+        {
+            return Ok(inode.block[n as usize]);
+        }
+        let n = n - DIRECT_BLOCKS;
+        if n < per_block
+        {
+            return self.indirect(inode.block[12], n);
+        }
+        let n = n - per_block;
+        if n < per_block * per_block
+        {
+            let table = self.indirect(inode.block[13], n / per_block)?;
+            return self.indirect(table, n % per_block);
+        }
+        let n = n - per_block * per_block;
+        let table = self.indirect(inode.block[14], n / (per_block * per_block))?;
+        let table = self.indirect(table, n / per_block % per_block)?;
+        return self.indirect(table, n % per_block);
+    }
+
+    fn read_data(&self, inode:&RawInode, offset:usize, buf:&mut [u8]) -> Result<usize, FsError>
+    {
+        let count = (inode.size as usize).saturating_sub(offset).min(buf.len());
+        if count == 0
+        {
+            return Ok(0);
+        }
+        if inode.is_fast_symlink()
+        {
+            let mut target = [0u8; FAST_LINK_LENGTH];
+            for (i, pointer) in inode.block.iter().enumerate()
+            {
+                target[4 * i..4 * i + 4].copy_from_slice(&pointer.to_le_bytes());
+            }
+            buf[..count].copy_from_slice(&target[offset..offset + count]);
+            return Ok(count);
+        }
+        let mut done = 0;
+        while done < count
+        {
+            let position = offset + done;
+            let within = position % self.block_size;
+            let length = (self.block_size - within).min(count - done);
+            match self.block_of(inode, (position / self.block_size) as u64)?
+            {
+                0 => buf[done..done + length].fill(0),
+                block => self.read_bytes(block as u64 * self.block_size as u64 + within as u64, &mut buf[done..done + length])?,
+            }
+            done += length;
+        }
+        return Ok(count);
+    }
+
+    // Goes through the entries of directory `dir` until `wanted` picks one
+    // by its name and inode number
+    fn scan(&self, dir:u32, mut wanted:impl FnMut(&[u8], u32) -> bool) -> Result<bool, FsError>
+    {
+        let inode = self.inode(dir)?;
+        if inode.kind() != FileType::Directory
+        {
+            return Err(FsError::NotADirectory);
+        }
+        let mut block = [0u8; MAX_BLOCK_SIZE];
+        let block = &mut block[..self.block_size];
+        for n in 0..inode.size.div_ceil(self.block_size as u64)
+        {
+            match self.block_of(&inode, n)?
+            {
+                0 => continue,
+                number => self.read_bytes(number as u64 * self.block_size as u64, block)?,
+            }
+            let mut at = 0;
+            while at + 8 <= self.block_size
+            {
+                let number = get_u32(block, at);
+                let length = get_u16(block, at + 4) as usize;
+                let name_length = block[at + 6] as usize;
+                if length < 8 || at + length > self.block_size || 8 + name_length > length
+                {
+                    return Err(FsError::Corrupted);
+                }
+                if number != 0 && wanted(&block[at + 8..at + 8 + name_length], number)
+                {
+                    return Ok(true);
+                }
+                at += length;
+            }
+        }
+        return Ok(false);
+    }
+
+    // What entry `number` found in `dir` stands for: the inode itself, or
+    // where it leads if it is a symbolic link that leads somewhere
+    fn follow(&self, dir:u32, number:u32, depth:usize) -> Result<Inode, FsError>
+    {
+        let inode = self.inode(number)?;
+        if !inode.is_symlink()
+        {
+            return Ok(Inode::new(inode.kind(), number as usize));
+        }
+        let mut target = [0u8; MAX_LINK_LENGTH];
+        let length = self.read_data(&inode, 0, &mut target)?;
+        let followed = match core::str::from_utf8(&target[..length])
+        {
+            Ok(path) if depth < MAX_LINK_DEPTH => self.walk(dir, path, depth + 1),
+            _ => Err(FsError::NotFound),
+        };
+        return Ok(followed.unwrap_or(Inode::new(FileType::File, number as usize)));
+    }
+
+    // Resolves a link's target, "/" being the root of this filesystem
+    fn walk(&self, dir:u32, path:&str, depth:usize) -> Result<Inode, FsError>
+    {
+        let mut current = Inode::new(FileType::Directory, if path.starts_with('/') { ROOT_INODE } else { dir } as usize);
+        for name in path.split('/').filter(|name| !name.is_empty())
+        {
+            current = self.lookup_from(current, name, depth)?;
+        }
+        return Ok(current);
+    }
+
+    fn lookup_from(&self, dir:Inode, name:&str, depth:usize) -> Result<Inode, FsError>
+    {
+        if dir.kind != FileType::Directory
+        {
+            return Err(FsError::NotADirectory);
+        }
+        if name == "."
+        {
+            return Ok(dir);
+        }
+        let mut found = 0;
+        self.scan(dir.number as u32, |entry, number| {
+            if entry != name.as_bytes()
+            {
+                return false;
+            }
+            found = number;
+            return true;
+        })?;
+        if found == 0
+        {
+            return Err(FsError::NotFound);
+        }
+        return self.follow(dir.number as u32, found, depth);
+    }
+}
+
+impl FileSystem for Ext2Fs
+{
+    fn root(&self) -> Inode
+    {
+        Inode::new(FileType::Directory, ROOT_INODE as usize)
+    }
+
+    fn lookup(&self, dir:Inode, name:&str) -> Result<Inode, FsError>
+    {
+        self.lookup_from(dir, name, 0)
+    }
+
+    fn create(&mut self, _dir:Inode, _name:&str) -> Result<Inode, FsError>
+    {
+        Err(FsError::ReadOnly)
+    }
+
+    fn mkdir(&mut self, _dir:Inode, _name:&str) -> Result<Inode, FsError>
+    {
+        Err(FsError::ReadOnly)
+    }
+
+    fn unlink(&mut self, _dir:Inode, _name:&str) -> Result<(), FsError>
+    {
+        Err(FsError::ReadOnly)
+    }
+
+    fn rmdir(&mut self, _dir:Inode, _name:&str) -> Result<(), FsError>
+    {
+        Err(FsError::ReadOnly)
+    }
+
+    fn rename(&mut self, _old_dir:Inode, _old_name:&str, _new_dir:Inode, _new_name:&str) -> Result<(), FsError>
+    {
+        Err(FsError::ReadOnly)
+    }
+
+    fn read(&self, file:Inode, offset:usize, buf:&mut [u8]) -> Result<usize, FsError>
+    {
+        let inode = self.inode(file.number as u32)?;
+        if inode.kind() == FileType::Directory
+        {
+            return Err(FsError::IsADirectory);
+        }
+        return self.read_data(&inode, offset, buf);
+    }
+
+    fn write(&mut self, _file:Inode, _offset:usize, _data:&[u8]) -> Result<usize, FsError>
+    {
+        Err(FsError::ReadOnly)
+    }
+
+    fn truncate(&mut self, _file:Inode, _size:usize) -> Result<(), FsError>
+    {
+        Err(FsError::ReadOnly)
+    }
+
+    fn readdir(&self, dir:Inode, index:usize) -> Result<Option<DirEntry>, FsError>
+    {
+        let mut seen = 0;
+        let mut name = [0u8; MAX_NAME_LENGTH];
+        let mut length = 0;
+        let mut found = 0;
+        self.scan(dir.number as u32, |entry, number| {
+            if !is_listed(entry)
+            {
+                return false;
+            }
+            seen += 1;
+            if seen <= index
+            {
+                return false;
+            }
+            length = entry.len();
+            name[..length].copy_from_slice(entry);
+            found = number;
+            return true;
+        })?;
+        if found == 0
+        {
+            return Ok(None);
+        }
+        // A link to a directory is listed as the link, so that walking the
+        // tree can not go round in circles
+        let inode = match self.follow(dir.number as u32, found, 0)?
+        {
+            inode if inode.kind == FileType::Directory && inode.number != found as usize => Inode::new(FileType::File, found as usize),
+            inode => inode,
+        };
+        return Ok(Some(DirEntry::new(&name[..length], inode)));
+    }
+
+    fn stat(&self, inode:Inode) -> Result<Metadata, FsError>
+    {
+        let raw = self.inode(inode.number as u32)?;
+        let size = match inode.kind
+        {
+            FileType::File => raw.size as usize,
+            FileType::Directory => {
+                let mut count = 0;
+                self.scan(inode.number as u32, |entry, _| {
+                    if is_listed(entry)
+                    {
+                        count += 1;
+                    }
+                    return false;
+                })?;
+                count
+            }
+        };
+        let mut metadata = Metadata::new(inode, size);
+        metadata.created = raw.ctime as u64;
+        metadata.modified = raw.mtime as u64;
+        metadata.owner = raw.uid as u32;
+        metadata.mode = raw.mode & 0o7777;
+        return Ok(metadata);
+    }
+}
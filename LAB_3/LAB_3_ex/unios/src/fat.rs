@@ -0,0 +1,1457 @@
+use core::cell::Cell;
+use crate::ata::Drive;
+use crate::block::{BlockDevice, SECTOR_SIZE};
+use crate::rtc::{self, DateTime};
+use crate::vfs::{DirEntry, FileSystem, FileType, FsError, Inode, Metadata, MAX_NAME_LENGTH};
+
+const ATTR_READ_ONLY:u8 = 0x01;
+const ATTR_VOLUME_ID:u8 = 0x08;
+const ATTR_DIRECTORY:u8 = 0x10;
+const ATTR_ARCHIVE:u8 = 0x20;
+// read-only, hidden, system and volume id together mark a long name entry
+const ATTR_LONG_NAME:u8 = 0x0F;
+
+const ENTRY_SIZE:usize = 32;
+const ENTRIES_PER_SECTOR:usize = SECTOR_SIZE / ENTRY_SIZE;
+const END_OF_DIRECTORY:u8 = 0x00;
+const DELETED:u8 = 0xE5;
+
+// A long name is spread over up to 20 entries of 13 UCS-2 characters each,
+// the last one first and flagged
+const LAST_LONG_ENTRY:u8 = 0x40;
+const CHARS_PER_LONG_ENTRY:usize = 13;
+const MAX_LONG_ENTRIES:usize = 20;
+const MAX_LONG_NAME:usize = MAX_LONG_ENTRIES * CHARS_PER_LONG_ENTRY;
+const LONG_NAME_OFFSETS:[usize; CHARS_PER_LONG_ENTRY] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+// Byte 12 of a short entry: the name or extension is shown in lower case
+const CASE_LOWER_BASE:u8 = 0x08;
+const CASE_LOWER_EXTENSION:u8 = 0x10;
+
+// Where a short entry keeps its times, each a u16 time and a u16 date
+const CREATED_TIME:usize = 14;
+const ACCESSED_DATE:usize = 18;
+const MODIFIED_TIME:usize = 22;
+
+const FIRST_CLUSTER:u32 = 2;
+// The root directory is inode 0 whatever cluster it is in
+const ROOT_NUMBER:usize = 0;
+// How many renamed files are remembered, see `FatFs`
+const MAX_MOVED:usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FatType
+{
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+/// A FAT12, FAT16 or FAT32 volume with VFAT long names, on a whole disk or
+/// on its first FAT partition. Only the FAT sector read last is cached,
+/// everything else goes to the disk.
+///
+/// A directory's inode number is its first cluster. A file's is its first
+/// cluster too, with where its short entry is (sector * 16 + entry in the
+/// sector) in the high half to find it fast. Every file has a cluster, even
+/// an empty one: an empty file made elsewhere gets one when it is first
+/// looked up. Renaming a file moves its short entry, so the new place is
+/// remembered for the last MAX_MOVED renamed files; an inode whose file has
+/// gone elsewhere and been forgotten reads as not found, never as another
+/// file.
+pub struct FatFs
+{
+    device:Drive,
+    fat_type:FatType,
+    sectors_per_cluster:u64,
+    fat_start:u64,
+    fat_sectors:u64,
+    fat_count:u64,
+    // the fixed root directory of FAT12 and FAT16
+    root_start:u64,
+    root_sectors:u64,
+    // the root directory's first cluster on FAT32, 0 otherwise
+    root_cluster:u32,
+    data_start:u64,
+    cluster_count:u32,
+    // where looking for a free cluster goes on, like the hint in FSInfo
+    next_free:Cell<u32>,
+    // the FAT sector read last, so a scan of the FAT reads each one once
+    fat_sector:Cell<Option<(u64, [u8; SECTOR_SIZE])>>,
+    // first cluster and short entry of renamed files, oldest overwritten
+    moved:[Option<(u32, usize)>; MAX_MOVED],
+    moved_next:usize,
+}
+
+// A file or directory read from a directory
+struct Entry
+{
+    name:[u8; MAX_LONG_NAME],
+    length:usize,
+    short:[u8; ENTRY_SIZE],
+    // directory slots from the first long name entry to the short entry
+    first_slot:usize,
+    slot:usize,
+    // sector * ENTRIES_PER_SECTOR + entry in it, of the short entry
+    position:usize,
+}
+
+fn get_u16(buf:&[u8], offset:usize) -> u16
+{
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn get_u32(buf:&[u8], offset:usize) -> u32
+{
+    u32::from_le_bytes([buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]])
+}
+
+fn put_u16(buf:&mut [u8], offset:usize, value:u16)
+{
+    buf[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buf:&mut [u8], offset:usize, value:u32)
+{
+    buf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn entry_size(short:&[u8]) -> usize
+{
+    get_u32(short, 28) as usize
+}
+
+fn set_entry_cluster(short:&mut [u8], cluster:u32)
+{
+    put_u16(short, 20, (cluster >> 16) as u16);
+    put_u16(short, 26, cluster as u16);
+}
+
+// The first cluster identifies the file, the entry's place finds it
+fn file_inode(cluster:u32, position:usize) -> Inode
+{
+    Inode::new(FileType::File, position << 32 | cluster as usize)
+}
+
+fn is_directory(short:&[u8]) -> bool
+{
+    short[11] & ATTR_DIRECTORY != 0
+}
+
+// A time and date the DOS way, in two second steps from 1980 on
+fn put_time(short:&mut [u8], offset:usize, seconds:u64)
+{
+    let time = DateTime::from_unix(seconds);
+    if time.year < 1980
+    {
+        return;
+    }
+    put_u16(short, offset, (time.hour << 11 | time.minute << 5 | time.second / 2) as u16);
+    put_u16(short, offset + 2, ((time.year - 1980).min(127) << 9 | time.month << 5 | time.day) as u16);
+}
+
+// 0 for a time that was never set
+fn get_time(short:&[u8], offset:usize) -> u64
+{
+    let time = get_u16(short, offset) as u32;
+    let date = get_u16(short, offset + 2) as u32;
+    if date == 0
+    {
+        return 0;
+    }
+    let time = DateTime
+    {
+        year: 1980 + (date >> 9),
+        month: date >> 5 & 0x0F,
+        day: date & 0x1F,
+        hour: time >> 11,
+        minute: time >> 5 & 0x3F,
+        second: (time & 0x1F) * 2,
+    };
+    return time.to_unix();
+}
+
+// Sets the creation, access and write times of a new entry
+fn stamp_new(short:&mut [u8])
+{
+    let now = rtc::now();
+    short[13] = 0;
+    put_time(short, CREATED_TIME, now);
+    put_time(short, MODIFIED_TIME, now);
+    put_u16(short, ACCESSED_DATE, get_u16(short, MODIFIED_TIME + 2));
+}
+
+fn stamp_modified(short:&mut [u8])
+{
+    put_time(short, MODIFIED_TIME, rtc::now());
+    put_u16(short, ACCESSED_DATE, get_u16(short, MODIFIED_TIME + 2));
+}
+
+fn entry_metadata(inode:Inode, size:usize, short:&[u8]) -> Metadata
+{
+    let mut metadata = Metadata::new(inode, size);
+    metadata.created = get_time(short, CREATED_TIME);
+    metadata.modified = get_time(short, MODIFIED_TIME);
+    if short[11] & ATTR_READ_ONLY != 0
+    {
+        metadata.mode &= !0o222;
+    }
+    return metadata;
+}
+
+// The checksum of a short name that its long name entries carry
+fn checksum(short:&[u8]) -> u8
+{
+    short[..11].iter().fold(0u8, |sum, &byte| (sum >> 1).wrapping_add(sum << 7).wrapping_add(byte))
+}
+
+// Characters a short name can have besides letters and digits
+fn is_short_char(byte:u8) -> bool
+{
+    byte.is_ascii_alphanumeric() || b"!#$%&'()-@^_`{}~".contains(&byte)
+}
+
+// Names are ASCII without the characters FAT does not allow
+fn check_name(name:&str) -> Result<(), FsError>
+{
+    if name.is_empty() || name == "." || name == ".." || name.ends_with('.') || name.ends_with(' ')
+        || name.bytes().any(|byte| byte < 0x20 || byte > 0x7E || b"\"*/:<>?\\|".contains(&byte))
+    {
+        return Err(FsError::InvalidName);
+    }
+    if name.len() > MAX_NAME_LENGTH
+    {
+        return Err(FsError::NameTooLong);
+    }
+    return Ok(());
+}
+
+// `name` split at its last dot, which can not be the first character
+fn split_extension(name:&str) -> (&str, &str)
+{
+    match name.rfind('.')
+    {
+        Some(i) if i > 0 => (&name[..i], &name[i + 1..]),
+        _ => (name, ""),
+    }
+}
+
+// "readme.txt" fits a short name as it is, with the case bits telling
+// Linux to show it in lower case. Mixed case does not fit.
+fn exact_short_name(name:&str) -> Option<([u8; 11], u8)>
+{
+    let (base, extension) = split_extension(name);
+    if base.is_empty() || base.len() > 8 || extension.len() > 3 || base.contains('.')
+        || !base.bytes().chain(extension.bytes()).all(is_short_char)
+    {
+        return None;
+    }
+    let lower = |part:&str| -> Option<bool> {
+        let has_lower = part.bytes().any(|byte| byte.is_ascii_lowercase());
+        let has_upper = part.bytes().any(|byte| byte.is_ascii_uppercase());
+        if has_lower && has_upper { None } else { Some(has_lower) }
+    };
+    let mut case = 0;
+    if lower(base)?
+    {
+        case |= CASE_LOWER_BASE;
+    }
+    if lower(extension)?
+    {
+        case |= CASE_LOWER_EXTENSION;
+    }
+    let mut short = [b' '; 11];
+    for (i, byte) in base.bytes().enumerate()
+    {
+        short[i] = byte.to_ascii_uppercase();
+    }
+    for (i, byte) in extension.bytes().enumerate()
+    {
+        short[8 + i] = byte.to_ascii_uppercase();
+    }
+    return Some((short, case));
+}
+
+impl Entry
+{
+    fn name(&self) -> &[u8]
+    {
+        &self.name[..self.length]
+    }
+
+    // Whether `readdir` shows it. A long name longer than a DirEntry holds
+    // would come out cut and lead nowhere, or to another file.
+    fn is_listed(&self) -> bool
+    {
+        self.length <= MAX_NAME_LENGTH
+    }
+
+    fn is_directory(&self) -> bool
+    {
+        is_directory(&self.short)
+    }
+
+    // "NAME.EXT" from the short entry, lowered as its case bits say
+    fn use_short_name(&mut self)
+    {
+        let case = self.short[12];
+        let mut length = 0;
+        for i in 0..11
+        {
+            let byte = self.short[i];
+            if byte == b' '
+            {
+                continue;
+            }
+            if i >= 8 && length > 0 && !self.name[..length].contains(&b'.')
+            {
+                self.name[length] = b'.';
+                length += 1;
+            }
+            let lower = if i < 8 { case & CASE_LOWER_BASE != 0 } else { case & CASE_LOWER_EXTENSION != 0 };
+            self.name[length] = if lower { byte.to_ascii_lowercase() } else { byte };
+            length += 1;
+        }
+        self.length = length;
+    }
+}
+
+impl FatFs
+{
+    /// Reads the boot sector of `device`, or of the first FAT partition in
+    /// its partition table.
+    pub fn new(mut device:Drive) -> Result<FatFs, FsError>
+    {
+        let mut sector = [0u8; SECTOR_SIZE];
+        device.read_sectors(0, &mut sector)?;
+        if let Some(fs) = FatFs::parse(device, 0, &sector)
+        {
+            return Ok(fs);
+        }
+        if get_u16(&sector, 510) != 0xAA55
+        {
+            return Err(FsError::NotAFileSystem);
+        }
+        for partition in 0..4
+        {
+            let entry = &sector[446 + 16 * partition..446 + 16 * (partition + 1)];
+            let start = get_u32(entry, 8) as u64;
+            let mut boot = [0u8; SECTOR_SIZE];
+            if entry[4] == 0 || start == 0 || device.read_sectors(start, &mut boot).is_err()
+            {
+                continue;
+            }
+            if let Some(fs) = FatFs::parse(device, start, &boot)
+            {
+                return Ok(fs);
+            }
+        }
+        return Err(FsError::NotAFileSystem);
+    }
+
+    // The volume whose boot sector is `boot`, at sector `start` of `device`
+    fn parse(device:Drive, start:u64, boot:&[u8; SECTOR_SIZE]) -> Option<FatFs>
+    {
+        let sectors_per_cluster = boot[13] as u64;
+        let reserved = get_u16(boot, 14) as u64;
+        let fat_count = boot[16] as u64;
+        if get_u16(boot, 510) != 0xAA55 || get_u16(boot, 11) as usize != SECTOR_SIZE
+            || !sectors_per_cluster.is_power_of_two() || reserved == 0 || fat_count == 0
+        {
+            return None;
+        }
+        let root_entries = get_u16(boot, 17) as u64;
+        let total_sectors = match get_u16(boot, 19)
+        {
+            0 => get_u32(boot, 32) as u64,
+            count => count as u64,
+        };
+        let fat_sectors = match get_u16(boot, 22)
+        {
+            0 => get_u32(boot, 36) as u64,
+            count => count as u64,
+        };
+        let root_sectors = (root_entries * ENTRY_SIZE as u64).div_ceil(SECTOR_SIZE as u64);
+        let data_sectors = total_sectors.checked_sub(reserved + fat_count * fat_sectors + root_sectors)?;
+        let cluster_count = (data_sectors / sectors_per_cluster) as u32;
+        // The number of clusters alone decides the type
+        let fat_type = match cluster_count
+        {
+            0..=4084 => FatType::Fat12,
+            4085..=65524 => FatType::Fat16,
+            _ => FatType::Fat32,
+        };
+        let fat_start = start + reserved;
+        let root_start = fat_start + fat_count * fat_sectors;
+        return Some(FatFs
+        {
+            device,
+            fat_type,
+            sectors_per_cluster,
+            fat_start,
+            fat_sectors,
+            fat_count,
+            root_start,
+            root_sectors,
+            root_cluster: if fat_type == FatType::Fat32 { get_u32(boot, 44) } else { 0 },
+            data_start: root_start + root_sectors,
+            cluster_count,
+            next_free: Cell::new(FIRST_CLUSTER),
+            fat_sector: Cell::new(None),
+            moved: [None; MAX_MOVED],
+            moved_next: 0,
+        });
+    }
+
+    pub fn drive(&self) -> Drive
+    {
+        self.device
+    }
+
+    /// "fat12", "fat16" or "fat32".
+    pub fn kind(&self) -> &'static str
+    {
+        match self.fat_type
+        {
+            FatType::Fat12 => "fat12",
+            FatType::Fat16 => "fat16",
+            FatType::Fat32 => "fat32",
+        }
+    }
+
+    fn read_sector(&self, lba:u64, sector:&mut [u8; SECTOR_SIZE]) -> Result<(), FsError>
+    {
+        let mut device = self.device;
+        return Ok(device.read_sectors(lba, sector)?);
+    }
+
+    fn write_sector(&self, lba:u64, sector:&[u8; SECTOR_SIZE]) -> Result<(), FsError>
+    {
+        let mut device = self.device;
+        return Ok(device.write_sectors(lba, sector)?);
+    }
+
+    fn cluster_bytes(&self) -> usize
+    {
+        self.sectors_per_cluster as usize * SECTOR_SIZE
+    }
+
+    // A cluster number from the disk can be anything, one off the volume
+    // means it is damaged
+    fn cluster_sector(&self, cluster:u32) -> Result<u64, FsError>
+    {
+        if self.is_end(cluster)
+        {
+            return Err(FsError::Corrupted);
+        }
+        return Ok(self.data_start + (cluster - FIRST_CLUSTER) as u64 * self.sectors_per_cluster);
+    }
+
+    // How many sectors a directory can have at most, a longer chain loops
+    fn max_dir_sectors(&self) -> u64
+    {
+        self.root_sectors + self.cluster_count as u64 * self.sectors_per_cluster
+    }
+
+    // Reads `bytes` at byte `offset` of the first FAT, or writes them to all
+    // of the copies
+    fn fat_bytes(&self, offset:usize, bytes:&mut [u8], write:bool) -> Result<(), FsError>
+    {
+        let copies = if write { self.fat_count } else { 1 };
+        let mut sector = [0u8; SECTOR_SIZE];
+        for copy in 0..copies
+        {
+            let mut loaded = None;
+            for (i, byte) in bytes.iter_mut().enumerate()
+            {
+                let lba = self.fat_start + copy * self.fat_sectors + ((offset + i) / SECTOR_SIZE) as u64;
+                if loaded != Some(lba)
+                {
+                    if let (true, Some(previous)) = (write, loaded)
+                    {
+                        self.write_fat_sector(previous, &sector)?;
+                    }
+                    self.read_fat_sector(lba, &mut sector)?;
+                    loaded = Some(lba);
+                }
+                if write
+                {
+                    sector[(offset + i) % SECTOR_SIZE] = *byte;
+                }
+                else
+                {
+                    *byte = sector[(offset + i) % SECTOR_SIZE];
+                }
+            }
+            if let (true, Some(lba)) = (write, loaded)
+            {
+                self.write_fat_sector(lba, &sector)?;
+            }
+        }
+        return Ok(());
+    }
+
+    fn read_fat_sector(&self, lba:u64, sector:&mut [u8; SECTOR_SIZE]) -> Result<(), FsError>
+    {
+        if let Some((cached, bytes)) = self.fat_sector.get()
+        {
+            if cached == lba
+            {
+                *sector = bytes;
+                return Ok(());
+            }
+        }
+        self.read_sector(lba, sector)?;
+        self.fat_sector.set(Some((lba, *sector)));
+        return Ok(());
+    }
+
+    fn write_fat_sector(&self, lba:u64, sector:&[u8; SECTOR_SIZE]) -> Result<(), FsError>
+    {
+        // Dropped first, so a failed write leaves nothing stale behind
+        self.fat_sector.set(None);
+        self.write_sector(lba, sector)?;
+        self.fat_sector.set(Some((lba, *sector)));
+        return Ok(());
+    }
+
+    fn next_cluster(&self, cluster:u32) -> Result<u32, FsError>
+    {
+        if self.is_end(cluster)
+        {
+            return Err(FsError::Corrupted);
+        }
+        let mut bytes = [0u8; 4];
+        match self.fat_type
+        {
+            FatType::Fat12 => {
+                self.fat_bytes(cluster as usize * 3 / 2, &mut bytes[..2], false)?;
+                let pair = get_u16(&bytes, 0) as u32;
+                return Ok(if cluster & 1 == 1 { pair >> 4 } else { pair & 0xFFF });
+            }
+            FatType::Fat16 => {
+                self.fat_bytes(cluster as usize * 2, &mut bytes[..2], false)?;
+                return Ok(get_u16(&bytes, 0) as u32);
+            }
+            FatType::Fat32 => {
+                self.fat_bytes(cluster as usize * 4, &mut bytes, false)?;
+                return Ok(get_u32(&bytes, 0) & 0x0FFF_FFFF);
+            }
+        }
+    }
+
+    fn set_next_cluster(&self, cluster:u32, next:u32) -> Result<(), FsError>
+    {
+        // Never written past the FAT
+        if self.is_end(cluster)
+        {
+            return Err(FsError::Corrupted);
+        }
+        let mut bytes = [0u8; 4];
+        match self.fat_type
+        {
+            FatType::Fat12 => {
+                // Two entries share the middle byte
+                let offset = cluster as usize * 3 / 2;
+                self.fat_bytes(offset, &mut bytes[..2], false)?;
+                let pair = get_u16(&bytes, 0);
+                let pair = if cluster & 1 == 1
+                {
+                    (pair & 0x000F) | (next as u16) << 4
+                }
+                else
+                {
+                    (pair & 0xF000) | (next as u16 & 0x0FFF)
+                };
+                put_u16(&mut bytes, 0, pair);
+                return self.fat_bytes(offset, &mut bytes[..2], true);
+            }
+            FatType::Fat16 => {
+                put_u16(&mut bytes, 0, next as u16);
+                return self.fat_bytes(cluster as usize * 2, &mut bytes[..2], true);
+            }
+            FatType::Fat32 => {
+                // The top four bits are reserved and kept
+                self.fat_bytes(cluster as usize * 4, &mut bytes, false)?;
+                let value = (get_u32(&bytes, 0) & 0xF000_0000) | (next & 0x0FFF_FFFF);
+                put_u32(&mut bytes, 0, value);
+                return self.fat_bytes(cluster as usize * 4, &mut bytes, true);
+            }
+        }
+    }
+
+    fn end_of_chain(&self) -> u32
+    {
+        match self.fat_type
+        {
+            FatType::Fat12 => 0xFFF,
+            FatType::Fat16 => 0xFFFF,
+            FatType::Fat32 => 0x0FFF_FFFF,
+        }
+    }
+
+    // Whether a FAT entry ends a chain. Bad and reserved values do as well,
+    // so a damaged chain is never followed off the volume.
+    fn is_end(&self, next:u32) -> bool
+    {
+        next < FIRST_CLUSTER || next >= FIRST_CLUSTER + self.cluster_count
+    }
+
+    // Takes a free cluster and makes it the end of a chain. The search
+    // starts after the cluster taken last and wraps around.
+    fn allocate_cluster(&self, zeroed:bool) -> Result<u32, FsError>
+    {
+        let start = self.next_free.get().saturating_sub(FIRST_CLUSTER) % self.cluster_count.max(1);
+        for i in 0..self.cluster_count
+        {
+            let cluster = FIRST_CLUSTER + (start + i) % self.cluster_count;
+            if self.next_cluster(cluster)? != 0
+            {
+                continue;
+            }
+            self.set_next_cluster(cluster, self.end_of_chain())?;
+            self.next_free.set(cluster + 1);
+            if zeroed
+            {
+                let zeros = [0u8; SECTOR_SIZE];
+                for i in 0..self.sectors_per_cluster
+                {
+                    self.write_sector(self.cluster_sector(cluster)? + i, &zeros)?;
+                }
+            }
+            return Ok(cluster);
+        }
+        return Err(FsError::NoSpace);
+    }
+
+    fn free_chain(&self, first:u32) -> Result<(), FsError>
+    {
+        let mut cluster = first;
+        // A chain can not be longer than the volume, even a damaged one
+        for _ in 0..self.cluster_count
+        {
+            if self.is_end(cluster)
+            {
+                break;
+            }
+            let next = self.next_cluster(cluster)?;
+            self.set_next_cluster(cluster, 0)?;
+            cluster = next;
+        }
+        return Ok(());
+    }
+
+    // The cluster after `cluster`, appended to the chain when there is none
+    fn next_or_new_cluster(&self, cluster:u32) -> Result<u32, FsError>
+    {
+        let next = self.next_cluster(cluster)?;
+        if !self.is_end(next)
+        {
+            return Ok(next);
+        }
+        let new = self.allocate_cluster(false)?;
+        self.set_next_cluster(cluster, new)?;
+        return Ok(new);
+    }
+
+    fn entry_cluster(&self, short:&[u8]) -> u32
+    {
+        let high = if self.fat_type == FatType::Fat32 { get_u16(short, 20) as u32 } else { 0 };
+        return high << 16 | get_u16(short, 26) as u32;
+    }
+
+    // Clusters of directories, 0 for the root
+    fn dir_cluster(&self, dir:Inode) -> Result<u32, FsError>
+    {
+        if dir.kind != FileType::Directory
+        {
+            return Err(FsError::NotADirectory);
+        }
+        return Ok(dir.number as u32);
+    }
+
+    fn dir_inode(&self, cluster:u32) -> Inode
+    {
+        let number = if cluster == self.root_cluster { ROOT_NUMBER } else { cluster as usize };
+        return Inode::new(FileType::Directory, number);
+    }
+
+    // A file with no cluster yet is given one here, so its inode never changes
+    fn inode_of(&self, entry:&Entry) -> Result<Inode, FsError>
+    {
+        if entry.is_directory()
+        {
+            return Ok(self.dir_inode(self.entry_cluster(&entry.short)));
+        }
+        let mut cluster = self.entry_cluster(&entry.short);
+        if cluster == 0
+        {
+            cluster = self.allocate_cluster(false)?;
+            let mut short = entry.short;
+            set_entry_cluster(&mut short, cluster);
+            self.write_short_entry(entry.position, &short)?;
+        }
+        return Ok(file_inode(cluster, entry.position));
+    }
+
+    // Sector `n` of the directory starting at `cluster`, None past its end
+    fn dir_sector(&self, cluster:u32, n:u64) -> Result<Option<u64>, FsError>
+    {
+        if cluster == 0 && self.fat_type != FatType::Fat32
+        {
+            return Ok(if n < self.root_sectors { Some(self.root_start + n) } else { None });
+        }
+        let mut cluster = if cluster == 0 { self.root_cluster } else { cluster };
+        for _ in 0..n / self.sectors_per_cluster
+        {
+            cluster = self.next_cluster(cluster)?;
+            if self.is_end(cluster)
+            {
+                return Ok(None);
+            }
+        }
+        return Ok(Some(self.cluster_sector(cluster)? + n % self.sectors_per_cluster));
+    }
+
+    // Goes through the files and directories of the directory at `cluster`,
+    // putting long names together, until `wanted` picks one
+    fn find(&self, cluster:u32, mut wanted:impl FnMut(&Entry) -> bool) -> Result<Option<Entry>, FsError>
+    {
+        let mut entry = Entry { name: [0; MAX_LONG_NAME], length: 0, short: [0; ENTRY_SIZE], first_slot: 0, slot: 0, position: 0 };
+        // The checksum and the next sequence number of the long name being read
+        let mut long_name:Option<(u8, u8)> = None;
+        let mut sector = [0u8; SECTOR_SIZE];
+        let mut n = 0;
+        while let Some(lba) = self.dir_sector(cluster, n)?
+        {
+            if n >= self.max_dir_sectors()
+            {
+                return Err(FsError::Corrupted);
+            }
+            self.read_sector(lba, &mut sector)?;
+            for i in 0..ENTRIES_PER_SECTOR
+            {
+                let raw = &sector[i * ENTRY_SIZE..(i + 1) * ENTRY_SIZE];
+                let slot = n as usize * ENTRIES_PER_SECTOR + i;
+                if raw[0] == END_OF_DIRECTORY
+                {
+                    return Ok(None);
+                }
+                if raw[0] == DELETED
+                {
+                    long_name = None;
+                    continue;
+                }
+                if raw[11] == ATTR_LONG_NAME
+                {
+                    let sequence = raw[0] & 0x1F;
+                    if raw[0] & LAST_LONG_ENTRY != 0 && (1..=MAX_LONG_ENTRIES as u8).contains(&sequence)
+                    {
+                        long_name = Some((raw[13], sequence));
+                        entry.first_slot = slot;
+                        entry.length = sequence as usize * CHARS_PER_LONG_ENTRY;
+                    }
+                    match long_name
+                    {
+                        Some((sum, next)) if next == sequence && sum == raw[13] => {
+                            let start = (sequence as usize - 1) * CHARS_PER_LONG_ENTRY;
+                            for (k, &offset) in LONG_NAME_OFFSETS.iter().enumerate()
+                            {
+                                // Past the end come 0 and then 0xFFFF, the rest of Unicode is shown as '?'
+                                entry.name[start + k] = match get_u16(raw, offset)
+                                {
+                                    0 | 0xFFFF => 0,
+                                    char if char < 0x80 => char as u8,
+                                    _ => b'?',
+                                };
+                            }
+                            long_name = Some((sum, next - 1));
+                        }
+                        _ => long_name = None,
+                    }
+                    continue;
+                }
+                // Volume labels, "." and ".."
+                if raw[11] & ATTR_VOLUME_ID != 0 || raw[0] == b'.'
+                {
+                    long_name = None;
+                    continue;
+                }
+
+                entry.short.copy_from_slice(raw);
+                entry.slot = slot;
+                entry.position = lba as usize * ENTRIES_PER_SECTOR + i;
+                match long_name
+                {
+                    Some((sum, 0)) if sum == checksum(raw) => {
+                        entry.length = entry.name[..entry.length].iter().position(|&byte| byte == 0).unwrap_or(entry.length);
+                    }
+                    _ => {
+                        entry.first_slot = slot;
+                        entry.use_short_name();
+                    }
+                }
+                long_name = None;
+                if wanted(&entry)
+                {
+                    return Ok(Some(entry));
+                }
+            }
+            n += 1;
+        }
+        return Ok(None);
+    }
+
+    // Names are compared without case, like everywhere FAT is used
+    fn find_name(&self, cluster:u32, name:&str) -> Result<Option<Entry>, FsError>
+    {
+        self.find(cluster, |entry| entry.name().eq_ignore_ascii_case(name.as_bytes()))
+    }
+
+    // The directory a directory is in, from its ".." entry
+    fn parent_cluster(&self, cluster:u32) -> Result<u32, FsError>
+    {
+        if cluster == 0 || cluster == self.root_cluster
+        {
+            return Ok(0);
+        }
+        let mut sector = [0u8; SECTOR_SIZE];
+        self.read_sector(self.cluster_sector(cluster)?, &mut sector)?;
+        let dot_dot = &sector[ENTRY_SIZE..2 * ENTRY_SIZE];
+        if &dot_dot[..2] != b".."
+        {
+            return Err(FsError::Corrupted);
+        }
+        return Ok(self.entry_cluster(dot_dot));
+    }
+
+    // Where the short entry of a file is, and the entry. It is where the
+    // inode says, or where the file was renamed to; a slot that holds
+    // another file now does not count.
+    fn short_entry(&self, file:Inode) -> Result<(usize, [u8; ENTRY_SIZE]), FsError>
+    {
+        if file.kind != FileType::File
+        {
+            return Err(FsError::IsADirectory);
+        }
+        let cluster = file.number as u32;
+        let moved = self.moved.iter().flatten().find(|(moved, _)| *moved == cluster).map(|&(_, position)| position);
+        for position in core::iter::once(file.number >> 32).chain(moved)
+        {
+            let mut sector = [0u8; SECTOR_SIZE];
+            self.read_sector((position / ENTRIES_PER_SECTOR) as u64, &mut sector)?;
+            let offset = position % ENTRIES_PER_SECTOR * ENTRY_SIZE;
+            let mut short = [0u8; ENTRY_SIZE];
+            short.copy_from_slice(&sector[offset..offset + ENTRY_SIZE]);
+            let live = short[0] != END_OF_DIRECTORY && short[0] != DELETED && short[11] != ATTR_LONG_NAME && !is_directory(&short);
+            if live && self.entry_cluster(&short) == cluster
+            {
+                return Ok((position, short));
+            }
+        }
+        return Err(FsError::NotFound);
+    }
+
+    fn write_short_entry(&self, position:usize, short:&[u8; ENTRY_SIZE]) -> Result<(), FsError>
+    {
+        let mut sector = [0u8; SECTOR_SIZE];
+        let lba = (position / ENTRIES_PER_SECTOR) as u64;
+        self.read_sector(lba, &mut sector)?;
+        let offset = position % ENTRIES_PER_SECTOR * ENTRY_SIZE;
+        sector[offset..offset + ENTRY_SIZE].copy_from_slice(short);
+        return self.write_sector(lba, &sector);
+    }
+
+    // Remembers that the file starting at `cluster` has its short entry at
+    // `position` now
+    fn remember_move(&mut self, cluster:u32, position:usize)
+    {
+        let slot = match self.moved.iter().position(|moved| moved.map_or(false, |(moved, _)| moved == cluster))
+        {
+            Some(slot) => slot,
+            None => {
+                let slot = self.moved_next;
+                self.moved_next = (self.moved_next + 1) % MAX_MOVED;
+                slot
+            }
+        };
+        self.moved[slot] = Some((cluster, position));
+    }
+
+    // Changes the first byte of the directory slots `first..=last`
+    fn mark_slots(&self, cluster:u32, first:usize, last:usize, mark:u8) -> Result<(), FsError>
+    {
+        let mut sector = [0u8; SECTOR_SIZE];
+        for slot in first..=last
+        {
+            let lba = self.dir_sector(cluster, (slot / ENTRIES_PER_SECTOR) as u64)?.ok_or(FsError::Corrupted)?;
+            self.read_sector(lba, &mut sector)?;
+            sector[slot % ENTRIES_PER_SECTOR * ENTRY_SIZE] = mark;
+            self.write_sector(lba, &sector)?;
+        }
+        return Ok(());
+    }
+
+    // Puts `raw` in slot `slot` of a directory and returns where it is
+    fn write_slot(&self, cluster:u32, slot:usize, raw:&[u8; ENTRY_SIZE]) -> Result<usize, FsError>
+    {
+        let lba = self.dir_sector(cluster, (slot / ENTRIES_PER_SECTOR) as u64)?.ok_or(FsError::Corrupted)?;
+        let mut sector = [0u8; SECTOR_SIZE];
+        self.read_sector(lba, &mut sector)?;
+        let offset = slot % ENTRIES_PER_SECTOR * ENTRY_SIZE;
+        sector[offset..offset + ENTRY_SIZE].copy_from_slice(raw);
+        self.write_sector(lba, &sector)?;
+        return Ok(lba as usize * ENTRIES_PER_SECTOR + slot % ENTRIES_PER_SECTOR);
+    }
+
+    // The first of `count` free slots in a row, the directory grows by a
+    // cluster when it has none. The fixed root of FAT12/16 can not grow.
+    fn free_slots(&self, cluster:u32, count:usize) -> Result<usize, FsError>
+    {
+        let mut sector = [0u8; SECTOR_SIZE];
+        let (mut start, mut run) = (0, 0);
+        let mut n = 0;
+        loop
+        {
+            if n >= self.max_dir_sectors()
+            {
+                return Err(FsError::Corrupted);
+            }
+            let lba = match self.dir_sector(cluster, n)?
+            {
+                Some(lba) => lba,
+                None => {
+                    if cluster == 0 && self.fat_type != FatType::Fat32
+                    {
+                        return Err(FsError::DirectoryFull);
+                    }
+                    let mut last = if cluster == 0 { self.root_cluster } else { cluster };
+                    for _ in 0..self.cluster_count
+                    {
+                        let next = self.next_cluster(last)?;
+                        if self.is_end(next)
+                        {
+                            break;
+                        }
+                        last = next;
+                    }
+                    let new = self.allocate_cluster(true)?;
+                    self.set_next_cluster(last, new)?;
+                    continue;
+                }
+            };
+            self.read_sector(lba, &mut sector)?;
+            for i in 0..ENTRIES_PER_SECTOR
+            {
+                let first = sector[i * ENTRY_SIZE];
+                if first != END_OF_DIRECTORY && first != DELETED
+                {
+                    run = 0;
+                    continue;
+                }
+                if run == 0
+                {
+                    start = n as usize * ENTRIES_PER_SECTOR + i;
+                }
+                run += 1;
+                if run == count
+                {
+                    return Ok(start);
+                }
+            }
+            n += 1;
+        }
+    }
+
+    // A short name for `name`: the name itself when it fits 8.3, otherwise
+    // its first characters with a "~N" tail no other entry has. The flag
+    // says whether long name entries are needed.
+    fn short_name_for(&self, cluster:u32, name:&str) -> Result<([u8; 11], u8, bool), FsError>
+    {
+        if let Some((short, case)) = exact_short_name(name)
+        {
+            return Ok((short, case, false));
+        }
+        let (base, extension) = split_extension(name);
+        let fix = |byte:u8| if is_short_char(byte) { byte.to_ascii_uppercase() } else { b'_' };
+        let mut basis = [b' '; 11];
+        let mut length = 0;
+        for byte in base.bytes().filter(|&byte| byte != b' ' && byte != b'.').take(8)
+        {
+            basis[length] = fix(byte);
+            length += 1;
+        }
+        for (i, byte) in extension.bytes().filter(|&byte| byte != b' ').take(3).enumerate()
+        {
+            basis[8 + i] = fix(byte);
+        }
+
+        let mut digits = [0u8; 7];
+        for number in 1..1_000_000u32
+        {
+            // "~" and the number, written backwards
+            let mut tail = 0;
+            let mut rest = number;
+            while rest > 0
+            {
+                digits[tail] = b'0' + (rest % 10) as u8;
+                rest /= 10;
+                tail += 1;
+            }
+            digits[tail] = b'~';
+            tail += 1;
+            let mut short = basis;
+            let keep = length.min(8 - tail);
+            for i in 0..tail
+            {
+                short[keep + i] = digits[tail - 1 - i];
+            }
+            for i in keep + tail..8
+            {
+                short[i] = b' ';
+            }
+            if self.find(cluster, |entry| entry.short[..11] == short)?.is_none()
+            {
+                return Ok((short, 0, true));
+            }
+        }
+        return Err(FsError::DirectoryFull);
+    }
+
+    // Adds `name` to a directory with the attributes, cluster and size of
+    // `template`, and returns where its short entry is
+    fn add_entry(&self, cluster:u32, name:&str, template:&[u8; ENTRY_SIZE]) -> Result<usize, FsError>
+    {
+        check_name(name)?;
+        if self.find_name(cluster, name)?.is_some()
+        {
+            return Err(FsError::AlreadyExists);
+        }
+        return self.insert_entry(cluster, name, template);
+    }
+
+    // `add_entry` without looking for the name first
+    fn insert_entry(&self, cluster:u32, name:&str, template:&[u8; ENTRY_SIZE]) -> Result<usize, FsError>
+    {
+        let (short_name, case, long) = self.short_name_for(cluster, name)?;
+        let long_count = if long { name.len().div_ceil(CHARS_PER_LONG_ENTRY) } else { 0 };
+        let first = self.free_slots(cluster, long_count + 1)?;
+
+        let sum = checksum(&short_name);
+        for part in 0..long_count
+        {
+            let sequence = long_count - part;
+            let mut raw = [0u8; ENTRY_SIZE];
+            raw[0] = sequence as u8 | if part == 0 { LAST_LONG_ENTRY } else { 0 };
+            raw[11] = ATTR_LONG_NAME;
+            raw[13] = sum;
+            for (k, &offset) in LONG_NAME_OFFSETS.iter().enumerate()
+            {
+                let index = (sequence - 1) * CHARS_PER_LONG_ENTRY + k;
+                let char = match index
+                {
+                    index if index < name.len() => name.as_bytes()[index] as u16,
+                    index if index == name.len() => 0,
+                    _ => 0xFFFF,
+                };
+                put_u16(&mut raw, offset, char);
+            }
+            self.write_slot(cluster, first + part, &raw)?;
+        }
+        let mut short = *template;
+        short[..11].copy_from_slice(&short_name);
+        short[12] = case;
+        return self.write_slot(cluster, first + long_count, &short);
+    }
+
+    fn remove_entry(&self, cluster:u32, entry:&Entry) -> Result<(), FsError>
+    {
+        self.mark_slots(cluster, entry.first_slot, entry.slot, DELETED)
+    }
+
+    fn write_file(&self, file:Inode, offset:usize, data:&[u8]) -> Result<usize, FsError>
+    {
+        let (position, mut short) = self.short_entry(file)?;
+        if data.is_empty()
+        {
+            return Ok(0);
+        }
+        let size = entry_size(&short);
+        if offset.checked_add(data.len()).map_or(true, |end| end > u32::MAX as usize)
+        {
+            return Err(FsError::FileTooLarge);
+        }
+        if offset > size
+        {
+            self.truncate_file(file, offset)?;
+            short = self.short_entry(file)?.1;
+        }
+
+        let cluster_bytes = self.cluster_bytes();
+        let mut cluster = self.entry_cluster(&short);
+        // Data only goes to clusters of the volume, whatever the entry says
+        if self.is_end(cluster)
+        {
+            return Err(FsError::Corrupted);
+        }
+        let mut written = 0;
+        let result = (|| -> Result<(), FsError> {
+            for _ in 0..offset / cluster_bytes
+            {
+                cluster = self.next_or_new_cluster(cluster)?;
+            }
+            let mut sector = [0u8; SECTOR_SIZE];
+            while written < data.len()
+            {
+                let position = offset + written;
+                let in_cluster = position % cluster_bytes;
+                if in_cluster == 0 && written > 0
+                {
+                    cluster = self.next_or_new_cluster(cluster)?;
+                }
+                let lba = self.cluster_sector(cluster)? + (in_cluster / SECTOR_SIZE) as u64;
+                let start = in_cluster % SECTOR_SIZE;
+                let count = (SECTOR_SIZE - start).min(data.len() - written);
+                // A whole sector is overwritten without reading it first
+                if count < SECTOR_SIZE
+                {
+                    self.read_sector(lba, &mut sector)?;
+                }
+                sector[start..start + count].copy_from_slice(&data[written..written + count]);
+                self.write_sector(lba, &sector)?;
+                written += count;
+            }
+            return Ok(());
+        })();
+
+        if offset + written > size
+        {
+            put_u32(&mut short, 28, (offset + written) as u32);
+        }
+        stamp_modified(&mut short);
+        self.write_short_entry(position, &short)?;
+        result?;
+        return Ok(written);
+    }
+
+    fn truncate_file(&self, file:Inode, size:usize) -> Result<(), FsError>
+    {
+        let (position, mut short) = self.short_entry(file)?;
+        let old_size = entry_size(&short);
+        if size > u32::MAX as usize
+        {
+            return Err(FsError::FileTooLarge);
+        }
+        if size > old_size
+        {
+            let zeros = [0u8; SECTOR_SIZE];
+            let mut at = old_size;
+            while at < size
+            {
+                at += self.write_file(file, at, &zeros[..(size - at).min(SECTOR_SIZE)])?;
+            }
+            return Ok(());
+        }
+
+        // The first cluster stays even for an empty file, it is the inode
+        let keep = size.div_ceil(self.cluster_bytes()).max(1);
+        let mut last = self.entry_cluster(&short);
+        for _ in 1..keep
+        {
+            last = self.next_cluster(last)?;
+            if self.is_end(last)
+            {
+                return Err(FsError::Corrupted);
+            }
+        }
+        let rest = self.next_cluster(last)?;
+        if !self.is_end(rest)
+        {
+            self.set_next_cluster(last, self.end_of_chain())?;
+            self.free_chain(rest)?;
+        }
+        put_u32(&mut short, 28, size as u32);
+        stamp_modified(&mut short);
+        return self.write_short_entry(position, &short);
+    }
+}
+
+impl FileSystem for FatFs
+{
+    fn root(&self) -> Inode
+    {
+        Inode::new(FileType::Directory, ROOT_NUMBER)
+    }
+
+    fn lookup(&self, dir:Inode, name:&str) -> Result<Inode, FsError>
+    {
+        let cluster = self.dir_cluster(dir)?;
+        match name
+        {
+            "." => Ok(dir),
+            ".." => Ok(self.dir_inode(self.parent_cluster(cluster)?)),
+            _ => match self.find_name(cluster, name)?
+            {
+                Some(entry) => self.inode_of(&entry),
+                None => Err(FsError::NotFound),
+            },
+        }
+    }
+
+    fn create(&mut self, dir:Inode, name:&str) -> Result<Inode, FsError>
+    {
+        let parent = self.dir_cluster(dir)?;
+        check_name(name)?;
+        if self.find_name(parent, name)?.is_some()
+        {
+            return Err(FsError::AlreadyExists);
+        }
+        // Even an empty file has a cluster, it is the inode
+        let cluster = self.allocate_cluster(false)?;
+        let mut template = [0u8; ENTRY_SIZE];
+        template[11] = ATTR_ARCHIVE;
+        set_entry_cluster(&mut template, cluster);
+        stamp_new(&mut template);
+        match self.insert_entry(parent, name, &template)
+        {
+            Ok(position) => Ok(file_inode(cluster, position)),
+            Err(error) => {
+                self.free_chain(cluster)?;
+                Err(error)
+            }
+        }
+    }
+
+    fn mkdir(&mut self, dir:Inode, name:&str) -> Result<Inode, FsError>
+    {
+        let parent = self.dir_cluster(dir)?;
+        check_name(name)?;
+        if self.find_name(parent, name)?.is_some()
+        {
+            return Err(FsError::AlreadyExists);
+        }
+        let cluster = self.allocate_cluster(true)?;
+
+        // Every directory but the root starts with "." and ".."
+        let mut sector = [0u8; SECTOR_SIZE];
+        for (i, (dots, target)) in [(&b".          "[..], cluster), (&b"..         "[..], parent)].iter().enumerate()
+        {
+            let raw = &mut sector[i * ENTRY_SIZE..(i + 1) * ENTRY_SIZE];
+            raw[..11].copy_from_slice(dots);
+            raw[11] = ATTR_DIRECTORY;
+            set_entry_cluster(raw, *target);
+            stamp_new(raw);
+        }
+        let mut template = [0u8; ENTRY_SIZE];
+        template[11] = ATTR_DIRECTORY;
+        set_entry_cluster(&mut template, cluster);
+        stamp_new(&mut template);
+        let added = self.cluster_sector(cluster).and_then(|lba| self.write_sector(lba, &sector))
+            .and_then(|_| self.add_entry(parent, name, &template));
+        if let Err(error) = added
+        {
+            self.free_chain(cluster)?;
+            return Err(error);
+        }
+        return Ok(self.dir_inode(cluster));
+    }
+
+    fn unlink(&mut self, dir:Inode, name:&str) -> Result<(), FsError>
+    {
+        let cluster = self.dir_cluster(dir)?;
+        let entry = self.find_name(cluster, name)?.ok_or(FsError::NotFound)?;
+        if entry.is_directory()
+        {
+            return Err(FsError::IsADirectory);
+        }
+        self.free_chain(self.entry_cluster(&entry.short))?;
+        return self.remove_entry(cluster, &entry);
+    }
+
+    fn rmdir(&mut self, dir:Inode, name:&str) -> Result<(), FsError>
+    {
+        let cluster = self.dir_cluster(dir)?;
+        let entry = self.find_name(cluster, name)?.ok_or(FsError::NotFound)?;
+        if !entry.is_directory()
+        {
+            return Err(FsError::NotADirectory);
+        }
+        let first = self.entry_cluster(&entry.short);
+        if self.find(first, |_| true)?.is_some()
+        {
+            return Err(FsError::DirectoryNotEmpty);
+        }
+        self.free_chain(first)?;
+        return self.remove_entry(cluster, &entry);
+    }
+
+    fn rename(&mut self, old_dir:Inode, old_name:&str, new_dir:Inode, new_name:&str) -> Result<(), FsError>
+    {
+        let old_parent = self.dir_cluster(old_dir)?;
+        let new_parent = self.dir_cluster(new_dir)?;
+        check_name(new_name)?;
+        let mut entry = self.find_name(old_parent, old_name)?.ok_or(FsError::NotFound)?;
+        if !entry.is_directory() && self.entry_cluster(&entry.short) == 0
+        {
+            // Given its cluster first, so the moved entry keeps the inode
+            self.inode_of(&entry)?;
+            entry = self.find_name(old_parent, old_name)?.ok_or(FsError::NotFound)?;
+        }
+        // A file of the new name is replaced, but only after the new entry
+        // is written. Only the case changes when both names find the same
+        // entry.
+        let mut replaced = None;
+        if let Some(existing) = self.find_name(new_parent, new_name)?
+        {
+            if existing.position == entry.position
+            {
+                if existing.name() == new_name.as_bytes()
+                {
+                    return Ok(());
+                }
+            }
+            else if !existing.is_directory() && !entry.is_directory()
+            {
+                replaced = Some(existing);
+            }
+            else
+            {
+                return Err(FsError::AlreadyExists);
+            }
+        }
+
+        let moved = self.entry_cluster(&entry.short);
+        if entry.is_directory()
+        {
+            let mut cluster = new_parent;
+            // Climbing up from the new place must not pass the directory itself
+            for _ in 0..self.cluster_count
+            {
+                if cluster == moved
+                {
+                    return Err(FsError::IntoItself);
+                }
+                if cluster == 0
+                {
+                    break;
+                }
+                cluster = self.parent_cluster(cluster)?;
+            }
+        }
+
+        // The new entry is written before the old one and the replaced file
+        // go, so a failure leaves both where they were. For a moment the
+        // name is there twice.
+        let position = self.insert_entry(new_parent, new_name, &entry.short)?;
+        self.remove_entry(old_parent, &entry)?;
+        if !entry.is_directory()
+        {
+            self.remember_move(moved, position);
+        }
+        if let Some(existing) = replaced
+        {
+            self.remove_entry(new_parent, &existing)?;
+            self.free_chain(self.entry_cluster(&existing.short))?;
+        }
+        if entry.is_directory() && old_parent != new_parent
+        {
+            let mut sector = [0u8; SECTOR_SIZE];
+            let lba = self.cluster_sector(moved)?;
+            self.read_sector(lba, &mut sector)?;
+            set_entry_cluster(&mut sector[ENTRY_SIZE..2 * ENTRY_SIZE], new_parent);
+            self.write_sector(lba, &sector)?;
+        }
+        return Ok(());
+    }
+
+    fn read(&self, file:Inode, offset:usize, buf:&mut [u8]) -> Result<usize, FsError>
+    {
+        let short = self.short_entry(file)?.1;
+        let count = entry_size(&short).saturating_sub(offset).min(buf.len());
+        if count == 0
+        {
+            return Ok(0);
+        }
+        let cluster_bytes = self.cluster_bytes();
+        let mut cluster = self.entry_cluster(&short);
+        for _ in 0..offset / cluster_bytes
+        {
+            cluster = self.next_cluster(cluster)?;
+        }
+        let mut sector = [0u8; SECTOR_SIZE];
+        let mut done = 0;
+        while done < count
+        {
+            let in_cluster = (offset + done) % cluster_bytes;
+            if in_cluster == 0 && done > 0
+            {
+                cluster = self.next_cluster(cluster)?;
+            }
+            if self.is_end(cluster)
+            {
+                return Err(FsError::Corrupted);
+            }
+            self.read_sector(self.cluster_sector(cluster)? + (in_cluster / SECTOR_SIZE) as u64, &mut sector)?;
+            let start = in_cluster % SECTOR_SIZE;
+            let length = (SECTOR_SIZE - start).min(count - done);
+            buf[done..done + length].copy_from_slice(&sector[start..start + length]);
+            done += length;
+        }
+        return Ok(count);
+    }
+
+    fn write(&mut self, file:Inode, offset:usize, data:&[u8]) -> Result<usize, FsError>
+    {
+        self.write_file(file, offset, data)
+    }
+
+    fn truncate(&mut self, file:Inode, size:usize) -> Result<(), FsError>
+    {
+        self.truncate_file(file, size)
+    }
+
+    fn readdir(&self, dir:Inode, index:usize) -> Result<Option<DirEntry>, FsError>
+    {
+        let cluster = self.dir_cluster(dir)?;
+        let mut seen = 0;
+        let found = self.find(cluster, |entry| {
+            if !entry.is_listed()
+            {
+                return false;
+            }
+            seen += 1;
+            seen > index
+        })?;
+        match found
+        {
+            Some(entry) => Ok(Some(DirEntry::new(entry.name(), self.inode_of(&entry)?))),
+            None => Ok(None),
+        }
+    }
+
+    fn stat(&self, inode:Inode) -> Result<Metadata, FsError>
+    {
+        if inode.kind == FileType::File
+        {
+            let short = self.short_entry(inode)?.1;
+            return Ok(entry_metadata(inode, entry_size(&short), &short));
+        }
+        let cluster = self.dir_cluster(inode)?;
+        let mut count = 0;
+        self.find(cluster, |entry| {
+            if entry.is_listed()
+            {
+                count += 1;
+            }
+            false
+        })?;
+        // A directory's times are in its "." entry; the root has none
+        if cluster == 0 || cluster == self.root_cluster
+        {
+            return Ok(Metadata::new(inode, count));
+        }
+        let mut sector = [0u8; SECTOR_SIZE];
+        self.read_sector(self.cluster_sector(cluster)?, &mut sector)?;
+        return Ok(entry_metadata(inode, count, &sector[..ENTRY_SIZE]));
+    }
+}
@@ -0,0 +1,197 @@
+use crate::process::{self, ARGS_LENGTH};
+use crate::task;
+use crate::vfs::{self, FsError, Inode};
+use crate::vga_buf::{SCREEN, BUF_WIDTH};
+
+// The game runs in a window in the top right corner, so the shell stays
+// usable while the game thread keeps redrawing it.
+const LIFE_HEIGHT: usize = 10;
+const LIFE_WIDTH: usize = 30;
+const LIFE_LINE: u32 = 0;
+const LIFE_COL: u32 = BUF_WIDTH - LIFE_WIDTH as u32;
+
+const GENERATIONS: usize = 300;
+const GENERATION_DELAY_MS: u64 = 100;
+
+const MAP: [&str; LIFE_HEIGHT] = [
+    "                              ",
+    "  x                           ",
+    "   x              xxx         ",
+    " xxx                          ",
+    "                              ",
+    "          xx           xx     ",
+    "          xx          x  x    ",
+    "            xx         xx     ",
+    "            xx                ",
+    "                              ",
+];
+
+// A map file is at most this long, with "\r\n" after every line
+const MAP_FILE_SIZE: usize = LIFE_HEIGHT * (LIFE_WIDTH + 2);
+
+pub enum MapError
+{
+    Read(FsError),
+    // more lines or columns than the window has
+    TooLarge,
+}
+
+impl MapError
+{
+    pub fn message(&self) -> &'static str
+    {
+        match self
+        {
+            MapError::Read(error) => error.message(),
+            MapError::TooLarge => "A map is at most 10 lines of 30 columns",
+        }
+    }
+}
+
+/// Reads a starting map: up to 10 lines of up to 30 columns, 'x' for a
+/// live cell and anything else for a dead one.
+pub fn load_map(file:Inode) -> Result<[[u8; LIFE_WIDTH]; LIFE_HEIGHT], MapError>
+{
+    let size = vfs::stat(file).map_err(MapError::Read)?.size;
+    if size > MAP_FILE_SIZE
+    {
+        return Err(MapError::TooLarge);
+    }
+    let mut buf = [0u8; MAP_FILE_SIZE];
+    let mut done = 0;
+    while done < size
+    {
+        match vfs::read_at(file, done, &mut buf[done..size]).map_err(MapError::Read)?
+        {
+            0 => break,
+            count => done += count,
+        }
+    }
+
+    let mut map = [[b' '; LIFE_WIDTH]; LIFE_HEIGHT];
+    let text = &buf[..done];
+    // A newline after the last line does not start another one
+    let text = text.strip_suffix(b"\n").unwrap_or(text);
+    for (i, line) in text.split(|&byte| byte == b'\n').enumerate()
+    {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if i >= LIFE_HEIGHT || line.len() > LIFE_WIDTH
+        {
+            return Err(MapError::TooLarge);
+        }
+        map[i][..line.len()].copy_from_slice(line);
+    }
+    return Ok(map);
+}
+
+/// Plays the map at the path the process was started with, or the built-in
+/// one without a path.
+pub fn game_of_life()
+{
+    let mut current_gen: [[u8; LIFE_WIDTH]; LIFE_HEIGHT] = [[b' '; LIFE_WIDTH]; LIFE_HEIGHT];
+
+    let mut args = [b'\0'; ARGS_LENGTH];
+    let length = process::args(&mut args);
+    let path = core::str::from_utf8(&args[..length]).unwrap_or("");
+    if path.is_empty()
+    {
+        for i in 0..MAP.len()
+        {
+            for (j, byte) in MAP[i].bytes().enumerate()
+            {
+                current_gen[i][j] = byte;
+            }
+        }
+    }
+    else
+    {
+        // The shell has read it once already, it can only fail if it changed
+        match vfs::resolve(process::cwd(), path).map_err(MapError::Read).and_then(load_map)
+        {
+            Ok(map) => current_gen = map,
+            Err(_) => process::exit(1),
+        }
+    }
+
+    output_to_the_screen(&current_gen);
+
+    for _ in 0..GENERATIONS
+    {
+        task::sleep(GENERATION_DELAY_MS);
+        current_gen = next_generation(current_gen);
+
+        output_to_the_screen(&current_gen);
+    }
+
+    clear_window();
+}
+
+pub fn output_to_the_screen(area:&[[u8; LIFE_WIDTH]; LIFE_HEIGHT])
+{
+    let mut screen = SCREEN.lock();
+    for i in 0..area.len()
+    {
+        for j in 0..area[0].len()
+        {
+            let cell = if area[i][j] == b'x' { b'x' } else { b'.' };
+            screen.write_at(LIFE_LINE + i as u32, LIFE_COL + j as u32, cell);
+        }
+    }
+}
+
+fn clear_window()
+{
+    let mut screen = SCREEN.lock();
+    for i in 0..LIFE_HEIGHT
+    {
+        for j in 0..LIFE_WIDTH
+        {
+            screen.write_at(LIFE_LINE + i as u32, LIFE_COL + j as u32, b' ');
+        }
+    }
+}
+
+// The window is small, so it wraps around at the edges to keep gliders alive
+pub fn finding_all_neighbors_of_a_cell(area:[[u8; LIFE_WIDTH]; LIFE_HEIGHT], rows:usize, columns:usize) -> u32
+{
+    let mut count = 0;
+
+    for di in [LIFE_HEIGHT - 1, 0, 1]
+    {
+        for dj in [LIFE_WIDTH - 1, 0, 1]
+        {
+            if di == 0 && dj == 0
+            {
+                continue;
+            }
+            if area[(rows + di) % LIFE_HEIGHT][(columns + dj) % LIFE_WIDTH] == b'x'
+            {
+                count += 1;
+            }
+        }
+    }
+    return count;
+}
+
+pub fn next_generation(area:[[u8; LIFE_WIDTH]; LIFE_HEIGHT]) -> [[u8; LIFE_WIDTH]; LIFE_HEIGHT]
+{
+    let mut next_generation:[[u8; LIFE_WIDTH]; LIFE_HEIGHT] = [[b' '; LIFE_WIDTH]; LIFE_HEIGHT];
+
+    for i in 0..area.len()
+    {
+        for j in 0..area[0].len()
+        {
+            let count_of_neighbors = finding_all_neighbors_of_a_cell(area, i, j);
+
+            if area[i][j] == b'x' && (count_of_neighbors == 3 || count_of_neighbors == 2)
+            {
+                next_generation[i][j] = b'x';
+            }
+            else if area[i][j] != b'x' && count_of_neighbors == 3
+            {
+                next_generation[i][j] = b'x';
+            }
+        }
+    }
+    return next_generation;
+}
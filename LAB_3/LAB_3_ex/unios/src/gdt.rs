@@ -0,0 +1,71 @@
+use core::ptr::{addr_of, addr_of_mut};
+use lazy_static::lazy_static;
+use x86_64::VirtAddr;
+use x86_64::instructions::segmentation::{Segment, CS, DS, ES, SS};
+use x86_64::instructions::tables::load_tss;
+use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
+use x86_64::structures::tss::TaskStateSegment;
+
+pub const DOUBLE_FAULT_IST_INDEX:u16 = 0;
+const DOUBLE_FAULT_STACK_SIZE:usize = 4096 * 5;
+
+static mut DOUBLE_FAULT_STACK: [u8; DOUBLE_FAULT_STACK_SIZE] = [0; DOUBLE_FAULT_STACK_SIZE];
+
+// Mutable because the kernel stack used on a ring 3 -> ring 0 switch
+// (privilege_stack_table[0]) changes with every task switch.
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
+
+pub struct Selectors
+{
+    pub kernel_code:SegmentSelector,
+    pub kernel_data:SegmentSelector,
+    pub user_data:SegmentSelector,
+    pub user_code:SegmentSelector,
+    tss:SegmentSelector,
+}
+
+lazy_static!
+{
+    static ref GDT: (GlobalDescriptorTable, Selectors) =
+    {
+        let tss = unsafe {
+            let stack_start = VirtAddr::from_ptr(addr_of!(DOUBLE_FAULT_STACK));
+            (*addr_of_mut!(TSS)).interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] =
+                stack_start + DOUBLE_FAULT_STACK_SIZE;
+            &*addr_of!(TSS)
+        };
+
+        let mut gdt = GlobalDescriptorTable::new();
+        let kernel_code = gdt.add_entry(Descriptor::kernel_code_segment());
+        let kernel_data = gdt.add_entry(Descriptor::kernel_data_segment());
+        let user_data = gdt.add_entry(Descriptor::user_data_segment());
+        let user_code = gdt.add_entry(Descriptor::user_code_segment());
+        let tss = gdt.add_entry(Descriptor::tss_segment(tss));
+        (gdt, Selectors { kernel_code, kernel_data, user_data, user_code, tss })
+    };
+}
+
+pub fn init()
+{
+    GDT.0.load();
+    unsafe {
+        CS::set_reg(GDT.1.kernel_code);
+        SS::set_reg(GDT.1.kernel_data);
+        DS::set_reg(GDT.1.kernel_data);
+        ES::set_reg(GDT.1.kernel_data);
+        load_tss(GDT.1.tss);
+    }
+}
+
+pub fn selectors() -> &'static Selectors
+{
+    &GDT.1
+}
+
+/// Sets the stack the CPU switches to when an interrupt arrives in ring 3.
+pub fn set_kernel_stack(stack_top:u64)
+{
+    unsafe {
+        (*addr_of_mut!(TSS)).privilege_stack_table[0] = VirtAddr::new(stack_top);
+    }
+}
@@ -0,0 +1,239 @@
+use crate::sync::Mutex;
+use crate::vfs::{self, DirEntry, FileType, FsError, Inode, MAX_PATH_LENGTH};
+
+/// Most names one pattern component matches in a directory.
+pub const MAX_MATCHES:usize = 64;
+/// Most components with `*`, `?` or `[` in one pattern.
+pub const MAX_DEPTH:usize = 8;
+
+type Matches = [DirEntry; MAX_MATCHES];
+
+const NO_MATCHES:Matches = [DirEntry::EMPTY; MAX_MATCHES];
+// The matches of each pattern component on the way down. They are too big
+// for the stack of the recursion, one level each is kept here instead.
+static MATCHES: Mutex<[Matches; MAX_DEPTH]> = Mutex::new([NO_MATCHES; MAX_DEPTH]);
+
+/// Whether `word` has `*`, `?` or `[` in it.
+pub fn is_pattern(word:&str) -> bool
+{
+    word.bytes().any(|byte| matches!(byte, b'*' | b'?' | b'['))
+}
+
+/// Whether `byte` is in the set that starts after a '[' in `class`, and the
+/// length of the set with its ']'. None when there is no ']', and the '['
+/// stands for itself.
+pub fn match_class(class:&[u8], byte:u8) -> Option<(bool, usize)>
+{
+    match_class_with(class, b"!^", |low, high| (low..=high).contains(&byte))
+}
+
+/// `match_class` for any syntax: a set starting with one of `negators`
+/// is negated, and `in_range(low, high)` says whether the character looked
+/// for is one of `low..=high`. The negation is applied last.
+pub fn match_class_with(class:&[u8], negators:&[u8], in_range:impl Fn(u8, u8) -> bool) -> Option<(bool, usize)>
+{
+    let negated = class.first().map_or(false, |first| negators.contains(first));
+    let mut i = if negated { 1 } else { 0 };
+    let mut found = false;
+    // A ']' right at the start is one of the characters
+    let mut first = true;
+    while i < class.len()
+    {
+        let low = class[i];
+        if low == b']' && !first
+        {
+            return Some((found != negated, i + 1));
+        }
+        first = false;
+        if i + 2 < class.len() && class[i + 1] == b'-' && class[i + 2] != b']'
+        {
+            found |= in_range(low, class[i + 2]);
+            i += 3;
+        }
+        else
+        {
+            found |= in_range(low, low);
+            i += 1;
+        }
+    }
+    return None;
+}
+
+/// Whether `name` matches `pattern` as a whole: `*` stands for any run of
+/// characters, `?` for one, `[abc]`, `[a-z]` and `[!abc]` for one of a set.
+pub fn matches(pattern:&[u8], name:&[u8]) -> bool
+{
+    let (mut p, mut n) = (0, 0);
+    // Where to go on after the last '*' when what follows it fails
+    let mut retry:Option<(usize, usize)> = None;
+    while n < name.len()
+    {
+        let step = match pattern.get(p)
+        {
+            Some(b'*') => {
+                retry = Some((p + 1, n));
+                p += 1;
+                continue;
+            }
+            Some(b'?') => Some(1),
+            Some(b'[') => match match_class(&pattern[p + 1..], name[n])
+            {
+                Some((true, length)) => Some(1 + length),
+                Some((false, _)) => None,
+                None if name[n] == b'[' => Some(1),
+                None => None,
+            },
+            Some(&byte) if byte == name[n] => Some(1),
+            _ => None,
+        };
+        match (step, retry)
+        {
+            (Some(length), _) => {
+                p += length;
+                n += 1;
+            }
+            // The '*' takes one more character
+            (None, Some((after, taken))) => {
+                p = after;
+                n = taken + 1;
+                retry = Some((after, taken + 1));
+            }
+            (None, None) => return false,
+        }
+    }
+    return pattern[p..].iter().all(|&byte| byte == b'*');
+}
+
+// Where an expansion is: the path so far, the matches of every level and
+// whether any level had more than MAX_MATCHES
+struct Walk<'a>
+{
+    path:[u8; MAX_PATH_LENGTH],
+    matches:&'a mut [Matches; MAX_DEPTH],
+    cut:bool,
+    found:&'a mut dyn FnMut(&str),
+}
+
+/// Calls `found` with every path `pattern` matches, starting at `dir` and
+/// with `prefix` in front of each path, such as "/" or "~/". The matches of
+/// every component come in name order. Names starting with a dot are only
+/// matched by a component that starts with one too. Returns how many paths
+/// were found, and whether a directory had more than MAX_MATCHES matches
+/// and only the first of them in name order were taken.
+pub fn expand(dir:Inode, prefix:&str, pattern:&str, found:&mut dyn FnMut(&str)) -> Result<(usize, bool), FsError>
+{
+    if prefix.len() > MAX_PATH_LENGTH
+    {
+        return Err(FsError::NameTooLong);
+    }
+    let mut matches = MATCHES.lock();
+    let mut walk = Walk { path: [0u8; MAX_PATH_LENGTH], matches: &mut matches, cut: false, found };
+    walk.path[..prefix.len()].copy_from_slice(prefix.as_bytes());
+    let count = expand_from(&mut walk, 0, dir, pattern, prefix.len())?;
+    return Ok((count, walk.cut));
+}
+
+// Adds `name` and maybe a '/' to the path, None when it does not fit
+fn push(path:&mut [u8; MAX_PATH_LENGTH], length:usize, name:&[u8], slash:bool) -> Option<usize>
+{
+    let end = length + name.len() + if slash { 1 } else { 0 };
+    if end > MAX_PATH_LENGTH
+    {
+        return None;
+    }
+    path[length..length + name.len()].copy_from_slice(name);
+    if slash
+    {
+        path[end - 1] = b'/';
+    }
+    return Some(end);
+}
+
+// `depth` is the number of components with a pattern above this one
+fn expand_from(walk:&mut Walk, depth:usize, dir:Inode, pattern:&str, length:usize) -> Result<usize, FsError>
+{
+    let (component, rest) = match pattern.split_once('/')
+    {
+        Some((component, rest)) => (component, Some(rest.trim_start_matches('/'))),
+        None => (pattern, None),
+    };
+
+    // A name without a pattern is looked up, and the rest goes on from it
+    if !is_pattern(component)
+    {
+        let inode = match vfs::lookup(dir, component)
+        {
+            Ok(inode) => inode,
+            Err(FsError::NotFound) | Err(FsError::NotADirectory) => return Ok(0),
+            Err(error) => return Err(error),
+        };
+        return go_on(walk, depth, inode, component.as_bytes(), rest, length);
+    }
+    if depth == MAX_DEPTH
+    {
+        return Err(FsError::NameTooLong);
+    }
+
+    // The first MAX_MATCHES names in order are kept, a later one that sorts
+    // before the last of them takes its place
+    let mut count = 0;
+    let mut index = 0;
+    while let Some(entry) = vfs::readdir(dir, index)?
+    {
+        index += 1;
+        let name = entry.name().as_bytes();
+        if (name.starts_with(b".") && !component.starts_with('.')) || !matches(component.as_bytes(), name)
+        {
+            continue;
+        }
+        let names = &mut walk.matches[depth];
+        if count < MAX_MATCHES
+        {
+            names[count] = entry;
+            count += 1;
+            continue;
+        }
+        walk.cut = true;
+        let last = (0..MAX_MATCHES).max_by(|&a, &b| names[a].name().cmp(names[b].name())).unwrap_or(0);
+        if entry.name() < names[last].name()
+        {
+            names[last] = entry;
+        }
+    }
+    walk.matches[depth][..count].sort_unstable_by(|a, b| a.name().cmp(b.name()));
+
+    let mut total = 0;
+    for i in 0..count
+    {
+        // Copied out, the level below reuses the table
+        let entry = walk.matches[depth][i];
+        total += go_on(walk, depth + 1, entry.inode, entry.name().as_bytes(), rest, length)?;
+    }
+    return Ok(total);
+}
+
+// `inode` called `name` matched a component: it is found when the pattern
+// ends there, the rest is looked for in it otherwise
+fn go_on(walk:&mut Walk, depth:usize, inode:Inode, name:&[u8], rest:Option<&str>, length:usize) -> Result<usize, FsError>
+{
+    let directory = inode.kind == FileType::Directory;
+    match rest
+    {
+        None => {
+            let end = push(&mut walk.path, length, name, false).ok_or(FsError::NameTooLong)?;
+            (walk.found)(core::str::from_utf8(&walk.path[..end]).unwrap_or("?"));
+            return Ok(1);
+        }
+        // "d*/" only matches directories
+        Some(_) if !directory => return Ok(0),
+        Some("") => {
+            let end = push(&mut walk.path, length, name, true).ok_or(FsError::NameTooLong)?;
+            (walk.found)(core::str::from_utf8(&walk.path[..end]).unwrap_or("?"));
+            return Ok(1);
+        }
+        Some(rest) => {
+            let end = push(&mut walk.path, length, name, true).ok_or(FsError::NameTooLong)?;
+            return expand_from(walk, depth, inode, rest, end);
+        }
+    }
+}
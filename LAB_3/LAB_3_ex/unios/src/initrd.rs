@@ -0,0 +1,15 @@
+use crate::tarfs::TarFs;
+use crate::vfs::{self, FsError, Inode, Mounted};
+
+// Packed by build.rs from the `initrd` directory
+static ARCHIVE:&[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/initrd.tar"));
+
+/// The directory in "/" the files built into the kernel show up in.
+pub const MOUNT_POINT:&str = "initrd";
+
+/// Mounts the archive read-only as /initrd. Nothing is made on the root
+/// filesystem for it, so it never ends up in the disk image.
+pub fn mount() -> Result<Inode, FsError>
+{
+    return vfs::mount_virtual(MOUNT_POINT, Mounted::Tar(TarFs::new(ARCHIVE)?));
+}
@@ -1,57 +1,75 @@
 use lazy_static::lazy_static;
-use x86_64::structures::idt::{InterruptStackFrame, InterruptDescriptorTable};
+use x86_64::{PrivilegeLevel, VirtAddr};
+use x86_64::registers::control::Cr2;
+use x86_64::structures::idt::{InterruptStackFrame, InterruptDescriptorTable, PageFaultErrorCode};
 use pic8259::ChainedPics;
 use x86_64::instructions::port::Port;
-use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
-use spin::Mutex;
+use core::sync::atomic::{AtomicU64, Ordering};
+use crate::{gdt, keyboard, println, process, signal, syscall, task};
+use crate::sync::{IrqSafeMutex, LEVEL_HANDLERS, LEVEL_PICS};
 
 const PIC_1_OFFSET: u8 = 32;
 const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 const TIMER_INTERRUPT: u8 = PIC_1_OFFSET;
 const KEYBOARD_INTERRUPT: u8 = PIC_1_OFFSET + 1;
 
+// The PIT counts down from this input clock; we reprogram it from the default
+// ~18.2 Hz so the scheduler gets a reasonably short time slice.
+const PIT_INPUT_FREQUENCY_HZ: u32 = 1_193_182;
+pub const TIMER_FREQUENCY_HZ: u32 = 100;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
 lazy_static!
 {
     static ref IDT: InterruptDescriptorTable =
     {
         let mut idt = InterruptDescriptorTable::new();
-        idt.double_fault.set_handler_fn(double_fault_handler);
+        unsafe {
+            idt.double_fault.set_handler_fn(double_fault_handler)
+                .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+        }
+        idt.page_fault.set_handler_fn(page_fault_handler);
+        idt.general_protection_fault.set_handler_fn(general_protection_fault_handler);
+        idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+        idt.divide_error.set_handler_fn(divide_error_handler);
         idt[TIMER_INTERRUPT as usize].set_handler_fn(timer_interrupt_handler);
         idt[KEYBOARD_INTERRUPT as usize].set_handler_fn(keyboard_interrupt_handler);
+        // A trap gate, so interrupts stay on in a system call: write to a
+        // full pipe sleeps until the reader drains it, which needs the timer.
+        // That is safe because every task enters on its own kernel stack
+        // (the TSS one is switched with the task) and the kernel's locks
+        // turn interrupts off themselves where they must.
+        unsafe {
+            idt[syscall::SYSCALL_INTERRUPT as usize]
+                .set_handler_addr(VirtAddr::new(syscall::syscall_entry as unsafe extern "C" fn() as usize as u64))
+                .set_privilege_level(PrivilegeLevel::Ring3)
+                .disable_interrupts(false);
+        }
         idt
     };
 }
 
 lazy_static!
 {
-    static ref KEYBOARD: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> = Mutex::new
-    (
-        Keyboard::new
-        (
-            layouts::Us104Key,
-            ScancodeSet1,
-            HandleControl::Ignore
-        )
-    );
-}
-
-lazy_static!
-{
-    static ref CUSTOM_HANDLERS: Mutex<CustomHandlers> = Mutex::new
+    static ref CUSTOM_HANDLERS: IrqSafeMutex<CustomHandlers> = IrqSafeMutex::new
     (
+        "CUSTOM_HANDLERS",
+        LEVEL_HANDLERS,
         {
             let mut ch = CustomHandlers
             {
                 timer_interrupt_handler: || {},
-                keyboard_interrupt_handler: |dk| {}
             };
             ch
         }
     );
 }
 
-static PICS: Mutex<ChainedPics> = spin::Mutex::new
+static PICS: IrqSafeMutex<ChainedPics> = IrqSafeMutex::new
     (
+    "PICS",
+    LEVEL_PICS,
     unsafe {
         ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET)
     }
@@ -63,12 +81,28 @@ pub fn init()
     unsafe {
         PICS.lock().initialize()
     }
+    init_pit();
     x86_64::instructions::interrupts::enable();
 }
 
-pub fn set_keyboard_interrupt_handler(handler: fn(DecodedKey))
+fn init_pit()
 {
-    CUSTOM_HANDLERS.lock().keyboard_interrupt_handler  = handler;
+    let divisor = PIT_INPUT_FREQUENCY_HZ / TIMER_FREQUENCY_HZ;
+    let mut cmd_port: Port<u8> = Port::new(0x43);
+    let mut data_port: Port<u8> = Port::new(0x40);
+
+    unsafe {
+        // channel 0, lobyte/hibyte, rate generator
+        cmd_port.write(0x34);
+        data_port.write((divisor & 0xFF) as u8);
+        data_port.write((divisor >> 8) as u8);
+    }
+}
+
+/// Number of timer interrupts since `init`.
+pub fn ticks() -> u64
+{
+    TICKS.load(Ordering::Relaxed)
 }
 
 pub fn set_timer_interrupt_handler(handler: fn())
@@ -79,7 +113,6 @@ pub fn set_timer_interrupt_handler(handler: fn())
 struct CustomHandlers
 {
     timer_interrupt_handler: fn(),
-    keyboard_interrupt_handler: fn(DecodedKey),
 }
 
 extern "x86-interrupt" fn double_fault_handler(stack_frame: InterruptStackFrame, _error_code: u64) -> !
@@ -87,30 +120,86 @@ extern "x86-interrupt" fn double_fault_handler(stack_frame: InterruptStackFrame,
     panic!("DOUBLE FAULT\n{:#?}", stack_frame);
 }
 
-extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame)
+fn is_user_mode(stack_frame: &InterruptStackFrame) -> bool
+{
+    stack_frame.code_segment & 3 == 3
+}
+
+// A fault in ring 3 only takes down the process that caused it. The signal
+// can not be caught or ignored: returning would just fault again.
+fn kill_faulting_process(stack_frame: &InterruptStackFrame, signal: i32, fault: &str) -> !
 {
+    println!();
+    println!("[kernel] {} at {:#x} in process {}, killed",
+             fault, stack_frame.instruction_pointer.as_u64(), process::current_pid());
+    process::kill_current(signal);
+}
+
+extern "x86-interrupt" fn page_fault_handler(stack_frame: InterruptStackFrame, error_code: PageFaultErrorCode)
+{
+    if is_user_mode(&stack_frame)
+    {
+        println!();
+        println!("[kernel] Segmentation fault: access to {:#x} ({:?})", Cr2::read().as_u64(), error_code);
+        kill_faulting_process(&stack_frame, signal::SIGSEGV, "Page fault");
+    }
+    panic!("PAGE FAULT at {:?} ({:?})\n{:#?}", Cr2::read(), error_code, stack_frame);
+}
+
+extern "x86-interrupt" fn general_protection_fault_handler(stack_frame: InterruptStackFrame, error_code: u64)
+{
+    if is_user_mode(&stack_frame)
+    {
+        kill_faulting_process(&stack_frame, signal::SIGSEGV, "General protection fault");
+    }
+    panic!("GENERAL PROTECTION FAULT ({:#x})\n{:#?}", error_code, stack_frame);
+}
+
+extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: InterruptStackFrame)
+{
+    if is_user_mode(&stack_frame)
+    {
+        kill_faulting_process(&stack_frame, signal::SIGILL, "Invalid opcode");
+    }
+    panic!("INVALID OPCODE\n{:#?}", stack_frame);
+}
+
+extern "x86-interrupt" fn divide_error_handler(stack_frame: InterruptStackFrame)
+{
+    if is_user_mode(&stack_frame)
+    {
+        kill_faulting_process(&stack_frame, signal::SIGFPE, "Divide error");
+    }
+    panic!("DIVIDE ERROR\n{:#?}", stack_frame);
+}
+
+extern "x86-interrupt" fn timer_interrupt_handler(stack_frame: InterruptStackFrame)
+{
+    TICKS.fetch_add(1, Ordering::Relaxed);
+
     // delegate call to custom handlers function
-    (CUSTOM_HANDLERS.lock().timer_interrupt_handler)();
+    let handler = CUSTOM_HANDLERS.lock().timer_interrupt_handler;
+    handler();
     unsafe {
         PICS.lock().notify_end_of_interrupt(TIMER_INTERRUPT);
     }
+
+    // Ctrl-C that arrived while the process was running ends it here
+    process::handle_fatal_signals(is_user_mode(&stack_frame));
+
+    // Preempt the running task. No locks may be held here: the next task can
+    // run for a long time before this one resumes and returns from the handler.
+    task::schedule();
 }
 
 extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame)
 {
-    let mut keyboard = KEYBOARD.lock();
     let mut port = Port::new(0x60);
 
     let scancode: u8 = unsafe { port.read() };
 
-    if let Ok(Some(key_event)) = keyboard.add_byte(scancode)
-    {
-        if let Some(key) = keyboard.process_keyevent(key_event)
-        {
-            // delegate call to custom handlers function
-            (CUSTOM_HANDLERS.lock().keyboard_interrupt_handler)(key);
-        }
-    }
+    // Readers blocked in keyboard::read_char are woken from here
+    keyboard::handle_scancode(scancode);
 
     unsafe {
         PICS.lock().notify_end_of_interrupt(KEYBOARD_INTERRUPT);
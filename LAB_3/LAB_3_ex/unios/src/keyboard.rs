@@ -0,0 +1,111 @@
+use lazy_static::lazy_static;
+use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
+use crate::{process, signal};
+use crate::sync::{IrqSafeMutex, WaitQueue, LEVEL_KEYBOARD, LEVEL_KEYS};
+
+const KEY_QUEUE_SIZE:usize = 64;
+
+/// Ctrl+letter arrives as the ASCII control code, Ctrl-A = 1 up to Ctrl-Z = 26.
+pub const CTRL_C:u8 = 0x03;
+pub const CTRL_Z:u8 = 0x1a;
+
+lazy_static!
+{
+    static ref KEYBOARD: IrqSafeMutex<Keyboard<layouts::Us104Key, ScancodeSet1>> = IrqSafeMutex::new
+    (
+        "KEYBOARD",
+        LEVEL_KEYBOARD,
+        Keyboard::new
+        (
+            layouts::Us104Key,
+            ScancodeSet1,
+            HandleControl::MapLettersToUnicode
+        )
+    );
+}
+
+static KEYS: IrqSafeMutex<KeyQueue> = IrqSafeMutex::new
+(
+    "KEYS",
+    LEVEL_KEYS,
+    KeyQueue
+    {
+        keys: [0; KEY_QUEUE_SIZE],
+        head: 0,
+        len: 0,
+    }
+);
+
+// Tasks sleeping in `read_char` until a key arrives
+static READERS: WaitQueue = WaitQueue::new();
+
+// Typed characters waiting to be read; when it is full new keys are dropped.
+struct KeyQueue
+{
+    keys:[u8; KEY_QUEUE_SIZE],
+    head:usize,
+    len:usize,
+}
+
+impl KeyQueue
+{
+    fn push(&mut self, key:u8)
+    {
+        if self.len == KEY_QUEUE_SIZE
+        {
+            return;
+        }
+        self.keys[(self.head + self.len) % KEY_QUEUE_SIZE] = key;
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<u8>
+    {
+        if self.len == 0
+        {
+            return None;
+        }
+        let key = self.keys[self.head];
+        self.head = (self.head + 1) % KEY_QUEUE_SIZE;
+        self.len -= 1;
+        return Some(key);
+    }
+}
+
+/// Called by the keyboard interrupt handler with every scancode read from the
+/// controller. Queues the decoded character and wakes a reader.
+pub fn handle_scancode(scancode:u8)
+{
+    let key = {
+        let mut keyboard = KEYBOARD.lock();
+        match keyboard.add_byte(scancode)
+        {
+            Ok(Some(key_event)) => keyboard.process_keyevent(key_event),
+            _ => None,
+        }
+    };
+
+    if let Some(DecodedKey::Unicode(c)) = key
+    {
+        // Ctrl-C interrupts and Ctrl-Z suspends the foreground job, if there
+        // is none the shell gets the key
+        let signal = match c as u8
+        {
+            CTRL_C => signal::SIGINT,
+            CTRL_Z => signal::SIGTSTP,
+            _ => 0,
+        };
+        if signal != 0 && process::signal_foreground(signal)
+        {
+            return;
+        }
+        KEYS.lock().push(c as u8);
+        READERS.wake_one();
+    }
+}
+
+/// Blocks the calling task until a character has been typed and returns it.
+pub fn read_char() -> u8
+{
+    READERS.wait_until(|| KEYS.lock().pop())
+}
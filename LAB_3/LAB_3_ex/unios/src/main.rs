@@ -5,17 +5,48 @@
 use core::fmt::Write;
 use core::panic::PanicInfo;
 use core::ptr::write;
-use pc_keyboard::DecodedKey;
+use bootloader::{entry_point, BootInfo};
 use crate::vga_buf::SCREEN;
 
 mod vga_buf;
+mod sync;
+mod block;
+mod ata;
+mod rtc;
+mod gdt;
+mod memory;
 mod interrupts;
+mod keyboard;
 mod shell;
+mod task;
+mod process;
+mod programs;
+mod usermode;
+mod elf;
+mod syscall;
+mod pipe;
+mod ramfs;
+mod signal;
+mod vfs;
+mod fat;
+mod ext2;
+mod tarfs;
+mod initrd;
+mod glob;
+mod regex;
+mod stdio;
+mod game_of_life;
 
 /// This function is called on panic.
 #[panic_handler]
 fn panic(_info: &PanicInfo) -> !
 {
+    x86_64::instructions::interrupts::disable();
+    // The panic may have happened with the screen or other locks held
+    unsafe {
+        sync::forget_held_locks();
+        SCREEN.force_unlock();
+    }
     println!("----------------------------------------------");
     println!("{}", _info);
     println!("----------------------------------------------");
@@ -26,26 +57,28 @@ fn panic(_info: &PanicInfo) -> !
     }
 }
 
-fn my_keyboard_handler(key: DecodedKey)
-{
-    shell::handle_keyboard_interrupt(key);
-}
-
 fn my_timer_handler()
 {
 
 }
 
-#[no_mangle]
-pub extern "C" fn _start() -> !
+entry_point!(kernel_main);
+
+fn kernel_main(boot_info: &'static BootInfo) -> !
 {
+    gdt::init();
+    memory::init(boot_info);
+    ata::init();
+    vfs::init();
     shell::initialize();
-    interrupts::set_keyboard_interrupt_handler(my_keyboard_handler);
     interrupts::set_timer_interrupt_handler(my_timer_handler);
+    process::init();
+    process::spawn("shell", shell::run);
     interrupts::init();
 
+    // From here on the boot context is the idle task of the scheduler
     loop
     {
-
+        x86_64::instructions::hlt();
     }
 }
\ No newline at end of file
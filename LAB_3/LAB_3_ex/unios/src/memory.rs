@@ -0,0 +1,348 @@
+use bootloader::BootInfo;
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use x86_64::{PhysAddr, VirtAddr};
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags,
+    PhysFrame, Size4KiB, Translate,
+};
+use x86_64::structures::paging::mapper::TranslateResult;
+use crate::sync::{IrqSafeMutex, LEVEL_MEMORY};
+
+pub const PAGE_SIZE:u64 = 4096;
+
+/// Everything a process can touch lives under this single level 4 entry,
+/// so every address space shares the kernel's other entries as they are.
+pub const USER_PML4_INDEX:usize = 128;
+pub const USER_BASE:u64 = (USER_PML4_INDEX as u64) << 39;
+pub const USER_END:u64 = USER_BASE + (1 << 39);
+
+/// Hands out the usable frames of the boot memory map. Freed frames are kept
+/// in a list threaded through the frames themselves and are reused first.
+struct BootInfoFrameAllocator
+{
+    memory_map:&'static MemoryMap,
+    // where the frames never handed out start: a region of the map and an
+    // address in it, 0 for its start
+    region:usize,
+    next_address:u64,
+    free_list:u64,
+    physical_memory_offset:u64,
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator
+{
+    fn allocate_frame(&mut self) -> Option<PhysFrame>
+    {
+        if self.free_list != 0
+        {
+            let frame = PhysFrame::containing_address(PhysAddr::new(self.free_list));
+            self.free_list = unsafe { *((self.physical_memory_offset + self.free_list) as *const u64) };
+            return Some(frame);
+        }
+        loop
+        {
+            let region = self.memory_map.get(self.region)?;
+            if region.region_type == MemoryRegionType::Usable
+            {
+                let address = self.next_address.max(region.range.start_addr());
+                if address < region.range.end_addr()
+                {
+                    self.next_address = address + PAGE_SIZE;
+                    return Some(PhysFrame::containing_address(PhysAddr::new(address)));
+                }
+            }
+            self.region += 1;
+            self.next_address = 0;
+        }
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BootInfoFrameAllocator
+{
+    unsafe fn deallocate_frame(&mut self, frame:PhysFrame)
+    {
+        let address = frame.start_address().as_u64();
+        *((self.physical_memory_offset + address) as *mut u64) = self.free_list;
+        self.free_list = address;
+    }
+}
+
+struct Memory
+{
+    physical_memory_offset:VirtAddr,
+    frames:BootInfoFrameAllocator,
+    kernel_page_table:PhysFrame,
+}
+
+static MEMORY:IrqSafeMutex<Option<Memory>> = IrqSafeMutex::new("MEMORY", LEVEL_MEMORY, None);
+
+impl Memory
+{
+    fn table(&self, frame:PhysFrame) -> &'static mut PageTable
+    {
+        let virt = self.physical_memory_offset + frame.start_address().as_u64();
+        unsafe { &mut *virt.as_mut_ptr() }
+    }
+
+    fn mapper(&self, level_4_table:PhysFrame) -> OffsetPageTable<'static>
+    {
+        unsafe { OffsetPageTable::new(self.table(level_4_table), self.physical_memory_offset) }
+    }
+
+    fn allocate_zeroed_frame(&mut self) -> Option<PhysFrame>
+    {
+        let frame = self.frames.allocate_frame()?;
+        let virt = self.physical_memory_offset + frame.start_address().as_u64();
+        unsafe {
+            core::ptr::write_bytes(virt.as_mut_ptr::<u8>(), 0, PAGE_SIZE as usize);
+        }
+        return Some(frame);
+    }
+}
+
+fn with_memory<R>(f:impl FnOnce(&mut Memory) -> R) -> R
+{
+    let mut memory = MEMORY.lock();
+    f(memory.as_mut().expect("memory::init was not called"))
+}
+
+/// Needs the bootloader's `map_physical_memory` feature: page tables and user
+/// frames are reached through the complete physical memory mapping.
+pub fn init(boot_info:&'static BootInfo)
+{
+    let (kernel_page_table, _) = Cr3::read();
+    *MEMORY.lock() = Some(Memory
+    {
+        physical_memory_offset: VirtAddr::new(boot_info.physical_memory_offset),
+        frames: BootInfoFrameAllocator
+        {
+            memory_map: &boot_info.memory_map,
+            region: 0,
+            next_address: 0,
+            free_list: 0,
+            physical_memory_offset: boot_info.physical_memory_offset,
+        },
+        kernel_page_table,
+    });
+}
+
+pub fn kernel_page_table() -> PhysFrame
+{
+    with_memory(|memory| memory.kernel_page_table)
+}
+
+/// Returns true when every byte of `address..address + len` is mapped
+/// user-accessible in the active address space.
+pub fn is_user_range(address:u64, len:u64, writable:bool) -> bool
+{
+    if address < USER_BASE || len > USER_END - address
+    {
+        return false;
+    }
+    if len == 0
+    {
+        return true;
+    }
+
+    let (level_4_table, _) = Cr3::read();
+    with_memory(|memory| {
+        let mapper = memory.mapper(level_4_table);
+        let mut page = address & !(PAGE_SIZE - 1);
+        while page < address + len
+        {
+            match mapper.translate(VirtAddr::new(page))
+            {
+                TranslateResult::Mapped { flags, .. } => {
+                    if !flags.contains(PageTableFlags::USER_ACCESSIBLE)
+                        || (writable && !flags.contains(PageTableFlags::WRITABLE))
+                    {
+                        return false;
+                    }
+                }
+                _ => return false,
+            }
+            page += PAGE_SIZE;
+        }
+        true
+    })
+}
+
+/// A level 4 page table with the kernel half copied from the boot page table
+/// and a private user region at `USER_BASE..USER_END`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddressSpace
+{
+    level_4_table:PhysFrame,
+}
+
+impl AddressSpace
+{
+    pub fn new() -> Option<AddressSpace>
+    {
+        with_memory(|memory| {
+            let kernel_table = memory.table(memory.kernel_page_table);
+            if !kernel_table[USER_PML4_INDEX].is_unused()
+            {
+                // the bootloader put something where user space is meant to go
+                return None;
+            }
+            let frame = memory.allocate_zeroed_frame()?;
+            let table = memory.table(frame);
+            for i in 0..512
+            {
+                if i != USER_PML4_INDEX
+                {
+                    table[i] = kernel_table[i].clone();
+                }
+            }
+            Some(AddressSpace { level_4_table: frame })
+        })
+    }
+
+    pub fn level_4_table(&self) -> PhysFrame
+    {
+        self.level_4_table
+    }
+
+    /// Backs `address..address + len` with zeroed frames. Pages that are
+    /// already mapped keep their contents and get `flags` added.
+    pub fn map(&mut self, address:u64, len:u64, flags:PageTableFlags) -> bool
+    {
+        if address < USER_BASE || len > USER_END - address
+        {
+            return false;
+        }
+        let flags = supported_flags(flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE);
+        let level_4_table = self.level_4_table;
+
+        with_memory(|memory| {
+            let mut page = address & !(PAGE_SIZE - 1);
+            while page < address + len
+            {
+                let page_to_map: Page<Size4KiB> = Page::containing_address(VirtAddr::new(page));
+                let mut mapper = memory.mapper(level_4_table);
+
+                if let TranslateResult::Mapped { flags: old_flags, .. } = mapper.translate(page_to_map.start_address())
+                {
+                    // A page shared by two segments gets the permissions of both
+                    let mut merged = old_flags | flags;
+                    if !(old_flags.contains(PageTableFlags::NO_EXECUTE) && flags.contains(PageTableFlags::NO_EXECUTE))
+                    {
+                        merged.remove(PageTableFlags::NO_EXECUTE);
+                    }
+                    match unsafe { mapper.update_flags(page_to_map, merged) }
+                    {
+                        Ok(flush) => flush.ignore(),
+                        Err(_) => return false,
+                    }
+                }
+                else
+                {
+                    let frame = match memory.allocate_zeroed_frame()
+                    {
+                        Some(frame) => frame,
+                        None => return false,
+                    };
+                    // Not the active address space, nothing to flush
+                    match unsafe { mapper.map_to(page_to_map, frame, flags, &mut memory.frames) }
+                    {
+                        Ok(flush) => flush.ignore(),
+                        Err(_) => return false,
+                    }
+                }
+                page += PAGE_SIZE;
+            }
+            true
+        })
+    }
+
+    /// Copies `bytes` to `address` through the physical memory mapping, so the
+    /// address space does not have to be active.
+    pub fn write(&mut self, address:u64, bytes:&[u8]) -> bool
+    {
+        let level_4_table = self.level_4_table;
+        with_memory(|memory| {
+            let mapper = memory.mapper(level_4_table);
+            let mut written = 0;
+            while written < bytes.len()
+            {
+                let target = address + written as u64;
+                let physical = match mapper.translate_addr(VirtAddr::new(target))
+                {
+                    Some(physical) => physical,
+                    None => return false,
+                };
+                let in_page = (PAGE_SIZE - target % PAGE_SIZE) as usize;
+                let count = in_page.min(bytes.len() - written);
+                let virt = memory.physical_memory_offset + physical.as_u64();
+                unsafe {
+                    core::ptr::copy_nonoverlapping(bytes[written..].as_ptr(), virt.as_mut_ptr::<u8>(), count);
+                }
+                written += count;
+            }
+            true
+        })
+    }
+
+    /// Frees every user page, the page tables that map them and the level 4
+    /// table itself. Must not be the active address space.
+    pub fn destroy(self)
+    {
+        with_memory(|memory| {
+            let level_4 = memory.table(self.level_4_table);
+            let entry = &level_4[USER_PML4_INDEX];
+            if !entry.is_unused()
+            {
+                free_table(memory, entry.frame().unwrap(), 3);
+            }
+            unsafe {
+                memory.frames.deallocate_frame(self.level_4_table);
+            }
+        });
+    }
+}
+
+// Frees a page table of the given level together with everything below it
+fn free_table(memory:&mut Memory, frame:PhysFrame, level:u8)
+{
+    let table = memory.table(frame);
+    for entry in table.iter()
+    {
+        if entry.is_unused()
+        {
+            continue;
+        }
+        if let Ok(child) = entry.frame()
+        {
+            if level > 1
+            {
+                free_table(memory, child, level - 1);
+            }
+            else
+            {
+                unsafe {
+                    memory.frames.deallocate_frame(child);
+                }
+            }
+        }
+    }
+    unsafe {
+        memory.frames.deallocate_frame(frame);
+    }
+}
+
+// NO_EXECUTE is a reserved bit unless EFER.NXE is on
+fn supported_flags(flags:PageTableFlags) -> PageTableFlags
+{
+    use x86_64::registers::model_specific::{Efer, EferFlags};
+
+    if Efer::read().contains(EferFlags::NO_EXECUTE_ENABLE)
+    {
+        flags
+    }
+    else
+    {
+        flags & !PageTableFlags::NO_EXECUTE
+    }
+}
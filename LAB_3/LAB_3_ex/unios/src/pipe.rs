@@ -0,0 +1,148 @@
+use crate::sync::{IrqSafeMutex, WaitQueue, LEVEL_PIPES};
+
+pub const MAX_PIPES:usize = 8;
+const PIPE_SIZE:usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum End
+{
+    Read,
+    Write,
+}
+
+#[derive(Clone, Copy)]
+struct Pipe
+{
+    buffer:[u8; PIPE_SIZE],
+    head:usize,
+    len:usize,
+    // open ends; the slot is free again once both drop to 0
+    readers:usize,
+    writers:usize,
+}
+
+const FREE_PIPE:Pipe = Pipe
+{
+    buffer: [0; PIPE_SIZE],
+    head: 0,
+    len: 0,
+    readers: 0,
+    writers: 0,
+};
+
+impl Pipe
+{
+    fn is_free(&self) -> bool
+    {
+        self.readers == 0 && self.writers == 0
+    }
+}
+
+static PIPES: IrqSafeMutex<[Pipe; MAX_PIPES]> = IrqSafeMutex::new("PIPES", LEVEL_PIPES, [FREE_PIPE; MAX_PIPES]);
+
+const EMPTY_QUEUE:WaitQueue = WaitQueue::new();
+// Readers wait for data or for the last writer to go away, writers for room
+static READERS: [WaitQueue; MAX_PIPES] = [EMPTY_QUEUE; MAX_PIPES];
+static WRITERS: [WaitQueue; MAX_PIPES] = [EMPTY_QUEUE; MAX_PIPES];
+
+/// Creates a pipe with one read and one write end open. Returns its id.
+pub fn create() -> Option<usize>
+{
+    let mut pipes = PIPES.lock();
+    let id = (0..MAX_PIPES).find(|&i| pipes[i].is_free())?;
+    pipes[id] = FREE_PIPE;
+    pipes[id].readers = 1;
+    pipes[id].writers = 1;
+    return Some(id);
+}
+
+/// Opens one more `end` of pipe `id`, for a process that inherits it.
+pub fn open(id:usize, end:End)
+{
+    let mut pipes = PIPES.lock();
+    match end
+    {
+        End::Read => pipes[id].readers += 1,
+        End::Write => pipes[id].writers += 1,
+    }
+}
+
+/// Closes one `end` of pipe `id`. Readers see end of file after the last
+/// writer is gone, writers stop writing after the last reader is gone.
+pub fn close(id:usize, end:End)
+{
+    {
+        let mut pipes = PIPES.lock();
+        match end
+        {
+            End::Read => pipes[id].readers = pipes[id].readers.saturating_sub(1),
+            End::Write => pipes[id].writers = pipes[id].writers.saturating_sub(1),
+        }
+    }
+    READERS[id].wake_all();
+    WRITERS[id].wake_all();
+}
+
+/// Reads up to `buf.len()` bytes, blocking while the pipe is empty. Returns
+/// 0 at end of file.
+pub fn read(id:usize, buf:&mut [u8]) -> usize
+{
+    if buf.is_empty()
+    {
+        return 0;
+    }
+    let count = READERS[id].wait_until(|| {
+        let mut pipes = PIPES.lock();
+        let pipe = &mut pipes[id];
+        if pipe.len == 0
+        {
+            return if pipe.writers == 0 { Some(0) } else { None };
+        }
+        let count = pipe.len.min(buf.len());
+        for i in 0..count
+        {
+            buf[i] = pipe.buffer[(pipe.head + i) % PIPE_SIZE];
+        }
+        pipe.head = (pipe.head + count) % PIPE_SIZE;
+        pipe.len -= count;
+        Some(count)
+    });
+    WRITERS[id].wake_all();
+    return count;
+}
+
+/// Writes all of `bytes`, blocking while the pipe is full. Stops early and
+/// returns how much was written when there is nobody left to read it.
+pub fn write(id:usize, bytes:&[u8]) -> usize
+{
+    let mut written = 0;
+    while written < bytes.len()
+    {
+        let count = WRITERS[id].wait_until(|| {
+            let mut pipes = PIPES.lock();
+            let pipe = &mut pipes[id];
+            if pipe.readers == 0
+            {
+                return Some(0);
+            }
+            if pipe.len == PIPE_SIZE
+            {
+                return None;
+            }
+            let count = (PIPE_SIZE - pipe.len).min(bytes.len() - written);
+            for i in 0..count
+            {
+                pipe.buffer[(pipe.head + pipe.len + i) % PIPE_SIZE] = bytes[written + i];
+            }
+            pipe.len += count;
+            Some(count)
+        });
+        if count == 0
+        {
+            break;
+        }
+        written += count;
+        READERS[id].wake_all();
+    }
+    return written;
+}
@@ -0,0 +1,983 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::lazy_static;
+use crate::{elf, glob, println};
+use crate::memory::{self, AddressSpace};
+use crate::programs::{self, Entry};
+use crate::signal::{self, DefaultAction, Handler, NSIG, SIGCHLD, SIGCONT, SIGTSTP};
+use crate::stdio::{self, Stdio};
+use crate::sync::{IrqSafeMutex, WaitQueue, LEVEL_PROCESSES};
+use crate::task::{self, TaskState, MAX_TASKS};
+use crate::usermode;
+use crate::vfs::{self, FileType, Inode};
+
+pub const MAX_PROCESSES:usize = MAX_TASKS;
+pub const PROCESS_NAME_LENGTH:usize = 16;
+// Room for the paths a pattern in a command line expands to
+pub const ARGS_LENGTH:usize = 1024;
+pub const MAX_ARGS:usize = 8 + glob::MAX_MATCHES;
+
+/// Exit statuses of a child whose program could not be found or could not
+/// be started, as in sh.
+pub const EXIT_NOT_FOUND:i32 = 127;
+pub const EXIT_NOT_EXECUTABLE:i32 = 126;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecError
+{
+    NotFound,
+    OutOfMemory,
+    BadImage,
+    // an executable file longer than elf::MAX_FILE_SIZE
+    TooLarge,
+}
+
+/// The boot context. Orphans are handed to it and it reaps them, like init on Linux.
+pub const KERNEL_PID:usize = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessState
+{
+    Free,
+    Ready,
+    Running,
+    Sleeping,
+    // suspended by job control until `resume`
+    Stopped,
+    Zombie,
+}
+
+/// What a parent saw happen to its child.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitStatus
+{
+    Exited(i32),
+    // terminated by the signal
+    Signaled(i32),
+    Stopped,
+}
+
+impl WaitStatus
+{
+    /// The status as sh reports it in $?: 128 + signal for a killed child.
+    pub fn code(&self) -> i32
+    {
+        match *self
+        {
+            WaitStatus::Exited(status) => status,
+            WaitStatus::Signaled(signal) => 128 + signal,
+            WaitStatus::Stopped => 128 + SIGTSTP,
+        }
+    }
+}
+
+/// Process control block.
+#[derive(Clone, Copy)]
+pub struct Process
+{
+    pub pid:usize,
+    pub parent_pid:usize,
+    pub state:ProcessState,
+    pub name:[u8; PROCESS_NAME_LENGTH],
+    pub exit_status:i32,
+    // the signal that terminated it, 0 if it exited by itself
+    pub term_signal:i32,
+    task_id:usize,
+    entry:fn(),
+    // None for processes that only run kernel code
+    address_space:Option<AddressSpace>,
+    stdio:Stdio,
+    // the working directory, None for the root
+    cwd:Option<Inode>,
+    // bit n set while signal n waits to be handled
+    pending:u32,
+    handlers:[Handler; NSIG],
+}
+
+impl Process
+{
+    pub fn name(&self) -> &str
+    {
+        core::str::from_utf8(&self.name).unwrap_or("?").trim_matches('\0')
+    }
+
+    pub fn is_free(&self) -> bool
+    {
+        self.state == ProcessState::Free
+    }
+
+    // How a zombie ended, for its parent
+    fn wait_status(&self) -> WaitStatus
+    {
+        match self.term_signal
+        {
+            0 => WaitStatus::Exited(self.exit_status),
+            signal => WaitStatus::Signaled(signal),
+        }
+    }
+
+    // A bit for every signal with a ring 3 handler
+    fn caught_in_user_mode(&self) -> u32
+    {
+        let mut mask = 0;
+        for (signal, handler) in self.handlers.iter().enumerate()
+        {
+            if let Handler::User(..) = handler
+            {
+                mask |= 1 << signal;
+            }
+        }
+        return mask;
+    }
+
+    fn set_name(&mut self, name:&str)
+    {
+        self.name = [b'\0'; PROCESS_NAME_LENGTH];
+        for (i, byte) in name.bytes().take(PROCESS_NAME_LENGTH).enumerate()
+        {
+            self.name[i] = byte;
+        }
+    }
+}
+
+pub struct ProcessTable
+{
+    pub processes:[Process; MAX_PROCESSES],
+    // the argument string of each slot, kept out of Process as it is long
+    // and processes get copied around whole
+    args:[[u8; ARGS_LENGTH]; MAX_PROCESSES],
+    next_pid:usize,
+}
+
+const FREE_PROCESS: Process = Process
+{
+    pid: 0,
+    parent_pid: 0,
+    state: ProcessState::Free,
+    name: [b'\0'; PROCESS_NAME_LENGTH],
+    exit_status: 0,
+    term_signal: 0,
+    task_id: 0,
+    entry: || {},
+    address_space: None,
+    stdio: stdio::CONSOLE,
+    cwd: None,
+    pending: 0,
+    handlers: [Handler::Default; NSIG],
+};
+
+lazy_static!
+{
+    static ref PROCESSES: IrqSafeMutex<ProcessTable> = IrqSafeMutex::new
+    (
+        "PROCESSES",
+        LEVEL_PROCESSES,
+        ProcessTable
+        {
+            processes: [FREE_PROCESS; MAX_PROCESSES],
+            args: [[b'\0'; ARGS_LENGTH]; MAX_PROCESSES],
+            next_pid: KERNEL_PID,
+        }
+    );
+}
+
+// Parents sleeping in `waitpid`. Woken whenever a process exits or is
+// stopped, each one checks its own child.
+static CHILD_CHANGED: WaitQueue = WaitQueue::new();
+
+// Processes sleeping in `pause` until a signal arrives
+static SIGNALLED: WaitQueue = WaitQueue::new();
+
+// The process Ctrl-C and Ctrl-Z signal, 0 when the shell itself has the console
+static FOREGROUND: AtomicUsize = AtomicUsize::new(0);
+
+// What is left to do for a posted signal once the table is unlocked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Delivery
+{
+    Done,
+    // a handler runs when the process gets to `handle_signals`
+    Pending,
+    Stopped,
+    Terminate,
+}
+
+impl ProcessTable
+{
+    fn slot_of(&self, pid:usize) -> Option<usize>
+    {
+        for i in 0..MAX_PROCESSES
+        {
+            if !self.processes[i].is_free() && self.processes[i].pid == pid
+            {
+                return Some(i);
+            }
+        }
+        return None;
+    }
+
+    fn slot_of_task(&self, task_id:usize) -> Option<usize>
+    {
+        for i in 0..MAX_PROCESSES
+        {
+            let process = &self.processes[i];
+            if !process.is_free() && process.state != ProcessState::Zombie && process.task_id == task_id
+            {
+                return Some(i);
+            }
+        }
+        return None;
+    }
+
+    fn current_pid(&self) -> usize
+    {
+        match self.slot_of_task(task::current_id())
+        {
+            Some(slot) => self.processes[slot].pid,
+            None => KERNEL_PID,
+        }
+    }
+
+    fn allocate(&mut self, name:&str, args:&str, parent_pid:usize, entry:fn(), stdio:Stdio) -> Option<usize>
+    {
+        let slot = (0..MAX_PROCESSES).find(|&i| self.processes[i].is_free())?;
+
+        let mut process = FREE_PROCESS;
+        process.pid = self.next_pid;
+        process.parent_pid = parent_pid;
+        process.state = ProcessState::Ready;
+        process.entry = entry;
+        process.stdio = stdio;
+        process.set_name(name);
+        self.args[slot] = [b'\0'; ARGS_LENGTH];
+        for (i, byte) in args.bytes().take(ARGS_LENGTH).enumerate()
+        {
+            self.args[slot][i] = byte;
+        }
+
+        self.next_pid += 1;
+        self.processes[slot] = process;
+        return Some(slot);
+    }
+
+    // Acts on `signal` as far as it can with the table locked
+    fn post(&mut self, slot:usize, signal:i32) -> Delivery
+    {
+        let process = &mut self.processes[slot];
+        // SIGCONT continues the process even when it is caught or ignored
+        if signal == SIGCONT && process.state == ProcessState::Stopped
+        {
+            process.state = ProcessState::Ready;
+            task::set_stopped(process.task_id, false);
+        }
+
+        match process.handlers[signal as usize]
+        {
+            Handler::Ignore => Delivery::Done,
+            Handler::Call(_) | Handler::User(..) => {
+                process.pending |= 1 << signal;
+                Delivery::Pending
+            }
+            Handler::Default => match signal::default_action(signal)
+            {
+                DefaultAction::Ignore | DefaultAction::Continue => Delivery::Done,
+                DefaultAction::Stop => {
+                    process.state = ProcessState::Stopped;
+                    task::set_stopped(process.task_id, true);
+                    Delivery::Stopped
+                }
+                DefaultAction::Terminate => {
+                    // for when it is the current process, which can not be
+                    // terminated from under its own feet
+                    process.pending |= 1 << signal;
+                    Delivery::Terminate
+                }
+            },
+        }
+    }
+
+    fn exit(&mut self, slot:usize, status:i32, term_signal:i32)
+    {
+        let pid = self.processes[slot].pid;
+        self.processes[slot].state = ProcessState::Zombie;
+        self.processes[slot].exit_status = status;
+        self.processes[slot].term_signal = term_signal;
+        self.processes[slot].pending = 0;
+
+        let parent_pid = self.processes[slot].parent_pid;
+        if let Some(parent) = self.slot_of(parent_pid)
+        {
+            if parent_pid != KERNEL_PID
+            {
+                self.post(parent, SIGCHLD);
+            }
+        }
+
+        // Orphans go to the kernel process, which does not wait for anybody
+        for i in 0..MAX_PROCESSES
+        {
+            if self.processes[i].is_free() || self.processes[i].parent_pid != pid
+            {
+                continue;
+            }
+            if self.processes[i].state == ProcessState::Zombie
+            {
+                self.processes[i] = FREE_PROCESS;
+            }
+            else
+            {
+                self.processes[i].parent_pid = KERNEL_PID;
+            }
+        }
+
+        if self.processes[slot].parent_pid == KERNEL_PID
+        {
+            self.processes[slot] = FREE_PROCESS;
+        }
+    }
+}
+
+/// Registers the boot context as the kernel process. Call once before `spawn`.
+pub fn init()
+{
+    let mut table = PROCESSES.lock();
+    if let Some(slot) = table.allocate("kernel", "", 0, || {}, stdio::CONSOLE)
+    {
+        table.processes[slot].state = ProcessState::Running;
+        table.processes[slot].task_id = task::current_id();
+    }
+}
+
+fn process_main()
+{
+    let entry = {
+        let table = PROCESSES.lock();
+        match table.slot_of_task(task::current_id())
+        {
+            Some(slot) => table.processes[slot].entry,
+            None => || {},
+        }
+    };
+    entry();
+    exit(0);
+}
+
+/// Starts `entry` in a new process that is a child of the current one. Returns its pid.
+///
+/// This is our fork: all kernel threads share one address space, so instead of
+/// duplicating the caller the child starts fresh at `entry`.
+pub fn spawn(name:&str, entry:fn()) -> Option<usize>
+{
+    spawn_with_args(name, "", entry)
+}
+
+/// Like `spawn`, but the child can read `args` back with `args`.
+pub fn spawn_with_args(name:&str, args:&str, entry:fn()) -> Option<usize>
+{
+    spawn_with_stdio(name, args, entry, stdio())
+}
+
+/// Like `spawn_with_args`, with `stdio` in place of the caller's standard
+/// input and output. The child takes its own reference on the pipes in it.
+pub fn spawn_with_stdio(name:&str, args:&str, entry:fn(), stdio:Stdio) -> Option<usize>
+{
+    let mut table = PROCESSES.lock();
+    let parent_pid = table.current_pid();
+    let cwd = table.slot_of_task(task::current_id()).and_then(|slot| table.processes[slot].cwd);
+    let slot = table.allocate(name, args, parent_pid, entry, stdio)?;
+    table.processes[slot].cwd = cwd;
+
+    match task::spawn(process_main)
+    {
+        Some(task_id) => {
+            stdio.open();
+            table.processes[slot].task_id = task_id;
+            Some(table.processes[slot].pid)
+        }
+        None => {
+            table.processes[slot] = FREE_PROCESS;
+            None
+        }
+    }
+}
+
+/// Terminates the current process with `status`.
+pub fn exit(status:i32) -> !
+{
+    finish(status, 0);
+}
+
+/// Terminates the current process because of `signal`. The exit status is
+/// 128 + signal, the value sh reports for a child killed by a signal.
+pub fn kill_current(signal:i32) -> !
+{
+    finish(128 + signal, signal);
+}
+
+fn finish(status:i32, term_signal:i32) -> !
+{
+    let address_space = {
+        let mut table = PROCESSES.lock();
+        match table.slot_of_task(task::current_id())
+        {
+            Some(slot) => table.processes[slot].address_space.take(),
+            None => None,
+        }
+    };
+    if let Some(address_space) = address_space
+    {
+        task::set_page_table(memory::kernel_page_table());
+        address_space.destroy();
+    }
+
+    let stdio = {
+        let mut table = PROCESSES.lock();
+        match table.slot_of_task(task::current_id())
+        {
+            Some(slot) => {
+                let stdio = table.processes[slot].stdio;
+                table.exit(slot, status, term_signal);
+                Some(stdio)
+            }
+            None => None,
+        }
+    };
+    // Readers of our output see end of file now
+    if let Some(stdio) = stdio
+    {
+        stdio.close();
+    }
+    CHILD_CHANGED.wake_all();
+    SIGNALLED.wake_all();
+    task::exit();
+}
+
+/// Makes `space` the address space of the current process and frees the one
+/// it had before.
+pub fn replace_address_space(space:AddressSpace)
+{
+    let old = {
+        let mut table = PROCESSES.lock();
+        match table.slot_of_task(task::current_id())
+        {
+            Some(slot) => table.processes[slot].address_space.replace(space),
+            None => None,
+        }
+    };
+    task::set_page_table(space.level_4_table());
+    if let Some(old) = old
+    {
+        old.destroy();
+    }
+}
+
+/// Standard input and output of the current process.
+pub fn stdio() -> Stdio
+{
+    let table = PROCESSES.lock();
+    match table.slot_of_task(task::current_id())
+    {
+        Some(slot) => table.processes[slot].stdio,
+        None => stdio::CONSOLE,
+    }
+}
+
+/// Swaps the standard input and output of the current process for `stdio`
+/// and returns the old ones. References on pipes move with them.
+pub fn replace_stdio(stdio:Stdio) -> Stdio
+{
+    let mut table = PROCESSES.lock();
+    match table.slot_of_task(task::current_id())
+    {
+        Some(slot) => core::mem::replace(&mut table.processes[slot].stdio, stdio),
+        None => stdio::CONSOLE,
+    }
+}
+
+/// The working directory of the current process, which its children start
+/// in too.
+pub fn cwd() -> Inode
+{
+    let cwd = {
+        let table = PROCESSES.lock();
+        table.slot_of_task(task::current_id()).and_then(|slot| table.processes[slot].cwd)
+    };
+    return cwd.unwrap_or_else(vfs::root);
+}
+
+pub fn set_cwd(dir:Inode)
+{
+    let mut table = PROCESSES.lock();
+    if let Some(slot) = table.slot_of_task(task::current_id())
+    {
+        table.processes[slot].cwd = Some(dir);
+    }
+}
+
+/// Whether the working directory of any live process is `dir` or below
+/// it, so that it can not be removed or unmounted. True when it can not be
+/// told.
+pub fn any_cwd_within(dir:Inode) -> bool
+{
+    // Copied out first: looking up ".." may sleep
+    let mut cwds = [None; MAX_PROCESSES];
+    {
+        let table = PROCESSES.lock();
+        for (cwd, process) in cwds.iter_mut().zip(table.processes.iter())
+        {
+            if !process.is_free() && process.state != ProcessState::Zombie
+            {
+                *cwd = process.cwd;
+            }
+        }
+    }
+    return cwds.iter().flatten().any(|&cwd| vfs::is_within(cwd, dir).unwrap_or(true));
+}
+
+pub fn current_pid() -> usize
+{
+    PROCESSES.lock().current_pid()
+}
+
+/// Copies the argument string the current process was spawned with into `buf`.
+pub fn args(buf:&mut [u8; ARGS_LENGTH]) -> usize
+{
+    let table = PROCESSES.lock();
+    *buf = match table.slot_of_task(task::current_id())
+    {
+        Some(slot) => table.args[slot],
+        None => [b'\0'; ARGS_LENGTH],
+    };
+    return buf.iter().position(|&byte| byte == b'\0').unwrap_or(ARGS_LENGTH);
+}
+
+/// Replaces the current process with the registered program `argv[0]`, or
+/// with the ELF executable at that path when it has a slash in it. Like
+/// execvp it only returns when the program cannot be started.
+pub fn exec(argv:&[&str]) -> ExecError
+{
+    let name = match argv.first()
+    {
+        Some(name) => *name,
+        None => return ExecError::NotFound,
+    };
+    // A name with a slash in it is the path of an ELF executable
+    if name.contains('/')
+    {
+        let file = match vfs::resolve(cwd(), name)
+        {
+            Ok(file) if file.kind == FileType::File => file,
+            _ => return ExecError::NotFound,
+        };
+        return elf::exec_elf_file(file, argv);
+    }
+    let program = match programs::find(name)
+    {
+        Some(program) => program,
+        None => return ExecError::NotFound,
+    };
+
+    match program.entry
+    {
+        Entry::Kernel(main) => {
+            become_program(argv);
+            exit(main(argv))
+        }
+        Entry::User(image) => usermode::exec_flat(image(), argv),
+        Entry::Elf(image) => elf::exec_elf(image(), argv),
+    }
+}
+
+/// The current process takes the name of the program it execs, the last
+/// part of `argv[0]`. Called once nothing can make the exec fail anymore.
+pub fn become_program(argv:&[&str])
+{
+    let name = argv.first().map_or("", |name| name.rsplit('/').next().unwrap_or(name));
+    let mut table = PROCESSES.lock();
+    if let Some(slot) = table.slot_of_task(task::current_id())
+    {
+        table.processes[slot].set_name(name);
+        // The handlers belong to the old program, ignored signals stay ignored
+        for handler in table.processes[slot].handlers.iter_mut()
+        {
+            if let Handler::Call(_) | Handler::User(..) = handler
+            {
+                *handler = Handler::Default;
+            }
+        }
+    }
+}
+
+/// Child side of `run`: splits its arguments into argv and execs them.
+pub fn exec_from_args()
+{
+    let mut buf = [b'\0'; ARGS_LENGTH];
+    let len = args(&mut buf);
+    let line = core::str::from_utf8(&buf[..len]).unwrap_or("");
+
+    let mut argv = [""; MAX_ARGS];
+    let mut argc = 0;
+    for word in line.split(' ').filter(|word| !word.is_empty()).take(MAX_ARGS)
+    {
+        argv[argc] = word;
+        argc += 1;
+    }
+
+    match exec(&argv[..argc])
+    {
+        ExecError::NotFound => {
+            println!("{}: command not found", argv[0]);
+            exit(EXIT_NOT_FOUND);
+        }
+        error => {
+            println!("{}: cannot execute: {:?}", argv[0], error);
+            exit(EXIT_NOT_EXECUTABLE);
+        }
+    }
+}
+
+/// Waits for the child `pid` to exit, reaps it and returns its exit status,
+/// 128 + signal if a signal killed it. Returns None if `pid` is not a child of
+/// the current process.
+pub fn waitpid(pid:usize) -> Option<i32>
+{
+    wait(pid).map(|status| status.code())
+}
+
+/// Like `waitpid`, but tells an exit from a kill by a signal.
+pub fn wait(pid:usize) -> Option<WaitStatus>
+{
+    let status = CHILD_CHANGED.wait_until(|| {
+        let mut table = PROCESSES.lock();
+        let parent_pid = table.current_pid();
+        let slot = match table.slot_of(pid)
+        {
+            Some(slot) if table.processes[slot].parent_pid == parent_pid => slot,
+            _ => return Some(None),
+        };
+        if table.processes[slot].state != ProcessState::Zombie
+        {
+            return None;
+        }
+        let status = table.processes[slot].wait_status();
+        table.processes[slot] = FREE_PROCESS;
+        Some(Some(status))
+    });
+    // SIGCHLD from the child is usually waiting by now
+    handle_signals();
+    return status;
+}
+
+/// Reaps the child `pid` and returns how it ended if it has exited.
+/// Never blocks.
+pub fn try_waitpid(pid:usize) -> Option<WaitStatus>
+{
+    let mut table = PROCESSES.lock();
+    let parent_pid = table.current_pid();
+    let slot = table.slot_of(pid)?;
+    let process = table.processes[slot];
+    if process.parent_pid != parent_pid || process.state != ProcessState::Zombie
+    {
+        return None;
+    }
+    table.processes[slot] = FREE_PROCESS;
+    return Some(process.wait_status());
+}
+
+/// Gives the console to the child `pid` and waits until it exits, in which
+/// case it is reaped, or gets stopped by Ctrl-Z. Returns None if `pid` is not
+/// a child of the current process.
+pub fn wait_foreground(pid:usize) -> Option<WaitStatus>
+{
+    FOREGROUND.store(pid, Ordering::Relaxed);
+    let status = CHILD_CHANGED.wait_until(|| {
+        let mut table = PROCESSES.lock();
+        let parent_pid = table.current_pid();
+        let slot = match table.slot_of(pid)
+        {
+            Some(slot) if table.processes[slot].parent_pid == parent_pid => slot,
+            _ => return Some(None),
+        };
+        match table.processes[slot].state
+        {
+            ProcessState::Zombie => {
+                let status = table.processes[slot].wait_status();
+                table.processes[slot] = FREE_PROCESS;
+                Some(Some(status))
+            }
+            ProcessState::Stopped => Some(Some(WaitStatus::Stopped)),
+            _ => None,
+        }
+    });
+    FOREGROUND.store(0, Ordering::Relaxed);
+    return status;
+}
+
+/// Continues a process suspended by SIGTSTP or SIGSTOP, without running its
+/// SIGCONT handler.
+pub fn resume(pid:usize) -> bool
+{
+    let mut table = PROCESSES.lock();
+    let slot = match table.slot_of(pid)
+    {
+        Some(slot) if table.processes[slot].state == ProcessState::Stopped => slot,
+        _ => return false,
+    };
+    table.processes[slot].state = ProcessState::Ready;
+    task::set_stopped(table.processes[slot].task_id, false);
+    return true;
+}
+
+/// Called by the keyboard interrupt on Ctrl-C and Ctrl-Z with SIGINT or
+/// SIGTSTP. Returns false if the shell has the console, in which case the
+/// key is delivered as usual.
+pub fn signal_foreground(signal:i32) -> bool
+{
+    match FOREGROUND.load(Ordering::Relaxed)
+    {
+        0 => false,
+        pid => send(pid, signal),
+    }
+}
+
+/// Sends `signal` to the process `pid`, like kill(2). A signal to the current
+/// process is handled before returning. Returns false if there is no such
+/// process, it is the kernel or `signal` is not valid.
+pub fn kill(pid:usize, signal:i32) -> bool
+{
+    if !send(pid, signal)
+    {
+        return false;
+    }
+    if pid == current_pid()
+    {
+        handle_signals();
+    }
+    return true;
+}
+
+// Posts `signal` and carries out its default action on other processes. It
+// never terminates the current process, so interrupt handlers can use it.
+fn send(pid:usize, signal:i32) -> bool
+{
+    if !signal::is_valid(signal)
+    {
+        return false;
+    }
+    let (delivery, is_current) = {
+        let mut table = PROCESSES.lock();
+        let slot = match table.slot_of(pid)
+        {
+            Some(slot) if pid != KERNEL_PID && table.processes[slot].state != ProcessState::Zombie => slot,
+            _ => return false,
+        };
+        let is_current = table.processes[slot].task_id == task::current_id();
+        (table.post(slot, signal), is_current)
+    };
+    match delivery
+    {
+        Delivery::Terminate if !is_current => terminate(pid, signal),
+        Delivery::Stopped => CHILD_CHANGED.wake_all(),
+        Delivery::Pending => SIGNALLED.wake_all(),
+        _ => {}
+    }
+    return true;
+}
+
+// Ends the process `pid`, which is not the current one, as killed by
+// `signal`. One that holds a sleeping lock is left running with the signal
+// pending, and ends itself at its next safe point (see `handle_signals`).
+fn terminate(pid:usize, signal:i32)
+{
+    let (address_space, stdio) = {
+        let mut table = PROCESSES.lock();
+        let slot = match table.slot_of(pid)
+        {
+            Some(slot) if table.processes[slot].state != ProcessState::Zombie => slot,
+            _ => return,
+        };
+        // Off the run queue before anything it uses is freed
+        if !task::kill(table.processes[slot].task_id)
+        {
+            return;
+        }
+        let address_space = table.processes[slot].address_space.take();
+        let stdio = table.processes[slot].stdio;
+        table.exit(slot, 128 + signal, signal);
+        (address_space, stdio)
+    };
+    // Not active: only the current process runs with its own page table
+    if let Some(address_space) = address_space
+    {
+        address_space.destroy();
+    }
+    stdio.close();
+    CHILD_CHANGED.wake_all();
+    SIGNALLED.wake_all();
+}
+
+/// Sets what the current process does with `signal` and returns the handler
+/// it had before. Returns None for SIGKILL, SIGSTOP and invalid signals.
+pub fn set_handler(signal:i32, handler:Handler) -> Option<Handler>
+{
+    if !signal::can_catch(signal)
+    {
+        return None;
+    }
+    let mut table = PROCESSES.lock();
+    let slot = table.slot_of_task(task::current_id())?;
+    let process = &mut table.processes[slot];
+    // Ignoring a signal also throws away the one that is waiting
+    if let Handler::Ignore = handler
+    {
+        process.pending &= !(1 << signal);
+    }
+    return Some(core::mem::replace(&mut process.handlers[signal as usize], handler));
+}
+
+/// Delivers the signals pending for the current process: runs their handlers
+/// or terminates it. Processes get here when they use their standard streams,
+/// make a system call, wait for a child or `pause`.
+pub fn handle_signals()
+{
+    loop
+    {
+        let (signal, handler) = {
+            let mut table = PROCESSES.lock();
+            let slot = match table.slot_of_task(task::current_id())
+            {
+                Some(slot) => slot,
+                None => return,
+            };
+            let process = &mut table.processes[slot];
+            // Those go to ring 3, see `take_user_signal`
+            let pending = process.pending & !process.caught_in_user_mode();
+            if pending == 0
+            {
+                return;
+            }
+            let signal = pending.trailing_zeros() as i32;
+            let handler = process.handlers[signal as usize];
+            // Terminating now would keep the locks held forever, the signal
+            // waits for the next call made without any
+            if matches!(handler, Handler::Default) && signal::default_action(signal) == DefaultAction::Terminate
+                && task::holds_locks(task::current_id())
+            {
+                return;
+            }
+            process.pending &= !(1 << signal);
+            (signal, handler)
+        };
+        match handler
+        {
+            Handler::Call(function) => function(signal),
+            Handler::Ignore | Handler::User(..) => {}
+            Handler::Default => {
+                if signal::default_action(signal) == DefaultAction::Terminate
+                {
+                    kill_current(signal);
+                }
+            }
+        }
+    }
+}
+
+/// Takes a pending signal the current process catches in ring 3, with the
+/// handler and the address it returns to. The system call return delivers it.
+pub fn take_user_signal() -> Option<(i32, u64, u64)>
+{
+    let mut table = PROCESSES.lock();
+    let slot = table.slot_of_task(task::current_id())?;
+    let process = &mut table.processes[slot];
+    let pending = process.pending & process.caught_in_user_mode();
+    if pending == 0
+    {
+        return None;
+    }
+    let signal = pending.trailing_zeros() as i32;
+    process.pending &= !(1 << signal);
+    match process.handlers[signal as usize]
+    {
+        Handler::User(handler, restorer) => Some((signal, handler, restorer)),
+        _ => None,
+    }
+}
+
+/// Terminates the current process if a signal is pending for it whose
+/// default action is to do so. Called by the timer interrupt after the end
+/// of interrupt, so that Ctrl-C also stops a process that never makes a call.
+/// Only ring 3 code (`user_mode`) or kernel code that holds no sleeping lock
+/// is ended there; otherwise the signal stays pending.
+pub fn handle_fatal_signals(user_mode:bool)
+{
+    if !user_mode && task::holds_locks(task::current_id())
+    {
+        return;
+    }
+    let fatal = {
+        let table = PROCESSES.lock();
+        let slot = match table.slot_of_task(task::current_id())
+        {
+            Some(slot) => slot,
+            None => return,
+        };
+        let process = &table.processes[slot];
+        (1..NSIG as i32).find(|&signal| {
+            process.pending & (1 << signal) != 0
+                && matches!(process.handlers[signal as usize], Handler::Default)
+                && signal::default_action(signal) == DefaultAction::Terminate
+        })
+    };
+    if let Some(signal) = fatal
+    {
+        kill_current(signal);
+    }
+}
+
+/// Sleeps until a signal is posted to the current process and handles it,
+/// like pause(2).
+pub fn pause()
+{
+    SIGNALLED.wait_until(|| {
+        let table = PROCESSES.lock();
+        match table.slot_of_task(task::current_id())
+        {
+            Some(slot) if table.processes[slot].pending == 0 => None,
+            _ => Some(()),
+        }
+    });
+    handle_signals();
+}
+
+/// Frees every zombie child of `parent_pid` without looking at their exit status.
+pub fn reap_zombie_children(parent_pid:usize)
+{
+    let mut table = PROCESSES.lock();
+    for i in 0..MAX_PROCESSES
+    {
+        let process = table.processes[i];
+        if process.state == ProcessState::Zombie && process.parent_pid == parent_pid
+        {
+            table.processes[i] = FREE_PROCESS;
+        }
+    }
+}
+
+/// Copies the process table with the scheduler state of every live process filled in.
+pub fn snapshot() -> [Process; MAX_PROCESSES]
+{
+    let mut processes = PROCESSES.lock().processes;
+    for process in processes.iter_mut()
+    {
+        if process.is_free() || process.state == ProcessState::Zombie || process.state == ProcessState::Stopped
+        {
+            continue;
+        }
+        process.state = match task::state(process.task_id)
+        {
+            Some(TaskState::Running) => ProcessState::Running,
+            Some(TaskState::Sleeping) | Some(TaskState::Blocked) => ProcessState::Sleeping,
+            _ => ProcessState::Ready,
+        };
+    }
+    return processes;
+}
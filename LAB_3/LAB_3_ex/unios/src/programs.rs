@@ -0,0 +1,537 @@
+use core::arch::global_asm;
+use core::ptr::addr_of;
+use crate::{out, outln, println};
+use crate::process::{self, WaitStatus};
+use crate::signal::{self, Handler, SIGCHLD, SIGTERM};
+use crate::stdio::{self, LineReader, MAX_LINE_LENGTH};
+use crate::vfs::{self, FileType, FsError, Inode, MAX_PATH_LENGTH};
+use crate::{regex, task};
+
+/// A program that `run` can start in a child process.
+pub struct Program
+{
+    pub name:&'static str,
+    pub entry:Entry,
+}
+
+pub enum Entry
+{
+    /// Runs in the kernel. Gets the arguments with the program name in
+    /// `argv[0]` and returns the exit status.
+    Kernel(fn(&[&str]) -> i32),
+    /// A flat ring 3 binary, loaded at `usermode::USER_CODE_BASE`.
+    User(fn() -> &'static [u8]),
+    /// A ring 3 ELF64 executable.
+    Elf(fn() -> &'static [u8]),
+}
+
+pub static PROGRAMS: [Program; 12] = [
+    Program { name: "echo", entry: Entry::Kernel(echo) },
+    Program { name: "sleep", entry: Entry::Kernel(sleep) },
+    Program { name: "true", entry: Entry::Kernel(|_| 0) },
+    Program { name: "false", entry: Entry::Kernel(|_| 1) },
+    Program { name: "hello", entry: Entry::User(|| image(addr_of!(user_hello_start), addr_of!(user_hello_end))) },
+    Program { name: "segv", entry: Entry::User(|| image(addr_of!(user_segv_start), addr_of!(user_segv_end))) },
+    Program { name: "priv", entry: Entry::User(|| image(addr_of!(user_priv_start), addr_of!(user_priv_end))) },
+    Program { name: "args", entry: Entry::Elf(|| image(addr_of!(user_args_elf_start), addr_of!(user_args_elf_end))) },
+    Program { name: "grep", entry: Entry::Kernel(grep) },
+    Program { name: "wc", entry: Entry::Kernel(wc) },
+    Program { name: "sort", entry: Entry::Kernel(sort) },
+    Program { name: "signals", entry: Entry::Kernel(signals) },
+];
+
+// sort keeps its whole input in memory
+const SORT_MAX_LINES:usize = 64;
+// Directories `grep -r` goes down into
+const GREP_MAX_DEPTH:usize = 16;
+
+// Programs from the separate `user` crate. Build it first (see user/README.md),
+// then build the kernel with `--features user-programs`.
+#[cfg(feature = "user-programs")]
+pub static USER_CRATE_PROGRAMS: [Program; 2] = [
+    Program { name: "hello_rs", entry: Entry::Elf(|| include_bytes!("../../user/target/x86_64-unios-user/release/hello")) },
+    Program { name: "catch_rs", entry: Entry::Elf(|| include_bytes!("../../user/target/x86_64-unios-user/release/catch")) },
+];
+
+#[cfg(not(feature = "user-programs"))]
+pub static USER_CRATE_PROGRAMS: [Program; 0] = [];
+
+// Position independent ring 3 programs, talking to the kernel through the
+// calls in syscall.rs. They are only ever copied, never run in place.
+//   hello - prints a greeting and exits with 0
+//   segv  - writes to address 0 and gets killed by the page fault
+//   priv  - runs the privileged `cli` and gets killed by the protection fault
+global_asm!(
+    ".pushsection .rodata.user_programs, \"a\"",
+
+    ".global user_hello_start",
+    ".global user_hello_end",
+    "user_hello_start:",
+    "    mov $1, %eax",
+    "    lea .Lhello_message(%rip), %rdi",
+    "    mov $(.Lhello_message_end - .Lhello_message), %esi",
+    "    int $0x80",
+    "    mov $0, %eax",
+    "    xor %edi, %edi",
+    "    int $0x80",
+    ".Lhello_message:",
+    "    .ascii \"Hello from ring 3!\\n\"",
+    ".Lhello_message_end:",
+    "user_hello_end:",
+
+    ".global user_segv_start",
+    ".global user_segv_end",
+    "user_segv_start:",
+    "    movq $0, 0",
+    "    mov $0, %eax",
+    "    xor %edi, %edi",
+    "    int $0x80",
+    "user_segv_end:",
+
+    ".global user_priv_start",
+    ".global user_priv_end",
+    "user_priv_start:",
+    "    cli",
+    "    mov $0, %eax",
+    "    xor %edi, %edi",
+    "    int $0x80",
+    "user_priv_end:",
+
+    ".popsection",
+    options(att_syntax)
+);
+
+// A minimal ELF64 executable written out by hand: the ELF header, a single
+// read+execute PT_LOAD segment covering the whole file at USER_BASE, and code
+// that prints every argv entry on its own line.
+global_asm!(
+    ".pushsection .rodata.user_programs, \"a\"",
+    ".balign 8",
+    ".global user_args_elf_start",
+    ".global user_args_elf_end",
+    "user_args_elf_start:",
+    // e_ident: magic, ELFCLASS64, ELFDATA2LSB, EV_CURRENT, System V ABI
+    "    .byte 0x7f, 0x45, 0x4c, 0x46, 2, 1, 1, 0",
+    "    .quad 0",
+    "    .short 2",                   // e_type: ET_EXEC
+    "    .short 0x3e",                // e_machine: x86_64
+    "    .long 1",                    // e_version
+    "    .quad 0x400000000000 + (.Largs_code - user_args_elf_start)", // e_entry
+    "    .quad .Largs_phdr - user_args_elf_start", // e_phoff
+    "    .quad 0",                    // e_shoff
+    "    .long 0",                    // e_flags
+    "    .short 64",                  // e_ehsize
+    "    .short 56",                  // e_phentsize
+    "    .short 1",                   // e_phnum
+    "    .short 64",                  // e_shentsize
+    "    .short 0",                   // e_shnum
+    "    .short 0",                   // e_shstrndx
+    ".Largs_phdr:",
+    "    .long 1",                    // p_type: PT_LOAD
+    "    .long 5",                    // p_flags: R + X
+    "    .quad 0",                    // p_offset
+    "    .quad 0x400000000000",       // p_vaddr
+    "    .quad 0x400000000000",       // p_paddr
+    "    .quad user_args_elf_end - user_args_elf_start", // p_filesz
+    "    .quad user_args_elf_end - user_args_elf_start", // p_memsz
+    "    .quad 0x1000",               // p_align
+    ".Largs_code:",
+    "    mov (%rsp), %r12",           // argc
+    "    lea 8(%rsp), %r13",          // argv
+    "    xor %r14, %r14",
+    "1:  cmp %r12, %r14",
+    "    jge 4f",
+    "    mov (%r13,%r14,8), %rdi",
+    "    xor %rsi, %rsi",
+    "2:  cmpb $0, (%rdi,%rsi)",       // strlen
+    "    je 3f",
+    "    inc %rsi",
+    "    jmp 2b",
+    "3:  mov $1, %eax",
+    "    int $0x80",
+    "    mov $1, %eax",
+    "    lea .Largs_newline(%rip), %rdi",
+    "    mov $1, %esi",
+    "    int $0x80",
+    "    inc %r14",
+    "    jmp 1b",
+    "4:  mov $0, %eax",
+    "    xor %edi, %edi",
+    "    int $0x80",
+    ".Largs_newline:",
+    "    .byte 10",
+    "user_args_elf_end:",
+    ".popsection",
+    options(att_syntax)
+);
+
+extern "C"
+{
+    static user_hello_start: u8;
+    static user_hello_end: u8;
+    static user_segv_start: u8;
+    static user_segv_end: u8;
+    static user_priv_start: u8;
+    static user_priv_end: u8;
+    static user_args_elf_start: u8;
+    static user_args_elf_end: u8;
+}
+
+fn image(start:*const u8, end:*const u8) -> &'static [u8]
+{
+    unsafe { core::slice::from_raw_parts(start, end as usize - start as usize) }
+}
+
+pub fn find(name:&str) -> Option<&'static Program>
+{
+    PROGRAMS.iter()
+        .chain(USER_CRATE_PROGRAMS.iter())
+        .find(|program| program.name == name)
+}
+
+/// Whether `run` can start `name`: a program above, or the path of an ELF
+/// executable when it has a slash in it.
+pub fn is_runnable(name:&str) -> bool
+{
+    name.contains('/') || find(name).is_some()
+}
+
+fn echo(argv:&[&str]) -> i32
+{
+    for (i, word) in argv.iter().skip(1).enumerate()
+    {
+        if i > 0
+        {
+            out!(" ");
+        }
+        out!("{}", word);
+    }
+    outln!();
+    return 0;
+}
+
+fn sleep(argv:&[&str]) -> i32
+{
+    if argv.len() < 2
+    {
+        println!("sleep: missing operand");
+        return 1;
+    }
+    match argv[1].parse::<u64>()
+    {
+        Ok(seconds) => {
+            task::sleep(seconds * 1000);
+            0
+        }
+        Err(_) => {
+            println!("sleep: invalid time interval '{}'", argv[1]);
+            1
+        }
+    }
+}
+
+// What grep looks for and how it prints what it finds
+struct Search<'a>
+{
+    pattern:&'a [u8],
+    ignore_case:bool,
+    numbers:bool,
+    recursive:bool,
+    // every line starts with the name of its file
+    names:bool,
+    found:bool,
+    failed:bool,
+}
+
+// grep [-i] [-n] [-r] <pattern> [paths...]: prints the lines that match the
+// pattern, a simple regular expression. With no paths it reads standard
+// input, or with -r the current directory. -i ignores case, -n numbers the
+// lines, -r goes through directories. Lines longer than MAX_LINE_LENGTH are
+// searched in pieces.
+fn grep(argv:&[&str]) -> i32
+{
+    let mut search = Search
+    {
+        pattern: b"",
+        ignore_case: false,
+        numbers: false,
+        recursive: false,
+        names: false,
+        found: false,
+        failed: false,
+    };
+    let mut first = 1;
+    while first < argv.len() && argv[first].len() > 1 && argv[first].starts_with('-')
+    {
+        for option in argv[first][1..].chars()
+        {
+            match option
+            {
+                'i' => search.ignore_case = true,
+                'n' => search.numbers = true,
+                'r' => search.recursive = true,
+                _ => {
+                    println!("grep: invalid option -- '{}'", option);
+                    return 2;
+                }
+            }
+        }
+        first += 1;
+    }
+    if first == argv.len()
+    {
+        println!("grep: missing pattern");
+        return 2;
+    }
+    search.pattern = argv[first].as_bytes();
+    let paths = &argv[first + 1..];
+
+    search.names = paths.len() > 1 || search.recursive;
+    if paths.is_empty() && search.recursive
+    {
+        // Like GNU grep, -r alone searches the current directory, and the
+        // names are shown from there without "./"
+        let mut name = [0u8; MAX_PATH_LENGTH];
+        grep_path(process::cwd(), &mut name, 0, &mut search, 0);
+    }
+    else if paths.is_empty()
+    {
+        grep_lines(&mut LineReader::new(), "", &mut search);
+    }
+    for &path in paths
+    {
+        // "~" is the home directory, as in the shell
+        let (start, rest) = match path.strip_prefix('~')
+        {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => (vfs::root(), rest),
+            _ => (process::cwd(), path),
+        };
+        match vfs::resolve(start, rest)
+        {
+            Ok(inode) => {
+                let mut name = [0u8; MAX_PATH_LENGTH];
+                name[..path.len()].copy_from_slice(path.as_bytes());
+                grep_path(inode, &mut name, path.len(), &mut search, 0);
+            }
+            Err(error) => grep_error(&mut search, path, error),
+        }
+    }
+    // like grep: 1 when nothing matched, 2 when something went wrong
+    return if search.failed { 2 } else if search.found { 0 } else { 1 };
+}
+
+fn grep_error(search:&mut Search, path:&str, error:FsError)
+{
+    println!("grep: {}: {}", path, error.message());
+    search.failed = true;
+}
+
+// Searches the file at `path`, or with -r everything below the directory
+fn grep_path(inode:Inode, path:&mut [u8; MAX_PATH_LENGTH], length:usize, search:&mut Search, depth:usize)
+{
+    // The current directory has no name of its own under -r alone
+    let name = if length == 0 { "." } else { core::str::from_utf8(&path[..length]).unwrap_or("?") };
+    if inode.kind == FileType::File
+    {
+        let mut reader = LineReader::from_file(inode);
+        grep_lines(&mut reader, name, search);
+        if let Some(error) = reader.error()
+        {
+            grep_error(search, name, error);
+        }
+        return;
+    }
+    if !search.recursive
+    {
+        grep_error(search, name, FsError::IsADirectory);
+        return;
+    }
+    if depth + 1 >= GREP_MAX_DEPTH
+    {
+        println!("grep: {}: deeper directories left out", name);
+        return;
+    }
+
+    let mut index = 0;
+    loop
+    {
+        let entry = match vfs::readdir(inode, index)
+        {
+            Ok(Some(entry)) => entry,
+            Ok(None) => return,
+            Err(error) => {
+                let name = if length == 0 { "." } else { core::str::from_utf8(&path[..length]).unwrap_or("?") };
+                grep_error(search, name, error);
+                return;
+            }
+        };
+        index += 1;
+        if matches!(entry.name(), "." | "..")
+        {
+            continue;
+        }
+        let child = entry.name().as_bytes();
+        let slash = if length == 0 || path[..length].ends_with(b"/") { 0 } else { 1 };
+        let end = length + slash + child.len();
+        if end > MAX_PATH_LENGTH
+        {
+            grep_error(search, entry.name(), FsError::NameTooLong);
+            continue;
+        }
+        if slash == 1
+        {
+            path[length] = b'/';
+        }
+        path[length + slash..end].copy_from_slice(child);
+        grep_path(entry.inode, path, end, search, depth + 1);
+    }
+}
+
+// Prints the lines of `reader` that match, after "name:" and "number:" when
+// asked for
+fn grep_lines(reader:&mut LineReader, name:&str, search:&mut Search)
+{
+    let mut line = [0u8; MAX_LINE_LENGTH];
+    let mut number = 0;
+    // A long line comes in pieces, only the first has its start
+    let mut starts = true;
+    while let Some(len) = reader.read_line(&mut line)
+    {
+        if starts
+        {
+            number += 1;
+        }
+        let piece_starts = starts;
+        starts = reader.ended_line();
+        if !regex::is_match(search.pattern, &line[..len], search.ignore_case, piece_starts, starts)
+        {
+            continue;
+        }
+        search.found = true;
+        if search.names
+        {
+            out!("{}:", name);
+        }
+        if search.numbers
+        {
+            out!("{}:", number);
+        }
+        outln!("{}", core::str::from_utf8(&line[..len]).unwrap_or("?"));
+    }
+}
+
+// Counts the lines, words and bytes of standard input
+fn wc(_argv:&[&str]) -> i32
+{
+    let mut buf = [0u8; 128];
+    let (mut lines, mut words, mut bytes) = (0, 0, 0);
+    let mut in_word = false;
+    loop
+    {
+        let count = stdio::read(&mut buf);
+        if count == 0
+        {
+            break;
+        }
+        for &byte in &buf[..count]
+        {
+            bytes += 1;
+            if byte == b'\n'
+            {
+                lines += 1;
+            }
+            if byte == b' ' || byte == b'\n' || byte == b'\t'
+            {
+                in_word = false;
+            }
+            else if !in_word
+            {
+                in_word = true;
+                words += 1;
+            }
+        }
+    }
+    outln!("{:>7} {:>7} {:>7}", lines, words, bytes);
+    return 0;
+}
+
+// Prints the lines of standard input in byte order
+fn sort(_argv:&[&str]) -> i32
+{
+    let mut lines = [[0u8; MAX_LINE_LENGTH]; SORT_MAX_LINES];
+    let mut lengths = [0usize; SORT_MAX_LINES];
+    let mut count = 0;
+
+    let mut reader = LineReader::new();
+    let mut line = [0u8; MAX_LINE_LENGTH];
+    while let Some(len) = reader.read_line(&mut line)
+    {
+        if count == SORT_MAX_LINES
+        {
+            println!("sort: more than {} lines", SORT_MAX_LINES);
+            return 1;
+        }
+        lines[count] = line;
+        lengths[count] = len;
+        count += 1;
+    }
+
+    let mut order = [0usize; SORT_MAX_LINES];
+    for i in 0..count
+    {
+        order[i] = i;
+    }
+    order[..count].sort_unstable_by(|&a, &b| lines[a][..lengths[a]].cmp(&lines[b][..lengths[b]]));
+
+    for &i in &order[..count]
+    {
+        outln!("{}", core::str::from_utf8(&lines[i][..lengths[i]]).unwrap_or("?"));
+    }
+    return 0;
+}
+
+// The LAB_1 parent/child experiment with signals: one child catches SIGTERM
+// and exits by itself, the other one is killed by it. SIGCHLD tells the
+// parent; two of them arriving together are delivered once.
+fn signals(_argv:&[&str]) -> i32
+{
+    process::set_handler(SIGCHLD, Handler::Call(|_| outln!("parent: got SIGCHLD")));
+
+    let catcher = process::spawn("catcher", || {
+        process::set_handler(SIGTERM, Handler::Call(|number| {
+            outln!("child {}: caught SIG{}, exiting", process::current_pid(), signal::name(number));
+        }));
+        process::pause();
+    });
+    let victim = process::spawn("victim", || loop
+    {
+        process::pause();
+    });
+    let (catcher, victim) = match (catcher, victim)
+    {
+        (Some(catcher), Some(victim)) => (catcher, victim),
+        _ => {
+            println!("signals: the maximum number of processes");
+            return 1;
+        }
+    };
+
+    // Let both children get to pause()
+    task::sleep(100);
+    outln!("parent {}: sending SIGTERM to {} and {}", process::current_pid(), catcher, victim);
+    process::kill(catcher, SIGTERM);
+    process::kill(victim, SIGTERM);
+
+    for &pid in &[catcher, victim]
+    {
+        match process::wait(pid)
+        {
+            Some(WaitStatus::Exited(status)) => outln!("parent: child {} exited with {}", pid, status),
+            Some(status @ WaitStatus::Signaled(number)) => outln!("parent: child {} killed by SIG{}, status {}",
+                                                                  pid, signal::name(number), status.code()),
+            _ => outln!("parent: lost child {}", pid),
+        }
+    }
+    return 0;
+}
+
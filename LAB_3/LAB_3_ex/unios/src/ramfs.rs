@@ -0,0 +1,1203 @@
+use core::fmt;
+use crate::block::{BlockDevice, SECTOR_SIZE};
+use crate::rtc;
+use crate::vfs::{DirEntry, FileSystem, FileType, FsError, Inode, Metadata};
+
+const NAME_LENGTH:usize = 10;
+
+const MAX_SIZE_OF_DIRECTORIES:usize = 20;
+const MAX_SIZE_OF_CHILDREN_DIRECTORIES:usize = 10;
+
+const MAX_SIZE_FILES_IN_DIRECTORY:usize = 10;
+const MAX_SIZE_FILES:usize = 20;
+
+const CLEAR_MARKER_DIRECTORY:usize = MAX_SIZE_OF_DIRECTORIES + 1;
+const CLEAR_MARKER_FILE:usize = MAX_SIZE_FILES + 1;
+
+/// Most bytes a file can hold.
+pub const FILE_CAPACITY:usize = 2 * 1024;
+
+const ROOT_INDEX:usize = 0;
+
+// What a slot keeps besides its name and links. A mode of 0 stands for the
+// default one, as slots saved before there were modes have it.
+#[derive(Debug, Clone, Copy)]
+struct Attributes
+{
+    created:u32,
+    modified:u32,
+    owner:u16,
+    mode:u16,
+}
+
+const NO_ATTRIBUTES:Attributes = Attributes { created: 0, modified: 0, owner: 0, mode: 0 };
+
+impl Attributes
+{
+    fn now() -> Attributes
+    {
+        let now = rtc::now() as u32;
+        return Attributes { created: now, modified: now, ..NO_ATTRIBUTES };
+    }
+
+    fn touch(&mut self)
+    {
+        self.modified = rtc::now() as u32;
+    }
+
+    fn metadata(&self, inode:Inode, size:usize) -> Metadata
+    {
+        let mut metadata = Metadata::new(inode, size);
+        metadata.created = self.created as u64;
+        metadata.modified = self.modified as u64;
+        metadata.owner = self.owner as u32;
+        if self.mode != 0
+        {
+            metadata.mode = self.mode;
+        }
+        return metadata;
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Dir
+{
+    index:usize,
+    name:[u8; NAME_LENGTH],
+    parent_index:usize,
+    child_count:usize,
+    child_indexes:[usize; MAX_SIZE_OF_CHILDREN_DIRECTORIES],
+    files_indexes:[usize; MAX_SIZE_FILES_IN_DIRECTORY],
+    attributes:Attributes,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct File
+{
+    index:usize,
+    name:[u8; NAME_LENGTH],
+    size:usize,
+    folder_index:usize,
+    context:[u8; FILE_CAPACITY],
+    attributes:Attributes,
+}
+
+const FREE_DIR:Dir = Dir
+{
+    index: CLEAR_MARKER_DIRECTORY,
+    name: [b'\0'; NAME_LENGTH],
+    parent_index: CLEAR_MARKER_DIRECTORY,
+    child_count: 0,
+    child_indexes: [CLEAR_MARKER_DIRECTORY; MAX_SIZE_OF_CHILDREN_DIRECTORIES],
+    files_indexes: [CLEAR_MARKER_FILE; MAX_SIZE_FILES_IN_DIRECTORY],
+    attributes: NO_ATTRIBUTES,
+};
+
+const FREE_FILE:File = File
+{
+    index: CLEAR_MARKER_FILE,
+    name: [b'\0'; NAME_LENGTH],
+    size: 0,
+    folder_index: CLEAR_MARKER_DIRECTORY,
+    context: [0; FILE_CAPACITY],
+    attributes: NO_ATTRIBUTES,
+};
+
+impl Dir
+{
+    // The table of child directories or the one of files
+    fn entries(&mut self, kind:FileType) -> (&mut [usize], usize)
+    {
+        match kind
+        {
+            FileType::Directory => (&mut self.child_indexes, CLEAR_MARKER_DIRECTORY),
+            FileType::File => (&mut self.files_indexes, CLEAR_MARKER_FILE),
+        }
+    }
+
+    fn list(&mut self, kind:FileType, index:usize) -> bool
+    {
+        let (entries, marker) = self.entries(kind);
+        match entries.iter().position(|&i| i == marker)
+        {
+            Some(slot) => entries[slot] = index,
+            None => return false,
+        }
+        if kind == FileType::Directory
+        {
+            self.child_count += 1;
+        }
+        return true;
+    }
+
+    fn unlist(&mut self, kind:FileType, index:usize)
+    {
+        let (entries, marker) = self.entries(kind);
+        remove_index(entries, index, marker);
+        if kind == FileType::Directory
+        {
+            self.child_count -= 1;
+        }
+    }
+}
+
+/// Something `fsck` found wrong with the tables. Numbers are slots, the
+/// inode numbers `stat` shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Problem
+{
+    /// The root slot is not marked as the root.
+    BadRoot,
+    /// A used slot says it is another one.
+    WrongIndex(FileType, usize),
+    /// A file and the size it claims, more than a file can hold.
+    TooLarge(usize, usize),
+    /// A directory with free places between its entries.
+    Gap(usize),
+    /// A directory, the child count it keeps and the one its table has.
+    WrongCount(usize, usize, usize),
+    /// A directory lists a slot that is free, out of range, itself or the root.
+    BadEntry(usize, FileType, usize),
+    /// An entry is listed a second time, by the directory given.
+    ListedTwice(FileType, usize, usize),
+    /// An entry listed by one directory points at another as its parent.
+    WrongParent(FileType, usize, usize, usize),
+    /// An entry is missing from the directory it points at.
+    Unlisted(FileType, usize, usize),
+    /// An entry can not be reached from the root.
+    Orphan(FileType, usize),
+}
+
+fn kind_name(kind:FileType) -> &'static str
+{
+    match kind
+    {
+        FileType::Directory => "directory",
+        FileType::File => "file",
+    }
+}
+
+impl fmt::Display for Problem
+{
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result
+    {
+        match *self
+        {
+            Problem::BadRoot => write!(f, "the root directory is not marked as the root"),
+            Problem::WrongIndex(kind, index) => write!(f, "{} {} is marked as another slot", kind_name(kind), index),
+            Problem::TooLarge(index, size) => write!(f, "file {} claims {} bytes, more than {}", index, size, FILE_CAPACITY),
+            Problem::Gap(dir) => write!(f, "directory {} has free places between its entries", dir),
+            Problem::WrongCount(dir, kept, counted) => {
+                write!(f, "directory {} counts {} subdirectories but lists {}", dir, kept, counted)
+            }
+            Problem::BadEntry(dir, kind, index) => {
+                write!(f, "directory {} lists {} {}, which is free or can not be there", dir, kind_name(kind), index)
+            }
+            Problem::ListedTwice(kind, index, dir) => {
+                write!(f, "{} {} is listed a second time, by directory {}", kind_name(kind), index, dir)
+            }
+            Problem::WrongParent(kind, index, dir, parent) => {
+                write!(f, "{} {} is in directory {} but points at directory {}", kind_name(kind), index, dir, parent)
+            }
+            Problem::Unlisted(kind, index, dir) => {
+                write!(f, "{} {} is missing from its directory {}", kind_name(kind), index, dir)
+            }
+            Problem::Orphan(kind, index) => write!(f, "{} {} can not be reached from the root", kind_name(kind), index),
+        }
+    }
+}
+
+// What fsck needs of a file, without its contents
+#[derive(Clone, Copy)]
+struct FileLink
+{
+    index:usize,
+    folder_index:usize,
+    size:usize,
+}
+
+/// The filesystem the shell started with: fixed tables of directories and
+/// files in memory. Inode numbers are indexes into them.
+pub struct RamFs
+{
+    dirs:[Dir; MAX_SIZE_OF_DIRECTORIES],
+    files:[File; MAX_SIZE_FILES],
+    // changed since it was last loaded or saved
+    dirty:bool,
+}
+
+// On-disk layout, in sectors of 512 bytes, numbers little-endian:
+//
+//   0                  superblock: the magic "UNIOSFS1", then u32 version,
+//                      directory count, child directories and files per
+//                      directory, file count, file capacity, name length, and
+//                      the first sector of the inodes, the entries and the data
+//   INODES_START       an inode of INODE_SIZE bytes per slot, directories first,
+//                      then files: u8 kind (0 free, 1 file, 2 directory),
+//                      name[10] padded with zeros, u8 0, u32 parent directory,
+//                      u32 size in bytes, u32 created and u32 modified in
+//                      seconds since 1970, u16 owner, u16 mode (0 default)
+//   ENTRIES_START      ENTRIES_SIZE bytes per directory: u16 child directories[10],
+//                      then u16 files[10], NO_ENTRY in a free slot
+//   DATA_START         FILE_CAPACITY bytes for every file slot, in order
+//
+// The layout mirrors the tables, so a slot's place on disk never changes.
+const MAGIC:&[u8; 8] = b"UNIOSFS1";
+const VERSION:u32 = 1;
+const INODE_SIZE:usize = 32;
+const ENTRIES_SIZE:usize = 64;
+const NO_ENTRY:u16 = 0xFFFF;
+const KIND_FREE:u8 = 0;
+const KIND_FILE:u8 = 1;
+const KIND_DIRECTORY:u8 = 2;
+
+const INODES_BYTES:usize = (MAX_SIZE_OF_DIRECTORIES + MAX_SIZE_FILES) * INODE_SIZE;
+const ENTRIES_BYTES:usize = MAX_SIZE_OF_DIRECTORIES * ENTRIES_SIZE;
+const INODES_START:u64 = 1;
+const ENTRIES_START:u64 = INODES_START + sectors(INODES_BYTES);
+const DATA_START:u64 = ENTRIES_START + sectors(ENTRIES_BYTES);
+const FILE_SECTORS:u64 = sectors(FILE_CAPACITY);
+/// Sectors a disk needs to hold the filesystem.
+pub const IMAGE_SECTORS:u64 = DATA_START + MAX_SIZE_FILES as u64 * FILE_SECTORS;
+
+const fn sectors(bytes:usize) -> u64
+{
+    bytes.div_ceil(SECTOR_SIZE) as u64
+}
+
+fn put_u32(buf:&mut [u8], offset:usize, value:u32)
+{
+    buf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn get_u32(buf:&[u8], offset:usize) -> u32
+{
+    u32::from_le_bytes([buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]])
+}
+
+fn put_u16(buf:&mut [u8], offset:usize, value:u16)
+{
+    buf[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn get_u16(buf:&[u8], offset:usize) -> u16
+{
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn superblock() -> [u8; SECTOR_SIZE]
+{
+    let mut sector = [0u8; SECTOR_SIZE];
+    sector[..8].copy_from_slice(MAGIC);
+    let fields = [
+        VERSION,
+        MAX_SIZE_OF_DIRECTORIES as u32,
+        MAX_SIZE_OF_CHILDREN_DIRECTORIES as u32,
+        MAX_SIZE_FILES_IN_DIRECTORY as u32,
+        MAX_SIZE_FILES as u32,
+        FILE_CAPACITY as u32,
+        NAME_LENGTH as u32,
+        INODES_START as u32,
+        ENTRIES_START as u32,
+        DATA_START as u32,
+    ];
+    for (i, &field) in fields.iter().enumerate()
+    {
+        put_u32(&mut sector, 8 + 4 * i, field);
+    }
+    return sector;
+}
+
+fn encode_inode(buf:&mut [u8], kind:u8, name:&[u8; NAME_LENGTH], parent:usize, size:usize, attributes:&Attributes)
+{
+    buf[0] = kind;
+    buf[1..1 + NAME_LENGTH].copy_from_slice(name);
+    put_u32(buf, 12, parent as u32);
+    put_u32(buf, 16, size as u32);
+    put_u32(buf, 20, attributes.created);
+    put_u32(buf, 24, attributes.modified);
+    put_u16(buf, 28, attributes.owner);
+    put_u16(buf, 30, attributes.mode);
+}
+
+fn decode_attributes(buf:&[u8]) -> Attributes
+{
+    Attributes
+    {
+        created: get_u32(buf, 20),
+        modified: get_u32(buf, 24),
+        owner: get_u16(buf, 28),
+        mode: get_u16(buf, 30),
+    }
+}
+
+// An index from the entries of a directory, None for a free slot. Fails on
+// one that is out of the table.
+fn decode_entry(buf:&[u8], offset:usize, limit:usize) -> Result<Option<usize>, FsError>
+{
+    match get_u16(buf, offset)
+    {
+        NO_ENTRY => Ok(None),
+        index if (index as usize) < limit => Ok(Some(index as usize)),
+        _ => Err(FsError::NotAFileSystem),
+    }
+}
+
+// Checks that the tables read from a disk agree with each other, so that
+// nothing loaded from them can point outside them or walk up in a loop
+fn check_tables(inodes:&[u8], entries:&[u8]) -> Result<(), FsError>
+{
+    let kind_of = |slot:usize| inodes[slot * INODE_SIZE];
+    let parent_of = |slot:usize| get_u32(&inodes[slot * INODE_SIZE..], 12) as usize;
+    if kind_of(ROOT_INDEX) != KIND_DIRECTORY || parent_of(ROOT_INDEX) != ROOT_INDEX
+    {
+        return Err(FsError::NotAFileSystem);
+    }
+    for i in 0..MAX_SIZE_OF_DIRECTORIES + MAX_SIZE_FILES
+    {
+        let inode = &inodes[i * INODE_SIZE..(i + 1) * INODE_SIZE];
+        let parent = get_u32(inode, 12) as usize;
+        let wrong_kind = if i < MAX_SIZE_OF_DIRECTORIES { KIND_FILE } else { KIND_DIRECTORY };
+        if inode[0] == wrong_kind || inode[0] > KIND_DIRECTORY
+            || (inode[0] != KIND_FREE && (parent >= MAX_SIZE_OF_DIRECTORIES || kind_of(parent) != KIND_DIRECTORY))
+            || get_u32(inode, 16) as usize > FILE_CAPACITY
+        {
+            return Err(FsError::NotAFileSystem);
+        }
+    }
+    for i in 0..MAX_SIZE_OF_DIRECTORIES
+    {
+        let record = &entries[i * ENTRIES_SIZE..];
+        for slot in 0..MAX_SIZE_OF_CHILDREN_DIRECTORIES
+        {
+            if let Some(child) = decode_entry(record, 2 * slot, MAX_SIZE_OF_DIRECTORIES)?
+            {
+                // A listed entry has to point back, or walking up could loop
+                if kind_of(child) != KIND_DIRECTORY || child == ROOT_INDEX || child == i || parent_of(child) != i
+                {
+                    return Err(FsError::NotAFileSystem);
+                }
+            }
+        }
+        for slot in 0..MAX_SIZE_FILES_IN_DIRECTORY
+        {
+            if let Some(file) = decode_entry(record, 2 * (MAX_SIZE_OF_CHILDREN_DIRECTORIES + slot), MAX_SIZE_FILES)?
+            {
+                if kind_of(MAX_SIZE_OF_DIRECTORIES + file) != KIND_FILE || parent_of(MAX_SIZE_OF_DIRECTORIES + file) != i
+                {
+                    return Err(FsError::NotAFileSystem);
+                }
+            }
+        }
+    }
+    // Every directory has to reach the root by its parents
+    for i in (0..MAX_SIZE_OF_DIRECTORIES).filter(|&i| kind_of(i) == KIND_DIRECTORY)
+    {
+        let mut dir = i;
+        let mut steps = 0;
+        while dir != ROOT_INDEX
+        {
+            if steps == MAX_SIZE_OF_DIRECTORIES
+            {
+                return Err(FsError::NotAFileSystem);
+            }
+            dir = parent_of(dir);
+            steps += 1;
+        }
+    }
+    return Ok(());
+}
+
+fn dir_inode(index:usize) -> Inode
+{
+    Inode::new(FileType::Directory, index)
+}
+
+fn file_inode(index:usize) -> Inode
+{
+    Inode::new(FileType::File, index)
+}
+
+fn check_name(name:&str) -> Result<(), FsError>
+{
+    if name.is_empty() || name == "." || name == ".." || name.contains('/')
+    {
+        return Err(FsError::InvalidName);
+    }
+    if name.len() > NAME_LENGTH
+    {
+        return Err(FsError::NameTooLong);
+    }
+    return Ok(());
+}
+
+fn name_matches(stored:&[u8; NAME_LENGTH], name:&str) -> bool
+{
+    let length = stored.iter().position(|&byte| byte == b'\0').unwrap_or(NAME_LENGTH);
+    &stored[..length] == name.as_bytes()
+}
+
+fn to_name(name:&str) -> [u8; NAME_LENGTH]
+{
+    let mut stored = [b'\0'; NAME_LENGTH];
+    stored[..name.len()].copy_from_slice(name.as_bytes());
+    return stored;
+}
+
+// Takes `index` out of a table of indexes and moves the ones after it down,
+// so the used slots always come first
+fn remove_index(indexes:&mut [usize], index:usize, marker:usize)
+{
+    if let Some(position) = indexes.iter().position(|&i| i == index)
+    {
+        indexes.copy_within(position + 1.., position);
+        indexes[indexes.len() - 1] = marker;
+    }
+}
+
+// Moves the used entries of a table of indexes to the front, keeping their
+// order. Returns whether any had to move.
+fn compact(indexes:&mut [usize], marker:usize) -> bool
+{
+    let mut used = 0;
+    let mut moved = false;
+    for i in 0..indexes.len()
+    {
+        if indexes[i] == marker
+        {
+            continue;
+        }
+        if i != used
+        {
+            indexes[used] = indexes[i];
+            indexes[i] = marker;
+            moved = true;
+        }
+        used += 1;
+    }
+    return moved;
+}
+
+impl RamFs
+{
+    pub fn new() -> RamFs
+    {
+        let mut fs = RamFs
+        {
+            dirs: [FREE_DIR; MAX_SIZE_OF_DIRECTORIES],
+            files: [FREE_FILE; MAX_SIZE_FILES],
+            dirty: false,
+        };
+        fs.dirs[ROOT_INDEX].index = ROOT_INDEX;
+        fs.dirs[ROOT_INDEX].parent_index = ROOT_INDEX;
+        fs.dirs[ROOT_INDEX].attributes = Attributes::now();
+        return fs;
+    }
+
+    /// Writes an empty filesystem to `device`.
+    pub fn format(device:&mut dyn BlockDevice) -> Result<(), FsError>
+    {
+        let root = Dir { index: ROOT_INDEX, parent_index: ROOT_INDEX, attributes: Attributes::now(), ..FREE_DIR };
+        return RamFs::write_tables(device, &[root], &[]);
+    }
+
+    // Writes the superblock, inodes and entries of the given slots; the
+    // slots after them are written as free
+    fn write_tables(device:&mut dyn BlockDevice, dirs:&[Dir], files:&[File]) -> Result<(), FsError>
+    {
+        if device.sector_count() < IMAGE_SECTORS
+        {
+            return Err(FsError::NoSpace);
+        }
+        let mut inodes = [0u8; sectors(INODES_BYTES) as usize * SECTOR_SIZE];
+        let mut entries = [0xFFu8; sectors(ENTRIES_BYTES) as usize * SECTOR_SIZE];
+        for (i, dir) in dirs.iter().enumerate().filter(|(_, dir)| dir.index != CLEAR_MARKER_DIRECTORY)
+        {
+            encode_inode(&mut inodes[i * INODE_SIZE..], KIND_DIRECTORY, &dir.name, dir.parent_index, 0, &dir.attributes);
+            let record = &mut entries[i * ENTRIES_SIZE..(i + 1) * ENTRIES_SIZE];
+            for (slot, &child) in dir.child_indexes.iter().enumerate().filter(|(_, &child)| child != CLEAR_MARKER_DIRECTORY)
+            {
+                put_u16(record, 2 * slot, child as u16);
+            }
+            for (slot, &file) in dir.files_indexes.iter().enumerate().filter(|(_, &file)| file != CLEAR_MARKER_FILE)
+            {
+                put_u16(record, 2 * (MAX_SIZE_OF_CHILDREN_DIRECTORIES + slot), file as u16);
+            }
+        }
+        for (i, file) in files.iter().enumerate().filter(|(_, file)| file.index != CLEAR_MARKER_FILE)
+        {
+            let offset = (MAX_SIZE_OF_DIRECTORIES + i) * INODE_SIZE;
+            encode_inode(&mut inodes[offset..], KIND_FILE, &file.name, file.folder_index, file.size, &file.attributes);
+        }
+        device.write_sectors(0, &superblock())?;
+        device.write_sectors(INODES_START, &inodes)?;
+        device.write_sectors(ENTRIES_START, &entries)?;
+        return Ok(());
+    }
+
+    /// Writes the whole filesystem to `device` if it changed since the last
+    /// load or save.
+    pub fn save_to(&mut self, device:&mut dyn BlockDevice) -> Result<(), FsError>
+    {
+        if !self.dirty
+        {
+            return Ok(());
+        }
+        RamFs::write_tables(device, &self.dirs, &self.files)?;
+        for (i, file) in self.files.iter().enumerate().filter(|(_, file)| file.index != CLEAR_MARKER_FILE)
+        {
+            device.write_sectors(DATA_START + i as u64 * FILE_SECTORS, &file.context)?;
+        }
+        self.dirty = false;
+        return Ok(());
+    }
+
+    /// Replaces the tables with the filesystem on `device`. They are left
+    /// alone when it holds none or its tables do not agree, and emptied when
+    /// reading it fails midway.
+    pub fn load_from(&mut self, device:&mut dyn BlockDevice) -> Result<(), FsError>
+    {
+        self.load(device, true)
+    }
+
+    /// Like `load_from`, but takes damaged tables as they are so that `fsck`
+    /// can repair them. Only the superblock has to be right. Nothing but
+    /// `fsck` and `save_to` should be used before the repair.
+    pub fn load_damaged_from(&mut self, device:&mut dyn BlockDevice) -> Result<(), FsError>
+    {
+        self.load(device, false)
+    }
+
+    fn load(&mut self, device:&mut dyn BlockDevice, strict:bool) -> Result<(), FsError>
+    {
+        let mut sector = [0u8; SECTOR_SIZE];
+        device.read_sectors(0, &mut sector)?;
+        // A filesystem made with other table sizes can not be loaded
+        if sector[..] != superblock()[..]
+        {
+            return Err(FsError::NotAFileSystem);
+        }
+        let mut inodes = [0u8; sectors(INODES_BYTES) as usize * SECTOR_SIZE];
+        let mut entries = [0u8; sectors(ENTRIES_BYTES) as usize * SECTOR_SIZE];
+        device.read_sectors(INODES_START, &mut inodes)?;
+        device.read_sectors(ENTRIES_START, &mut entries)?;
+        if strict
+        {
+            check_tables(&inodes, &entries)?;
+        }
+        // Damaged tables keep what is out of range, fsck drops it. An index
+        // that happens to be the clear marker reads as a free slot.
+        let entry = |record:&[u8], offset:usize, limit:usize| match decode_entry(record, offset, limit)
+        {
+            Ok(entry) => entry,
+            Err(_) => Some(get_u16(record, offset) as usize),
+        };
+
+        for (i, dir) in self.dirs.iter_mut().enumerate()
+        {
+            *dir = FREE_DIR;
+            let inode = &inodes[i * INODE_SIZE..];
+            if inode[0] != KIND_DIRECTORY
+            {
+                continue;
+            }
+            dir.index = i;
+            dir.name.copy_from_slice(&inode[1..1 + NAME_LENGTH]);
+            dir.parent_index = get_u32(inode, 12) as usize;
+            dir.attributes = decode_attributes(inode);
+            let record = &entries[i * ENTRIES_SIZE..];
+            for slot in 0..MAX_SIZE_OF_CHILDREN_DIRECTORIES
+            {
+                if let Some(child) = entry(record, 2 * slot, MAX_SIZE_OF_DIRECTORIES)
+                {
+                    dir.child_indexes[dir.child_count] = child;
+                    dir.child_count += 1;
+                }
+            }
+            let mut count = 0;
+            for slot in 0..MAX_SIZE_FILES_IN_DIRECTORY
+            {
+                if let Some(file) = entry(record, 2 * (MAX_SIZE_OF_CHILDREN_DIRECTORIES + slot), MAX_SIZE_FILES)
+                {
+                    dir.files_indexes[count] = file;
+                    count += 1;
+                }
+            }
+        }
+        for i in 0..MAX_SIZE_FILES
+        {
+            self.files[i] = FREE_FILE;
+            let inode = &inodes[(MAX_SIZE_OF_DIRECTORIES + i) * INODE_SIZE..];
+            if inode[0] != KIND_FILE
+            {
+                continue;
+            }
+            let file = &mut self.files[i];
+            file.index = i;
+            file.name.copy_from_slice(&inode[1..1 + NAME_LENGTH]);
+            file.folder_index = get_u32(inode, 12) as usize;
+            file.size = get_u32(inode, 16) as usize;
+            file.attributes = decode_attributes(inode);
+            if let Err(error) = device.read_sectors(DATA_START + i as u64 * FILE_SECTORS, &mut file.context)
+            {
+                self.clear();
+                return Err(error.into());
+            }
+            // Bytes past the end read as zeros after growing the file
+            for byte in file.context[file.size.min(FILE_CAPACITY)..].iter_mut()
+            {
+                *byte = 0;
+            }
+        }
+        self.dirty = false;
+        return Ok(());
+    }
+
+    // Back to an empty root
+    fn clear(&mut self)
+    {
+        for dir in self.dirs.iter_mut()
+        {
+            *dir = FREE_DIR;
+        }
+        for file in self.files.iter_mut()
+        {
+            *file = FREE_FILE;
+        }
+        self.dirs[ROOT_INDEX].index = ROOT_INDEX;
+        self.dirs[ROOT_INDEX].parent_index = ROOT_INDEX;
+        self.dirs[ROOT_INDEX].attributes = Attributes::now();
+        self.dirty = true;
+    }
+
+    /// Checks that the directory and file tables agree with each other and
+    /// calls `report` with every problem. With `repair` they are fixed too:
+    /// entries go back to the directory that lists them or that they point
+    /// at, what can not be reached from the root is freed and counts are
+    /// recomputed. Returns how many problems there were.
+    pub fn fsck(&mut self, repair:bool, report:&mut dyn FnMut(Problem)) -> usize
+    {
+        // The checks work on copies and go on as if every problem was fixed,
+        // so none comes back as another one
+        let mut dirs = self.dirs;
+        let mut files = [FileLink { index: CLEAR_MARKER_FILE, folder_index: CLEAR_MARKER_DIRECTORY, size: 0 }; MAX_SIZE_FILES];
+        for (link, file) in files.iter_mut().zip(self.files.iter())
+        {
+            *link = FileLink { index: file.index, folder_index: file.folder_index, size: file.size };
+        }
+        let mut count = 0;
+        let mut found = |problem:Problem| {
+            count += 1;
+            report(problem);
+        };
+
+        if dirs[ROOT_INDEX].index != ROOT_INDEX || dirs[ROOT_INDEX].parent_index != ROOT_INDEX
+        {
+            found(Problem::BadRoot);
+            dirs[ROOT_INDEX].index = ROOT_INDEX;
+            dirs[ROOT_INDEX].parent_index = ROOT_INDEX;
+        }
+        for (i, dir) in dirs.iter_mut().enumerate()
+        {
+            if dir.index != CLEAR_MARKER_DIRECTORY && dir.index != i
+            {
+                found(Problem::WrongIndex(FileType::Directory, i));
+                dir.index = i;
+            }
+        }
+        for (i, file) in files.iter_mut().enumerate().filter(|(_, file)| file.index != CLEAR_MARKER_FILE)
+        {
+            if file.index != i
+            {
+                found(Problem::WrongIndex(FileType::File, i));
+                file.index = i;
+            }
+            if file.size > FILE_CAPACITY
+            {
+                found(Problem::TooLarge(i, file.size));
+                file.size = FILE_CAPACITY;
+            }
+        }
+
+        // Used entries come first and child_count says how many dirs there are
+        for (i, dir) in dirs.iter_mut().enumerate().filter(|(_, dir)| dir.index != CLEAR_MARKER_DIRECTORY)
+        {
+            let children_moved = compact(&mut dir.child_indexes, CLEAR_MARKER_DIRECTORY);
+            let files_moved = compact(&mut dir.files_indexes, CLEAR_MARKER_FILE);
+            if children_moved || files_moved
+            {
+                found(Problem::Gap(i));
+            }
+            let listed = dir.child_indexes.iter().filter(|&&child| child != CLEAR_MARKER_DIRECTORY).count();
+            if dir.child_count != listed
+            {
+                found(Problem::WrongCount(i, dir.child_count, listed));
+                dir.child_count = listed;
+            }
+        }
+
+        // Every entry names a used slot and is listed once
+        let mut dir_listed_by = [None; MAX_SIZE_OF_DIRECTORIES];
+        let mut file_listed_by = [None; MAX_SIZE_FILES];
+        for kind in [FileType::Directory, FileType::File]
+        {
+            for d in 0..MAX_SIZE_OF_DIRECTORIES
+            {
+                if dirs[d].index == CLEAR_MARKER_DIRECTORY
+                {
+                    continue;
+                }
+                let mut slot = 0;
+                loop
+                {
+                    let (entries, marker) = dirs[d].entries(kind);
+                    let entry = match entries.get(slot)
+                    {
+                        Some(&entry) if entry != marker => entry,
+                        _ => break,
+                    };
+                    let (used, listed_by, parent) = match kind
+                    {
+                        FileType::Directory => (
+                            entry < MAX_SIZE_OF_DIRECTORIES && entry != ROOT_INDEX && entry != d
+                                && dirs[entry].index != CLEAR_MARKER_DIRECTORY,
+                            &mut dir_listed_by[..],
+                            dirs.get(entry).map_or(CLEAR_MARKER_DIRECTORY, |dir| dir.parent_index),
+                        ),
+                        FileType::File => (
+                            entry < MAX_SIZE_FILES && files[entry].index != CLEAR_MARKER_FILE,
+                            &mut file_listed_by[..],
+                            files.get(entry).map_or(CLEAR_MARKER_DIRECTORY, |file| file.folder_index),
+                        ),
+                    };
+                    // The listing to drop, the one of its parent stays
+                    let dropped = if !used
+                    {
+                        found(Problem::BadEntry(d, kind, entry));
+                        d
+                    }
+                    else
+                    {
+                        match listed_by[entry]
+                        {
+                            None => {
+                                listed_by[entry] = Some(d);
+                                slot += 1;
+                                continue;
+                            }
+                            Some(first) if first != d && parent == d => {
+                                listed_by[entry] = Some(d);
+                                found(Problem::ListedTwice(kind, entry, first));
+                                first
+                            }
+                            Some(_) => {
+                                found(Problem::ListedTwice(kind, entry, d));
+                                d
+                            }
+                        }
+                    };
+                    dirs[dropped].unlist(kind, entry);
+                    // The entries of `d` after the dropped one moved down
+                    if dropped != d
+                    {
+                        slot += 1;
+                    }
+                }
+            }
+        }
+
+        // Every entry points back at the directory that lists it, and one
+        // nobody lists goes back into the directory it points at
+        for kind in [FileType::Directory, FileType::File]
+        {
+            let slots = if kind == FileType::Directory { MAX_SIZE_OF_DIRECTORIES } else { MAX_SIZE_FILES };
+            for i in 0..slots
+            {
+                let (used, listed_by, parent) = match kind
+                {
+                    FileType::Directory => (
+                        i != ROOT_INDEX && dirs[i].index != CLEAR_MARKER_DIRECTORY,
+                        dir_listed_by[i],
+                        dirs[i].parent_index,
+                    ),
+                    FileType::File => (files[i].index != CLEAR_MARKER_FILE, file_listed_by[i], files[i].folder_index),
+                };
+                if !used || listed_by == Some(parent)
+                {
+                    continue;
+                }
+                let new_parent = match listed_by
+                {
+                    Some(dir) => {
+                        found(Problem::WrongParent(kind, i, dir, parent));
+                        dir
+                    }
+                    None => {
+                        let can_list = parent < MAX_SIZE_OF_DIRECTORIES && dirs[parent].index != CLEAR_MARKER_DIRECTORY
+                            && !(kind == FileType::Directory && parent == i);
+                        // What has nowhere to go is freed as an orphan below
+                        if !can_list || !dirs[parent].list(kind, i)
+                        {
+                            continue;
+                        }
+                        found(Problem::Unlisted(kind, i, parent));
+                        parent
+                    }
+                };
+                match kind
+                {
+                    FileType::Directory => dirs[i].parent_index = new_parent,
+                    FileType::File => files[i].folder_index = new_parent,
+                }
+            }
+        }
+
+        // What the root does not lead to is lost, such as a loop of
+        // directories or the contents of a freed one
+        let mut reached = [false; MAX_SIZE_OF_DIRECTORIES];
+        reached[ROOT_INDEX] = true;
+        let mut changed = true;
+        while changed
+        {
+            changed = false;
+            for d in 0..MAX_SIZE_OF_DIRECTORIES
+            {
+                if !reached[d]
+                {
+                    continue;
+                }
+                for &child in dirs[d].child_indexes.iter().filter(|&&child| child != CLEAR_MARKER_DIRECTORY)
+                {
+                    changed |= !reached[child];
+                    reached[child] = true;
+                }
+            }
+        }
+        for (i, dir) in dirs.iter_mut().enumerate()
+        {
+            if dir.index != CLEAR_MARKER_DIRECTORY && !reached[i]
+            {
+                found(Problem::Orphan(FileType::Directory, i));
+                *dir = FREE_DIR;
+            }
+        }
+        for (i, file) in files.iter_mut().enumerate()
+        {
+            if file.index != CLEAR_MARKER_FILE && !reached.get(file.folder_index).copied().unwrap_or(false)
+            {
+                found(Problem::Orphan(FileType::File, i));
+                file.index = CLEAR_MARKER_FILE;
+            }
+        }
+
+        if repair && count > 0
+        {
+            self.dirs = dirs;
+            for (file, link) in self.files.iter_mut().zip(files.iter())
+            {
+                if link.index == CLEAR_MARKER_FILE
+                {
+                    *file = FREE_FILE;
+                    continue;
+                }
+                file.index = link.index;
+                file.folder_index = link.folder_index;
+                file.size = link.size;
+            }
+            self.dirty = true;
+        }
+        return count;
+    }
+
+    fn dir(&self, inode:Inode) -> Result<&Dir, FsError>
+    {
+        if inode.kind != FileType::Directory
+        {
+            return Err(FsError::NotADirectory);
+        }
+        match self.dirs.get(inode.number)
+        {
+            Some(dir) if dir.index != CLEAR_MARKER_DIRECTORY => Ok(dir),
+            _ => Err(FsError::NotFound),
+        }
+    }
+
+    fn file_index(&self, inode:Inode) -> Result<usize, FsError>
+    {
+        if inode.kind != FileType::File
+        {
+            return Err(FsError::IsADirectory);
+        }
+        match self.files.get(inode.number)
+        {
+            Some(file) if file.index != CLEAR_MARKER_FILE => Ok(inode.number),
+            _ => Err(FsError::NotFound),
+        }
+    }
+
+    fn find(&self, dir:&Dir, name:&str) -> Option<Inode>
+    {
+        for &index in dir.child_indexes.iter()
+        {
+            if index != CLEAR_MARKER_DIRECTORY && name_matches(&self.dirs[index].name, name)
+            {
+                return Some(dir_inode(index));
+            }
+        }
+        for &index in dir.files_indexes.iter()
+        {
+            if index != CLEAR_MARKER_FILE && name_matches(&self.files[index].name, name)
+            {
+                return Some(file_inode(index));
+            }
+        }
+        return None;
+    }
+
+    // Checks that `name` can be added to `dir` and returns its table index
+    fn check_new_entry(&self, dir:Inode, name:&str) -> Result<usize, FsError>
+    {
+        check_name(name)?;
+        let parent = self.dir(dir)?;
+        if self.find(parent, name).is_some()
+        {
+            return Err(FsError::AlreadyExists);
+        }
+        return Ok(dir.number);
+    }
+}
+
+impl FileSystem for RamFs
+{
+    fn root(&self) -> Inode
+    {
+        dir_inode(ROOT_INDEX)
+    }
+
+    fn lookup(&self, dir:Inode, name:&str) -> Result<Inode, FsError>
+    {
+        let parent = self.dir(dir)?;
+        match name
+        {
+            "." => Ok(dir),
+            ".." => Ok(dir_inode(parent.parent_index)),
+            _ => self.find(parent, name).ok_or(FsError::NotFound),
+        }
+    }
+
+    fn create(&mut self, dir:Inode, name:&str) -> Result<Inode, FsError>
+    {
+        let parent_index = self.check_new_entry(dir, name)?;
+        let file_index = (0..MAX_SIZE_FILES)
+            .find(|&i| self.files[i].index == CLEAR_MARKER_FILE)
+            .ok_or(FsError::NoSpace)?;
+        let slot = (0..MAX_SIZE_FILES_IN_DIRECTORY)
+            .find(|&i| self.dirs[parent_index].files_indexes[i] == CLEAR_MARKER_FILE)
+            .ok_or(FsError::DirectoryFull)?;
+
+        self.files[file_index] = File
+        {
+            index: file_index,
+            name: to_name(name),
+            folder_index: parent_index,
+            attributes: Attributes::now(),
+            ..FREE_FILE
+        };
+        self.dirs[parent_index].files_indexes[slot] = file_index;
+        self.dirs[parent_index].attributes.touch();
+        self.dirty = true;
+        return Ok(file_inode(file_index));
+    }
+
+    fn mkdir(&mut self, dir:Inode, name:&str) -> Result<Inode, FsError>
+    {
+        let parent_index = self.check_new_entry(dir, name)?;
+        let dir_index = (0..MAX_SIZE_OF_DIRECTORIES)
+            .find(|&i| self.dirs[i].index == CLEAR_MARKER_DIRECTORY)
+            .ok_or(FsError::NoSpace)?;
+        let slot = (0..MAX_SIZE_OF_CHILDREN_DIRECTORIES)
+            .find(|&i| self.dirs[parent_index].child_indexes[i] == CLEAR_MARKER_DIRECTORY)
+            .ok_or(FsError::DirectoryFull)?;
+
+        self.dirs[dir_index] = Dir
+        {
+            index: dir_index,
+            name: to_name(name),
+            parent_index,
+            attributes: Attributes::now(),
+            ..FREE_DIR
+        };
+        self.dirs[parent_index].child_indexes[slot] = dir_index;
+        self.dirs[parent_index].child_count += 1;
+        self.dirs[parent_index].attributes.touch();
+        self.dirty = true;
+        return Ok(dir_inode(dir_index));
+    }
+
+    fn unlink(&mut self, dir:Inode, name:&str) -> Result<(), FsError>
+    {
+        let inode = self.lookup(dir, name)?;
+        let file_index = self.file_index(inode)?;
+        remove_index(&mut self.dirs[dir.number].files_indexes, file_index, CLEAR_MARKER_FILE);
+        self.dirs[dir.number].attributes.touch();
+        self.files[file_index] = FREE_FILE;
+        self.dirty = true;
+        return Ok(());
+    }
+
+    fn rmdir(&mut self, dir:Inode, name:&str) -> Result<(), FsError>
+    {
+        check_name(name)?;
+        let inode = self.lookup(dir, name)?;
+        let target = self.dir(inode)?;
+        if target.child_count > 0 || target.files_indexes.iter().any(|&index| index != CLEAR_MARKER_FILE)
+        {
+            return Err(FsError::DirectoryNotEmpty);
+        }
+        remove_index(&mut self.dirs[dir.number].child_indexes, inode.number, CLEAR_MARKER_DIRECTORY);
+        self.dirs[dir.number].child_count -= 1;
+        self.dirs[dir.number].attributes.touch();
+        self.dirs[inode.number] = FREE_DIR;
+        self.dirty = true;
+        return Ok(());
+    }
+
+    fn rename(&mut self, old_dir:Inode, old_name:&str, new_dir:Inode, new_name:&str) -> Result<(), FsError>
+    {
+        check_name(old_name)?;
+        check_name(new_name)?;
+        let inode = self.lookup(old_dir, old_name)?;
+        match self.find(self.dir(new_dir)?, new_name)
+        {
+            Some(existing) if existing == inode => return Ok(()),
+            Some(existing) if existing.kind == FileType::File && inode.kind == FileType::File => {
+                self.unlink(new_dir, new_name)?;
+            }
+            Some(_) => return Err(FsError::AlreadyExists),
+            None => {}
+        }
+
+        match inode.kind
+        {
+            FileType::Directory => {
+                let mut index = new_dir.number;
+                loop
+                {
+                    if index == inode.number
+                    {
+                        return Err(FsError::IntoItself);
+                    }
+                    if index == ROOT_INDEX
+                    {
+                        break;
+                    }
+                    index = self.dirs[index].parent_index;
+                }
+                if new_dir != old_dir
+                {
+                    let slot = (0..MAX_SIZE_OF_CHILDREN_DIRECTORIES)
+                        .find(|&i| self.dirs[new_dir.number].child_indexes[i] == CLEAR_MARKER_DIRECTORY)
+                        .ok_or(FsError::DirectoryFull)?;
+                    self.dirs[new_dir.number].child_indexes[slot] = inode.number;
+                    self.dirs[new_dir.number].child_count += 1;
+                    remove_index(&mut self.dirs[old_dir.number].child_indexes, inode.number, CLEAR_MARKER_DIRECTORY);
+                    self.dirs[old_dir.number].child_count -= 1;
+                    self.dirs[inode.number].parent_index = new_dir.number;
+                }
+                self.dirs[inode.number].name = to_name(new_name);
+            }
+            FileType::File => {
+                if new_dir != old_dir
+                {
+                    let slot = (0..MAX_SIZE_FILES_IN_DIRECTORY)
+                        .find(|&i| self.dirs[new_dir.number].files_indexes[i] == CLEAR_MARKER_FILE)
+                        .ok_or(FsError::DirectoryFull)?;
+                    self.dirs[new_dir.number].files_indexes[slot] = inode.number;
+                    remove_index(&mut self.dirs[old_dir.number].files_indexes, inode.number, CLEAR_MARKER_FILE);
+                    self.files[inode.number].folder_index = new_dir.number;
+                }
+                self.files[inode.number].name = to_name(new_name);
+            }
+        }
+        self.dirs[old_dir.number].attributes.touch();
+        self.dirs[new_dir.number].attributes.touch();
+        self.dirty = true;
+        return Ok(());
+    }
+
+    fn read(&self, file:Inode, offset:usize, buf:&mut [u8]) -> Result<usize, FsError>
+    {
+        let file = &self.files[self.file_index(file)?];
+        let count = file.size.saturating_sub(offset).min(buf.len());
+        if count == 0
+        {
+            return Ok(0);
+        }
+        buf[..count].copy_from_slice(&file.context[offset..offset + count]);
+        return Ok(count);
+    }
+
+    fn write(&mut self, file:Inode, offset:usize, data:&[u8]) -> Result<usize, FsError>
+    {
+        let file = &mut self.files[self.file_index(file)?];
+        let count = FILE_CAPACITY.saturating_sub(offset).min(data.len());
+        if count == 0
+        {
+            return if data.is_empty() { Ok(0) } else { Err(FsError::FileTooLarge) };
+        }
+        file.context[offset..offset + count].copy_from_slice(&data[..count]);
+        file.size = file.size.max(offset + count);
+        file.attributes.touch();
+        self.dirty = true;
+        return Ok(count);
+    }
+
+    fn truncate(&mut self, file:Inode, size:usize) -> Result<(), FsError>
+    {
+        let file = &mut self.files[self.file_index(file)?];
+        if size > FILE_CAPACITY
+        {
+            return Err(FsError::FileTooLarge);
+        }
+        if size < file.size
+        {
+            // Growing it again later has to bring back zeros
+            for byte in file.context[size..file.size].iter_mut()
+            {
+                *byte = 0;
+            }
+        }
+        file.size = size;
+        file.attributes.touch();
+        self.dirty = true;
+        return Ok(());
+    }
+
+    fn readdir(&self, dir:Inode, index:usize) -> Result<Option<DirEntry>, FsError>
+    {
+        let dir = self.dir(dir)?;
+        let dirs = dir.child_indexes.iter()
+            .filter(|&&i| i != CLEAR_MARKER_DIRECTORY)
+            .map(|&i| DirEntry::new(&self.dirs[i].name, dir_inode(i)));
+        let files = dir.files_indexes.iter()
+            .filter(|&&i| i != CLEAR_MARKER_FILE)
+            .map(|&i| DirEntry::new(&self.files[i].name, file_inode(i)));
+        return Ok(dirs.chain(files).nth(index));
+    }
+
+    fn stat(&self, inode:Inode) -> Result<Metadata, FsError>
+    {
+        match inode.kind
+        {
+            FileType::File => {
+                let file = &self.files[self.file_index(inode)?];
+                return Ok(file.attributes.metadata(inode, file.size));
+            }
+            FileType::Directory => {
+                let dir = self.dir(inode)?;
+                let size = dir.child_count + dir.files_indexes.iter().filter(|&&i| i != CLEAR_MARKER_FILE).count();
+                return Ok(dir.attributes.metadata(inode, size));
+            }
+        }
+    }
+}
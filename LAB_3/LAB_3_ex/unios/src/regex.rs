@@ -0,0 +1,89 @@
+use crate::glob;
+
+/// Whether a part of `text` matches `pattern`, a simple regular expression:
+/// `.` stands for any character, `[abc]`, `[a-z]` and `[^abc]` for one of a
+/// set, `*` repeats what is before it any number of times, `^` ties the
+/// match to the start of the text and `$` to its end. `\` takes the next
+/// character as it is. `text` may be a piece of a longer line: `^` only
+/// matches when it `starts` the line and `$` when it `ends` it.
+pub fn is_match(pattern:&[u8], text:&[u8], ignore_case:bool, starts:bool, ends:bool) -> bool
+{
+    if let Some(rest) = pattern.strip_prefix(b"^")
+    {
+        return starts && match_here(rest, text, ignore_case, ends);
+    }
+    return (0..=text.len()).any(|start| match_here(pattern, &text[start..], ignore_case, ends));
+}
+
+// The length of the first element of `pattern`
+fn atom_length(pattern:&[u8]) -> usize
+{
+    match pattern[0]
+    {
+        b'\\' if pattern.len() > 1 => 2,
+        b'[' => match match_class(&pattern[1..], 0, false)
+        {
+            Some((_, length)) => 1 + length,
+            // Without a ']' the '[' is just a character
+            None => 1,
+        },
+        _ => 1,
+    }
+}
+
+// A set after '[': only "[^" negates, "[!" is glob syntax. With
+// `ignore_case` either case of `byte` is in the set, and only then is the
+// set negated, so [^a] does not match 'A'.
+fn match_class(class:&[u8], byte:u8, ignore_case:bool) -> Option<(bool, usize)>
+{
+    glob::match_class_with(class, b"^", |low, high| {
+        let range = low..=high;
+        range.contains(&byte) || (ignore_case && (range.contains(&byte.to_ascii_lowercase()) || range.contains(&byte.to_ascii_uppercase())))
+    })
+}
+
+fn atom_matches(atom:&[u8], byte:u8, ignore_case:bool) -> bool
+{
+    let same = |wanted:u8| if ignore_case { wanted.eq_ignore_ascii_case(&byte) } else { wanted == byte };
+    match atom
+    {
+        [b'.'] => true,
+        [b'\\', wanted] => same(*wanted),
+        [b'[', class @ ..] if atom.len() > 1 => match_class(class, byte, ignore_case).map_or(false, |(found, _)| found),
+        _ => same(atom[0]),
+    }
+}
+
+// Whether `pattern` matches from the start of `text` on
+fn match_here(pattern:&[u8], text:&[u8], ignore_case:bool, ends:bool) -> bool
+{
+    if pattern.is_empty()
+    {
+        return true;
+    }
+    if pattern == b"$"
+    {
+        return ends && text.is_empty();
+    }
+    let length = atom_length(pattern);
+    let atom = &pattern[..length];
+    if pattern.get(length) == Some(&b'*')
+    {
+        // As few repeats as possible first, then one more each time
+        let rest = &pattern[length + 1..];
+        let mut i = 0;
+        loop
+        {
+            if match_here(rest, &text[i..], ignore_case, ends)
+            {
+                return true;
+            }
+            if i == text.len() || !atom_matches(atom, text[i], ignore_case)
+            {
+                return false;
+            }
+            i += 1;
+        }
+    }
+    return !text.is_empty() && atom_matches(atom, text[0], ignore_case) && match_here(&pattern[length..], &text[1..], ignore_case, ends);
+}
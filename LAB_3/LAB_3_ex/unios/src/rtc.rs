@@ -0,0 +1,155 @@
+use core::fmt;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::Port;
+
+// The CMOS is read through an index and a data port
+const CMOS_INDEX:u16 = 0x70;
+const CMOS_DATA:u16 = 0x71;
+
+const REG_SECONDS:u8 = 0x00;
+const REG_MINUTES:u8 = 0x02;
+const REG_HOURS:u8 = 0x04;
+const REG_DAY:u8 = 0x07;
+const REG_MONTH:u8 = 0x08;
+const REG_YEAR:u8 = 0x09;
+const REG_STATUS_A:u8 = 0x0A;
+const REG_STATUS_B:u8 = 0x0B;
+
+const UPDATE_IN_PROGRESS:u8 = 0x80;
+const STATUS_B_24_HOUR:u8 = 0x02;
+const STATUS_B_BINARY:u8 = 0x04;
+const HOUR_PM:u8 = 0x80;
+
+/// A moment in the calendar, the way the RTC keeps it. QEMU's RTC runs on
+/// UTC unless told otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime
+{
+    pub year:u32,
+    pub month:u32,
+    pub day:u32,
+    pub hour:u32,
+    pub minute:u32,
+    pub second:u32,
+}
+
+fn read_register(register:u8) -> u8
+{
+    unsafe
+    {
+        Port::<u8>::new(CMOS_INDEX).write(register);
+        Port::<u8>::new(CMOS_DATA).read()
+    }
+}
+
+// The time registers as they are, read while no update is going on
+fn read_raw() -> [u8; 6]
+{
+    while read_register(REG_STATUS_A) & UPDATE_IN_PROGRESS != 0
+    {
+        core::hint::spin_loop();
+    }
+    [REG_SECONDS, REG_MINUTES, REG_HOURS, REG_DAY, REG_MONTH, REG_YEAR].map(read_register)
+}
+
+/// The time the RTC shows now.
+pub fn read() -> DateTime
+{
+    let (mut raw, status) = without_interrupts(|| {
+        // Two equal readings in a row can not have an update between them
+        let mut raw = read_raw();
+        loop
+        {
+            let again = read_raw();
+            if again == raw
+            {
+                break;
+            }
+            raw = again;
+        }
+        (raw, read_register(REG_STATUS_B))
+    });
+
+    let pm = raw[2] & HOUR_PM != 0;
+    raw[2] &= !HOUR_PM;
+    if status & STATUS_B_BINARY == 0
+    {
+        for value in raw.iter_mut()
+        {
+            *value = (*value >> 4) * 10 + (*value & 0x0F);
+        }
+    }
+    let mut hour = raw[2] as u32;
+    if status & STATUS_B_24_HOUR == 0
+    {
+        hour = hour % 12 + if pm { 12 } else { 0 };
+    }
+    return DateTime
+    {
+        year: 2000 + raw[5] as u32,
+        month: raw[4] as u32,
+        day: raw[3] as u32,
+        hour,
+        minute: raw[1] as u32,
+        second: raw[0] as u32,
+    };
+}
+
+/// Seconds since 1970-01-01 00:00, what filesystems keep.
+pub fn now() -> u64
+{
+    read().to_unix()
+}
+
+// Days from 1970-01-01 to the given date of the Gregorian calendar
+fn days_from_civil(year:u32, month:u32, day:u32) -> i64
+{
+    let year = year as i64 - if month <= 2 { 1 } else { 0 };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    return era * 146097 + day_of_era - 719468;
+}
+
+impl DateTime
+{
+    pub fn to_unix(&self) -> u64
+    {
+        let days = days_from_civil(self.year, self.month, self.day).max(0) as u64;
+        return days * 86400 + (self.hour * 3600 + self.minute * 60 + self.second) as u64;
+    }
+
+    pub fn from_unix(seconds:u64) -> DateTime
+    {
+        let days = (seconds / 86400) as i64 + 719468;
+        let era = days.div_euclid(146097);
+        let day_of_era = days - era * 146097;
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_index = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+        let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+        let time = (seconds % 86400) as u32;
+        return DateTime
+        {
+            year: year as u32,
+            month: month as u32,
+            day: day as u32,
+            hour: time / 3600,
+            minute: time / 60 % 60,
+            second: time % 60,
+        };
+    }
+}
+
+impl fmt::Display for DateTime
+{
+    // 2024-05-17 13:05
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "{:04}-{:02}-{:02} {:02}:{:02}", self.year, self.month, self.day, self.hour, self.minute)
+    }
+}
@@ -1,19 +1,14 @@
 use core::slice::SliceIndex;
-use crate::{print, println};
+use crate::{out, outln, print, println};
 use crate::vga_buf::SCREEN;
-use pc_keyboard::DecodedKey;
 use lazy_static::lazy_static;
-
-const MAX_SIZE_OF_DIRECTORIES:usize = 20;
-const MAX_SIZE_OF_CHILDREN_DIRECTORIES:usize = 10;
-
-const MAX_SIZE_FILES_IN_DIRECTORY:usize = 10;
-const MAX_SIZE_FILES:usize = 20;
-
-const CLEAR_MARKER_DIRECTORY:usize = MAX_SIZE_OF_DIRECTORIES + 1;
-const CLEAR_MARKER_FILE:usize = MAX_SIZE_FILES + 1;
-
-const MAX_SIZE_DIRECTORY_NAME:usize = 10;
+use crate::{ata, game_of_life, glob, initrd, keyboard, pipe, process, programs, rtc, signal, vfs};
+use crate::pipe::End;
+use crate::stdio::{Input, Output, Stdio};
+use crate::sync::Mutex;
+use crate::process::{Process, ProcessState, WaitStatus, MAX_PROCESSES};
+use crate::signal::Handler;
+use crate::vfs::{DirEntry, FileType, FsError, Inode, Metadata, Mounted, OpenFile, MAX_MOUNTS, MAX_PATH_LENGTH};
 
 const COMMAND_LENGTH:usize = 10;
 const ARGUMENT_LENGTH:usize = 50;
@@ -22,60 +17,94 @@ const BUF_HEIGHT:u32 = 25;
 const BUF_WIDTH:u32 = 80;
 const BUF_SIZE:usize = (BUF_HEIGHT * BUF_WIDTH) as usize;
 
+const MAX_JOBS:usize = 8;
+// Entries `ls` sorts at once
+const MAX_LISTED:usize = 64;
+// Directories `ls -R` goes down into
+const MAX_LIST_DEPTH:usize = 8;
+// Directories `find` goes down into
+const MAX_FIND_DEPTH:usize = 16;
+const MAX_PIPELINE_STAGES:usize = 4;
+
+// Commands run by the shell itself rather than in a child process. In a
+// pipeline they can only come first, as they never read standard input.
+const BUILTIN_COMMANDS:[&str; 29] = [
+    "cur_dir", "pwd", "make_dir", "change_dir", "dir_tree", "remove_dir", "clear", "make_file", "remove_file",
+    "rm", "mv", "cp", "dump_file", "edit_file", "life", "ps", "pstree", "run", "jobs", "fg", "kill",
+    "mount", "umount", "sync", "mkfs", "fsck", "ls", "stat", "find",
+];
+
+// A sleeping lock: the shell stays locked while a command sleeps or waits
+// for a child, which an IrqSafeMutex does not allow.
 lazy_static! 
 {
-    static ref SH: spin::Mutex<Shell> = spin::Mutex::new
+    static ref SH: Mutex<Shell> = Mutex::new
     ({
         let mut sh = Shell::new();
         sh
     });
 }
 
-pub fn handle_keyboard_interrupt(key: DecodedKey) 
+pub fn initialize()
 {
-    match key 
+    // Picks up the files of the last session
+    if let Some(index) = vfs::mount_first_disk()
     {
-        DecodedKey::Unicode(c) => SH.lock().on_key_pressed(c as u8),
-        DecodedKey::RawKey(rk) => {}
+        println!("[Ok] Files loaded from ata{}", index);
     }
-}
-
-pub fn initialize()
-{
+    mount_initrd();
+    lazy_static::initialize(&SH);
     print_start();
 }
 
-#[derive(Debug, Clone, Copy)]
-struct Dir
+/// Entry point of the shell thread: sleeps until a key is typed and handles it.
+pub fn run()
 {
-    index:usize,
-    name:[u8; MAX_SIZE_DIRECTORY_NAME],
-    parent_index:usize,
-    child_count:usize,
-    child_indexes:[usize; MAX_SIZE_OF_CHILDREN_DIRECTORIES],
-    files_indexes:[usize; MAX_SIZE_FILES_IN_DIRECTORY],
+    // Like an interactive sh, only its children die from these
+    for &number in &[signal::SIGINT, signal::SIGTERM, signal::SIGTSTP]
+    {
+        process::set_handler(number, Handler::Ignore);
+    }
+    loop
+    {
+        let key = keyboard::read_char();
+        SH.lock().on_key_pressed(key);
+    }
 }
 
-struct Dirs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum JobKind
 {
-    dirs:[Dir; MAX_SIZE_OF_DIRECTORIES],
+    Free,
+    Process(usize),
+    // the file editor suspended with Ctrl-Z: the file and the cursor line and column
+    Editor(DirEntry, u32, u32),
 }
 
+/// A job is numbered by its slot + 1, so `%1` is `jobs[0]`.
 #[derive(Debug, Clone, Copy)]
-struct File
+struct Job
 {
-    index:usize,
-    name:[u8; MAX_SIZE_DIRECTORY_NAME],
-    count_lines:usize,
-    folder_index:usize,
-    context:[u8;BUF_SIZE],
+    kind:JobKind,
+    stopped:bool,
+    command:[u8; 80],
 }
 
-struct Files
+impl Job
 {
-    files:[File; MAX_SIZE_FILES],
+    fn command(&self) -> &str
+    {
+        core::str::from_utf8(&self.command).unwrap_or("?").trim_matches('\0')
+    }
 }
 
+const FREE_JOB:Job = Job
+{
+    kind: JobKind::Free,
+    stopped: false,
+    command: [b'\0'; 80],
+};
+
 pub fn split(array:[u8; 80], buf_len:usize) -> ([u8; COMMAND_LENGTH], [u8; ARGUMENT_LENGTH])
 {
     let mut command:[u8; COMMAND_LENGTH] = [b'\0'; COMMAND_LENGTH];
@@ -108,630 +137,2479 @@ pub fn compare(line:&str, array:[u8; COMMAND_LENGTH]) -> bool
     let mut i = 0;
     for symbol in line.bytes()
     {
-        if symbol != array[i]
+        if i == COMMAND_LENGTH || symbol != array[i]
         {
             return false;
         }
         i += 1;
     }
-    return true;
+    // a shorter command name must not match a longer one ("ps" vs "pstree")
+    return i == COMMAND_LENGTH || array[i] == b'\0';
 }
 
-fn print_start()
+fn print_process_tree(processes:&[Process; MAX_PROCESSES], pid:usize, prefix:&mut [u8; BUF_WIDTH as usize], column:usize)
 {
-    print!(" $ ");
+    let mut label_length = 0;
+    for process in processes.iter()
+    {
+        if !process.is_free() && process.pid == pid
+        {
+            out!("{}({})", process.name(), pid);
+            label_length = process.name().len() + 2 + count_digits(pid);
+            break;
+        }
+    }
+
+    let mut children = [0; MAX_PROCESSES];
+    let mut child_count = 0;
+    for process in processes.iter()
+    {
+        if !process.is_free() && process.parent_pid == pid && process.pid != pid
+        {
+            children[child_count] = process.pid;
+            child_count += 1;
+        }
+    }
+
+    // column where the "+", "|" and "`" connectors of this node's children go
+    let bar = column + label_length + 1;
+    if child_count == 0
+    {
+        outln!();
+        return;
+    }
+    if child_count == 1
+    {
+        out!("---");
+        extend_prefix(prefix, column, bar, b' ');
+        print_process_tree(processes, children[0], prefix, bar + 2);
+        return;
+    }
+
+    for i in 0..child_count
+    {
+        let is_last = i == child_count - 1;
+        if i == 0
+        {
+            out!("-+-");
+        }
+        else
+        {
+            print_prefix(prefix, bar);
+            out!("{}", if is_last { "`-" } else { "|-" });
+        }
+        extend_prefix(prefix, column, bar, if is_last { b' ' } else { b'|' });
+        print_process_tree(processes, children[i], prefix, bar + 2);
+    }
 }
 
-fn print_command_not_found(cmd:[u8; COMMAND_LENGTH])
+fn extend_prefix(prefix:&mut [u8; BUF_WIDTH as usize], from:usize, bar:usize, bar_symbol:u8)
 {
-    println!();
-    print!("[error] Command \'{}\' is not supported", core::str::from_utf8(&cmd).unwrap().trim_matches('\0'));
+    for i in from..bar.min(prefix.len())
+    {
+        prefix[i] = b' ';
+    }
+    if bar < prefix.len()
+    {
+        prefix[bar] = bar_symbol;
+    }
 }
 
-struct Shell 
+fn print_prefix(prefix:&[u8; BUF_WIDTH as usize], length:usize)
 {
-    buf:[u8; 80],
-    buf_len:usize,
-    dirs:Dirs,
-    files:Files,
-    curr_dir:usize,
-    is_editing_file:bool,
-    current_editing_file:usize,
+    for i in 0..length.min(prefix.len())
+    {
+        out!("{}", prefix[i] as char);
+    }
 }
 
-impl Shell 
+fn count_digits(mut number:usize) -> usize
 {
-    pub fn new() -> Shell 
+    let mut digits = 1;
+    while number >= 10
     {
-        let mut shell:Shell = Shell 
-        {
-            buf: [0; 80],
-            buf_len: 0,
-            dirs: Dirs{
-                dirs: ([Dir
-                {
-                    index: CLEAR_MARKER_DIRECTORY,
-                    name: [b' '; MAX_SIZE_DIRECTORY_NAME],
-                    parent_index: 0,
-                    child_count: 0,
-                    child_indexes: [CLEAR_MARKER_DIRECTORY; MAX_SIZE_OF_CHILDREN_DIRECTORIES],
-                    files_indexes: [CLEAR_MARKER_FILE; MAX_SIZE_FILES_IN_DIRECTORY],
-                }; MAX_SIZE_OF_DIRECTORIES]),
-            },
-            curr_dir: 0,
-            files: Files
-            {
-                files: [File
-                {
-                    index: CLEAR_MARKER_FILE,
-                    name: [b'\0'; MAX_SIZE_DIRECTORY_NAME],
-                    count_lines: 0,
-                    folder_index: CLEAR_MARKER_DIRECTORY,
-                    context: [b' '; BUF_SIZE],
-                }; MAX_SIZE_FILES]
-            },
-            is_editing_file: false,
-            current_editing_file: CLEAR_MARKER_FILE,
-        };
+        number /= 10;
+        digits += 1;
+    }
+    return digits;
+}
 
-        let root_dir = Dir
-        {
-            index: 0,
-            name: [b'r', b'o', b'o', b't', b'\0', b'\0', b'\0', b'\0', b'\0', b'\0'],
-            parent_index: 0,
-            child_count: 0,
-            child_indexes: [CLEAR_MARKER_DIRECTORY; MAX_SIZE_OF_CHILDREN_DIRECTORIES],
-            files_indexes: [CLEAR_MARKER_FILE; MAX_SIZE_FILES_IN_DIRECTORY],
-        };
+// Strips a trailing `&` from the command line and reports whether there was one
+fn take_background_flag(buf:&mut [u8; 80], buf_len:&mut usize) -> bool
+{
+    let mut len = *buf_len;
+    while len > 0 && buf[len - 1] == b' '
+    {
+        len -= 1;
+    }
+    if len == 0 || buf[len - 1] != b'&'
+    {
+        return false;
+    }
+    len -= 1;
+    while len > 0 && buf[len - 1] == b' '
+    {
+        len -= 1;
+    }
+    for i in len..*buf_len
+    {
+        buf[i] = b'\0';
+    }
+    *buf_len = len;
+    return true;
+}
 
-        shell.dirs.dirs[0] = root_dir;
-        return shell;
+// Strips `> path` or `>> path` from the end of the command line. Returns the
+// path, its length and whether it was `>>`.
+fn take_redirect(buf:&mut [u8; 80], buf_len:&mut usize) -> Option<([u8; 80], usize, bool)>
+{
+    let at = buf[..*buf_len].iter().rposition(|&byte| byte == b'>')?;
+    let append = at > 0 && buf[at - 1] == b'>';
+    let mut path = [0u8; 80];
+    let mut length = 0;
+    for &byte in buf[at + 1..*buf_len].iter().filter(|&&byte| byte != b' ')
+    {
+        path[length] = byte;
+        length += 1;
+    }
+    let mut len = if append { at - 1 } else { at };
+    while len > 0 && buf[len - 1] == b' '
+    {
+        len -= 1;
+    }
+    for i in len..*buf_len
+    {
+        buf[i] = b'\0';
     }
+    *buf_len = len;
+    return Some((path, length, append));
+}
 
-    pub fn on_key_pressed(&mut self, key: u8) 
+// Like sh, tells why a foreground child died, unless it was Ctrl-C
+fn print_killed(number:i32)
+{
+    if number != signal::SIGINT
     {
-        match key 
-        {
-            b'\n' => {
-                if self.is_editing_file
-                {
-                    self.files.files[self.current_editing_file].count_lines += 1;
-                    println!();
-                    return;
-                }
+        print!("\n{}", signal::description(number));
+    }
+}
 
-                let argument = split(self.buf, self.buf_len);
-                self.command_distributor(argument);
-                self.buf_len = 0;
+// The argument without the padding
+fn argument_text(argument:&[u8; ARGUMENT_LENGTH]) -> &str
+{
+    core::str::from_utf8(argument).unwrap_or("").trim_matches('\0').trim()
+}
 
-                if self.is_editing_file
-                {
-                    return;
-                }
+// A program typed without "run", None for builtins and unknown names
+fn program_command(command:&[u8; COMMAND_LENGTH]) -> Option<&'static programs::Program>
+{
+    let name = core::str::from_utf8(command).unwrap_or("").trim_matches('\0');
+    if BUILTIN_COMMANDS.contains(&name)
+    {
+        return None;
+    }
+    return programs::find(name);
+}
 
-                println!();
-                print_start();
-            }
-            8 => { // backspace
-                if self.is_editing_file
-                {
-                    SCREEN.lock().delete_last_char(0);
-                    return;
-                }
-                SCREEN.lock().delete_last_char(3);
-                if self.buf_len > 0
-                {
-                    self.buf_len -= 1;
-                }
-                self.buf[self.buf_len] = 0;
-            }
-            32 => { // space
-                print!("{}", key as char);
+// Options given as "-x" letters before the rest of an argument
+#[derive(Debug, Clone, Copy)]
+struct Options
+{
+    letters:u64,
+}
 
-                if self.is_editing_file
-                {
-                    return;
-                }
+impl Options
+{
+    fn bit(letter:char) -> u64
+    {
+        match letter
+        {
+            'a'..='z' => 1 << (letter as u64 - 'a' as u64),
+            'A'..='Z' => 1 << (26 + letter as u64 - 'A' as u64),
+            _ => 0,
+        }
+    }
 
-                self.buf[self.buf_len] = b' ';
-                self.buf_len += 1;
-            }
-            96 => { // `
-                if self.is_editing_file
-                {
-                    self.is_editing_file = false;
-                    self.files.files[self.current_editing_file].count_lines += 1;
-                    self.files.files[self.current_editing_file].context = SCREEN.lock().get_buffer();
-                    self.clear();
+    fn has(&self, letter:char) -> bool
+    {
+        self.letters & Options::bit(letter) != 0
+    }
+}
 
-                    print!("\n[ok] File \"{}\" saved succesfully!\n", core::str::from_utf8(
-                        &self.files.files[self.current_editing_file].name.clone()).unwrap().trim_matches('\0'));
-                    print_start();
-                }
+// Takes "-r -f" or "-rf" off the front of `text`. Fails with the first
+// letter that is not in `allowed`.
+fn take_options<'a>(text:&'a str, allowed:&str) -> Result<(Options, &'a str), char>
+{
+    let mut options = Options { letters: 0 };
+    let mut rest = text.trim_start();
+    while rest.starts_with('-') && rest.len() > 1
+    {
+        let end = rest.find(' ').unwrap_or(rest.len());
+        for letter in rest[1..end].chars()
+        {
+            if !allowed.contains(letter) || Options::bit(letter) == 0
+            {
+                return Err(letter);
             }
-            _ => {
-                if self.is_editing_file
-                {
-                    print!("{}", key as char);
-                    return;
-                }
+            options.letters |= Options::bit(letter);
+        }
+        rest = rest[end..].trim_start();
+    }
+    return Ok((options, rest));
+}
 
-                self.buf[self.buf_len] = key;
-                self.buf_len += 1;
+// Asks a yes or no question on the console, anything but "y" is a no
+fn confirm(question:core::fmt::Arguments) -> bool
+{
+    print!("\n{} [y/N] ", question);
+    let start = SCREEN.lock().col;
+    let mut answer = 0;
+    loop
+    {
+        match keyboard::read_char()
+        {
+            b'\n' => break,
+            keyboard::CTRL_C => {
+                print!("^C");
+                return false;
+            }
+            8 => {
+                SCREEN.lock().delete_last_char(start);
+                answer = 0;
+            }
+            key @ 32..=126 if answer == 0 => {
                 print!("{}", key as char);
+                answer = key;
             }
+            _ => {}
         }
     }
+    return answer == b'y' || answer == b'Y';
+}
+
+fn print_fs_error(error:FsError, name:&str)
+{
+    print!("\n[Error] \"{}\": {}", name, error.message());
+}
+
+// A pattern matched more paths than are taken, the first in order were used
+fn print_cut(pattern:&str)
+{
+    print!("\n[Error] \"{}\" matches more than {} paths, the rest are left out", pattern, glob::MAX_MATCHES);
+}
+
+// An entry of a directory as `ls` shows it
+#[derive(Debug, Clone, Copy)]
+struct Listed
+{
+    entry:DirEntry,
+    metadata:Metadata,
+}
 
-    fn command_distributor(&mut self, argument:([u8; COMMAND_LENGTH], [u8;ARGUMENT_LENGTH]))
+// "drwxr-xr-x"
+fn mode_text(kind:FileType, mode:u16) -> [u8; 10]
+{
+    let mut text = *b"----------";
+    if kind == FileType::Directory
     {
-        if compare("cur_dir", argument.0)
-        {
-            self.cur_dir(self.dirs.dirs[self.curr_dir]);
-        }
-        else if compare("make_dir", argument.0) 
-        {
-            self.make_dir(argument.1);
-        }
-        else if compare("change_dir", argument.0) 
+        text[0] = b'd';
+    }
+    for (i, letter) in b"rwxrwxrwx".iter().enumerate()
+    {
+        if mode & (0o400 >> i) != 0
         {
-            self.change_dir(argument.1);
+            text[1 + i] = *letter;
         }
-        else if compare("dir_tree", argument.0) 
+    }
+    return text;
+}
+
+// A time as "2024-05-17 13:05", or "-" when the filesystem keeps none,
+// padded to the same width
+struct Time(u64);
+
+impl core::fmt::Display for Time
+{
+    fn fmt(&self, f:&mut core::fmt::Formatter) -> core::fmt::Result
+    {
+        if self.0 == 0
         {
-            self.dir_tree(self.dirs.dirs[self.curr_dir]);
+            return write!(f, "{:16}", "-");
         }
-        else if compare("remove_dir", argument.0) 
+        return write!(f, "{}", rtc::DateTime::from_unix(self.0));
+    }
+}
+
+// "/" after a directory's name
+fn marker(kind:FileType) -> &'static str
+{
+    match kind
+    {
+        FileType::Directory => "/",
+        FileType::File => "",
+    }
+}
+
+// Prints what `ls` shows for `inode`: the entries of a directory, or the
+// file alone. The directories among them go to `subdirs` in the order they
+// were shown, and their count is returned.
+fn list_entries(inode:Inode, path:&str, options:Options, subdirs:&mut [Inode; MAX_LISTED]) -> usize
+{
+    let filler = Listed { entry: DirEntry::new(b"", inode), metadata: Metadata::new(inode, 0) };
+    let mut listed = [filler; MAX_LISTED];
+    let mut count = 0;
+    let mut left_out = 0;
+    let mut add = |name:&[u8], inode:Inode| {
+        match vfs::stat(inode)
         {
-            self.remove_dir(argument.1);
+            Ok(metadata) if count < MAX_LISTED => {
+                listed[count] = Listed { entry: DirEntry::new(name, inode), metadata };
+                count += 1;
+            }
+            Ok(_) => left_out += 1,
+            Err(error) => print_fs_error(error, core::str::from_utf8(name).unwrap_or("?")),
         }
-        else if compare("clear", argument.0) 
+    };
+    if inode.kind == FileType::File
+    {
+        add(path.as_bytes(), inode);
+    }
+    else
+    {
+        if options.has('a')
         {
-            self.clear();
+            add(b".", inode);
+            if let Ok(parent) = vfs::lookup(inode, "..")
+            {
+                add(b"..", parent);
+            }
         }
-        else if compare("make_file", argument.0)
+        let mut index = 0;
+        loop
         {
-            self.make_file(argument.1);
+            match vfs::readdir(inode, index)
+            {
+                Ok(Some(entry)) => {
+                    if options.has('a') || !entry.name().starts_with('.')
+                    {
+                        add(entry.name().as_bytes(), entry.inode);
+                    }
+                }
+                Ok(None) => break,
+                Err(error) => {
+                    print_fs_error(error, path);
+                    return 0;
+                }
+            }
+            index += 1;
         }
-        else if compare("remove_file", argument.0)
+    }
+
+    // "." and ".." stay in front whatever the order
+    let dots = listed[..count].iter().take_while(|item| matches!(item.entry.name(), "." | "..")).count();
+    let sorted = &mut listed[dots..count];
+    sorted.sort_unstable_by(|a, b| {
+        let by_name = a.entry.name().cmp(b.entry.name());
+        let order = if options.has('t')
         {
-            self.remove_file(argument.1);
+            b.metadata.modified.cmp(&a.metadata.modified).then(by_name)
         }
-        else if compare("dump_file", argument.0)
+        else if options.has('S')
         {
-            self.dump_file(argument.1);
+            b.metadata.size.cmp(&a.metadata.size).then(by_name)
         }
-        else if compare("edit_file", argument.0)
+        else
         {
-            self.edit_file(argument.1)
-        }
-        else 
+            by_name
+        };
+        return if options.has('r') { order.reverse() } else { order };
+    });
+
+    let listed = &listed[..count];
+    if options.has('l')
+    {
+        for item in listed
         {
-            print_command_not_found(argument.0);
+            let metadata = &item.metadata;
+            let mode = mode_text(metadata.inode.kind, metadata.mode);
+            outln!("{} {:>5} {:>8} {} {}{}", core::str::from_utf8(&mode).unwrap_or("?"), metadata.owner,
+                metadata.size, Time(metadata.modified), item.entry.name(), marker(metadata.inode.kind));
         }
     }
+    else
+    {
+        print_columns(listed);
+    }
+    if left_out > 0
+    {
+        outln!("... {} more not shown", left_out);
+    }
 
-    fn cur_dir(&mut self, curr_dir:Dir) -> usize{
-        let mut tree = 0;
-        if curr_dir.index > 0{
-            tree = self.cur_dir(self.dirs.dirs[curr_dir.parent_index]);
-        }
-        else{
-            println!();
-        }
-        print!("/{}", core::str::from_utf8(&curr_dir.name.clone()).unwrap().trim_matches('\0'));
-        return tree;
+    let mut subdirs_count = 0;
+    for item in listed[dots..].iter().filter(|item| item.entry.inode.kind == FileType::Directory)
+    {
+        subdirs[subdirs_count] = item.entry.inode;
+        subdirs_count += 1;
     }
+    return subdirs_count;
+}
 
-    fn make_dir(&mut self, argument:[u8; ARGUMENT_LENGTH])
+// Names in as many columns as fit the screen, going down each column first.
+// A pipe gets one name per line, which is easier to read for a program.
+fn print_columns(listed:&[Listed])
+{
+    if listed.is_empty()
+    {
+        return;
+    }
+    let width = |item:&Listed| item.entry.name().len() + marker(item.entry.inode.kind).len();
+    let mut widths = [0usize; MAX_LISTED];
+    let mut columns = if process::stdio().output == Output::Screen { listed.len() } else { 1 };
+    let mut rows = 1;
+    while columns > 0
     {
-        let mut name_size = 0;
-        for i in 0..ARGUMENT_LENGTH
+        rows = listed.len().div_ceil(columns);
+        columns = listed.len().div_ceil(rows);
+        for column in 0..columns
         {
-            if argument[i] == b'\0'
+            let end = listed.len().min((column + 1) * rows);
+            widths[column] = listed[column * rows..end].iter().map(width).max().unwrap_or(0);
+        }
+        // Two spaces between columns, and the last one must not reach the
+        // edge or the screen starts a line of its own
+        let total = widths[..columns].iter().sum::<usize>() + 2 * (columns - 1);
+        if total < BUF_WIDTH as usize || columns == 1
+        {
+            break;
+        }
+        columns -= 1;
+    }
+
+    for row in 0..rows
+    {
+        for index in (row..listed.len()).step_by(rows)
+        {
+            let item = &listed[index];
+            let name = item.entry.name();
+            let kind = marker(item.entry.inode.kind);
+            if index + rows >= listed.len()
+            {
+                out!("{}{}", name, kind);
+            }
+            else
+            {
+                out!("{}{}{:3$}", name, kind, "", widths[index / rows] + 2 - name.len() - kind.len());
+            }
+        }
+        outln!();
+    }
+}
+
+// ls -R: the directory in `path`, then every directory below it, each after
+// a line with its path
+fn list_tree(dir:Inode, path:&mut [u8; MAX_PATH_LENGTH], length:usize, options:Options, depth:usize)
+{
+    let name = core::str::from_utf8(&path[..length]).unwrap_or("?");
+    if depth > 0
+    {
+        outln!();
+    }
+    outln!("{}:", name);
+    let mut subdirs = [dir; MAX_LISTED];
+    let count = list_entries(dir, name, options, &mut subdirs);
+    for &subdir in &subdirs[..count]
+    {
+        if depth + 1 >= MAX_LIST_DEPTH
+        {
+            outln!("... deeper directories not shown");
+            return;
+        }
+        let entry = match vfs::find_entry(dir, subdir)
+        {
+            Ok(entry) => entry,
+            Err(error) => {
+                print_fs_error(error, core::str::from_utf8(&path[..length]).unwrap_or("?"));
+                continue;
+            }
+        };
+        let child = entry.name().as_bytes();
+        let slash = if path[..length].ends_with(b"/") { 0 } else { 1 };
+        let end = length + slash + child.len();
+        if end > MAX_PATH_LENGTH
+        {
+            print_fs_error(FsError::NameTooLong, entry.name());
+            continue;
+        }
+        if slash == 1
+        {
+            path[length] = b'/';
+        }
+        path[length + slash..end].copy_from_slice(child);
+        list_tree(subdir, path, end, options, depth + 1);
+    }
+}
+
+// Prints `path` when its name matches `name` and it is of the type `kind`,
+// then does the same for everything below it
+fn find_tree(inode:Inode, path:&mut [u8; MAX_PATH_LENGTH], length:usize, name:Option<&str>, kind:Option<FileType>,
+             depth:usize)
+{
+    let own = &path[..length];
+    let trimmed = if own.len() > 1 { own.strip_suffix(b"/").unwrap_or(own) } else { own };
+    let base = &trimmed[trimmed.iter().rposition(|&byte| byte == b'/').map_or(0, |slash| slash + 1)..];
+    if name.map_or(true, |name| glob::matches(name.as_bytes(), base)) && kind.map_or(true, |kind| kind == inode.kind)
+    {
+        outln!("{}", core::str::from_utf8(own).unwrap_or("?"));
+    }
+    if inode.kind != FileType::Directory
+    {
+        return;
+    }
+    if depth + 1 >= MAX_FIND_DEPTH
+    {
+        print!("\n[Error] \"{}\": deeper directories left out", core::str::from_utf8(own).unwrap_or("?"));
+        return;
+    }
+
+    let mut index = 0;
+    loop
+    {
+        let entry = match vfs::readdir(inode, index)
+        {
+            Ok(Some(entry)) => entry,
+            Ok(None) => return,
+            Err(error) => {
+                print_fs_error(error, core::str::from_utf8(&path[..length]).unwrap_or("?"));
+                return;
+            }
+        };
+        index += 1;
+        if matches!(entry.name(), "." | "..")
+        {
+            continue;
+        }
+        let child = entry.name().as_bytes();
+        let slash = if path[..length].ends_with(b"/") { 0 } else { 1 };
+        let end = length + slash + child.len();
+        if end > MAX_PATH_LENGTH
+        {
+            print_fs_error(FsError::NameTooLong, entry.name());
+            continue;
+        }
+        if slash == 1
+        {
+            path[length] = b'/';
+        }
+        path[length + slash..end].copy_from_slice(child);
+        find_tree(entry.inode, path, end, name, kind, depth + 1);
+    }
+}
+
+// Where the first word of `line` with a pattern is; options have none
+fn find_pattern(line:&str) -> Option<(usize, usize)>
+{
+    let mut start = 0;
+    for word in line.split(' ')
+    {
+        if !word.starts_with('-') && unquote(word).is_none() && glob::is_pattern(word)
+        {
+            return Some((start, start + word.len()));
+        }
+        start += word.len() + 1;
+    }
+    return None;
+}
+
+// A word in '' or "" is taken as it is, with no patterns in it
+fn unquote(word:&str) -> Option<&str>
+{
+    for quote in ['\'', '"']
+    {
+        if word.len() >= 2 && word.starts_with(quote) && word.ends_with(quote)
+        {
+            return Some(&word[1..word.len() - 1]);
+        }
+    }
+    return None;
+}
+
+// "%2" and "2" both name job 2, nothing means the most recent job
+fn parse_job_number(argument:[u8; ARGUMENT_LENGTH]) -> Option<usize>
+{
+    let text = core::str::from_utf8(&argument).unwrap_or("").trim_matches('\0').trim();
+    let text = text.strip_prefix('%').unwrap_or(text);
+    text.parse::<usize>().ok()
+}
+
+// The program a pipeline stage runs and its argument line; `run` is optional
+fn stage_program(stage:&[u8]) -> (&str, &str)
+{
+    let mut line = core::str::from_utf8(stage).unwrap_or("");
+    if let Some(rest) = line.strip_prefix("run ")
+    {
+        line = rest.trim_start();
+    }
+    let name = line.split(' ').next().unwrap_or("");
+    return (name, line);
+}
+
+// Keeps the mounted disk up to date, after every command
+fn sync_disk()
+{
+    if let Err(error) = vfs::sync()
+    {
+        print!("\n[Error] Saving to the disk: {}", error.message());
+    }
+}
+
+// The files built into the kernel, on /initrd
+fn mount_initrd()
+{
+    if let Err(error) = initrd::mount()
+    {
+        print!("\n[Error] Mounting /{}: {}", initrd::MOUNT_POINT, error.message());
+    }
+}
+
+// "ata1" names drive 1
+fn parse_drive(text:&str) -> Option<ata::Drive>
+{
+    text.strip_prefix("ata")?.parse::<usize>().ok().and_then(ata::drive)
+}
+
+fn print_start()
+{
+    print!(" $ ");
+}
+
+fn print_command_not_found(cmd:[u8; COMMAND_LENGTH])
+{
+    println!();
+    print!("[error] Command \'{}\' is not supported", core::str::from_utf8(&cmd).unwrap().trim_matches('\0'));
+}
+
+struct Shell 
+{
+    buf:[u8; 80],
+    buf_len:usize,
+    cwd:Inode,
+    is_editing_file:bool,
+    current_editing_file:DirEntry,
+    // lines typed into the file so far
+    editing_lines:usize,
+    jobs:[Job; MAX_JOBS],
+}
+
+impl Shell 
+{
+    pub fn new() -> Shell 
+    {
+        let shell:Shell = Shell 
+        {
+            buf: [0; 80],
+            buf_len: 0,
+            cwd: vfs::root(),
+            is_editing_file: false,
+            current_editing_file: DirEntry::new(b"", vfs::root()),
+            editing_lines: 0,
+            jobs: [FREE_JOB; MAX_JOBS],
+        };
+
+        return shell;
+    }
+
+    pub fn on_key_pressed(&mut self, key: u8) 
+    {
+        match key 
+        {
+            b'\n' => {
+                if self.is_editing_file
+                {
+                    self.editing_lines += 1;
+                    println!();
+                    return;
+                }
+
+                let background = take_background_flag(&mut self.buf, &mut self.buf_len);
+                match take_redirect(&mut self.buf, &mut self.buf_len)
+                {
+                    Some((path, length, append)) => {
+                        let path = core::str::from_utf8(&path[..length]).unwrap_or("");
+                        self.run_redirected(path, append, background);
+                    }
+                    None => self.run_line(background),
+                }
+                self.buf = [0; 80];
+                self.buf_len = 0;
+                self.update_jobs();
+                process::reap_zombie_children(process::current_pid());
+                sync_disk();
+
+                if self.is_editing_file
+                {
+                    return;
+                }
+
+                // Output written to stdout already ends its last line
+                if SCREEN.lock().col != 0
+                {
+                    println!();
+                }
+                print_start();
+            }
+            8 => { // backspace
+                if self.is_editing_file
+                {
+                    SCREEN.lock().delete_last_char(0);
+                    return;
+                }
+                SCREEN.lock().delete_last_char(3);
+                if self.buf_len > 0
+                {
+                    self.buf_len -= 1;
+                }
+                self.buf[self.buf_len] = 0;
+            }
+            32 => { // space
+                print!("{}", key as char);
+
+                if self.is_editing_file
+                {
+                    return;
+                }
+
+                self.buf[self.buf_len] = b' ';
+                self.buf_len += 1;
+            }
+            keyboard::CTRL_Z => {
+                if self.is_editing_file
+                {
+                    self.suspend_editor();
+                }
+            }
+            keyboard::CTRL_C => { // drops the line typed so far
+                if self.is_editing_file
+                {
+                    return;
+                }
+                print!("^C\n");
+                self.buf = [0; 80];
+                self.buf_len = 0;
+                print_start();
+            }
+            1..=31 => {} // other control keys
+            96 => { // `
+                if self.is_editing_file
+                {
+                    self.is_editing_file = false;
+                    self.editing_lines += 1;
+                    let saved = self.save_editor();
+                    self.clear();
+
+                    match saved
+                    {
+                        Ok(()) => print!("\n[ok] File \"{}\" saved succesfully!\n", self.current_editing_file.name()),
+                        Err(error) => print_fs_error(error, self.current_editing_file.name()),
+                    }
+                    sync_disk();
+                    print_start();
+                }
+            }
+            _ => {
+                if self.is_editing_file
+                {
+                    print!("{}", key as char);
+                    return;
+                }
+
+                self.buf[self.buf_len] = key;
+                self.buf_len += 1;
+                print!("{}", key as char);
+            }
+        }
+    }
+
+    // Runs a command once for every path the first pattern of its argument
+    // matches, as a command takes one path at a time; later patterns are
+    // expanded the same way for each of them. Programs get all the paths in
+    // their argument line instead, which can be longer than a command's.
+    fn run_line(&mut self, background:bool)
+    {
+        if self.buf[..self.buf_len].contains(&b'|')
+        {
+            if background
+            {
+                print!("\n[Error] A pipeline can not run in the background");
+            }
+            else
+            {
+                self.run_pipeline();
+            }
+        }
+        else
+        {
+            let argument = split(self.buf, self.buf_len);
+            self.run_expanded(argument, background);
+        }
+    }
+
+    // Runs the line with its standard output going to `path`
+    fn run_redirected(&mut self, path:&str, append:bool, background:bool)
+    {
+        if path.is_empty()
+        {
+            print!("\n[Error] No file to redirect the output to");
+            return;
+        }
+        let file = match self.open_output(path, append)
+        {
+            Ok(file) => file,
+            Err(error) => {
+                print_fs_error(error, path);
+                return;
+            }
+        };
+        let input = process::stdio().input;
+        let old = process::replace_stdio(Stdio { input, output: Output::File(file) });
+        self.run_line(background);
+        process::replace_stdio(old);
+    }
+
+    // The file `>` and `>>` write to, made when missing. `>` empties it and
+    // `>>` writes after its last byte.
+    fn open_output(&self, path:&str, append:bool) -> Result<OpenFile, FsError>
+    {
+        let (dir, name) = self.resolve_parent(path)?;
+        let inode = match vfs::lookup(dir, name)
+        {
+            Ok(inode) if inode.kind == FileType::Directory => return Err(FsError::IsADirectory),
+            Ok(inode) => inode,
+            Err(FsError::NotFound) => vfs::create(dir, name)?,
+            Err(error) => return Err(error),
+        };
+        let mut file = OpenFile::open(inode);
+        if append
+        {
+            file.seek(vfs::stat(inode)?.size);
+        }
+        else
+        {
+            vfs::truncate(inode, 0)?;
+        }
+        return Ok(file);
+    }
+
+    fn run_expanded(&mut self, argument:([u8; COMMAND_LENGTH], [u8; ARGUMENT_LENGTH]), background:bool)
+    {
+        let text = argument_text(&argument.1);
+        let run = compare("run", argument.0);
+        if let (false, Some(program)) = (run, program_command(&argument.0))
+        {
+            // "grep x f" is "run grep x f"
+            let mut words = [b'\0'; COMMAND_LENGTH + 1 + ARGUMENT_LENGTH];
+            let name = program.name.as_bytes();
+            words[..name.len()].copy_from_slice(name);
+            words[name.len()] = b' ';
+            words[name.len() + 1..name.len() + 1 + text.len()].copy_from_slice(text.as_bytes());
+            let words = core::str::from_utf8(&words[..name.len() + 1 + text.len()]).unwrap_or("");
+            let mut line = [b'\0'; process::ARGS_LENGTH];
+            if let Some(length) = self.expand_line(words, &mut line)
+            {
+                self.run_program(core::str::from_utf8(&line[..length]).unwrap_or(""), background);
+            }
+            return;
+        }
+        if run
+        {
+            let mut line = [b'\0'; process::ARGS_LENGTH];
+            if let Some(length) = self.expand_line(text, &mut line)
+            {
+                self.run(core::str::from_utf8(&line[..length]).unwrap_or(""), background);
+            }
+            return;
+        }
+        let (start, end) = match find_pattern(text)
+        {
+            Some(range) => range,
+            None => {
+                self.command_distributor(argument, background);
+                return;
+            }
+        };
+
+        let mut arguments = [[b'\0'; ARGUMENT_LENGTH]; glob::MAX_MATCHES];
+        let mut count = 0;
+        let mut too_many = false;
+        let mut too_long = false;
+        let expanded = self.expand(&text[start..end], &mut |path| {
+            let (before, after) = (&text.as_bytes()[..start], &text.as_bytes()[end..]);
+            let length = before.len() + path.len() + after.len();
+            if count == glob::MAX_MATCHES
+            {
+                too_many = true;
+            }
+            else if length > ARGUMENT_LENGTH
+            {
+                too_long = true;
+            }
+            else
+            {
+                let new = &mut arguments[count];
+                new[..before.len()].copy_from_slice(before);
+                new[before.len()..before.len() + path.len()].copy_from_slice(path.as_bytes());
+                new[before.len() + path.len()..length].copy_from_slice(after);
+                count += 1;
+            }
+        });
+        match expanded
+        {
+            Ok((0, _)) => print!("\n[Error] No match for \"{}\"", &text[start..end]),
+            Ok(_) if too_long => print_fs_error(FsError::NameTooLong, &text[start..end]),
+            Ok((_, cut)) => {
+                for new in &arguments[..count]
+                {
+                    self.run_expanded((argument.0, *new), background);
+                }
+                // The command still ran for the paths that were taken
+                if cut || too_many
+                {
+                    print_cut(&text[start..end]);
+                }
+            }
+            Err(error) => print_fs_error(error, &text[start..end]),
+        }
+    }
+
+    // Calls `found` with every path the pattern `word` matches: from the
+    // root after "/" or "~/", from the current directory otherwise
+    fn expand(&self, word:&str, found:&mut dyn FnMut(&str)) -> Result<(usize, bool), FsError>
+    {
+        if let Some(rest) = word.strip_prefix("~/")
+        {
+            return glob::expand(vfs::root(), "~/", rest.trim_start_matches('/'), found);
+        }
+        if word.starts_with('/')
+        {
+            return glob::expand(vfs::root(), "/", word.trim_start_matches('/'), found);
+        }
+        return glob::expand(self.cwd, "", word, found);
+    }
+
+    // Puts the paths every pattern of `line` matches in its place, for a
+    // program. `line` and `out` start with the program's name, so the words
+    // are its argv. Says what is wrong and returns None when that fails.
+    fn expand_line(&self, line:&str, out:&mut [u8; process::ARGS_LENGTH]) -> Option<usize>
+    {
+        let mut length = 0;
+        let mut words = 0;
+        let mut fits = true;
+        let mut cut = None;
+        for word in line.split(' ').filter(|word| !word.is_empty())
+        {
+            let mut put = |text:&str| {
+                let start = if length > 0 { length + 1 } else { 0 };
+                words += 1;
+                if start + text.len() > out.len() || words > process::MAX_ARGS
+                {
+                    fits = false;
+                    return;
+                }
+                if length > 0
+                {
+                    out[length] = b' ';
+                }
+                out[start..start + text.len()].copy_from_slice(text.as_bytes());
+                length = start + text.len();
+            };
+            if let Some(text) = unquote(word)
+            {
+                put(text);
+                continue;
+            }
+            if word.starts_with('-') || !glob::is_pattern(word)
+            {
+                put(word);
+                continue;
+            }
+            match self.expand(word, &mut put)
+            {
+                Ok((0, _)) => {
+                    print!("\n[Error] No match for \"{}\"", word);
+                    return None;
+                }
+                Ok((_, true)) => cut = Some(word),
+                Ok(_) => {}
+                Err(error) => {
+                    print_fs_error(error, word);
+                    return None;
+                }
+            }
+        }
+        if !fits
+        {
+            print!("\n[Error] A program takes at most {} arguments in {} characters", process::MAX_ARGS, out.len());
+            return None;
+        }
+        if let Some(word) = cut
+        {
+            print_cut(word);
+        }
+        return Some(length);
+    }
+
+    fn command_distributor(&mut self, argument:([u8; COMMAND_LENGTH], [u8;ARGUMENT_LENGTH]), background:bool)
+    {
+        if background && !compare("life", argument.0) && !compare("run", argument.0) && program_command(&argument.0).is_none()
+        {
+            print!("\n[Error] \'{}\' can not run in the background", core::str::from_utf8(&argument.0).unwrap().trim_matches('\0'));
+        }
+        else if compare("cur_dir", argument.0) || compare("pwd", argument.0)
+        {
+            self.cur_dir();
+        }
+        else if compare("make_dir", argument.0) 
+        {
+            self.make_dir(argument.1);
+        }
+        else if compare("change_dir", argument.0) 
+        {
+            self.change_dir(argument.1);
+        }
+        else if compare("dir_tree", argument.0) 
+        {
+            self.dir_tree(argument.1);
+        }
+        else if compare("ls", argument.0)
+        {
+            self.ls(argument.1);
+        }
+        else if compare("stat", argument.0)
+        {
+            self.stat(argument.1);
+        }
+        else if compare("find", argument.0)
+        {
+            self.find(argument.1);
+        }
+        else if compare("remove_dir", argument.0) 
+        {
+            self.remove(argument.1, true);
+        }
+        else if compare("rm", argument.0)
+        {
+            self.remove(argument.1, false);
+        }
+        else if compare("clear", argument.0) 
+        {
+            self.clear();
+        }
+        else if compare("make_file", argument.0)
+        {
+            self.make_file(argument.1);
+        }
+        else if compare("remove_file", argument.0)
+        {
+            self.remove_file(argument.1);
+        }
+        else if compare("mv", argument.0)
+        {
+            self.move_or_copy(argument.1, false);
+        }
+        else if compare("cp", argument.0)
+        {
+            self.move_or_copy(argument.1, true);
+        }
+        else if compare("dump_file", argument.0)
+        {
+            self.dump_file(argument.1);
+        }
+        else if compare("edit_file", argument.0)
+        {
+            self.edit_file(argument.1)
+        }
+        else if compare("life", argument.0)
+        {
+            self.life(argument.1);
+        }
+        else if compare("ps", argument.0)
+        {
+            self.ps();
+        }
+        else if compare("pstree", argument.0)
+        {
+            self.pstree();
+        }
+        else if compare("run", argument.0)
+        {
+            self.run(argument_text(&argument.1), background);
+        }
+        else if compare("jobs", argument.0)
+        {
+            self.jobs();
+        }
+        else if compare("fg", argument.0)
+        {
+            self.fg(argument.1);
+        }
+        else if compare("kill", argument.0)
+        {
+            self.kill(argument.1);
+        }
+        else if compare("mount", argument.0)
+        {
+            self.mount(argument.1);
+        }
+        else if compare("umount", argument.0)
+        {
+            self.umount(argument.1);
+        }
+        else if compare("sync", argument.0)
+        {
+            sync_disk();
+        }
+        else if compare("mkfs", argument.0)
+        {
+            self.mkfs(argument.1);
+        }
+        else if compare("fsck", argument.0)
+        {
+            self.fsck(argument.1);
+        }
+        else if let Some(program) = program_command(&argument.0)
+        {
+            // "grep x f" is "run grep x f"
+            let mut line = [b'\0'; COMMAND_LENGTH + 1 + ARGUMENT_LENGTH];
+            let name = program.name.as_bytes();
+            let arguments = argument_text(&argument.1).as_bytes();
+            line[..name.len()].copy_from_slice(name);
+            line[name.len()] = b' ';
+            line[name.len() + 1..name.len() + 1 + arguments.len()].copy_from_slice(arguments);
+            let length = name.len() + 1 + arguments.len();
+            self.run_program(core::str::from_utf8(&line[..length]).unwrap_or("").trim(), background);
+        }
+        else 
+        {
+            print_command_not_found(argument.0);
+        }
+    }
+
+    fn cur_dir(&mut self)
+    {
+        println!();
+        let mut path = [0u8; MAX_PATH_LENGTH];
+        match vfs::path_of(self.cwd, &mut path)
+        {
+            Ok(length) => outln!("{}", core::str::from_utf8(&path[..length]).unwrap_or("?")),
+            Err(error) => print!("[Error] {}", error.message()),
+        }
+    }
+
+    // `~` and `~/...` start at the home directory, which is the root
+    fn resolve(&self, path:&str) -> Result<Inode, FsError>
+    {
+        let (start, rest) = self.start_of(path);
+        vfs::resolve(start, rest)
+    }
+
+    fn resolve_parent<'a>(&self, path:&'a str) -> Result<(Inode, &'a str), FsError>
+    {
+        let (start, rest) = self.start_of(path);
+        vfs::resolve_parent(start, rest)
+    }
+
+    fn start_of<'a>(&self, path:&'a str) -> (Inode, &'a str)
+    {
+        if path == "~"
+        {
+            return (vfs::root(), "");
+        }
+        match path.strip_prefix("~/")
+        {
+            Some(rest) => (vfs::root(), rest),
+            None => (self.cwd, path),
+        }
+    }
+
+    fn make_dir(&mut self, argument:[u8; ARGUMENT_LENGTH])
+    {
+        let path = argument_text(&argument);
+        if path.is_empty()
+        {
+            print!("\n[Error] Specify a name of folder");
+            return;
+        }
+
+        match self.resolve_parent(path).and_then(|(dir, name)| vfs::mkdir(dir, name))
+        {
+            Ok(_) => print!("\n[ok] Created new dir \'{}\'", path),
+            Err(error) => print_fs_error(error, path),
+        }
+    }
+
+    // With no argument goes to the home directory
+    fn change_dir(&mut self, argument:[u8; ARGUMENT_LENGTH])
+    {
+        let path = argument_text(&argument);
+        let path = if path.is_empty() { "~" } else { path };
+        match self.resolve(path)
+        {
+            Ok(inode) if inode.kind == FileType::Directory => {
+                self.cwd = inode;
+                process::set_cwd(inode);
+                print!("\n[Ok] Directory has changed");
+            }
+            Ok(_) => print_fs_error(FsError::NotADirectory, path),
+            Err(error) => print_fs_error(error, path),
+        }
+    }
+
+    // remove_dir [-r] [-f] <path> and rm [-r] [-f] <path>. -r takes a whole
+    // directory with everything in it after asking, -f does not ask and says
+    // nothing about a path that does not exist.
+    fn remove(&mut self, argument:[u8; ARGUMENT_LENGTH], directories_only:bool)
+    {
+        let (options, path) = match take_options(argument_text(&argument), "rf")
+        {
+            Ok(parsed) => parsed,
+            Err(letter) => {
+                print!("\n[Error] Unknown option -{}", letter);
+                return;
+            }
+        };
+        if path.is_empty()
+        {
+            print!("\n[Error] {}", if directories_only { "Specify a name of folder" } else { "Specify a path" });
+            return;
+        }
+        let force = options.has('f');
+
+        let (dir, name) = match self.resolve_parent(path)
+        {
+            Ok(parent) => parent,
+            Err(FsError::NotFound) if force => return,
+            Err(error) => {
+                print_fs_error(error, path);
+                return;
+            }
+        };
+        let inode = match vfs::lookup(dir, name)
+        {
+            Ok(inode) => inode,
+            Err(FsError::NotFound) if force => return,
+            Err(error) => {
+                print_fs_error(error, path);
+                return;
+            }
+        };
+
+        let removed = match inode.kind
+        {
+            FileType::File if directories_only => Err(FsError::NotADirectory),
+            FileType::File => vfs::unlink(dir, name).map(|_| 1),
+            FileType::Directory if !options.has('r') => {
+                if !directories_only
+                {
+                    Err(FsError::IsADirectory)
+                }
+                else if process::any_cwd_within(inode)
+                {
+                    Err(FsError::Busy)
+                }
+                else
+                {
+                    vfs::rmdir(dir, name).map(|_| 1)
+                }
+            }
+            FileType::Directory => {
+                if process::any_cwd_within(inode)
+                {
+                    Err(FsError::Busy)
+                }
+                else if !force && !confirm(format_args!("Remove \"{}\" and everything in it?", path))
+                {
+                    print!("\n[Ok] Nothing removed");
+                    return;
+                }
+                else
+                {
+                    vfs::remove_tree(dir, name)
+                }
+            }
+        };
+        match removed
+        {
+            Ok(1) if directories_only => print!("\n[Ok] Directory \"{}\" deleted", path),
+            Ok(count) if directories_only => print!("\n[Ok] Directory \"{}\" deleted, {} entries in all", path, count),
+            Ok(_) => {}
+            Err(error) => print_fs_error(error, path),
+        }
+    }
+
+    // Where `mv` and `cp` put an entry called `name`: into `target` when it
+    // is a directory, otherwise at `target` itself
+    fn destination<'a>(&self, name:&'a str, target:&'a str) -> Result<(Inode, &'a str), FsError>
+    {
+        match self.resolve(target)
+        {
+            Ok(inode) if inode.kind == FileType::Directory => Ok((inode, name)),
+            Ok(_) | Err(FsError::NotFound) => self.resolve_parent(target),
+            Err(error) => Err(error),
+        }
+    }
+
+    // mv <from> <to> and cp [-r] <from> <to>
+    fn move_or_copy(&mut self, argument:[u8; ARGUMENT_LENGTH], copy:bool)
+    {
+        let (options, paths) = match take_options(argument_text(&argument), if copy { "r" } else { "" })
+        {
+            Ok(parsed) => parsed,
+            Err(letter) => {
+                print!("\n[Error] Unknown option -{}", letter);
+                return;
+            }
+        };
+        let mut words = paths.split(' ').filter(|word| !word.is_empty());
+        let (from, to) = match (words.next(), words.next(), words.next())
+        {
+            (Some(from), Some(to), None) => (from, to),
+            _ => {
+                print!("\n[Error] Specify where from and where to");
+                return;
+            }
+        };
+
+        let (dir, name) = match self.resolve_parent(from)
+        {
+            Ok(parent) => parent,
+            Err(error) => {
+                print_fs_error(error, from);
+                return;
+            }
+        };
+        let source = match vfs::lookup(dir, name)
+        {
+            Ok(inode) => inode,
+            Err(error) => {
+                print_fs_error(error, from);
+                return;
+            }
+        };
+        if copy && source.kind == FileType::Directory && !options.has('r')
+        {
+            print_fs_error(FsError::IsADirectory, from);
+            return;
+        }
+        let done = self.destination(name, to).and_then(|(new_dir, new_name)| {
+            if copy
+            {
+                vfs::copy_tree(source, new_dir, new_name).map(|_| ())
+            }
+            else
+            {
+                match vfs::rename(dir, name, new_dir, new_name)
+                {
+                    // Another filesystem gets a copy and this one loses the
+                    // original, which can not be in use, like for rm -r
+                    Err(FsError::CrossDevice) if source.kind == FileType::Directory
+                        && process::any_cwd_within(source) => Err(FsError::Busy),
+                    Err(FsError::CrossDevice) => {
+                        let existed = vfs::lookup(new_dir, new_name).is_ok();
+                        match vfs::copy_tree(source, new_dir, new_name)
+                        {
+                            Ok(_) => vfs::remove_tree(dir, name).map(|_| ()),
+                            // Half a copy is taken away again, the original is whole
+                            Err(error) => {
+                                if !existed
+                                {
+                                    let _ = vfs::remove_tree(new_dir, new_name);
+                                }
+                                Err(error)
+                            }
+                        }
+                    }
+                    moved => moved,
+                }
+            }
+        });
+        if let Err(error) = done
+        {
+            print_fs_error(error, to);
+        }
+    }
+
+    fn dir_tree(&mut self, argument:[u8; ARGUMENT_LENGTH])
+    {
+        let path = argument_text(&argument);
+        let dir = match self.resolve(if path.is_empty() { "." } else { path })
+        {
+            Ok(dir) if dir.kind == FileType::Directory => dir,
+            Ok(_) => {
+                print_fs_error(FsError::NotADirectory, path);
+                return;
+            }
+            Err(error) => {
+                print_fs_error(error, path);
+                return;
+            }
+        };
+
+        println!();
+        let root = vfs::root();
+        match vfs::lookup(dir, "..").and_then(|parent| vfs::find_entry(parent, dir))
+        {
+            Ok(entry) if dir != root => out!("/{}", entry.name()),
+            _ => out!("/"),
+        }
+        self.print_children_dirs(dir, 1);
+    }
+
+    // Directories first, then the files
+    fn print_children_dirs(&mut self, dir:Inode, tab_count:usize)
+    {
+        outln!();
+        let mut index = 0;
+        while let Ok(Some(entry)) = vfs::readdir(dir, index)
+        {
+            index += 1;
+            if entry.inode.kind == FileType::Directory
+            {
+                out!("{:1$}/{2}", "", tab_count * 4, entry.name());
+                self.print_children_dirs(entry.inode, tab_count + 1);
+            }
+        }
+        index = 0;
+        while let Ok(Some(entry)) = vfs::readdir(dir, index)
+        {
+            index += 1;
+            if entry.inode.kind == FileType::File
+            {
+                outln!("{:1$}/{2}.txt", "", tab_count * 4, entry.name());
+            }
+        }
+    }
+
+    // ls [-l] [-a] [-t|-S] [-r] [-R] [path]: -l adds the mode, owner, size
+    // and time of the last change, -a shows ".", ".." and names starting
+    // with a dot, -t sorts the newest first, -S the largest first and -r
+    // turns the order around. Names are sorted otherwise. -R goes on into
+    // every directory below.
+    fn ls(&mut self, argument:[u8; ARGUMENT_LENGTH])
+    {
+        let (options, path) = match take_options(argument_text(&argument), "latSrR")
+        {
+            Ok(parsed) => parsed,
+            Err(letter) => {
+                print!("\n[Error] Unknown option -{}", letter);
+                return;
+            }
+        };
+        let path = if path.is_empty() { "." } else { path };
+        let inode = match self.resolve(path)
+        {
+            Ok(inode) => inode,
+            Err(error) => {
+                print_fs_error(error, path);
+                return;
+            }
+        };
+
+        println!();
+        if options.has('R') && inode.kind == FileType::Directory
+        {
+            let mut buf = [0u8; MAX_PATH_LENGTH];
+            let length = path.len().min(MAX_PATH_LENGTH);
+            buf[..length].copy_from_slice(&path.as_bytes()[..length]);
+            list_tree(inode, &mut buf, length, options, 0);
+        }
+        else
+        {
+            list_entries(inode, path, options, &mut [inode; MAX_LISTED]);
+        }
+    }
+
+    // stat <path>: everything the filesystem keeps about it
+    fn stat(&mut self, argument:[u8; ARGUMENT_LENGTH])
+    {
+        let path = argument_text(&argument);
+        if path.is_empty()
+        {
+            print!("\n[Error] Specify a path");
+            return;
+        }
+        let metadata = match self.resolve(path).and_then(vfs::stat)
+        {
+            Ok(metadata) => metadata,
+            Err(error) => {
+                print_fs_error(error, path);
+                return;
+            }
+        };
+        let (kind, unit) = match metadata.inode.kind
+        {
+            FileType::Directory => ("directory", "entries"),
+            FileType::File => ("file", "bytes"),
+        };
+        let mode = mode_text(metadata.inode.kind, metadata.mode);
+        println!();
+        outln!("    Path: {}", path);
+        outln!("    Type: {}", kind);
+        outln!("    Size: {} {}", metadata.size, unit);
+        outln!("   Inode: {}", metadata.inode.number);
+        outln!("   Owner: {}", metadata.owner);
+        outln!("    Mode: {:04o} ({})", metadata.mode, core::str::from_utf8(&mode).unwrap_or("?"));
+        outln!(" Created: {}", Time(metadata.created));
+        outln!("Modified: {}", Time(metadata.modified));
+    }
+
+    // find [path] [-name pattern] [-type f|d]. The pattern is matched against
+    // names like the shell's, so it should be quoted.
+    fn find(&mut self, argument:[u8; ARGUMENT_LENGTH])
+    {
+        let mut path = None;
+        let mut name = None;
+        let mut kind = None;
+        let mut words = argument_text(&argument).split(' ').filter(|word| !word.is_empty());
+        while let Some(word) = words.next()
+        {
+            match word
+            {
+                "-name" => match words.next()
+                {
+                    Some(pattern) => name = Some(unquote(pattern).unwrap_or(pattern)),
+                    None => {
+                        print!("\n[Error] -name needs a pattern");
+                        return;
+                    }
+                },
+                "-type" => match words.next()
+                {
+                    Some("f") => kind = Some(FileType::File),
+                    Some("d") => kind = Some(FileType::Directory),
+                    _ => {
+                        print!("\n[Error] -type needs f or d");
+                        return;
+                    }
+                },
+                _ if word.starts_with('-') => {
+                    print!("\n[Error] Unknown option \'{}\'", word);
+                    return;
+                }
+                _ if path.is_some() => {
+                    print!("\n[Error] find takes one path");
+                    return;
+                }
+                _ => path = Some(word),
+            }
+        }
+
+        let path = path.unwrap_or(".");
+        let inode = match self.resolve(path)
+        {
+            Ok(inode) => inode,
+            Err(error) => {
+                print_fs_error(error, path);
+                return;
+            }
+        };
+        let mut buffer = [0u8; MAX_PATH_LENGTH];
+        buffer[..path.len()].copy_from_slice(path.as_bytes());
+        println!();
+        find_tree(inode, &mut buffer, path.len(), name, kind, 0);
+    }
+
+    fn clear(&mut self)
+    {
+        SCREEN.lock().clear();
+    }
+
+    fn make_file(&mut self, argument:[u8; ARGUMENT_LENGTH])
+    {
+        let path = argument_text(&argument);
+        let created = self.resolve_parent(path)
+            .and_then(|(dir, name)| vfs::create(dir, name).map(|inode| DirEntry::new(name.as_bytes(), inode)));
+        match created
+        {
+            Ok(file) => self.start_editor(file),
+            Err(error) => print_fs_error(error, path),
+        }
+    }
+
+    fn remove_file(&mut self, argument:[u8; ARGUMENT_LENGTH])
+    {
+        self.clear();
+        let path = argument_text(&argument);
+        if let Err(error) = self.resolve_parent(path).and_then(|(dir, name)| vfs::unlink(dir, name))
+        {
+            print_fs_error(error, path);
+        }
+    }
+
+    fn dump_file(&mut self, argument: [u8; ARGUMENT_LENGTH])
+    {
+        self.clear();
+        let path = argument_text(&argument);
+        let file = match self.find_file(path)
+        {
+            Ok(file) => file,
+            Err(error) => {
+                print_fs_error(error, path);
+                return;
+            }
+        };
+
+        let mut chunk = [0u8; BUF_WIDTH as usize];
+        let mut offset = 0;
+        let mut last = b'\n';
+        while let Ok(count) = vfs::read_at(file, offset, &mut chunk)
+        {
+            if count == 0
+            {
+                break;
+            }
+            offset += count;
+            last = chunk[count - 1];
+            for &byte in &chunk[..count]
+            {
+                out!("{}", byte as char);
+            }
+        }
+        if last != b'\n'
+        {
+            outln!();
+        }
+    }
+
+    fn edit_file(&mut self, argument:[u8; ARGUMENT_LENGTH])
+    {
+        let path = argument_text(&argument);
+        let opened = self.resolve_parent(path)
+            .and_then(|(_, name)| Ok(DirEntry::new(name.as_bytes(), self.find_file(path)?)));
+        match opened
+        {
+            Ok(file) => self.start_editor(file),
+            Err(error) => print_fs_error(error, path),
+        }
+    }
+
+    fn find_file(&self, path:&str) -> Result<Inode, FsError>
+    {
+        let inode = self.resolve(path)?;
+        if inode.kind != FileType::File
+        {
+            return Err(FsError::IsADirectory);
+        }
+        return Ok(inode);
+    }
+
+    fn start_editor(&mut self, file:DirEntry)
+    {
+        self.is_editing_file = true;
+        self.current_editing_file = file;
+        self.editing_lines = 0;
+        self.clear();
+    }
+
+    // Stores the lines typed so far as text, one '\n' after each screen row
+    // without its trailing spaces
+    fn save_editor(&mut self) -> Result<(), FsError>
+    {
+        let screen = SCREEN.lock().get_buffer();
+        let mut file = OpenFile::open(self.current_editing_file.inode);
+        for row in screen.chunks(BUF_WIDTH as usize).take(self.editing_lines)
+        {
+            let length = row.iter().rposition(|&byte| byte != b' ' && byte != b'\0').map_or(0, |i| i + 1);
+            file.write(&row[..length])?;
+            file.write(b"\n")?;
+        }
+        return vfs::truncate(file.inode, file.position());
+    }
+
+    // Lays the text of `file` out on a screen image, a row per line
+    fn screen_of(file:Inode) -> Result<[u8; BUF_SIZE], FsError>
+    {
+        let mut screen = [b' '; BUF_SIZE];
+        let mut chunk = [0u8; BUF_WIDTH as usize];
+        let (mut offset, mut position) = (0, 0);
+        loop
+        {
+            let count = vfs::read_at(file, offset, &mut chunk)?;
+            if count == 0
+            {
+                return Ok(screen);
+            }
+            offset += count;
+            for &byte in &chunk[..count]
+            {
+                if byte == b'\n'
+                {
+                    position += BUF_WIDTH as usize - position % BUF_WIDTH as usize;
+                }
+                else if position < BUF_SIZE
+                {
+                    screen[position] = byte;
+                    position += 1;
+                }
+            }
+        }
+    }
+
+    // The game draws in its corner while the shell goes on, so it is always
+    // a background job, with or without &. fg brings it to the foreground.
+    // `argument` is an optional map file, checked here so a bad one is
+    // reported at the prompt and not in the game's corner.
+    fn life(&mut self, argument:[u8; ARGUMENT_LENGTH])
+    {
+        let name = argument_text(&argument);
+        let mut path = [b'\0'; MAX_PATH_LENGTH];
+        let mut length = 0;
+        if !name.is_empty()
+        {
+            let file = self.resolve_parent(name).and_then(|(dir, file_name)| {
+                let file = vfs::lookup(dir, file_name)?;
+                // The game reads it again by the path from "/", as the file
+                // itself has no ".." to find its name from
+                length = vfs::path_of(dir, &mut path)?;
+                let separator = if length == 1 { "" } else { "/" };
+                let full = length + separator.len() + file_name.len();
+                if full >= process::ARGS_LENGTH
+                {
+                    return Err(FsError::NameTooLong);
+                }
+                path[length..length + separator.len()].copy_from_slice(separator.as_bytes());
+                path[length + separator.len()..full].copy_from_slice(file_name.as_bytes());
+                length = full;
+                return Ok(file);
+            });
+            let file = match file
+            {
+                Ok(file) => file,
+                Err(error) => {
+                    print_fs_error(error, name);
+                    return;
+                }
+            };
+            if let Err(error) = game_of_life::load_map(file)
+            {
+                print!("\n[Error] \"{}\": {}", name, error.message());
+                return;
+            }
+        }
+        let path = core::str::from_utf8(&path[..length]).unwrap_or("");
+        match process::spawn_with_args("life", path, game_of_life::game_of_life)
+        {
+            Some(pid) => self.start_background_job(pid),
+            None => print!("\n[Error] The maximum number of processes"),
+        }
+    }
+
+    fn run(&mut self, line:&str, background:bool)
+    {
+        if line.is_empty()
+        {
+            print!("\n[Error] Specify a program to run");
+            return;
+        }
+        self.run_program(line, background);
+    }
+
+    // Mirrors LAB_1 main_task2.c: fork, exec the program in the child, waitpid.
+    // `line` is the program name and its arguments.
+    fn run_program(&mut self, line:&str, background:bool)
+    {
+        let name = line.split(' ').next().unwrap_or("");
+        println!();
+        if !background
+        {
+            println!("parent {}", process::current_pid());
+        }
+        let pid = match process::spawn_with_args(name, line, process::exec_from_args)
+        {
+            Some(pid) => pid,
+            None => {
+                print!("[Error] The maximum number of processes");
+                return;
+            }
+        };
+
+        if background
+        {
+            self.start_background_job(pid);
+            return;
+        }
+        match self.wait_in_foreground(pid, self.buf)
+        {
+            Some(WaitStatus::Exited(0)) => print!("Success!"),
+            Some(WaitStatus::Exited(status)) => print!("Failed, exit code = {}", status),
+            Some(status @ WaitStatus::Signaled(number)) => print!("Failed, killed by SIG{} ({}), exit code = {}",
+                                                                  signal::name(number), signal::description(number), status.code()),
+            _ => {}
+        }
+    }
+
+    // cmd1 | cmd2 | ...: every program stage runs in its own child process,
+    // its stdout connected to the stdin of the next one through a pipe. A
+    // builtin can only be the first stage and writes into the pipe from here.
+    fn run_pipeline(&mut self)
+    {
+        let mut stages = [[b'\0'; 80]; MAX_PIPELINE_STAGES];
+        let mut lengths = [0; MAX_PIPELINE_STAGES];
+        let mut count = 0;
+        for part in self.buf[..self.buf_len].split(|&byte| byte == b'|')
+        {
+            let start = part.iter().position(|&byte| byte != b' ').unwrap_or(part.len());
+            let end = part.iter().rposition(|&byte| byte != b' ').map_or(start, |i| i + 1);
+            if start == end
+            {
+                print!("\n[Error] Empty command in pipeline");
+                return;
+            }
+            if count == MAX_PIPELINE_STAGES
+            {
+                print!("\n[Error] The maximum number of pipeline stages is {}", MAX_PIPELINE_STAGES);
+                return;
+            }
+            stages[count][..end - start].copy_from_slice(&part[start..end]);
+            lengths[count] = end - start;
+            count += 1;
+        }
+
+        // Program stages with their patterns expanded, which can make them
+        // longer than a typed line
+        let mut expanded = [[b'\0'; process::ARGS_LENGTH]; MAX_PIPELINE_STAGES];
+        let mut expanded_lengths = [0; MAX_PIPELINE_STAGES];
+        for i in 0..count
+        {
+            let (name, _) = stage_program(&stages[i][..lengths[i]]);
+            if BUILTIN_COMMANDS.contains(&name) && name != "run"
+            {
+                if i > 0
+                {
+                    print!("\n[Error] \'{}\' can not read from a pipe", name);
+                    return;
+                }
+            }
+            else if !programs::is_runnable(name)
+            {
+                print!("\n[Error] Command \'{}\' is not supported", name);
+                return;
+            }
+            else
+            {
+                let mut line = [b'\0'; process::ARGS_LENGTH];
+                let stage = core::str::from_utf8(&stages[i][..lengths[i]]).unwrap_or("");
+                // Without "run", the words are the program's argv
+                let stage = stage.strip_prefix("run ").map_or(stage, str::trim_start);
+                match self.expand_line(stage, &mut line)
+                {
+                    Some(length) => {
+                        expanded[i] = line;
+                        expanded_lengths[i] = length;
+                    }
+                    None => return,
+                }
+            }
+        }
+
+        println!();
+        let last_output = process::stdio().output;
+        let mut builtin_output = None;
+        // pid and stage number of every child
+        let mut children = [(0, 0); MAX_PIPELINE_STAGES];
+        let mut spawned = 0;
+        let mut input = Input::Empty;
+        for i in 0..count
+        {
+            let output = if i == count - 1
+            {
+                last_output
+            }
+            else
+            {
+                match pipe::create()
+                {
+                    Some(id) => Output::Pipe(id),
+                    None => {
+                        print!("[Error] The maximum number of pipes");
+                        break;
+                    }
+                }
+            };
+
+            // Empty for the builtin stage
+            let (name, args) = stage_program(&expanded[i][..expanded_lengths[i]]);
+            if programs::is_runnable(name)
+            {
+                match process::spawn_with_stdio(name, args, process::exec_from_args, Stdio { input, output })
+                {
+                    Some(pid) => {
+                        children[spawned] = (pid, i);
+                        spawned += 1;
+                    }
+                    None => print!("[Error] The maximum number of processes"),
+                }
+                // The child holds its own references now
+                if let Output::Pipe(id) = output
+                {
+                    pipe::close(id, End::Write);
+                }
+            }
+            else
             {
-                break;
+                builtin_output = Some(output);
+            }
+            if let Input::Pipe(id) = input
+            {
+                pipe::close(id, End::Read);
             }
-            name_size+=1;
+            input = match output
+            {
+                Output::Pipe(id) => Input::Pipe(id),
+                Output::Screen | Output::File(_) => Input::Empty,
+            };
         }
-
-        if  name_size == 0
+        // Only left over when creating a pipe failed
+        if let Input::Pipe(id) = input
         {
-            print!("\n[Error] Specify a name of folder");
-            return;
+            pipe::close(id, End::Read);
         }
 
-
-        if name_size > MAX_SIZE_DIRECTORY_NAME
+        if let Some(output) = builtin_output
         {
-            print!("\n[Error] The maximum number of characters has been exceeded");
-            return;
+            let old = process::replace_stdio(Stdio { input: Input::Empty, output });
+            self.run_expanded(split(stages[0], lengths[0]), false);
+            process::replace_stdio(old);
+            // End of file for the next stage
+            if let Output::Pipe(id) = output
+            {
+                pipe::close(id, End::Write);
+            }
         }
 
-        if self.get_folder_index_by_name(argument) != CLEAR_MARKER_DIRECTORY
+        for &(pid, stage) in &children[..spawned]
         {
-            print!("\n[Error] Directory \"{}\" already exist", core::str::from_utf8(&argument.clone())
-                .unwrap()
-                .trim_matches('\0'));
-            return;
-        }
-
-        let mut dir_index = CLEAR_MARKER_DIRECTORY;
-        for i in 0..MAX_SIZE_OF_DIRECTORIES{
-            if self.dirs.dirs[i].index == CLEAR_MARKER_DIRECTORY{
-                dir_index = i;
-                break;
+            if let Some(WaitStatus::Signaled(number)) = self.wait_in_foreground(pid, stages[stage])
+            {
+                print_killed(number);
             }
         }
+    }
 
-        if dir_index == CLEAR_MARKER_DIRECTORY{
-            print!("\n[Error] The maximum number of directories");
-            return;
-        }
+    fn add_job(&mut self, kind:JobKind, stopped:bool, command:[u8; 80]) -> Option<usize>
+    {
+        let slot = (0..MAX_JOBS).find(|&i| self.jobs[i].kind == JobKind::Free)?;
+        self.jobs[slot] = Job { kind, stopped, command };
+        return Some(slot + 1);
+    }
 
-        let mut free_index = 0;
-        for i in 0..MAX_SIZE_OF_CHILDREN_DIRECTORIES{
-            if self.dirs.dirs[self.curr_dir].child_indexes[i] == CLEAR_MARKER_DIRECTORY{
-                free_index = i;
-                break;
-            }
-        }
+    fn print_job(&self, number:usize, status:&str)
+    {
+        print!("\n[{}]  {:<10}{}", number, status, self.jobs[number - 1].command());
+    }
 
-        if free_index == CLEAR_MARKER_DIRECTORY{
-            print!("\n[Error] The maximum number of children directories");
-            return;
+    fn start_background_job(&mut self, pid:usize)
+    {
+        match self.add_job(JobKind::Process(pid), false, self.buf)
+        {
+            Some(number) => print!("\n[{}] {}", number, pid),
+            None => print!("\n[Error] The maximum number of jobs, {} runs untracked", pid),
         }
+    }
 
-        let mut directory: Dir = Dir{
-            index: dir_index,
-            name: [b'\0'; MAX_SIZE_DIRECTORY_NAME],
-            parent_index: self.curr_dir,
-            child_count: 0,
-            child_indexes: [CLEAR_MARKER_DIRECTORY; MAX_SIZE_OF_CHILDREN_DIRECTORIES],
-            files_indexes: [CLEAR_MARKER_FILE; MAX_SIZE_FILES_IN_DIRECTORY],
-        };
-        for i in 0..MAX_SIZE_DIRECTORY_NAME{
-            directory.name[i] = argument[i];
+    // Waits for `pid` with the console handed to it. Returns how it ended, or
+    // None when it was stopped with Ctrl-Z and became a job.
+    fn wait_in_foreground(&mut self, pid:usize, command:[u8; 80]) -> Option<WaitStatus>
+    {
+        match process::wait_foreground(pid)
+        {
+            Some(WaitStatus::Stopped) => {
+                match self.add_job(JobKind::Process(pid), true, command)
+                {
+                    Some(number) => self.print_job(number, "Stopped"),
+                    None => print!("\n[Error] The maximum number of jobs, {} stays stopped", pid),
+                }
+                None
+            }
+            Some(status) => Some(status),
+            None => {
+                print!("\n[Error] Lost child process {}", pid);
+                None
+            }
         }
-        self.dirs.dirs[dir_index] = directory;
-        self.dirs.dirs[self.curr_dir].child_indexes[free_index] = dir_index;
-        self.dirs.dirs[self.curr_dir].child_count += 1;
-
-        print!("\n[ok] Created new dir \'{}\'", core::str::from_utf8(&directory.name.clone()).unwrap().trim_matches('\0'));
     }
 
-    fn change_dir(&mut self, argument:[u8; ARGUMENT_LENGTH])
+    // Reports and forgets the background jobs that have finished
+    fn update_jobs(&mut self)
     {
-        if argument[0] == b'.'
+        for i in 0..MAX_JOBS
         {
-            self.curr_dir = self.dirs.dirs[self.curr_dir].parent_index;
-            print!("\n[Ok] Directory has changed");
-            return;
+            let pid = match self.jobs[i].kind
+            {
+                JobKind::Process(pid) => pid,
+                _ => continue,
+            };
+            match process::try_waitpid(pid)
+            {
+                Some(WaitStatus::Exited(0)) => self.print_job(i + 1, "Done"),
+                Some(WaitStatus::Signaled(number)) => self.print_job(i + 1, signal::description(number)),
+                Some(status) => print!("\n[{}]  Exit {:<5}{}", i + 1, status.code(), self.jobs[i].command()),
+                None => continue,
+            }
+            self.jobs[i] = FREE_JOB;
         }
-        let current_dir = self.dirs.dirs[self.curr_dir];
-
-        for dir_index in current_dir.child_indexes{
-
-            let mut is_same = true;
+    }
 
-            if dir_index == CLEAR_MARKER_DIRECTORY
+    fn jobs(&mut self)
+    {
+        println!();
+        for i in 0..MAX_JOBS
+        {
+            if self.jobs[i].kind != JobKind::Free
             {
-                continue;
+                let status = if self.jobs[i].stopped { "Stopped" } else { "Running" };
+                outln!("[{}]  {:<10}{}", i + 1, status, self.jobs[i].command());
             }
+        }
+    }
 
-            for i in 0..ARGUMENT_LENGTH
+    fn fg(&mut self, argument:[u8; ARGUMENT_LENGTH])
+    {
+        let number = match parse_job_number(argument)
+        {
+            Some(number) => number,
+            None => match (1..=MAX_JOBS).rev().find(|&n| self.jobs[n - 1].kind != JobKind::Free)
             {
-                if argument[i] == b'\0'
-                {
-                    break;
-                }
-                if i == MAX_SIZE_DIRECTORY_NAME{
-                    print!("[Error] The maximum number of characters has been exceeded");
+                Some(number) => number,
+                None => {
+                    print!("\n[Error] No current job");
                     return;
                 }
+            },
+        };
+        if number == 0 || number > MAX_JOBS || self.jobs[number - 1].kind == JobKind::Free
+        {
+            print!("\n[Error] No such job");
+            return;
+        }
 
-                if self.dirs.dirs[dir_index].name[i] != argument[i]
+        let job = self.jobs[number - 1];
+        self.jobs[number - 1] = FREE_JOB;
+        match job.kind
+        {
+            JobKind::Process(pid) => {
+                print!("\n{}", job.command());
+                if job.stopped
                 {
-                    is_same = false;
-                    break;
+                    process::resume(pid);
+                }
+                match self.wait_in_foreground(pid, job.command)
+                {
+                    Some(WaitStatus::Signaled(number)) => print_killed(number),
+                    Some(WaitStatus::Exited(status)) if status != 0 => print!("\nExit {}", status),
+                    _ => {}
                 }
             }
-            if is_same
-            {
-                self.curr_dir = self.dirs.dirs[dir_index].index;
-                print!("\n[Ok] Directory has changed");
-                return;
-            }
+            JobKind::Editor(file, line, col) => self.resume_editor(file, line, col),
+            JobKind::Free => {}
         }
-        print!("\nFolder \'{}\' does not exist", core::str::from_utf8(&argument.clone()).unwrap().trim_matches('\0'));
     }
 
-    fn remove_dir(&mut self, dir_name: [u8; ARGUMENT_LENGTH]){
-        let mut is_correct = false;
-
-        for i in 0..MAX_SIZE_DIRECTORY_NAME{
-            if dir_name[i] != b'\0'
+    // kill [-s SIGNAL | -SIGNAL] <pid> | %<job>, SIGTERM by default; kill -l
+    fn kill(&mut self, argument:[u8; ARGUMENT_LENGTH])
+    {
+        let text = core::str::from_utf8(&argument).unwrap_or("").trim_matches('\0').trim();
+        if text == "-l"
+        {
+            println!();
+            for number in signal::numbers()
             {
-                is_correct = true;
-                break;
+                outln!("{:>2}) SIG{}", number, signal::name(number));
             }
-        }
-        if !is_correct{
-            print!("\n[Error] Specify a name of folder");
             return;
         }
 
-        let cur_dir = self.dirs.dirs[self.curr_dir];
-        for i in 0..cur_dir.child_count{
-            let mut is_same = true;
-            let checking_dir = self.dirs.dirs[cur_dir.child_indexes[i]];
-            for j in 0..MAX_SIZE_DIRECTORY_NAME{
-                if checking_dir.name[j] != dir_name[j]{
-                    is_same = false;
-                    break;
+        let mut words = text.split(' ').filter(|word| !word.is_empty());
+        let mut word = words.next().unwrap_or("");
+        let mut number = signal::SIGTERM;
+        if word.starts_with('-')
+        {
+            let name = if word == "-s" { words.next().unwrap_or("") } else { &word[1..] };
+            number = match signal::parse(name)
+            {
+                Some(number) => number,
+                None => {
+                    print!("\n[Error] Unknown signal \"{}\"", name);
+                    return;
                 }
-            }
-            if !is_same{
-                continue;
-            }
-
-            if self.dirs.dirs[checking_dir.index].child_count > 0{
-                print!("[Error] Cannot delete a directory with children");
-                return;
-            }
-            self.dirs.dirs[self.curr_dir].child_count -= 1;
-            self.dirs.dirs[checking_dir.index] = Dir{
-                index: CLEAR_MARKER_DIRECTORY,
-                name: [b' '; MAX_SIZE_DIRECTORY_NAME],
-                parent_index: CLEAR_MARKER_DIRECTORY,
-                child_count: CLEAR_MARKER_DIRECTORY,
-                child_indexes: [CLEAR_MARKER_DIRECTORY; MAX_SIZE_OF_CHILDREN_DIRECTORIES],
-                files_indexes: [CLEAR_MARKER_FILE; MAX_SIZE_FILES_IN_DIRECTORY],
             };
-            self.dirs.dirs[cur_dir.index].child_indexes[i] = CLEAR_MARKER_DIRECTORY;
-            print!("\n[Ok] Directory \"{}\" deleted", core::str::from_utf8(&dir_name.clone())
-                .unwrap().trim_matches('\0'));
-            return;
+            word = words.next().unwrap_or("");
         }
-    }
 
-    fn dir_tree(&mut self, current_directory:Dir)
-    {
-        println!();
-        print!("/{}", core::str::from_utf8(&current_directory.name).unwrap().trim_matches('\0'));
-        self.print_children_dirs(current_directory, 1);
-    }
-
-    fn print_children_dirs(&mut self, current_directory:Dir, tab_count:usize)
-    {
-        println!();
-        for i in 0..MAX_SIZE_OF_CHILDREN_DIRECTORIES
+        let job = if let Some(job_text) = word.strip_prefix('%')
         {
-            if current_directory.child_indexes[i] == CLEAR_MARKER_DIRECTORY
-            {
-                continue;
-            }
-
-            let child_directory = self.dirs.dirs[current_directory.child_indexes[i]];
-            for tabs in 0..tab_count
+            match job_text.parse::<usize>()
             {
-                for tabs in 0..4
-                {
-                    print!(" ");
+                Ok(job_number) if job_number > 0 && job_number <= MAX_JOBS => Some(job_number - 1),
+                _ => {
+                    print!("\n[Error] No such job");
+                    return;
                 }
             }
-            print!("/{}", core::str::from_utf8(&child_directory.name).unwrap().trim_matches('\0'));
-            self.print_children_dirs(child_directory, tab_count + 1);
         }
-        for i in 0..MAX_SIZE_FILES_IN_DIRECTORY
+        else
         {
-            if current_directory.files_indexes[i] != CLEAR_MARKER_FILE
+            None
+        };
+        let pid = match job
+        {
+            Some(slot) => match self.jobs[slot].kind
             {
-                for tabs in 0..tab_count
-                {
-                    for tabs in 0..4{
-
-                        print!(" ");
-                    }
+                JobKind::Process(pid) => pid,
+                JobKind::Editor(..) => {
+                    // the text typed so far was saved when it was suspended
+                    self.jobs[slot] = FREE_JOB;
+                    return;
                 }
-                print!("/{}.txt", core::str::from_utf8(&self.files.files[current_directory.files_indexes[i]].name).unwrap().trim_matches('\0'));
-                println!();
-            }
-        }
-    }
-
-    fn get_folder_index_by_name(&mut self, argv: [u8; ARGUMENT_LENGTH]) -> usize
-    {
-        for i in 0..MAX_SIZE_OF_CHILDREN_DIRECTORIES
-        {
-            let mut is_same = true;
-            let dir_index = self.dirs.dirs[self.curr_dir].child_indexes[i];
-
-            if dir_index == CLEAR_MARKER_DIRECTORY
+                JobKind::Free => {
+                    print!("\n[Error] No such job");
+                    return;
+                }
+            },
+            None => match word.parse::<usize>()
             {
-                continue;
-            }
+                Ok(pid) => pid,
+                Err(_) => {
+                    print!("\n[Error] Usage: kill [-s SIGNAL | -SIGNAL] <pid> | %<job>, kill -l");
+                    return;
+                }
+            },
+        };
 
-            for i in 0..ARGUMENT_LENGTH
+        // It holds its own lock while running this, so it could neither end
+        // nor be continued again
+        if pid == process::current_pid()
+        {
+            print!("\n[Error] Process {} is the shell itself", pid);
+            return;
+        }
+        if !process::kill(pid, number)
+        {
+            print!("\n[Error] Can not send SIG{} to process {}", signal::name(number), pid);
+            return;
+        }
+        // keep `jobs` in step with what the job was told to do
+        for i in 0..MAX_JOBS
+        {
+            if self.jobs[i].kind == JobKind::Process(pid)
             {
-                if argv[i] == b'\0'
+                if number == signal::SIGCONT
                 {
-                    break;
+                    self.jobs[i].stopped = false;
                 }
-
-                if self.dirs.dirs[dir_index].name[i] != argv[i]
+                else if number == signal::SIGSTOP
                 {
-                    is_same = false;
-                    break;
+                    self.jobs[i].stopped = true;
                 }
             }
-
-            if is_same {
-                // return index of child_indeexes array of current folder
-                return i;
-            }
         }
-        return CLEAR_MARKER_DIRECTORY;
     }
 
-
-    fn clear(&mut self)
+    fn suspend_editor(&mut self)
     {
-        SCREEN.lock().clear();
+        let (line, col) = {
+            let screen = SCREEN.lock();
+            (screen.line, screen.col)
+        };
+        let file = self.current_editing_file;
+        self.editing_lines = line as usize + 1;
+        let saved = self.save_editor();
+        self.is_editing_file = false;
+        self.clear();
+        if let Err(error) = saved
+        {
+            print_fs_error(error, file.name());
+        }
+
+        let mut command = [b'\0'; 80];
+        let name = file.name().as_bytes();
+        let prefix = b"edit_file ";
+        command[..prefix.len()].copy_from_slice(prefix);
+        command[prefix.len()..prefix.len() + name.len()].copy_from_slice(name);
+        match self.add_job(JobKind::Editor(file, line, col), true, command)
+        {
+            Some(number) => self.print_job(number, "Stopped"),
+            None => print!("\n[Error] The maximum number of jobs, the file was saved"),
+        }
+        println!();
+        print_start();
     }
 
-    fn make_file(&mut self, argument:[u8; ARGUMENT_LENGTH])
+    fn resume_editor(&mut self, file:DirEntry, line:u32, col:u32)
     {
-        let mut name_length = 0;
-        let mut name = [b'\0'; MAX_SIZE_DIRECTORY_NAME];
-
-        for i in 0..ARGUMENT_LENGTH
+        let screen = match Shell::screen_of(file.inode)
         {
-            if argument[i] == b'\0'
-            {
-                break;
+            Ok(screen) => screen,
+            Err(_) => {
+                print!("\n[Error] The file was removed");
+                return;
             }
-            name[i] = argument[i];
-            name_length += 1;
-        }
-        if name_length > MAX_SIZE_DIRECTORY_NAME
+        };
+        self.is_editing_file = true;
+        self.current_editing_file = file;
+        self.editing_lines = line as usize;
+        SCREEN.lock().restore(&screen, line, col);
+    }
+
+    // mount lists the filesystems, mount ataN loads the root one from a
+    // disk and mount [-t fat|ext2] ataN <dir> puts a disk's files on a directory
+    fn mount(&mut self, argument:[u8; ARGUMENT_LENGTH])
+    {
+        let mut words = argument_text(&argument).split(' ').filter(|word| !word.is_empty());
+        let mut name = words.next();
+        let mut kind = None;
+        if name == Some("-t")
         {
-            print!("\n[Error] The maximum number of characters has been exceeded");
-            return;
+            kind = words.next();
+            name = words.next();
         }
-
-        let mut file_index = CLEAR_MARKER_FILE;
-
-        for i in 0..MAX_SIZE_FILES_IN_DIRECTORY
+        let (name, point) = match (name, words.next(), words.next())
         {
-            if self.files.files[i].index == CLEAR_MARKER_FILE
-            {
-                file_index = i;
-                break;
+            (None, _, _) if kind.is_none() => {
+                self.list_mounts();
+                return;
             }
-        }
-        if file_index == CLEAR_MARKER_FILE
+            (Some(name), point, None) if kind.is_none() || point.is_some() => (name, point),
+            _ => {
+                print!("\n[Error] Usage: mount [-t fat|ext2] ataN [directory]");
+                return;
+            }
+        };
+        let drive = match parse_drive(name)
         {
-            print!("\n[Error] The maximum number of files in directory");
-        }
+            Some(drive) => drive,
+            None => {
+                print!("\n[Error] No disk \"{}\"", name);
+                return;
+            }
+        };
 
-        for i in 0..MAX_SIZE_FILES
+        if let Some(point) = point
         {
-            if self.files.files[i].index == CLEAR_MARKER_FILE
+            let mounted = self.resolve(point).and_then(|dir| vfs::mount_at(dir, Mounted::open(kind, drive)?));
+            match mounted
             {
-                file_index = i;
-                break;
+                Ok(_) => print!("\n[Ok] {} mounted on {}", name, point),
+                Err(FsError::Busy) => print!("\n[Error] {} or {} is already mounted", name, point),
+                Err(error) => print_fs_error(error, name),
             }
-        }
-        if file_index == CLEAR_MARKER_FILE{
-            print!("\n[Error] The maximum number of files");
+            return;
         }
 
-        let mut file = File
+        // The files so far go to the disk they came from first
+        sync_disk();
+        let mounted = vfs::mount(drive);
+        match mounted
         {
-            index: file_index,
-            name: name,
-            count_lines: 0,
-            folder_index: self.curr_dir,
-            context: [b' '; BUF_SIZE],
-        };
-        self.is_editing_file = true;
-        self.current_editing_file = file_index;
-        SCREEN.lock().clear();
-
-        self.files.files[file_index] = file;
-
-        let mut index_for_folder = CLEAR_MARKER_FILE;
-        for i in 0..MAX_SIZE_OF_CHILDREN_DIRECTORIES{
-            if self.dirs.dirs[self.curr_dir].files_indexes[i] == CLEAR_MARKER_FILE{
-                index_for_folder = i;
-                break;
+            Ok(()) => print!("\n[Ok] Files loaded from {}", name),
+            Err(FsError::Busy) => {
+                print!("\n[Error] {} is mounted", name);
+                return;
             }
+            Err(error) => print_fs_error(error, name),
         }
-
-        self.dirs.dirs[self.curr_dir].files_indexes[index_for_folder] = file_index;
+        // Inodes of the old tree mean nothing in the new one, and what was
+        // mounted on it is gone
+        self.cwd = vfs::root();
+        process::set_cwd(self.cwd);
+        mount_initrd();
     }
 
-    fn remove_file(&mut self, argument:[u8; ARGUMENT_LENGTH])
+    // Lists what is wrong with the tables of the root filesystem, or with
+    // fsck ataN those of a disk that is not mounted, and offers to repair it
+    fn fsck(&mut self, argument:[u8; ARGUMENT_LENGTH])
     {
-        self.clear();
-        let cur_file_index = self.get_file_index(argument);
-        if cur_file_index == CLEAR_MARKER_FILE{
-            print!("\n[Error] File does not exist");
+        let name = argument_text(&argument);
+        if !name.is_empty()
+        {
+            self.fsck_disk(name);
+            return;
+        }
+        println!();
+        let problems = vfs::fsck(false, &mut |problem| outln!("{}", problem));
+        if problems == 0
+        {
+            print!("[Ok] No problems found");
+            return;
+        }
+        if !confirm(format_args!("{} problems found. Repair them?", problems))
+        {
             return;
         }
+        vfs::fsck(true, &mut |_| {});
+        // The current directory may have been freed as an orphan
+        if vfs::stat(self.cwd).is_err()
+        {
+            self.cwd = vfs::root();
+            process::set_cwd(self.cwd);
+        }
+        print!("\n[Ok] Repaired {} problems", problems);
+    }
 
-        self.files.files[cur_file_index] = File
+    // The disk is read again for the repair, it has not changed since
+    fn fsck_disk(&mut self, name:&str)
+    {
+        let drive = match parse_drive(name)
+        {
+            Some(drive) => drive,
+            None => {
+                print!("\n[Error] No disk \"{}\"", name);
+                return;
+            }
+        };
+        println!();
+        let problems = match vfs::fsck_disk(drive, false, &mut |problem| outln!("{}", problem))
         {
-            index: CLEAR_MARKER_FILE,
-            name: [b'\0'; MAX_SIZE_DIRECTORY_NAME],
-            count_lines: 0,
-            folder_index: CLEAR_MARKER_DIRECTORY,
-            context: [b' '; BUF_SIZE],
+            Ok(0) => {
+                print!("[Ok] No problems found on {}", name);
+                return;
+            }
+            Ok(problems) => problems,
+            Err(FsError::Busy) => {
+                print!("[Error] {} is mounted, run fsck without a disk for the shell files", name);
+                return;
+            }
+            Err(error) => {
+                print_fs_error(error, name);
+                return;
+            }
         };
+        if !confirm(format_args!("{} problems found on {}. Repair them?", problems, name))
+        {
+            return;
+        }
+        match vfs::fsck_disk(drive, true, &mut |_| {})
+        {
+            Ok(_) => print!("\n[Ok] Repaired {} problems, load the files with \"mount {}\"", problems, name),
+            Err(error) => print_fs_error(error, name),
+        }
+    }
 
-        for i in 0..MAX_SIZE_FILES_IN_DIRECTORY
+    fn list_mounts(&mut self)
+    {
+        println!();
+        match vfs::mounted_drive()
+        {
+            Some(drive) => outln!("ata{} ({}) on / type unios", drive.index(), drive.model()),
+            None => outln!("memory on / type unios"),
+        }
+        for index in 0..MAX_MOUNTS
         {
-            if self.dirs.dirs[self.curr_dir].child_indexes[i] == cur_file_index
+            if let Some((point, kind, drive)) = vfs::mount_info(index)
             {
-                self.dirs.dirs[self.curr_dir].child_indexes[i] = CLEAR_MARKER_FILE;
+                let mut path = [0u8; MAX_PATH_LENGTH];
+                let length = vfs::path_of(point, &mut path).unwrap_or(0);
+                let path = core::str::from_utf8(&path[..length]).unwrap_or("?");
+                match drive
+                {
+                    Some(drive) => outln!("ata{} ({}) on {} type {}", drive.index(), drive.model(), path, kind),
+                    None => outln!("memory on {} type {}", path, kind),
+                }
             }
         }
     }
 
-    fn dump_file(&mut self, argument: [u8; ARGUMENT_LENGTH])
+    // umount <dir> takes away what was mounted on the directory
+    fn umount(&mut self, argument:[u8; ARGUMENT_LENGTH])
     {
-        self.clear();
-        let cur_file_index = self.get_file_index(argument);
-        if cur_file_index == CLEAR_MARKER_FILE
+        let path = argument_text(&argument);
+        if path.is_empty()
         {
-            print!("\n[Error] File \"{}\" does not exist!", core::str::from_utf8(&argument.clone()).unwrap().trim_matches('\0'));
+            print!("\n[Error] Specify a directory");
             return;
         }
-
-        for i in 0..(BUF_WIDTH * (self.files.files[cur_file_index].count_lines) as u32)
+        let root = match self.resolve(path)
+        {
+            Ok(root) => root,
+            Err(error) => {
+                print_fs_error(error, path);
+                return;
+            }
+        };
+        if process::any_cwd_within(root)
         {
-            print!("{}", self.files.files[cur_file_index].context[i as usize] as char);
+            print_fs_error(FsError::Busy, path);
+            return;
+        }
+        match vfs::unmount(root)
+        {
+            Ok(()) => print!("\n[Ok] {} unmounted", path),
+            Err(FsError::NotFound) => print!("\n[Error] Nothing is mounted on {}", path),
+            Err(error) => print_fs_error(error, path),
         }
     }
 
-    fn edit_file(&mut self, argument:[u8; ARGUMENT_LENGTH])
+    // mkfs [-f] ataN, -f does not ask
+    fn mkfs(&mut self, argument:[u8; ARGUMENT_LENGTH])
     {
-        let cur_file_index = self.get_file_index(argument);
-        if cur_file_index == CLEAR_MARKER_FILE
+        let (options, name) = match take_options(argument_text(&argument), "f")
+        {
+            Ok(parsed) => parsed,
+            Err(letter) => {
+                print!("\n[Error] Unknown option -{}", letter);
+                return;
+            }
+        };
+        let drive = match parse_drive(name)
         {
-            print!("\n[Error] File \"{}\" does not exist!", core::str::from_utf8(&argument.clone()).unwrap().trim_matches('\0'));
+            Some(drive) => drive,
+            None => {
+                print!("\n[Error] No disk \"{}\"", name);
+                return;
+            }
+        };
+        if !options.has('f') && !confirm(format_args!("Erase everything on {}?", name))
+        {
+            print!("\n[Ok] Nothing written");
             return;
         }
-
-        self.is_editing_file = true;
-        self.current_editing_file = cur_file_index;
-        self.files.files[self.current_editing_file].count_lines = 0;
-        self.clear();
+        match vfs::format(drive)
+        {
+            Ok(()) => print!("\n[Ok] Empty filesystem written to {}, load it with \"mount {}\"", name, name),
+            Err(FsError::Busy) => print!("\n[Error] {} is mounted", name),
+            Err(error) => print_fs_error(error, name),
+        }
     }
 
-    fn get_file_index(&mut self, argument:[u8; ARGUMENT_LENGTH]) -> usize {
-        let mut cur_file_index = CLEAR_MARKER_FILE;
-        let mut is_same = true;
-        for i in 0..MAX_SIZE_FILES_IN_DIRECTORY{
-            cur_file_index = self.dirs.dirs[self.curr_dir].files_indexes[i];
-            if cur_file_index == CLEAR_MARKER_FILE{
+    fn ps(&mut self)
+    {
+        let processes = process::snapshot();
+        println!();
+        outln!("  PID  PPID STAT NAME");
+        for process in processes.iter()
+        {
+            if process.is_free()
+            {
                 continue;
             }
-            is_same = true;
-            for j in 0..MAX_SIZE_DIRECTORY_NAME{
-                if argument[j] == b'\0'{
-                    break;
-                }
-                if argument[j] != self.files.files[cur_file_index].name[j]{
-                    is_same = false;
-                    break;
-                }
-            }
-            if is_same {
-                return cur_file_index;
-            }
+            let state = match process.state
+            {
+                ProcessState::Running | ProcessState::Ready => 'R',
+                ProcessState::Sleeping => 'S',
+                ProcessState::Stopped => 'T',
+                ProcessState::Zombie => 'Z',
+                ProcessState::Free => '?',
+            };
+            outln!("{:>5} {:>5} {}    {}", process.pid, process.parent_pid, state, process.name());
         }
-        return CLEAR_MARKER_FILE;
     }
-}
\ No newline at end of file
+
+    // Same layout as `pstree -A -p`:
+    //   kernel(1)---shell(2)-+-life(3)
+    //                        `-life(4)
+    fn pstree(&mut self)
+    {
+        let processes = process::snapshot();
+        let mut prefix = [b' '; BUF_WIDTH as usize];
+        println!();
+        print_process_tree(&processes, process::KERNEL_PID, &mut prefix, 0);
+    }
+}
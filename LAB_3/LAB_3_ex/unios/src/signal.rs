@@ -0,0 +1,136 @@
+/// Signal numbers, the same as on Linux x86_64.
+pub const SIGHUP:i32 = 1;
+pub const SIGINT:i32 = 2;
+pub const SIGQUIT:i32 = 3;
+pub const SIGILL:i32 = 4;
+pub const SIGFPE:i32 = 8;
+pub const SIGKILL:i32 = 9;
+pub const SIGUSR1:i32 = 10;
+pub const SIGSEGV:i32 = 11;
+pub const SIGUSR2:i32 = 12;
+pub const SIGPIPE:i32 = 13;
+pub const SIGALRM:i32 = 14;
+pub const SIGTERM:i32 = 15;
+pub const SIGCHLD:i32 = 17;
+pub const SIGCONT:i32 = 18;
+pub const SIGSTOP:i32 = 19;
+pub const SIGTSTP:i32 = 20;
+
+/// Valid signals are 1 up to NSIG - 1, one bit each in a pending mask.
+pub const NSIG:usize = 32;
+
+/// What a process does when a signal is delivered to it.
+#[derive(Clone, Copy)]
+pub enum Handler
+{
+    Default,
+    Ignore,
+    // runs on the process's own task, with the signal number
+    Call(fn(i32)),
+    // ring 3 code at the first address, entered with the signal number on
+    // the way back from a system call. It returns to the second, which
+    // makes the SYS_SIGRETURN call.
+    User(u64, u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefaultAction
+{
+    Terminate,
+    Ignore,
+    Stop,
+    Continue,
+}
+
+struct Signal
+{
+    number:i32,
+    name:&'static str,
+    // what sh prints for a child killed by it
+    description:&'static str,
+    default_action:DefaultAction,
+}
+
+static SIGNALS: [Signal; 16] = [
+    Signal { number: SIGHUP, name: "HUP", description: "Hangup", default_action: DefaultAction::Terminate },
+    Signal { number: SIGINT, name: "INT", description: "Interrupt", default_action: DefaultAction::Terminate },
+    Signal { number: SIGQUIT, name: "QUIT", description: "Quit", default_action: DefaultAction::Terminate },
+    Signal { number: SIGILL, name: "ILL", description: "Illegal instruction", default_action: DefaultAction::Terminate },
+    Signal { number: SIGFPE, name: "FPE", description: "Floating point exception", default_action: DefaultAction::Terminate },
+    Signal { number: SIGKILL, name: "KILL", description: "Killed", default_action: DefaultAction::Terminate },
+    Signal { number: SIGUSR1, name: "USR1", description: "User defined signal 1", default_action: DefaultAction::Terminate },
+    Signal { number: SIGSEGV, name: "SEGV", description: "Segmentation fault", default_action: DefaultAction::Terminate },
+    Signal { number: SIGUSR2, name: "USR2", description: "User defined signal 2", default_action: DefaultAction::Terminate },
+    Signal { number: SIGPIPE, name: "PIPE", description: "Broken pipe", default_action: DefaultAction::Terminate },
+    Signal { number: SIGALRM, name: "ALRM", description: "Alarm clock", default_action: DefaultAction::Terminate },
+    Signal { number: SIGTERM, name: "TERM", description: "Terminated", default_action: DefaultAction::Terminate },
+    Signal { number: SIGCHLD, name: "CHLD", description: "Child exited", default_action: DefaultAction::Ignore },
+    Signal { number: SIGCONT, name: "CONT", description: "Continued", default_action: DefaultAction::Continue },
+    Signal { number: SIGSTOP, name: "STOP", description: "Stopped (signal)", default_action: DefaultAction::Stop },
+    Signal { number: SIGTSTP, name: "TSTP", description: "Stopped", default_action: DefaultAction::Stop },
+];
+
+fn find(signal:i32) -> Option<&'static Signal>
+{
+    SIGNALS.iter().find(|info| info.number == signal)
+}
+
+pub fn is_valid(signal:i32) -> bool
+{
+    signal > 0 && (signal as usize) < NSIG
+}
+
+/// SIGKILL and SIGSTOP always take their default action.
+pub fn can_catch(signal:i32) -> bool
+{
+    is_valid(signal) && signal != SIGKILL && signal != SIGSTOP
+}
+
+/// Signals without a name of their own terminate, like the unused ones on Linux.
+pub fn default_action(signal:i32) -> DefaultAction
+{
+    match find(signal)
+    {
+        Some(info) => info.default_action,
+        None => DefaultAction::Terminate,
+    }
+}
+
+/// Short name without the SIG prefix, "TERM" for SIGTERM.
+pub fn name(signal:i32) -> &'static str
+{
+    match find(signal)
+    {
+        Some(info) => info.name,
+        None => "?",
+    }
+}
+
+pub fn description(signal:i32) -> &'static str
+{
+    match find(signal)
+    {
+        Some(info) => info.description,
+        None => "Unknown signal",
+    }
+}
+
+/// Parses a signal given as a number, a name or a name with the SIG prefix,
+/// as `kill -s` accepts them.
+pub fn parse(text:&str) -> Option<i32>
+{
+    if let Ok(signal) = text.parse::<i32>()
+    {
+        return if is_valid(signal) { Some(signal) } else { None };
+    }
+    let name = text.strip_prefix("SIG").unwrap_or(text);
+    return SIGNALS.iter()
+        .find(|info| info.name.eq_ignore_ascii_case(name))
+        .map(|info| info.number);
+}
+
+/// Every named signal, for `kill -l`.
+pub fn numbers() -> impl Iterator<Item = i32>
+{
+    SIGNALS.iter().map(|info| info.number)
+}
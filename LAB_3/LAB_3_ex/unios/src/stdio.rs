@@ -0,0 +1,276 @@
+use core::fmt;
+use crate::{pipe, print, process, vga_buf};
+use crate::pipe::End;
+use crate::vfs::{FsError, Inode, OpenFile};
+
+/// Writes to the standard output of the current process. `print!` always
+/// goes to the screen and is meant for prompts, errors and kernel messages.
+#[macro_export]
+macro_rules! out
+{
+    ($($arg:tt)*) => ($crate::stdio::_print(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! outln
+{
+    () => ($crate::out!("\n"));
+    ($($arg:tt)*) => ($crate::out!("{}\n", format_args!($($arg)*)));
+}
+
+/// Longest line `LineReader` returns in one piece.
+pub const MAX_LINE_LENGTH:usize = 80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input
+{
+    // reads as an empty file
+    Empty,
+    Pipe(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Output
+{
+    Screen,
+    Pipe(usize),
+    // `>` and `>>`, each process writes from where it is in the file
+    File(OpenFile),
+}
+
+/// Standard input and output of a process. Children inherit them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stdio
+{
+    pub input:Input,
+    pub output:Output,
+}
+
+pub const CONSOLE:Stdio = Stdio
+{
+    input: Input::Empty,
+    output: Output::Screen,
+};
+
+impl Stdio
+{
+    /// Takes a reference on every pipe end, for a new owner of a copy.
+    pub fn open(&self)
+    {
+        if let Input::Pipe(id) = self.input
+        {
+            pipe::open(id, End::Read);
+        }
+        if let Output::Pipe(id) = self.output
+        {
+            pipe::open(id, End::Write);
+        }
+    }
+
+    pub fn close(&self)
+    {
+        if let Input::Pipe(id) = self.input
+        {
+            pipe::close(id, End::Read);
+        }
+        if let Output::Pipe(id) = self.output
+        {
+            pipe::close(id, End::Write);
+        }
+    }
+}
+
+struct PipeWriter(usize);
+
+impl fmt::Write for PipeWriter
+{
+    fn write_str(&mut self, s:&str) -> fmt::Result
+    {
+        pipe::write(self.0, s.as_bytes());
+        Ok(())
+    }
+}
+
+impl fmt::Write for OpenFile
+{
+    fn write_str(&mut self, s:&str) -> fmt::Result
+    {
+        self.write(s.as_bytes()).map(|_| ()).map_err(|_| fmt::Error)
+    }
+}
+
+// Keeps how far the current process got in the file it writes to
+fn moved_on(file:OpenFile)
+{
+    let mut stdio = process::stdio();
+    stdio.output = Output::File(file);
+    process::replace_stdio(stdio);
+}
+
+#[doc(hidden)]
+pub fn _print(args:fmt::Arguments)
+{
+    use core::fmt::Write;
+    process::handle_signals();
+    match process::stdio().output
+    {
+        Output::Screen => vga_buf::_print(args),
+        Output::Pipe(id) => {
+            let _ = PipeWriter(id).write_fmt(args);
+        }
+        Output::File(mut file) => {
+            let _ = file.write_fmt(args);
+            moved_on(file);
+        }
+    }
+}
+
+/// Writes raw bytes to standard output, for the write system call.
+pub fn write(bytes:&[u8])
+{
+    process::handle_signals();
+    match process::stdio().output
+    {
+        Output::Screen => match core::str::from_utf8(bytes)
+        {
+            Ok(text) => print!("{}", text),
+            Err(_) => {
+                for &byte in bytes
+                {
+                    print!("{}", byte as char);
+                }
+            }
+        },
+        Output::Pipe(id) => {
+            pipe::write(id, bytes);
+        }
+        Output::File(mut file) => {
+            let _ = file.write(bytes);
+            moved_on(file);
+        }
+    }
+}
+
+/// Reads from standard input. Returns 0 at end of file.
+pub fn read(buf:&mut [u8]) -> usize
+{
+    process::handle_signals();
+    match process::stdio().input
+    {
+        Input::Empty => 0,
+        Input::Pipe(id) => pipe::read(id, buf),
+    }
+}
+
+/// Splits standard input, or a file, into lines.
+pub struct LineReader
+{
+    buf:[u8; 128],
+    start:usize,
+    end:usize,
+    // None for standard input
+    file:Option<OpenFile>,
+    error:Option<FsError>,
+    // whether the last piece read_line gave ended its line
+    ended:bool,
+}
+
+impl LineReader
+{
+    pub fn new() -> LineReader
+    {
+        LineReader { buf: [0; 128], start: 0, end: 0, file: None, error: None, ended: true }
+    }
+
+    pub fn from_file(file:Inode) -> LineReader
+    {
+        LineReader { file: Some(OpenFile::open(file)), ..LineReader::new() }
+    }
+
+    /// Whether the last piece `read_line` returned was the end of its line,
+    /// false when the line goes on in the next one.
+    pub fn ended_line(&self) -> bool
+    {
+        self.ended
+    }
+
+    /// What stopped reading the file early, if anything did.
+    pub fn error(&self) -> Option<FsError>
+    {
+        self.error
+    }
+
+    fn fill(&mut self) -> usize
+    {
+        match self.file.as_mut()
+        {
+            None => read(&mut self.buf),
+            Some(file) => {
+                process::handle_signals();
+                match file.read(&mut self.buf)
+                {
+                    Ok(count) => count,
+                    Err(error) => {
+                        self.error = Some(error);
+                        0
+                    }
+                }
+            }
+        }
+    }
+
+    /// Copies the next line into `line` without its newline and returns its
+    /// length. Longer lines come back in pieces. Returns None at end of file.
+    pub fn read_line(&mut self, line:&mut [u8; MAX_LINE_LENGTH]) -> Option<usize>
+    {
+        let mut len = 0;
+        self.ended = true;
+        loop
+        {
+            if self.start == self.end
+            {
+                self.start = 0;
+                self.end = self.fill();
+                if self.end == 0
+                {
+                    return if len > 0 { Some(len) } else { None };
+                }
+            }
+            let byte = self.buf[self.start];
+            self.start += 1;
+            if byte == b'\n'
+            {
+                return Some(len);
+            }
+            line[len] = byte;
+            len += 1;
+            if len == MAX_LINE_LENGTH
+            {
+                // A newline right after a full line still ends it, and not
+                // the empty line after it
+                match self.peek()
+                {
+                    Some(b'\n') => self.start += 1,
+                    Some(_) => self.ended = false,
+                    None => {}
+                }
+                return Some(len);
+            }
+        }
+    }
+
+    // The next byte, left to be read. None at end of file.
+    fn peek(&mut self) -> Option<u8>
+    {
+        if self.start == self.end
+        {
+            self.start = 0;
+            self.end = self.fill();
+            if self.end == 0
+            {
+                return None;
+            }
+        }
+        return Some(self.buf[self.start]);
+    }
+}
@@ -0,0 +1,572 @@
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::panic::Location;
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+use x86_64::instructions::interrupts::{self, without_interrupts};
+use crate::task::{self, MAX_TASKS};
+
+// Lock levels. A lock may only be taken while every lock already held has a
+// lower level, so the order below is the one order all code has to follow.
+pub const LEVEL_HANDLERS:u8 = 10;
+pub const LEVEL_KEYBOARD:u8 = 10;
+pub const LEVEL_PICS:u8 = 10;
+pub const LEVEL_PROCESSES:u8 = 20;
+pub const LEVEL_SCHEDULER:u8 = 30;
+pub const LEVEL_MEMORY:u8 = 40;
+pub const LEVEL_KEYS:u8 = 50;
+pub const LEVEL_PIPES:u8 = 55;
+// State of the blocking types below, then the queue of their waiters
+pub const LEVEL_BLOCKING:u8 = 60;
+pub const LEVEL_WAIT_QUEUE:u8 = 70;
+// Printing is allowed under any other lock
+pub const LEVEL_SCREEN:u8 = 90;
+
+/// Spins this many times on a held lock before reporting a deadlock. With a
+/// single CPU and interrupts off nobody can release it in the meantime, so a
+/// timeout here is always a bug.
+const DEADLOCK_SPIN_LIMIT:u64 = 10_000_000;
+
+const MAX_HELD_LOCKS:usize = 8;
+const NO_OWNER:usize = usize::MAX;
+
+/// A spin lock that keeps interrupts disabled for as long as it is held, so an
+/// interrupt handler can never spin on a lock the code it interrupted holds.
+/// Interrupts are restored to their previous state when the guard is dropped.
+///
+/// Because the holder cannot be preempted, a guard must not be kept across
+/// `task::schedule`.
+pub struct IrqSafeMutex<T>
+{
+    name:&'static str,
+    level:u8,
+    locked:AtomicBool,
+    owner_task:AtomicUsize,
+    owner_location:AtomicPtr<Location<'static>>,
+    data:UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for IrqSafeMutex<T> {}
+unsafe impl<T: Send> Send for IrqSafeMutex<T> {}
+
+pub struct IrqSafeMutexGuard<'a, T>
+{
+    mutex:&'a IrqSafeMutex<T>,
+    interrupts_were_enabled:bool,
+}
+
+impl<T> IrqSafeMutex<T>
+{
+    /// `name` shows up in deadlock and lock order reports, `level` is one of
+    /// the `LEVEL_*` constants.
+    pub const fn new(name:&'static str, level:u8, data:T) -> IrqSafeMutex<T>
+    {
+        IrqSafeMutex
+        {
+            name,
+            level,
+            locked: AtomicBool::new(false),
+            owner_task: AtomicUsize::new(NO_OWNER),
+            owner_location: AtomicPtr::new(null_mut()),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Disables interrupts and takes the lock. Panics with the current owner
+    /// when the lock cannot be taken, and in debug builds when taking it would
+    /// break the lock order.
+    #[track_caller]
+    pub fn lock(&self) -> IrqSafeMutexGuard<'_, T>
+    {
+        let interrupts_were_enabled = interrupts::are_enabled();
+        interrupts::disable();
+        let location = Location::caller();
+
+        #[cfg(debug_assertions)]
+        check_lock_order(self.name, self.level, location);
+
+        let mut spins:u64 = 0;
+        while self.locked.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed).is_err()
+        {
+            spins += 1;
+            if spins == DEADLOCK_SPIN_LIMIT
+            {
+                self.report_deadlock(location);
+            }
+            core::hint::spin_loop();
+        }
+
+        self.owner_task.store(task::current_id(), Ordering::Relaxed);
+        self.owner_location.store(location as *const Location<'static> as *mut Location<'static>, Ordering::Relaxed);
+        push_held(self as *const IrqSafeMutex<T> as usize, self.name, self.level, location);
+        return IrqSafeMutexGuard { mutex: self, interrupts_were_enabled };
+    }
+
+    /// Releases the lock without a guard. Only for the panic handler, which
+    /// has to print no matter who was holding the screen.
+    pub unsafe fn force_unlock(&self)
+    {
+        self.owner_task.store(NO_OWNER, Ordering::Relaxed);
+        self.locked.store(false, Ordering::Release);
+    }
+
+    fn report_deadlock(&self, location:&'static Location<'static>) -> !
+    {
+        let owner_location = self.owner_location.load(Ordering::Relaxed);
+        // Safe: only ever set from a &'static Location
+        let owner_location = unsafe { owner_location.as_ref() };
+        match owner_location
+        {
+            Some(owner_location) => panic!(
+                "deadlock on {}: task {} at {} waits for task {}, which took it at {}",
+                self.name, task::current_id(), location,
+                self.owner_task.load(Ordering::Relaxed), owner_location
+            ),
+            None => panic!(
+                "deadlock on {}: task {} at {} waits for an unknown owner",
+                self.name, task::current_id(), location
+            ),
+        }
+    }
+}
+
+impl<'a, T> Deref for IrqSafeMutexGuard<'a, T>
+{
+    type Target = T;
+
+    fn deref(&self) -> &T
+    {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for IrqSafeMutexGuard<'a, T>
+{
+    fn deref_mut(&mut self) -> &mut T
+    {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> Drop for IrqSafeMutexGuard<'a, T>
+{
+    fn drop(&mut self)
+    {
+        pop_held(self.mutex as *const IrqSafeMutex<T> as usize);
+        self.mutex.owner_task.store(NO_OWNER, Ordering::Relaxed);
+        self.mutex.locked.store(false, Ordering::Release);
+        if self.interrupts_were_enabled
+        {
+            interrupts::enable();
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct HeldLock
+{
+    address:usize,
+    name:&'static str,
+    level:u8,
+    location:&'static Location<'static>,
+}
+
+struct HeldLocks
+{
+    locks:UnsafeCell<[Option<HeldLock>; MAX_HELD_LOCKS]>,
+    count:UnsafeCell<usize>,
+}
+
+// The locks held on this CPU. Only touched with interrupts disabled, and
+// there is a single CPU, so nothing can race with it.
+unsafe impl Sync for HeldLocks {}
+
+static HELD: HeldLocks = HeldLocks
+{
+    locks: UnsafeCell::new([None; MAX_HELD_LOCKS]),
+    count: UnsafeCell::new(0),
+};
+
+fn push_held(address:usize, name:&'static str, level:u8, location:&'static Location<'static>)
+{
+    unsafe {
+        let count = &mut *HELD.count.get();
+        if *count < MAX_HELD_LOCKS
+        {
+            (*HELD.locks.get())[*count] = Some(HeldLock { address, name, level, location });
+        }
+        *count += 1;
+    }
+}
+
+// Guards may be dropped in any order, so look the lock up instead of popping the top
+fn pop_held(address:usize)
+{
+    unsafe {
+        let locks = &mut *HELD.locks.get();
+        let count = &mut *HELD.count.get();
+        let tracked = (*count).min(MAX_HELD_LOCKS);
+        if let Some(i) = (0..tracked).rev().find(|&i| matches!(locks[i], Some(held) if held.address == address))
+        {
+            for j in i..tracked - 1
+            {
+                locks[j] = locks[j + 1];
+            }
+            locks[tracked - 1] = None;
+        }
+        *count = count.saturating_sub(1);
+    }
+}
+
+#[cfg(debug_assertions)]
+fn check_lock_order(name:&'static str, level:u8, location:&'static Location<'static>)
+{
+    let locks = unsafe { &*HELD.locks.get() };
+    for held in locks.iter().flatten()
+    {
+        if held.level >= level
+        {
+            let held = *held;
+            panic!(
+                "lock order violation: {} (level {}) taken at {} while holding {} (level {}) taken at {}",
+                name, level, location, held.name, held.level, held.location
+            );
+        }
+    }
+}
+
+/// Number of `IrqSafeMutex` guards alive on this CPU.
+pub fn held_lock_count() -> usize
+{
+    unsafe { *HELD.count.get() }
+}
+
+/// Forgets every held lock. The panic handler calls this before printing so
+/// that the report is not itself rejected as a lock order violation.
+pub unsafe fn forget_held_locks()
+{
+    *HELD.locks.get() = [None; MAX_HELD_LOCKS];
+    *HELD.count.get() = 0;
+}
+
+// Ids of the tasks waiting on something, oldest first
+struct TaskQueue
+{
+    ids:[usize; MAX_TASKS],
+    head:usize,
+    len:usize,
+}
+
+impl TaskQueue
+{
+    fn push(&mut self, id:usize)
+    {
+        // Every task waits on at most one queue at a time and leaves it when
+        // killed, so a full queue is a bug
+        if self.len == MAX_TASKS
+        {
+            panic!("wait queue full: task {} is queued while {} others are", id, self.len);
+        }
+        self.ids[(self.head + self.len) % MAX_TASKS] = id;
+        self.len += 1;
+    }
+
+    fn remove(&mut self, id:usize)
+    {
+        let mut kept = 0;
+        for i in 0..self.len
+        {
+            let other = self.ids[(self.head + i) % MAX_TASKS];
+            if other != id
+            {
+                self.ids[(self.head + kept) % MAX_TASKS] = other;
+                kept += 1;
+            }
+        }
+        self.len = kept;
+    }
+
+    fn pop(&mut self) -> Option<usize>
+    {
+        if self.len == 0
+        {
+            return None;
+        }
+        let id = self.ids[self.head];
+        self.head = (self.head + 1) % MAX_TASKS;
+        self.len -= 1;
+        return Some(id);
+    }
+}
+
+/// Tasks blocked until some event happens. Waking is safe from interrupt
+/// handlers, waiting is only allowed in a task.
+pub struct WaitQueue
+{
+    waiters:IrqSafeMutex<TaskQueue>,
+}
+
+impl WaitQueue
+{
+    pub const fn new() -> WaitQueue
+    {
+        WaitQueue
+        {
+            waiters: IrqSafeMutex::new("WAIT_QUEUE", LEVEL_WAIT_QUEUE, TaskQueue
+            {
+                ids: [0; MAX_TASKS],
+                head: 0,
+                len: 0,
+            }),
+        }
+    }
+
+    // Queues the current task and blocks it. The caller switches away with
+    // `task::schedule` while interrupts are still off.
+    fn block_current(&self)
+    {
+        self.waiters.lock().push(task::current_id());
+        task::block_current(self as *const WaitQueue as usize);
+    }
+
+    /// Blocks the current task until it is woken. Wakeups can be spurious,
+    /// so callers recheck their condition; `wait_until` does that for them.
+    pub fn wait(&self)
+    {
+        without_interrupts(|| {
+            self.block_current();
+            task::schedule();
+        });
+    }
+
+    /// Blocks until `condition` returns Some and returns its value. The
+    /// condition is checked with interrupts disabled, so a wakeup from an
+    /// interrupt handler cannot slip in between the check and going to sleep.
+    pub fn wait_until<R>(&self, mut condition:impl FnMut() -> Option<R>) -> R
+    {
+        loop
+        {
+            let result = without_interrupts(|| {
+                let result = condition();
+                if result.is_none()
+                {
+                    self.block_current();
+                    task::schedule();
+                }
+                result
+            });
+            if let Some(result) = result
+            {
+                return result;
+            }
+        }
+    }
+
+    /// Wakes the task that has waited longest. Returns false if nobody was waiting.
+    pub fn wake_one(&self) -> bool
+    {
+        loop
+        {
+            let id = self.waiters.lock().pop();
+            match id
+            {
+                // Tasks that exited while waiting are skipped
+                Some(id) => if task::wake(id) { return true; },
+                None => return false,
+            }
+        }
+    }
+
+    pub fn wake_all(&self)
+    {
+        while self.wake_one() {}
+    }
+}
+
+/// Takes the killed task `id` off the `WaitQueue` at address `queue`, for
+/// `task::kill`. The queue is still there: the task blocked in a call on it
+/// that never returns.
+pub fn remove_waiter(queue:usize, id:usize)
+{
+    let queue = unsafe { &*(queue as *const WaitQueue) };
+    queue.waiters.lock().remove(id);
+}
+
+/// A counting semaphore whose `acquire` sleeps instead of spinning.
+pub struct Semaphore
+{
+    count:IrqSafeMutex<usize>,
+    waiters:WaitQueue,
+}
+
+impl Semaphore
+{
+    pub const fn new(count:usize) -> Semaphore
+    {
+        Semaphore
+        {
+            count: IrqSafeMutex::new("SEMAPHORE", LEVEL_BLOCKING, count),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Takes one unit, blocking while there is none.
+    pub fn acquire(&self)
+    {
+        self.waiters.wait_until(|| self.take());
+    }
+
+    /// Takes one unit if there is one.
+    pub fn try_acquire(&self) -> bool
+    {
+        self.take().is_some()
+    }
+
+    /// Gives one unit back and wakes a waiter. Safe from interrupt handlers.
+    pub fn release(&self)
+    {
+        *self.count.lock() += 1;
+        self.waiters.wake_one();
+    }
+
+    fn take(&self) -> Option<()>
+    {
+        let mut count = self.count.lock();
+        if *count == 0
+        {
+            return None;
+        }
+        *count -= 1;
+        return Some(());
+    }
+}
+
+/// A mutex for tasks: a task that finds it locked sleeps until it is free.
+/// Unlike `IrqSafeMutex` interrupts stay on and the holder may sleep, wait
+/// for children or be preempted. Must not be used from interrupt handlers.
+pub struct Mutex<T>
+{
+    locked:IrqSafeMutex<bool>,
+    waiters:WaitQueue,
+    data:UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for Mutex<T> {}
+unsafe impl<T: Send> Send for Mutex<T> {}
+
+pub struct MutexGuard<'a, T>
+{
+    mutex:&'a Mutex<T>,
+}
+
+impl<T> Mutex<T>
+{
+    pub const fn new(data:T) -> Mutex<T>
+    {
+        Mutex
+        {
+            locked: IrqSafeMutex::new("MUTEX", LEVEL_BLOCKING, false),
+            waiters: WaitQueue::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, T>
+    {
+        self.waiters.wait_until(|| self.try_lock())
+    }
+
+    /// Every guard is counted for the task that holds it, see `task::holds_locks`.
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>>
+    {
+        // No tick between taking it and counting it
+        without_interrupts(|| {
+            {
+                let mut locked = self.locked.lock();
+                if *locked
+                {
+                    return None;
+                }
+                *locked = true;
+            }
+            task::count_lock(true);
+            return Some(MutexGuard { mutex: self });
+        })
+    }
+}
+
+impl<'a, T> Deref for MutexGuard<'a, T>
+{
+    type Target = T;
+
+    fn deref(&self) -> &T
+    {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for MutexGuard<'a, T>
+{
+    fn deref_mut(&mut self) -> &mut T
+    {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> Drop for MutexGuard<'a, T>
+{
+    fn drop(&mut self)
+    {
+        *self.mutex.locked.lock() = false;
+        self.mutex.waiters.wake_one();
+        task::count_lock(false);
+    }
+}
+
+/// A condition variable to wait on together with a `Mutex`.
+pub struct Condvar
+{
+    waiters:WaitQueue,
+}
+
+impl Condvar
+{
+    pub const fn new() -> Condvar
+    {
+        Condvar { waiters: WaitQueue::new() }
+    }
+
+    /// Unlocks `guard`, sleeps until notified and locks the mutex again.
+    /// Unlocking and going to sleep happen with interrupts off, so a notify
+    /// sent right after the unlock is not lost.
+    pub fn wait<'a, T>(&self, guard:MutexGuard<'a, T>) -> MutexGuard<'a, T>
+    {
+        let mutex = guard.mutex;
+        without_interrupts(|| {
+            self.waiters.block_current();
+            drop(guard);
+            task::schedule();
+        });
+        mutex.lock()
+    }
+
+    /// Waits for as long as `condition` holds for the protected data.
+    pub fn wait_while<'a, T>(&self, mut guard:MutexGuard<'a, T>, mut condition:impl FnMut(&mut T) -> bool) -> MutexGuard<'a, T>
+    {
+        while condition(&mut *guard)
+        {
+            guard = self.wait(guard);
+        }
+        return guard;
+    }
+
+    pub fn notify_one(&self)
+    {
+        self.waiters.wake_one();
+    }
+
+    pub fn notify_all(&self)
+    {
+        self.waiters.wake_all();
+    }
+}
@@ -0,0 +1,209 @@
+use core::arch::global_asm;
+use crate::{memory, process, stdio};
+use crate::signal::{self, Handler};
+
+/// User programs enter the kernel with `int 0x80`. The call number goes in
+/// rax, the arguments in rdi, rsi and rdx, and the result comes back in rax.
+/// The other registers are kept.
+pub const SYSCALL_INTERRUPT:u8 = 0x80;
+
+/// exit(status)
+pub const SYS_EXIT:u64 = 0;
+/// write(buf, len) to the screen, returns the number of bytes written
+pub const SYS_WRITE:u64 = 1;
+/// getpid()
+pub const SYS_GETPID:u64 = 2;
+/// kill(pid, signal), returns 0
+pub const SYS_KILL:u64 = 3;
+/// signal(signal, action, restorer) with SIG_DFL, SIG_IGN or the address of
+/// a handler, returns the previous one. A handler is called with the signal
+/// number in rdi when the program returns from its next system call, and
+/// returns to `restorer`, which has to make the SYS_SIGRETURN call.
+pub const SYS_SIGNAL:u64 = 4;
+/// sigreturn(), only from the restorer: goes back to where the handler
+/// interrupted the program.
+pub const SYS_SIGRETURN:u64 = 5;
+
+pub const SIG_DFL:u64 = 0;
+pub const SIG_IGN:u64 = 1;
+
+const SYSCALL_ERROR:u64 = u64::MAX;
+
+// Below rsp, which the interrupted code may still use
+const RED_ZONE:u64 = 128;
+// Carry, parity, adjust, zero, sign, trap, direction and overflow
+const USER_FLAGS:u64 = 0xdd5;
+
+/// Registers saved by `syscall_entry`, followed by the frame the CPU pushed.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct SyscallFrame
+{
+    pub r15:u64,
+    pub r14:u64,
+    pub r13:u64,
+    pub r12:u64,
+    pub r11:u64,
+    pub r10:u64,
+    pub r9:u64,
+    pub r8:u64,
+    pub rbp:u64,
+    pub rdi:u64,
+    pub rsi:u64,
+    pub rdx:u64,
+    pub rcx:u64,
+    pub rbx:u64,
+    pub rax:u64,
+    pub rip:u64,
+    pub cs:u64,
+    pub rflags:u64,
+    pub rsp:u64,
+    pub ss:u64,
+}
+
+// The x86-interrupt ABI gives no access to the caller's registers, so the
+// gate points at this stub instead. 15 pushes on top of the 5 words from the
+// CPU keep the stack 16-byte aligned for the call.
+global_asm!(
+    ".section .text",
+    ".global syscall_entry",
+    "syscall_entry:",
+    "push rax",
+    "push rbx",
+    "push rcx",
+    "push rdx",
+    "push rsi",
+    "push rdi",
+    "push rbp",
+    "push r8",
+    "push r9",
+    "push r10",
+    "push r11",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+    "mov rdi, rsp",
+    "call syscall_dispatch",
+    "pop r15",
+    "pop r14",
+    "pop r13",
+    "pop r12",
+    "pop r11",
+    "pop r10",
+    "pop r9",
+    "pop r8",
+    "pop rbp",
+    "pop rdi",
+    "pop rsi",
+    "pop rdx",
+    "pop rcx",
+    "pop rbx",
+    "pop rax",
+    "iretq",
+);
+
+extern "C"
+{
+    pub fn syscall_entry();
+}
+
+#[no_mangle]
+extern "C" fn syscall_dispatch(frame:&mut SyscallFrame)
+{
+    frame.rax = match frame.rax
+    {
+        SYS_EXIT => process::exit(frame.rdi as i32),
+        SYS_WRITE => sys_write(frame.rdi, frame.rsi),
+        SYS_GETPID => process::current_pid() as u64,
+        SYS_KILL => sys_kill(frame.rdi, frame.rsi),
+        SYS_SIGNAL => sys_signal(frame.rdi, frame.rsi, frame.rdx),
+        SYS_SIGRETURN => sys_sigreturn(frame),
+        _ => SYSCALL_ERROR,
+    };
+    // Signals are delivered on the way back to ring 3
+    process::handle_signals();
+    if let Some((signal, handler, restorer)) = process::take_user_signal()
+    {
+        enter_handler(frame, signal, handler, restorer);
+    }
+}
+
+// Makes the program go on in `handler` with `signal` in rdi. The frame it
+// had is saved on its stack, under the address of `restorer` for the
+// handler's return.
+fn enter_handler(frame:&mut SyscallFrame, signal:i32, handler:u64, restorer:u64)
+{
+    let size = core::mem::size_of::<SyscallFrame>() as u64;
+    let saved = frame.rsp.wrapping_sub(RED_ZONE + size) & !15;
+    // Aligned the way a call leaves it
+    let stack = saved.wrapping_sub(8);
+    if !memory::is_user_range(stack, size + 8, true)
+    {
+        process::kill_current(signal::SIGSEGV);
+    }
+    unsafe {
+        core::ptr::write_unaligned(saved as *mut SyscallFrame, *frame);
+        core::ptr::write_unaligned(stack as *mut u64, restorer);
+    }
+    frame.rip = handler;
+    frame.rsp = stack;
+    frame.rdi = signal as u64;
+}
+
+// The handler has returned and popped the restorer's address, rsp is at the
+// saved frame again. Only the registers and the flags the program may set
+// come back from it, never its privilege.
+fn sys_sigreturn(frame:&mut SyscallFrame) -> u64
+{
+    let size = core::mem::size_of::<SyscallFrame>() as u64;
+    if !memory::is_user_range(frame.rsp, size, false)
+    {
+        process::kill_current(signal::SIGSEGV);
+    }
+    let saved = unsafe { core::ptr::read_unaligned(frame.rsp as *const SyscallFrame) };
+    let (cs, ss, rflags) = (frame.cs, frame.ss, frame.rflags);
+    *frame = saved;
+    frame.cs = cs;
+    frame.ss = ss;
+    frame.rflags = (saved.rflags & USER_FLAGS) | (rflags & !USER_FLAGS);
+    return saved.rax;
+}
+
+fn sys_write(buf:u64, len:u64) -> u64
+{
+    if !memory::is_user_range(buf, len, false)
+    {
+        return SYSCALL_ERROR;
+    }
+
+    let bytes = unsafe { core::slice::from_raw_parts(buf as *const u8, len as usize) };
+    stdio::write(bytes);
+    return len;
+}
+
+fn sys_kill(pid:u64, signal:u64) -> u64
+{
+    if process::kill(pid as usize, signal as i32) { 0 } else { SYSCALL_ERROR }
+}
+
+fn sys_signal(signal:u64, action:u64, restorer:u64) -> u64
+{
+    let handler = match action
+    {
+        SIG_DFL => Handler::Default,
+        SIG_IGN => Handler::Ignore,
+        // Both run in ring 3, so they have to be the program's own code
+        _ if memory::is_user_range(action, 1, false) && memory::is_user_range(restorer, 1, false) => {
+            Handler::User(action, restorer)
+        }
+        _ => return SYSCALL_ERROR,
+    };
+    match process::set_handler(signal as i32, handler)
+    {
+        Some(Handler::Ignore) => SIG_IGN,
+        Some(Handler::User(address, _)) => address,
+        Some(_) => SIG_DFL,
+        None => SYSCALL_ERROR,
+    }
+}
@@ -0,0 +1,345 @@
+use crate::vfs::{DirEntry, FileSystem, FileType, FsError, Inode, Metadata, MAX_NAME_LENGTH};
+
+const BLOCK:usize = 512;
+const MAX_PATH:usize = 256;
+// The root directory has no header of its own
+const ROOT_NUMBER:usize = usize::MAX;
+
+/// A read-only filesystem over a ustar archive in memory. An inode number is
+/// the offset of the entry's header. Directories need entries of their own,
+/// as tar writes them; hard links, devices and the like are left out.
+pub struct TarFs
+{
+    archive:&'static [u8],
+}
+
+// A file or directory header of the archive
+struct Entry
+{
+    offset:usize,
+    // without "./" in front or '/' at the end
+    path:[u8; MAX_PATH],
+    length:usize,
+    size:usize,
+    kind:FileType,
+    mode:u16,
+    owner:u32,
+    modified:u64,
+}
+
+impl Entry
+{
+    fn path(&self) -> &[u8]
+    {
+        &self.path[..self.length]
+    }
+
+    fn parent(&self) -> &[u8]
+    {
+        match self.path().iter().rposition(|&byte| byte == b'/')
+        {
+            Some(slash) => &self.path[..slash],
+            None => &[],
+        }
+    }
+
+    fn name(&self) -> &[u8]
+    {
+        &self.path()[self.path().iter().rposition(|&byte| byte == b'/').map_or(0, |slash| slash + 1)..]
+    }
+
+    fn inode(&self) -> Inode
+    {
+        Inode::new(self.kind, self.offset)
+    }
+
+    // Whether `readdir` shows it. A name longer than a DirEntry holds would
+    // come out cut, maybe as the name of another entry.
+    fn is_listed(&self) -> bool
+    {
+        self.name().len() <= MAX_NAME_LENGTH
+    }
+}
+
+// A text field, up to its first NUL
+fn text(field:&[u8]) -> &[u8]
+{
+    &field[..field.iter().position(|&byte| byte == 0).unwrap_or(field.len())]
+}
+
+// A number field: octal digits, maybe after spaces, ended by a NUL or a space
+fn octal(field:&[u8]) -> Option<usize>
+{
+    let mut value:usize = 0;
+    for &byte in field.iter().skip_while(|&&byte| byte == b' ').take_while(|&&byte| byte != 0 && byte != b' ')
+    {
+        if !(b'0'..=b'7').contains(&byte)
+        {
+            return None;
+        }
+        value = value.checked_mul(8)?.checked_add((byte - b'0') as usize)?;
+    }
+    return Some(value);
+}
+
+impl TarFs
+{
+    /// Checks every header of `archive`.
+    pub fn new(archive:&'static [u8]) -> Result<TarFs, FsError>
+    {
+        let fs = TarFs { archive };
+        fs.find(|_| false)?;
+        return Ok(fs);
+    }
+
+    /// Always "tar".
+    pub fn kind(&self) -> &'static str
+    {
+        "tar"
+    }
+
+    // The entry whose header is at `offset` and where the next header is,
+    // None for one that is skipped and Err at the end of the archive
+    fn parse(&self, offset:usize) -> Result<(Option<Entry>, usize), FsError>
+    {
+        let header = self.archive.get(offset..offset + BLOCK).ok_or(FsError::NotFound)?;
+        if header.iter().all(|&byte| byte == 0)
+        {
+            return Err(FsError::NotFound);
+        }
+        // The checksum is taken with its own field counted as spaces
+        let sum:usize = header.iter().enumerate()
+            .map(|(i, &byte)| if (148..156).contains(&i) { b' ' as usize } else { byte as usize })
+            .sum();
+        let size = octal(&header[124..136]).ok_or(FsError::Corrupted)?;
+        let next = size.div_ceil(BLOCK).checked_mul(BLOCK).and_then(|data| (offset + BLOCK).checked_add(data));
+        if octal(&header[148..156]) != Some(sum) || next.map_or(true, |next| next > self.archive.len())
+        {
+            return Err(FsError::Corrupted);
+        }
+        let next = next.unwrap_or(self.archive.len());
+        let kind = match header[156]
+        {
+            b'0' | 0 | b'7' => FileType::File,
+            b'5' => FileType::Directory,
+            _ => return Ok((None, next)),
+        };
+
+        let mut entry = Entry
+        {
+            offset,
+            path: [0; MAX_PATH],
+            length: 0,
+            size,
+            kind,
+            mode: octal(&header[100..108]).unwrap_or(0) as u16 & 0o7777,
+            owner: octal(&header[108..116]).unwrap_or(0) as u32,
+            modified: octal(&header[136..148]).unwrap_or(0) as u64,
+        };
+        let prefix = if &header[257..262] == b"ustar" { text(&header[345..500]) } else { &[] };
+        let name = text(&header[..100]);
+        for part in [prefix, name]
+        {
+            if part.is_empty()
+            {
+                continue;
+            }
+            if entry.length > 0
+            {
+                entry.path[entry.length] = b'/';
+                entry.length += 1;
+            }
+            if entry.length + part.len() > MAX_PATH
+            {
+                return Err(FsError::Corrupted);
+            }
+            entry.path[entry.length..entry.length + part.len()].copy_from_slice(part);
+            entry.length += part.len();
+        }
+        // "./a/b/" and "/a/b" are both "a/b"
+        let mut start = 0;
+        while entry.path[start..entry.length].starts_with(b"./") || entry.path[start..entry.length].starts_with(b"/")
+        {
+            start += if entry.path[start] == b'/' { 1 } else { 2 };
+        }
+        let mut end = entry.length;
+        while end > start && entry.path[end - 1] == b'/'
+        {
+            end -= 1;
+        }
+        entry.path.copy_within(start..end, 0);
+        entry.length = end - start;
+        // The archive's own "./"
+        if entry.length == 0 || entry.path() == b"."
+        {
+            return Ok((None, next));
+        }
+        return Ok((Some(entry), next));
+    }
+
+    // Goes through the entries until `wanted` picks one
+    fn find(&self, mut wanted:impl FnMut(&Entry) -> bool) -> Result<Option<Entry>, FsError>
+    {
+        let mut offset = 0;
+        loop
+        {
+            match self.parse(offset)
+            {
+                Ok((Some(entry), _)) if wanted(&entry) => return Ok(Some(entry)),
+                Ok((_, next)) => offset = next,
+                Err(FsError::NotFound) => return Ok(None),
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    fn entry(&self, inode:Inode) -> Result<Entry, FsError>
+    {
+        match self.parse(inode.number)
+        {
+            Ok((Some(entry), _)) if entry.kind == inode.kind => Ok(entry),
+            _ => Err(FsError::NotFound),
+        }
+    }
+
+    // The path of a directory, empty for the root
+    fn dir_path(&self, dir:Inode) -> Result<([u8; MAX_PATH], usize), FsError>
+    {
+        if dir.kind != FileType::Directory
+        {
+            return Err(FsError::NotADirectory);
+        }
+        if dir.number == ROOT_NUMBER
+        {
+            return Ok(([0; MAX_PATH], 0));
+        }
+        let entry = self.entry(dir)?;
+        return Ok((entry.path, entry.length));
+    }
+}
+
+impl FileSystem for TarFs
+{
+    fn root(&self) -> Inode
+    {
+        Inode::new(FileType::Directory, ROOT_NUMBER)
+    }
+
+    fn lookup(&self, dir:Inode, name:&str) -> Result<Inode, FsError>
+    {
+        let (path, length) = self.dir_path(dir)?;
+        let path = &path[..length];
+        match name
+        {
+            "." => Ok(dir),
+            ".." => {
+                let parent = &path[..path.iter().rposition(|&byte| byte == b'/').unwrap_or(0)];
+                if parent.is_empty()
+                {
+                    return Ok(self.root());
+                }
+                let found = self.find(|entry| entry.kind == FileType::Directory && entry.path() == parent)?;
+                return Ok(found.map_or(self.root(), |entry| entry.inode()));
+            }
+            _ => {
+                let found = self.find(|entry| entry.parent() == path && entry.name() == name.as_bytes())?;
+                return found.map(|entry| entry.inode()).ok_or(FsError::NotFound);
+            }
+        }
+    }
+
+    fn create(&mut self, _dir:Inode, _name:&str) -> Result<Inode, FsError>
+    {
+        Err(FsError::ReadOnly)
+    }
+
+    fn mkdir(&mut self, _dir:Inode, _name:&str) -> Result<Inode, FsError>
+    {
+        Err(FsError::ReadOnly)
+    }
+
+    fn unlink(&mut self, _dir:Inode, _name:&str) -> Result<(), FsError>
+    {
+        Err(FsError::ReadOnly)
+    }
+
+    fn rmdir(&mut self, _dir:Inode, _name:&str) -> Result<(), FsError>
+    {
+        Err(FsError::ReadOnly)
+    }
+
+    fn rename(&mut self, _old_dir:Inode, _old_name:&str, _new_dir:Inode, _new_name:&str) -> Result<(), FsError>
+    {
+        Err(FsError::ReadOnly)
+    }
+
+    fn read(&self, file:Inode, offset:usize, buf:&mut [u8]) -> Result<usize, FsError>
+    {
+        if file.kind == FileType::Directory
+        {
+            return Err(FsError::IsADirectory);
+        }
+        let entry = self.entry(file)?;
+        let count = entry.size.saturating_sub(offset).min(buf.len());
+        let start = entry.offset + BLOCK + offset;
+        buf[..count].copy_from_slice(&self.archive[start..start + count]);
+        return Ok(count);
+    }
+
+    fn write(&mut self, _file:Inode, _offset:usize, _data:&[u8]) -> Result<usize, FsError>
+    {
+        Err(FsError::ReadOnly)
+    }
+
+    fn truncate(&mut self, _file:Inode, _size:usize) -> Result<(), FsError>
+    {
+        Err(FsError::ReadOnly)
+    }
+
+    fn readdir(&self, dir:Inode, index:usize) -> Result<Option<DirEntry>, FsError>
+    {
+        let (path, length) = self.dir_path(dir)?;
+        let mut seen = 0;
+        let found = self.find(|entry| {
+            if entry.parent() != &path[..length] || !entry.is_listed()
+            {
+                return false;
+            }
+            seen += 1;
+            return seen > index;
+        })?;
+        return Ok(found.map(|entry| DirEntry::new(entry.name(), entry.inode())));
+    }
+
+    fn stat(&self, inode:Inode) -> Result<Metadata, FsError>
+    {
+        let size = match inode.kind
+        {
+            FileType::File => self.entry(inode)?.size,
+            FileType::Directory => {
+                let (path, length) = self.dir_path(inode)?;
+                let mut count = 0;
+                self.find(|entry| {
+                    if entry.parent() == &path[..length] && entry.is_listed()
+                    {
+                        count += 1;
+                    }
+                    return false;
+                })?;
+                count
+            }
+        };
+        let mut metadata = Metadata::new(inode, size);
+        // The root has no header, so it keeps the defaults
+        if inode.number != ROOT_NUMBER
+        {
+            let entry = self.entry(inode)?;
+            // Tar keeps one time only
+            metadata.created = entry.modified;
+            metadata.modified = entry.modified;
+            metadata.owner = entry.owner;
+            metadata.mode = entry.mode;
+        }
+        return Ok(metadata);
+    }
+}
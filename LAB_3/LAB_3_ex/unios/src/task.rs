@@ -0,0 +1,475 @@
+use core::arch::global_asm;
+use core::ptr::addr_of_mut;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::lazy_static;
+use x86_64::instructions::interrupts::{self, without_interrupts};
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::PhysFrame;
+use crate::gdt;
+use crate::interrupts::{ticks, TIMER_FREQUENCY_HZ};
+use crate::sync::{self, IrqSafeMutex, LEVEL_SCHEDULER};
+
+pub const MAX_TASKS:usize = 16;
+const TASK_STACK_SIZE:usize = 64 * 1024;
+
+// Slot 0 is the boot context from `_start`. It never exits and runs whenever
+// nothing else is ready, so the scheduler always has something to switch to.
+const IDLE_TASK:usize = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskState
+{
+    Free,
+    Ready,
+    Running,
+    Sleeping,
+    // waiting in a WaitQueue until somebody calls `wake`
+    Blocked,
+    Finished,
+}
+
+#[derive(Clone, Copy)]
+struct Task
+{
+    id:usize,
+    state:TaskState,
+    rsp:u64,
+    entry:fn(),
+    wake_tick:u64,
+    // physical address of the level 4 page table this task runs with
+    page_table:u64,
+    // suspended by job control: not scheduled even when ready, once it
+    // holds no sleeping locks
+    stopped:bool,
+    // sync::Mutex guards the task holds
+    locks:usize,
+    // address of the sync::WaitQueue it is blocked on, 0 for none
+    queue:usize,
+}
+
+#[derive(Clone, Copy)]
+#[repr(C, align(16))]
+struct TaskStack([u8; TASK_STACK_SIZE]);
+
+static mut STACKS: [TaskStack; MAX_TASKS] = [TaskStack([0; TASK_STACK_SIZE]); MAX_TASKS];
+
+struct Scheduler
+{
+    tasks:[Task; MAX_TASKS],
+    current:usize,
+    next_id:usize,
+    kernel_page_table:u64,
+}
+
+// What `schedule` needs to switch from the current task to the next one
+struct Switch
+{
+    old_rsp:*mut u64,
+    new_rsp:u64,
+    page_table:u64,
+    kernel_stack_top:u64,
+}
+
+// Id of the running task, readable without the scheduler lock so that the
+// locks themselves can record their owner.
+static CURRENT_TASK_ID: AtomicUsize = AtomicUsize::new(0);
+
+lazy_static!
+{
+    static ref SCHEDULER: IrqSafeMutex<Scheduler> = IrqSafeMutex::new
+    ("SCHEDULER", LEVEL_SCHEDULER, {
+        let mut scheduler = Scheduler
+        {
+            tasks: [Task
+            {
+                id: 0,
+                state: TaskState::Free,
+                rsp: 0,
+                entry: || {},
+                wake_tick: 0,
+                page_table: 0,
+                stopped: false,
+                locks: 0,
+                queue: 0,
+            }; MAX_TASKS],
+            current: IDLE_TASK,
+            next_id: 1,
+            kernel_page_table: Cr3::read().0.start_address().as_u64(),
+        };
+        scheduler.tasks[IDLE_TASK].state = TaskState::Running;
+        scheduler.tasks[IDLE_TASK].page_table = scheduler.kernel_page_table;
+        scheduler
+    });
+}
+
+// switch_context(old_rsp: *mut u64, new_rsp: u64)
+//
+// Saves the callee-saved registers on the current stack, stores the stack
+// pointer into `old_rsp` and resumes whatever was saved on the `new_rsp` stack.
+// Caller-saved registers are already spilled by the compiler at the call site
+// (or by the x86-interrupt prologue when we come from the timer).
+global_asm!(
+    ".section .text",
+    ".global switch_context",
+    "switch_context:",
+    "push rbp",
+    "push rbx",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+    "mov [rdi], rsp",
+    "mov rsp, rsi",
+    "pop r15",
+    "pop r14",
+    "pop r13",
+    "pop r12",
+    "pop rbx",
+    "pop rbp",
+    "ret",
+);
+
+extern "C"
+{
+    fn switch_context(old_rsp: *mut u64, new_rsp: u64);
+}
+
+impl Scheduler
+{
+    fn slot_of(&self, id:usize) -> Option<usize>
+    {
+        for i in 0..MAX_TASKS
+        {
+            if self.tasks[i].state != TaskState::Free && self.tasks[i].id == id
+            {
+                return Some(i);
+            }
+        }
+        return None;
+    }
+
+    fn spawn(&mut self, entry:fn()) -> Option<usize>
+    {
+        let mut slot = None;
+        for i in 0..MAX_TASKS
+        {
+            if i == IDLE_TASK || i == self.current
+            {
+                continue;
+            }
+            // A finished task is off its stack for good, so its slot can be reused
+            if self.tasks[i].state == TaskState::Free || self.tasks[i].state == TaskState::Finished
+            {
+                slot = Some(i);
+                break;
+            }
+        }
+        let slot = slot?;
+
+        // Lay out the stack the way switch_context leaves it: six zeroed
+        // callee-saved registers, then the trampoline as the return address and
+        // a dummy return address above it so the trampoline sees an ABI-aligned rsp.
+        let stack_top = stack_top(slot);
+        let frame = (stack_top - 8 * 8) as *mut u64;
+        unsafe {
+            for i in 0..6
+            {
+                frame.add(i).write(0);
+            }
+            frame.add(6).write(task_trampoline as extern "C" fn() -> ! as usize as u64);
+            frame.add(7).write(0);
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.tasks[slot] = Task
+        {
+            id,
+            state: TaskState::Ready,
+            rsp: frame as u64,
+            entry,
+            wake_tick: 0,
+            page_table: self.kernel_page_table,
+            stopped: false,
+            locks: 0,
+            queue: 0,
+        };
+        return Some(id);
+    }
+
+    // Round-robin over the ready tasks after the current one. Returns None
+    // when the current task keeps the CPU.
+    fn pick_next(&mut self) -> Option<Switch>
+    {
+        let now = ticks();
+        for i in 0..MAX_TASKS
+        {
+            if self.tasks[i].state == TaskState::Sleeping && self.tasks[i].wake_tick <= now
+            {
+                self.tasks[i].state = TaskState::Ready;
+            }
+        }
+
+        let current = self.current;
+        if self.tasks[current].state == TaskState::Running
+        {
+            self.tasks[current].state = TaskState::Ready;
+        }
+
+        let mut next = IDLE_TASK;
+        for offset in 1..=MAX_TASKS
+        {
+            let i = (current + offset) % MAX_TASKS;
+            // A stopped task runs on until it gives back its locks, so it
+            // does not keep them from everybody else while it is stopped
+            let parked = self.tasks[i].stopped && self.tasks[i].locks == 0;
+            if i != IDLE_TASK && self.tasks[i].state == TaskState::Ready && !parked
+            {
+                next = i;
+                break;
+            }
+        }
+
+        self.tasks[next].state = TaskState::Running;
+        if next == current
+        {
+            return None;
+        }
+        self.current = next;
+        CURRENT_TASK_ID.store(self.tasks[next].id, Ordering::Relaxed);
+        return Some(Switch
+        {
+            old_rsp: &mut self.tasks[current].rsp as *mut u64,
+            new_rsp: self.tasks[next].rsp,
+            page_table: self.tasks[next].page_table,
+            kernel_stack_top: if next == IDLE_TASK { 0 } else { stack_top(next) },
+        });
+    }
+}
+
+fn stack_top(slot:usize) -> u64
+{
+    unsafe {
+        let stack = addr_of_mut!(STACKS).cast::<TaskStack>().add(slot);
+        (stack as u64) + TASK_STACK_SIZE as u64
+    }
+}
+
+fn load_page_table(page_table:u64)
+{
+    let (active, flags) = Cr3::read();
+    if active.start_address().as_u64() != page_table
+    {
+        unsafe {
+            Cr3::write(PhysFrame::containing_address(x86_64::PhysAddr::new(page_table)), flags);
+        }
+    }
+}
+
+extern "C" fn task_trampoline() -> !
+{
+    // We arrive here through switch_context with interrupts still disabled
+    let entry = {
+        let scheduler = SCHEDULER.lock();
+        scheduler.tasks[scheduler.current].entry
+    };
+    interrupts::enable();
+    entry();
+    exit();
+}
+
+/// Switches to the next ready task. Must be called with interrupts disabled;
+/// the timer interrupt handler calls it after every tick.
+pub fn schedule()
+{
+    let switch = SCHEDULER.lock().pick_next();
+    if let Some(switch) = switch
+    {
+        debug_assert!(sync::held_lock_count() == 0, "task switch while holding a lock");
+        // Kernel code and task stacks are mapped the same in every address
+        // space, so it is safe to change it before the stack switch.
+        load_page_table(switch.page_table);
+        if switch.kernel_stack_top != 0
+        {
+            gdt::set_kernel_stack(switch.kernel_stack_top);
+        }
+        unsafe {
+            switch_context(switch.old_rsp, switch.new_rsp);
+        }
+    }
+}
+
+/// Starts `entry` in a new kernel thread and returns its id.
+pub fn spawn(entry:fn()) -> Option<usize>
+{
+    SCHEDULER.lock().spawn(entry)
+}
+
+/// Gives the rest of the time slice to the next ready task.
+pub fn yield_now()
+{
+    without_interrupts(|| schedule());
+}
+
+/// Puts the current task to sleep for at least `ms` milliseconds.
+pub fn sleep(ms:u64)
+{
+    let sleep_ticks = (ms * TIMER_FREQUENCY_HZ as u64 + 999) / 1000;
+    without_interrupts(|| {
+        {
+            let mut scheduler = SCHEDULER.lock();
+            let current = scheduler.current;
+            if current == IDLE_TASK
+            {
+                return;
+            }
+            scheduler.tasks[current].wake_tick = ticks() + sleep_ticks;
+            scheduler.tasks[current].state = TaskState::Sleeping;
+        }
+        schedule();
+    });
+}
+
+/// Takes the current task off the run queue until `wake` is called for it.
+/// Call with interrupts disabled and follow with `schedule`; in between the
+/// caller records the task in the `sync::WaitQueue` at address `queue`,
+/// which `kill` takes it off again.
+pub fn block_current(queue:usize)
+{
+    let mut scheduler = SCHEDULER.lock();
+    let current = scheduler.current;
+    // The idle task must stay runnable, for it blocking is just a yield
+    if current != IDLE_TASK
+    {
+        scheduler.tasks[current].state = TaskState::Blocked;
+        scheduler.tasks[current].queue = queue;
+    }
+}
+
+/// Makes the blocked task `id` ready again. Returns false if it was not
+/// blocked, e.g. because it has exited in the meantime. Safe to call from
+/// interrupt handlers.
+pub fn wake(id:usize) -> bool
+{
+    let mut scheduler = SCHEDULER.lock();
+    match scheduler.slot_of(id)
+    {
+        Some(slot) if scheduler.tasks[slot].state == TaskState::Blocked => {
+            scheduler.tasks[slot].state = TaskState::Ready;
+            scheduler.tasks[slot].queue = 0;
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Stops or continues task `id`. A stopped task keeps its state, so it still
+/// wakes up from sleeping or blocking, but it does not run until continued.
+pub fn set_stopped(id:usize, stopped:bool) -> bool
+{
+    let mut scheduler = SCHEDULER.lock();
+    match scheduler.slot_of(id)
+    {
+        Some(slot) if slot != IDLE_TASK => {
+            scheduler.tasks[slot].stopped = stopped;
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Counts a sleeping lock taken (`taken`) or given back by the current task.
+pub fn count_lock(taken:bool)
+{
+    let mut scheduler = SCHEDULER.lock();
+    let current = scheduler.current;
+    let locks = &mut scheduler.tasks[current].locks;
+    *locks = if taken { *locks + 1 } else { locks.saturating_sub(1) };
+}
+
+/// Whether task `id` holds a sleeping lock. A task that does must not be
+/// ended or parked, or the lock is never given back.
+pub fn holds_locks(id:usize) -> bool
+{
+    let scheduler = SCHEDULER.lock();
+    scheduler.slot_of(id).map_or(false, |slot| scheduler.tasks[slot].locks > 0)
+}
+
+/// Terminates task `id`, which must not be the current one. Its slot and
+/// stack are reused by a later `spawn`. Returns false, leaving it alone,
+/// while it holds a sleeping lock.
+pub fn kill(id:usize) -> bool
+{
+    let mut scheduler = SCHEDULER.lock();
+    match scheduler.slot_of(id)
+    {
+        Some(slot) if slot != IDLE_TASK && slot != scheduler.current && scheduler.tasks[slot].locks == 0 => {
+            let task = &mut scheduler.tasks[slot];
+            // Its id would stay in the queue it waits on and fill it up
+            if task.state == TaskState::Blocked && task.queue != 0
+            {
+                sync::remove_waiter(task.queue, id);
+            }
+            task.state = TaskState::Finished;
+            task.queue = 0;
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Waits until the task `id` has returned from its entry function.
+pub fn join(id:usize)
+{
+    loop
+    {
+        let finished = {
+            let scheduler = SCHEDULER.lock();
+            match scheduler.slot_of(id)
+            {
+                Some(slot) => scheduler.tasks[slot].state == TaskState::Finished,
+                None => true,
+            }
+        };
+        if finished
+        {
+            return;
+        }
+        yield_now();
+    }
+}
+
+/// Terminates the current task.
+pub fn exit() -> !
+{
+    without_interrupts(|| {
+        {
+            let mut scheduler = SCHEDULER.lock();
+            let current = scheduler.current;
+            scheduler.tasks[current].state = TaskState::Finished;
+        }
+        schedule();
+    });
+    unreachable!("finished task was scheduled again");
+}
+
+pub fn current_id() -> usize
+{
+    CURRENT_TASK_ID.load(Ordering::Relaxed)
+}
+
+/// Returns the state of task `id`, or None once its slot has been reused.
+pub fn state(id:usize) -> Option<TaskState>
+{
+    let scheduler = SCHEDULER.lock();
+    scheduler.slot_of(id).map(|slot| scheduler.tasks[slot].state)
+}
+
+/// Switches the current task to another level 4 page table.
+pub fn set_page_table(level_4_table:PhysFrame)
+{
+    let mut scheduler = SCHEDULER.lock();
+    let current = scheduler.current;
+    scheduler.tasks[current].page_table = level_4_table.start_address().as_u64();
+    load_page_table(scheduler.tasks[current].page_table);
+}
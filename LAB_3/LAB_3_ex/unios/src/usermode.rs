@@ -0,0 +1,114 @@
+use core::arch::asm;
+use x86_64::structures::paging::PageTableFlags;
+use crate::gdt;
+use crate::memory::{AddressSpace, PAGE_SIZE, USER_BASE};
+use crate::process::{self, ExecError, MAX_ARGS};
+
+pub const USER_CODE_BASE:u64 = USER_BASE;
+pub const USER_STACK_TOP:u64 = USER_BASE + 0x4000_0000;
+pub const USER_STACK_SIZE:u64 = 4 * PAGE_SIZE;
+
+// interrupts enabled, reserved bit 1 set
+const USER_RFLAGS:u64 = 0x202;
+
+/// Runs a flat binary: `image` is copied to `USER_CODE_BASE` in a fresh address
+/// space and started at its first byte. Only returns if it could not be loaded.
+pub fn exec_flat(image:&[u8], argv:&[&str]) -> ExecError
+{
+    let mut space = match AddressSpace::new()
+    {
+        Some(space) => space,
+        None => return ExecError::OutOfMemory,
+    };
+
+    let loaded = space.map(USER_CODE_BASE, image.len() as u64, PageTableFlags::empty())
+        && space.write(USER_CODE_BASE, image);
+    let stack_pointer = if loaded { setup_stack(&mut space, argv) } else { None };
+    match stack_pointer
+    {
+        Some(stack_pointer) => {
+            process::become_program(argv);
+            start(space, USER_CODE_BASE, stack_pointer)
+        }
+        None => {
+            space.destroy();
+            ExecError::OutOfMemory
+        }
+    }
+}
+
+/// Maps the user stack and lays out argc, argv and empty envp and auxv on it
+/// the way the SysV ABI expects at program entry. Returns the initial rsp.
+pub fn setup_stack(space:&mut AddressSpace, argv:&[&str]) -> Option<u64>
+{
+    let stack_bottom = USER_STACK_TOP - USER_STACK_SIZE;
+    if argv.len() > MAX_ARGS
+        || !space.map(stack_bottom, USER_STACK_SIZE, PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE)
+    {
+        return None;
+    }
+
+    // The strings go at the very top
+    let mut pointer = USER_STACK_TOP;
+    let mut words = [0u64; MAX_ARGS + 5];
+    words[0] = argv.len() as u64;
+    for (i, arg) in argv.iter().enumerate()
+    {
+        pointer -= arg.len() as u64 + 1;
+        if pointer < stack_bottom || !space.write(pointer, arg.as_bytes()) || !space.write(pointer + arg.len() as u64, &[0])
+        {
+            return None;
+        }
+        words[1 + i] = pointer;
+    }
+
+    // argc, argv[..], NULL, envp NULL, AT_NULL pair; rsp must end up 16-byte aligned
+    let count = argv.len() + 5;
+    pointer &= !15;
+    if count % 2 == 1
+    {
+        pointer -= 8;
+    }
+    pointer -= count as u64 * 8;
+    for i in 0..count
+    {
+        if !space.write(pointer + i as u64 * 8, &words[i].to_le_bytes())
+        {
+            return None;
+        }
+    }
+    return Some(pointer);
+}
+
+/// Makes `space` the address space of the current process and drops to ring 3
+/// at `entry` with the given stack.
+pub fn start(space:AddressSpace, entry:u64, stack_pointer:u64) -> !
+{
+    process::replace_address_space(space);
+    enter_user_mode(entry, stack_pointer);
+}
+
+fn enter_user_mode(entry:u64, stack_pointer:u64) -> !
+{
+    let selectors = gdt::selectors();
+    let code = selectors.user_code.0 as u64;
+    let data = selectors.user_data.0 as u64;
+
+    // Build the frame an interrupt from ring 3 would have pushed and return through it
+    unsafe {
+        asm!(
+            "push {data}",
+            "push {stack}",
+            "push {rflags}",
+            "push {code}",
+            "push {entry}",
+            "iretq",
+            data = in(reg) data,
+            stack = in(reg) stack_pointer,
+            rflags = in(reg) USER_RFLAGS,
+            code = in(reg) code,
+            entry = in(reg) entry,
+            options(noreturn)
+        );
+    }
+}
@@ -0,0 +1,910 @@
+use lazy_static::lazy_static;
+use crate::ata::{self, Drive, MAX_DRIVES};
+use crate::block::BlockError;
+use crate::ext2::Ext2Fs;
+use crate::fat::FatFs;
+use crate::tarfs::TarFs;
+use crate::ramfs::{Problem, RamFs};
+use crate::sync::Mutex;
+
+/// Longest name a directory entry carries, longer ones are cut.
+pub const MAX_NAME_LENGTH:usize = 64;
+/// Longest path `path_of` builds.
+pub const MAX_PATH_LENGTH:usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType
+{
+    File,
+    Directory,
+}
+
+/// A file or directory of a filesystem. What `number` means is up to the
+/// filesystem, it only has to stay the same while the inode exists. On FAT
+/// an inode held over a rename still finds the file, but looking the file
+/// up again gives a number with its new place in it, see `FatFs`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Inode
+{
+    pub kind:FileType,
+    pub number:usize,
+    // which mounted filesystem it is on, filesystems themselves always see 0
+    mount:usize,
+}
+
+impl Inode
+{
+    pub fn new(kind:FileType, number:usize) -> Inode
+    {
+        Inode { kind, number, mount: 0 }
+    }
+}
+
+/// A name in a directory and the inode it stands for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirEntry
+{
+    name:[u8; MAX_NAME_LENGTH],
+    pub inode:Inode,
+}
+
+impl DirEntry
+{
+    /// No name, for filling arrays.
+    pub const EMPTY:DirEntry = DirEntry
+    {
+        name: [b'\0'; MAX_NAME_LENGTH],
+        inode: Inode { kind: FileType::File, number: 0, mount: 0 },
+    };
+
+    /// `name` is cut to MAX_NAME_LENGTH bytes.
+    pub fn new(name:&[u8], inode:Inode) -> DirEntry
+    {
+        let mut entry = DirEntry { name: [b'\0'; MAX_NAME_LENGTH], inode };
+        let length = name.len().min(MAX_NAME_LENGTH);
+        entry.name[..length].copy_from_slice(&name[..length]);
+        return entry;
+    }
+
+    pub fn name(&self) -> &str
+    {
+        core::str::from_utf8(&self.name).unwrap_or("?").trim_matches('\0')
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata
+{
+    pub inode:Inode,
+    // bytes for a file, entries without "." and ".." for a directory
+    pub size:usize,
+    // seconds since 1970, 0 when the filesystem does not keep them
+    pub created:u64,
+    pub modified:u64,
+    pub owner:u32,
+    // permission bits, like 0o644
+    pub mode:u16,
+}
+
+impl Metadata
+{
+    /// Metadata with no times, owned by 0 and with the usual mode for the
+    /// kind of `inode`.
+    pub fn new(inode:Inode, size:usize) -> Metadata
+    {
+        return Metadata { inode, size, created: 0, modified: 0, owner: 0, mode: default_mode(inode.kind) };
+    }
+}
+
+// 0o755 for a directory, 0o644 for a file
+fn default_mode(kind:FileType) -> u16
+{
+    match kind
+    {
+        FileType::Directory => 0o755,
+        FileType::File => 0o644,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError
+{
+    NotFound,
+    AlreadyExists,
+    NotADirectory,
+    IsADirectory,
+    DirectoryNotEmpty,
+    // empty, ".", ".." or with a '/'
+    InvalidName,
+    NameTooLong,
+    // no free inode left
+    NoSpace,
+    // the directory can not hold more entries
+    DirectoryFull,
+    FileTooLarge,
+    // the directory is somebody's current directory
+    Busy,
+    // a directory moved or copied into itself or below itself
+    IntoItself,
+    // rename between two filesystems
+    CrossDevice,
+    // reading or writing the disk failed
+    DeviceError,
+    // the disk holds no filesystem that can be loaded
+    NotAFileSystem,
+    // the filesystem on the disk contradicts itself
+    Corrupted,
+    // the filesystem can only be read
+    ReadOnly,
+}
+
+impl FsError
+{
+    pub fn message(&self) -> &'static str
+    {
+        match self
+        {
+            FsError::NotFound => "No such file or directory",
+            FsError::AlreadyExists => "Already exists",
+            FsError::NotADirectory => "Not a directory",
+            FsError::IsADirectory => "Is a directory",
+            FsError::DirectoryNotEmpty => "Directory is not empty",
+            FsError::InvalidName => "Invalid name",
+            FsError::NameTooLong => "The maximum number of characters has been exceeded",
+            FsError::NoSpace => "The maximum number of files and directories",
+            FsError::DirectoryFull => "The maximum number of entries in the directory",
+            FsError::FileTooLarge => "The maximum size of a file",
+            FsError::Busy => "Directory is in use",
+            FsError::IntoItself => "Can not move or copy a directory into itself",
+            FsError::CrossDevice => "Can not move between filesystems",
+            FsError::DeviceError => "Disk error",
+            FsError::NotAFileSystem => "No filesystem on the disk",
+            FsError::Corrupted => "The filesystem is damaged",
+            FsError::ReadOnly => "Read-only filesystem",
+        }
+    }
+}
+
+impl From<BlockError> for FsError
+{
+    fn from(_:BlockError) -> FsError
+    {
+        FsError::DeviceError
+    }
+}
+
+/// What a filesystem has to provide to be used through the VFS. Names are
+/// single path components; every directory also has "." and "..".
+pub trait FileSystem
+{
+    fn root(&self) -> Inode;
+    fn lookup(&self, dir:Inode, name:&str) -> Result<Inode, FsError>;
+    /// Creates an empty file.
+    fn create(&mut self, dir:Inode, name:&str) -> Result<Inode, FsError>;
+    fn mkdir(&mut self, dir:Inode, name:&str) -> Result<Inode, FsError>;
+    /// Removes a file.
+    fn unlink(&mut self, dir:Inode, name:&str) -> Result<(), FsError>;
+    /// Removes an empty directory.
+    fn rmdir(&mut self, dir:Inode, name:&str) -> Result<(), FsError>;
+    /// Moves an entry to `new_name` in `new_dir`, keeping its inode. A file
+    /// already there is replaced by a file.
+    fn rename(&mut self, old_dir:Inode, old_name:&str, new_dir:Inode, new_name:&str) -> Result<(), FsError>;
+    /// Reads from `offset` on, returns the number of bytes read, 0 at the end.
+    fn read(&self, file:Inode, offset:usize, buf:&mut [u8]) -> Result<usize, FsError>;
+    /// Writes at `offset`, growing the file. Returns the number of bytes
+    /// written, fewer than asked for when the file gets full.
+    fn write(&mut self, file:Inode, offset:usize, data:&[u8]) -> Result<usize, FsError>;
+    /// Cuts or grows the file to `size` bytes; grown bytes read as zeros.
+    fn truncate(&mut self, file:Inode, size:usize) -> Result<(), FsError>;
+    /// The entry number `index` of `dir`, None after the last one.
+    fn readdir(&self, dir:Inode, index:usize) -> Result<Option<DirEntry>, FsError>;
+    fn stat(&self, inode:Inode) -> Result<Metadata, FsError>;
+}
+
+// A sleeping lock: filesystem calls may take a while and are never made
+// from interrupt handlers.
+lazy_static!
+{
+    static ref ROOT_FS: Mutex<RamFs> = Mutex::new(RamFs::new());
+    // Where `fsck_disk` loads a disk that is not mounted
+    static ref CHECKED_FS: Mutex<RamFs> = Mutex::new(RamFs::new());
+}
+
+/// Builds the root filesystem. Call once from the boot stack, it is too big
+/// for a task stack.
+pub fn init()
+{
+    lazy_static::initialize(&ROOT_FS);
+    lazy_static::initialize(&CHECKED_FS);
+}
+
+// The disk the root filesystem was loaded from and is synced to. Always
+// locked before ROOT_FS.
+static ROOT_DEVICE: Mutex<Option<Drive>> = Mutex::new(None);
+
+/// Loads the root filesystem from `drive` and syncs it there from now on.
+/// Inodes from before are no longer valid afterwards, and whatever was
+/// mounted on the old tree is unmounted.
+pub fn mount(mut drive:Drive) -> Result<(), FsError>
+{
+    if in_use(drive)
+    {
+        return Err(FsError::Busy);
+    }
+    let mut device = ROOT_DEVICE.lock();
+    let loaded = ROOT_FS.lock().load_from(&mut drive);
+    match loaded
+    {
+        Ok(()) => *device = Some(drive),
+        // The tables are empty now and must not overwrite the old disk
+        Err(FsError::DeviceError) => *device = None,
+        // Nothing was loaded, the old tree is still there
+        Err(_) => return loaded,
+    }
+    let mut points = MOUNT_POINTS.lock();
+    for (point, mounted) in points.iter_mut().zip(MOUNTED.iter())
+    {
+        *point = None;
+        *mounted.lock() = None;
+    }
+    return loaded;
+}
+
+/// Mounts the first drive that holds a filesystem and returns its number.
+pub fn mount_first_disk() -> Option<usize>
+{
+    (0..MAX_DRIVES).find(|&index| ata::drive(index).map_or(false, |drive| mount(drive).is_ok()))
+}
+
+/// The drive the root filesystem lives on, None while it is only in memory.
+pub fn mounted_drive() -> Option<Drive>
+{
+    *ROOT_DEVICE.lock()
+}
+
+/// Writes the root filesystem to its drive if it changed.
+pub fn sync() -> Result<(), FsError>
+{
+    let mut device = ROOT_DEVICE.lock();
+    match device.as_mut()
+    {
+        Some(drive) => ROOT_FS.lock().save_to(drive),
+        None => Ok(()),
+    }
+}
+
+/// Checks the tables of the root filesystem and repairs them if asked to,
+/// see `RamFs::fsck`. Returns how many problems there were.
+pub fn fsck(repair:bool, report:&mut dyn FnMut(Problem)) -> usize
+{
+    ROOT_FS.lock().fsck(repair, report)
+}
+
+/// Checks the tables of the filesystem on `drive`, which can not be mounted,
+/// and with `repair` writes them back repaired. Unlike `mount` this loads
+/// tables that do not agree. Returns how many problems there were.
+pub fn fsck_disk(mut drive:Drive, repair:bool, report:&mut dyn FnMut(Problem)) -> Result<usize, FsError>
+{
+    let device = ROOT_DEVICE.lock();
+    if device.map_or(false, |mounted| mounted.index() == drive.index()) || in_use(drive)
+    {
+        return Err(FsError::Busy);
+    }
+    let mut fs = CHECKED_FS.lock();
+    fs.load_damaged_from(&mut drive)?;
+    let problems = fs.fsck(repair, report);
+    fs.save_to(&mut drive)?;
+    return Ok(problems);
+}
+
+/// Puts an empty filesystem on `drive`. It can not be the mounted one.
+pub fn format(mut drive:Drive) -> Result<(), FsError>
+{
+    let device = ROOT_DEVICE.lock();
+    if device.map_or(false, |mounted| mounted.index() == drive.index()) || in_use(drive)
+    {
+        return Err(FsError::Busy);
+    }
+    return RamFs::format(&mut drive);
+}
+
+/// Filesystems that can be mounted on a directory.
+pub enum Mounted
+{
+    Fat(FatFs),
+    Ext2(Ext2Fs),
+    Tar(TarFs),
+}
+
+impl Mounted
+{
+    /// Opens the filesystem of type `kind` ("fat" or "ext2") on `drive`,
+    /// or whichever of them is there when `kind` is None.
+    pub fn open(kind:Option<&str>, drive:Drive) -> Result<Mounted, FsError>
+    {
+        match kind
+        {
+            Some("fat") => FatFs::new(drive).map(Mounted::Fat),
+            Some("ext2") => Ext2Fs::new(drive).map(Mounted::Ext2),
+            Some(_) => Err(FsError::NotAFileSystem),
+            None => FatFs::new(drive).map(Mounted::Fat).or_else(|_| Ext2Fs::new(drive).map(Mounted::Ext2)),
+        }
+    }
+
+    fn fs(&mut self) -> &mut dyn FileSystem
+    {
+        match self
+        {
+            Mounted::Fat(fs) => fs,
+            Mounted::Ext2(fs) => fs,
+            Mounted::Tar(fs) => fs,
+        }
+    }
+
+    /// Short name of the filesystem type, for listing mounts.
+    pub fn kind(&self) -> &'static str
+    {
+        match self
+        {
+            Mounted::Fat(fs) => fs.kind(),
+            Mounted::Ext2(fs) => fs.kind(),
+            Mounted::Tar(fs) => fs.kind(),
+        }
+    }
+
+    /// The disk it is on, None for one in memory.
+    pub fn drive(&self) -> Option<Drive>
+    {
+        match self
+        {
+            Mounted::Fat(fs) => Some(fs.drive()),
+            Mounted::Ext2(fs) => Some(fs.drive()),
+            Mounted::Tar(_) => None,
+        }
+    }
+}
+
+/// Filesystems mounted besides the root one.
+pub const MAX_MOUNTS:usize = 4;
+
+const NOT_MOUNTED: Mutex<Option<Mounted>> = Mutex::new(None);
+// Mount number i + 1 is MOUNTED[i], the root filesystem is number 0
+static MOUNTED: [Mutex<Option<Mounted>>; MAX_MOUNTS] = [NOT_MOUNTED; MAX_MOUNTS];
+
+// The directory a filesystem is mounted on, and the root that replaces it.
+// A virtual one has no directory on disk, only a `name` shown in "/", and
+// its `point` is the root directory.
+#[derive(Debug, Clone, Copy)]
+struct MountPoint
+{
+    point:Inode,
+    root:Inode,
+    name:Option<&'static str>,
+}
+
+// Only held to copy entries in and out, never while calling a filesystem
+static MOUNT_POINTS: Mutex<[Option<MountPoint>; MAX_MOUNTS]> = Mutex::new([None; MAX_MOUNTS]);
+
+fn with_fs<R>(mount:usize, operation:impl FnOnce(&mut dyn FileSystem) -> Result<R, FsError>) -> Result<R, FsError>
+{
+    if mount == 0
+    {
+        let mut fs = ROOT_FS.lock();
+        return operation(&mut *fs);
+    }
+    match MOUNTED.get(mount - 1).map(|slot| slot.lock())
+    {
+        Some(mut slot) => match slot.as_mut()
+        {
+            Some(mounted) => operation(mounted.fs()),
+            None => Err(FsError::NotFound),
+        },
+        None => Err(FsError::NotFound),
+    }
+}
+
+// The inode as its own filesystem knows it
+fn local(inode:Inode) -> Inode
+{
+    Inode { mount: 0, ..inode }
+}
+
+// An inode a filesystem returned, the root mounted on it if it is a mount point
+fn global(mount:usize, inode:Inode) -> Inode
+{
+    let inode = Inode { mount, ..inode };
+    let points = MOUNT_POINTS.lock();
+    match points.iter().flatten().find(|mount_point| mount_point.name.is_none() && mount_point.point == inode)
+    {
+        Some(mount_point) => mount_point.root,
+        None => inode,
+    }
+}
+
+// The directory `root` is mounted on, if it is the root of a mounted filesystem
+fn point_under(root:Inode) -> Option<Inode>
+{
+    let points = MOUNT_POINTS.lock();
+    points.iter().flatten().find(|mount_point| mount_point.root == root).map(|mount_point| mount_point.point)
+}
+
+// The root mounted as `name` in `dir`, if `dir` is "/" and `name` is virtual
+fn virtual_root(dir:Inode, name:&str) -> Option<Inode>
+{
+    let found = {
+        let points = MOUNT_POINTS.lock();
+        points.iter().flatten().find(|mount_point| mount_point.name == Some(name)).map(|mount_point| mount_point.root)
+    };
+    return found.filter(|_| dir == root());
+}
+
+// The virtual mount number `index` counts in "/", as an entry
+fn virtual_entry(index:usize) -> Option<DirEntry>
+{
+    let points = MOUNT_POINTS.lock();
+    let mut named = points.iter().flatten().filter_map(|mount_point| mount_point.name.map(|name| (name, mount_point.root)));
+    return named.nth(index).map(|(name, root)| DirEntry::new(name.as_bytes(), root));
+}
+
+// Whether a filesystem mounted on a directory lives on `drive`
+fn in_use(drive:Drive) -> bool
+{
+    MOUNTED.iter().any(|slot| {
+        slot.lock().as_ref().and_then(Mounted::drive).map_or(false, |mounted| mounted.index() == drive.index())
+    })
+}
+
+/// Mounts `fs` on the directory `point`, hiding what is in it, and returns
+/// its root. A drive is mounted once at most.
+pub fn mount_at(point:Inode, fs:Mounted) -> Result<Inode, FsError>
+{
+    if let Some(drive) = fs.drive()
+    {
+        if in_use(drive) || mounted_drive().map_or(false, |root| root.index() == drive.index())
+        {
+            return Err(FsError::Busy);
+        }
+    }
+    if point.kind != FileType::Directory
+    {
+        return Err(FsError::NotADirectory);
+    }
+    // "/" and directories already covered by a filesystem stay as they are
+    if point == root() || point_under(point).is_some()
+    {
+        return Err(FsError::Busy);
+    }
+    return attach(MountPoint { point, root: point, name: None }, fs);
+}
+
+/// Mounts `fs` as `name` in "/" without a directory for it on disk, so
+/// nothing is written to the root filesystem. A directory of that name in
+/// "/" is hidden while it is mounted.
+pub fn mount_virtual(name:&'static str, fs:Mounted) -> Result<Inode, FsError>
+{
+    let root = root();
+    if virtual_root(root, name).is_some()
+    {
+        return Err(FsError::Busy);
+    }
+    return attach(MountPoint { point: root, root, name: Some(name) }, fs);
+}
+
+// Takes a free mount number for `fs`, `mount_point.root` is filled in here
+fn attach(mount_point:MountPoint, fs:Mounted) -> Result<Inode, FsError>
+{
+    let mut points = MOUNT_POINTS.lock();
+    let slot = points.iter().position(Option::is_none).ok_or(FsError::NoSpace)?;
+    let mut mounted = MOUNTED[slot].lock();
+    let root = Inode { mount: slot + 1, ..mounted.insert(fs).fs().root() };
+    points[slot] = Some(MountPoint { root, ..mount_point });
+    return Ok(root);
+}
+
+/// Unmounts the filesystem whose root is `root`.
+pub fn unmount(root:Inode) -> Result<(), FsError>
+{
+    let mut points = MOUNT_POINTS.lock();
+    let slot = points.iter()
+        .position(|mount_point| mount_point.map_or(false, |mount_point| mount_point.root == root))
+        .ok_or(FsError::NotFound)?;
+    points[slot] = None;
+    *MOUNTED[slot].lock() = None;
+    return Ok(());
+}
+
+/// The directory, filesystem type and drive of mount number `index`, for
+/// listing.
+pub fn mount_info(index:usize) -> Option<(Inode, &'static str, Option<Drive>)>
+{
+    let mount_point = MOUNT_POINTS.lock().get(index).copied().flatten()?;
+    // A virtual one is only known by its root, whose path ends in its name
+    let point = if mount_point.name.is_some() { mount_point.root } else { mount_point.point };
+    let mounted = MOUNTED[index].lock();
+    let mounted = mounted.as_ref()?;
+    return Some((point, mounted.kind(), mounted.drive()));
+}
+
+pub fn root() -> Inode
+{
+    let mut fs = ROOT_FS.lock();
+    return (&mut *fs as &mut dyn FileSystem).root();
+}
+
+pub fn lookup(dir:Inode, name:&str) -> Result<Inode, FsError>
+{
+    // ".." of a mounted root leads out of its filesystem
+    if name == ".."
+    {
+        if let Some(point) = point_under(dir)
+        {
+            return lookup(point, "..");
+        }
+    }
+    if let Some(root) = virtual_root(dir, name)
+    {
+        return Ok(root);
+    }
+    let found = with_fs(dir.mount, |fs| fs.lookup(local(dir), name))?;
+    return Ok(if name == "." { dir } else { global(dir.mount, found) });
+}
+
+pub fn create(dir:Inode, name:&str) -> Result<Inode, FsError>
+{
+    if virtual_root(dir, name).is_some()
+    {
+        return Err(FsError::AlreadyExists);
+    }
+    with_fs(dir.mount, |fs| fs.create(local(dir), name)).map(|inode| global(dir.mount, inode))
+}
+
+pub fn mkdir(dir:Inode, name:&str) -> Result<Inode, FsError>
+{
+    if virtual_root(dir, name).is_some()
+    {
+        return Err(FsError::AlreadyExists);
+    }
+    with_fs(dir.mount, |fs| fs.mkdir(local(dir), name)).map(|inode| global(dir.mount, inode))
+}
+
+pub fn unlink(dir:Inode, name:&str) -> Result<(), FsError>
+{
+    if virtual_root(dir, name).is_some()
+    {
+        return Err(FsError::Busy);
+    }
+    with_fs(dir.mount, |fs| fs.unlink(local(dir), name))
+}
+
+/// Removes an empty directory, never one a filesystem is mounted on.
+pub fn rmdir(dir:Inode, name:&str) -> Result<(), FsError>
+{
+    if point_under(lookup(dir, name)?).is_some()
+    {
+        return Err(FsError::Busy);
+    }
+    with_fs(dir.mount, |fs| fs.rmdir(local(dir), name))
+}
+
+pub fn rename(old_dir:Inode, old_name:&str, new_dir:Inode, new_name:&str) -> Result<(), FsError>
+{
+    if point_under(lookup(old_dir, old_name)?).is_some() || virtual_root(new_dir, new_name).is_some()
+    {
+        return Err(FsError::Busy);
+    }
+    if old_dir.mount != new_dir.mount
+    {
+        return Err(FsError::CrossDevice);
+    }
+    with_fs(old_dir.mount, |fs| fs.rename(local(old_dir), old_name, local(new_dir), new_name))
+}
+
+/// Reads from byte `offset` of `file` into `buf`, 0 means the end was reached.
+pub fn read_at(file:Inode, offset:usize, buf:&mut [u8]) -> Result<usize, FsError>
+{
+    with_fs(file.mount, |fs| fs.read(local(file), offset, buf))
+}
+
+/// Writes `data` at byte `offset` of `file`, growing it as needed.
+pub fn write_at(file:Inode, offset:usize, data:&[u8]) -> Result<usize, FsError>
+{
+    with_fs(file.mount, |fs| fs.write(local(file), offset, data))
+}
+
+/// Writes `data` after the last byte of `file`.
+pub fn append(file:Inode, data:&[u8]) -> Result<usize, FsError>
+{
+    with_fs(file.mount, |fs| {
+        let size = fs.stat(local(file))?.size;
+        fs.write(local(file), size, data)
+    })
+}
+
+/// Cuts `file` to `size` bytes, or grows it with zero bytes.
+pub fn truncate(file:Inode, size:usize) -> Result<(), FsError>
+{
+    with_fs(file.mount, |fs| fs.truncate(local(file), size))
+}
+
+/// A file read and written in order from a position that moves along, like
+/// a file descriptor. Copies move on their own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpenFile
+{
+    pub inode:Inode,
+    position:usize,
+}
+
+impl OpenFile
+{
+    /// Starts at the first byte.
+    pub fn open(inode:Inode) -> OpenFile
+    {
+        OpenFile { inode, position: 0 }
+    }
+
+    pub fn position(&self) -> usize
+    {
+        self.position
+    }
+
+    /// Moves to byte `position`, which may be past the end. A write there
+    /// fills the gap with zero bytes.
+    pub fn seek(&mut self, position:usize)
+    {
+        self.position = position;
+    }
+
+    /// Reads from the position on and moves past what was read.
+    pub fn read(&mut self, buf:&mut [u8]) -> Result<usize, FsError>
+    {
+        let count = read_at(self.inode, self.position, buf)?;
+        self.position += count;
+        return Ok(count);
+    }
+
+    /// Writes at the position and moves past what was written.
+    pub fn write(&mut self, data:&[u8]) -> Result<usize, FsError>
+    {
+        let count = write_at(self.inode, self.position, data)?;
+        self.position += count;
+        return Ok(count);
+    }
+}
+
+pub fn readdir(dir:Inode, index:usize) -> Result<Option<DirEntry>, FsError>
+{
+    if dir != root()
+    {
+        return read_entry(dir, index);
+    }
+    // "/" lists the virtual mounts first, then its own entries but the ones
+    // they hide
+    let mut virtuals = 0;
+    while let Some(entry) = virtual_entry(virtuals)
+    {
+        if virtuals == index
+        {
+            return Ok(Some(entry));
+        }
+        virtuals += 1;
+    }
+    let mut wanted = index - virtuals;
+    let mut next = 0;
+    while let Some(entry) = read_entry(dir, next)?
+    {
+        if virtual_root(dir, entry.name()).is_none()
+        {
+            if wanted == 0
+            {
+                return Ok(Some(entry));
+            }
+            wanted -= 1;
+        }
+        next += 1;
+    }
+    return Ok(None);
+}
+
+fn read_entry(dir:Inode, index:usize) -> Result<Option<DirEntry>, FsError>
+{
+    let entry = with_fs(dir.mount, |fs| fs.readdir(local(dir), index))?;
+    return Ok(entry.map(|entry| DirEntry { inode: global(dir.mount, entry.inode), ..entry }));
+}
+
+pub fn stat(inode:Inode) -> Result<Metadata, FsError>
+{
+    let metadata = with_fs(inode.mount, |fs| fs.stat(local(inode)))?;
+    return Ok(Metadata { inode, ..metadata });
+}
+
+/// The entry of `dir` that stands for `inode`, to get its name back.
+pub fn find_entry(dir:Inode, inode:Inode) -> Result<DirEntry, FsError>
+{
+    let mut index = 0;
+    while let Some(entry) = readdir(dir, index)?
+    {
+        if entry.inode == inode
+        {
+            return Ok(entry);
+        }
+        index += 1;
+    }
+    return Err(FsError::NotFound);
+}
+
+/// Removes `name` from `dir` together with everything below it. Returns how
+/// many files and directories were removed.
+pub fn remove_tree(dir:Inode, name:&str) -> Result<usize, FsError>
+{
+    let inode = lookup(dir, name)?;
+    if inode.kind == FileType::File
+    {
+        unlink(dir, name)?;
+        return Ok(1);
+    }
+    // What is mounted below stays, it has to be unmounted first
+    if point_under(inode).is_some()
+    {
+        return Err(FsError::Busy);
+    }
+    let mut removed = 0;
+    // Every removal shifts the rest down, so the first entry is always next
+    while let Some(entry) = readdir(inode, 0)?
+    {
+        removed += remove_tree(inode, entry.name())?;
+    }
+    rmdir(dir, name)?;
+    return Ok(removed + 1);
+}
+
+/// Whether `inode` is the directory `dir` or somewhere below it.
+pub fn is_within(inode:Inode, dir:Inode) -> Result<bool, FsError>
+{
+    let root = root();
+    let mut inode = inode;
+    loop
+    {
+        if inode == dir
+        {
+            return Ok(true);
+        }
+        if inode == root || inode.kind != FileType::Directory
+        {
+            return Ok(false);
+        }
+        inode = lookup(inode, "..")?;
+    }
+}
+
+// Copies the bytes of `source` over the file `name` in `dir`, which is created
+// when missing
+fn copy_file(source:Inode, dir:Inode, name:&str) -> Result<(), FsError>
+{
+    let copy = match lookup(dir, name)
+    {
+        Ok(existing) if existing == source => return Err(FsError::AlreadyExists),
+        Ok(existing) if existing.kind == FileType::Directory => return Err(FsError::IsADirectory),
+        Ok(existing) => {
+            truncate(existing, 0)?;
+            existing
+        }
+        Err(FsError::NotFound) => create(dir, name)?,
+        Err(error) => return Err(error),
+    };
+    let mut buf = [0u8; 256];
+    let mut reader = OpenFile::open(source);
+    loop
+    {
+        let count = reader.read(&mut buf)?;
+        if count == 0
+        {
+            return Ok(());
+        }
+        append(copy, &buf[..count])?;
+    }
+}
+
+/// Copies `source` to `name` in `dir`, with everything below it when it is a
+/// directory. Returns how many files and directories were copied.
+pub fn copy_tree(source:Inode, dir:Inode, name:&str) -> Result<usize, FsError>
+{
+    if source.kind == FileType::File
+    {
+        copy_file(source, dir, name)?;
+        return Ok(1);
+    }
+    // The copy would show up in the directory being copied
+    if is_within(dir, source)?
+    {
+        return Err(FsError::IntoItself);
+    }
+    let copy = mkdir(dir, name)?;
+    let mut copied = 1;
+    let mut index = 0;
+    while let Some(entry) = readdir(source, index)?
+    {
+        copied += copy_tree(entry.inode, copy, entry.name())?;
+        index += 1;
+    }
+    return Ok(copied);
+}
+
+/// Follows `path` from `dir`, or from the root when it starts with '/'.
+/// Repeated slashes and "." are skipped, ".." goes up and stays at the root.
+pub fn resolve(dir:Inode, path:&str) -> Result<Inode, FsError>
+{
+    let mut inode = if path.starts_with('/') { root() } else { dir };
+    for component in path.split('/').filter(|component| !component.is_empty() && *component != ".")
+    {
+        if inode.kind != FileType::Directory
+        {
+            return Err(FsError::NotADirectory);
+        }
+        inode = lookup(inode, component)?;
+    }
+    // "name/" has to be a directory
+    if path.ends_with('/') && inode.kind != FileType::Directory
+    {
+        return Err(FsError::NotADirectory);
+    }
+    return Ok(inode);
+}
+
+/// Splits `path` into the directory it is in and its last component, for
+/// creating or removing it. "a/b/" gives the directory a and "b".
+pub fn resolve_parent(dir:Inode, path:&str) -> Result<(Inode, &str), FsError>
+{
+    let path_without_slashes = path.trim_end_matches('/');
+    let (parent_path, name) = match path_without_slashes.rfind('/')
+    {
+        Some(i) => (&path_without_slashes[..i + 1], &path_without_slashes[i + 1..]),
+        None => ("", path_without_slashes),
+    };
+    if name.is_empty()
+    {
+        return Err(FsError::InvalidName);
+    }
+    let parent = resolve(dir, parent_path)?;
+    if parent.kind != FileType::Directory
+    {
+        return Err(FsError::NotADirectory);
+    }
+    return Ok((parent, name));
+}
+
+/// Writes the absolute path of the directory `dir` into `buf` and returns
+/// its length.
+pub fn path_of(dir:Inode, buf:&mut [u8; MAX_PATH_LENGTH]) -> Result<usize, FsError>
+{
+    let root = root();
+    // Built backwards from the end of `buf`
+    let mut start = MAX_PATH_LENGTH;
+    let mut inode = dir;
+    while inode != root
+    {
+        let parent = lookup(inode, "..")?;
+        if parent == inode
+        {
+            return Err(FsError::NotFound);
+        }
+        let entry = find_entry(parent, inode)?;
+        let name = entry.name().as_bytes();
+        if start < name.len() + 1
+        {
+            return Err(FsError::NameTooLong);
+        }
+        start -= name.len();
+        buf[start..start + name.len()].copy_from_slice(name);
+        start -= 1;
+        buf[start] = b'/';
+        inode = parent;
+    }
+    if start == MAX_PATH_LENGTH
+    {
+        start -= 1;
+        buf[start] = b'/';
+    }
+    buf.copy_within(start.., 0);
+    return Ok(MAX_PATH_LENGTH - start);
+}
@@ -1,7 +1,7 @@
 use core::fmt;
 use lazy_static::lazy_static;
-use spin::Mutex;
-use x86_64::instructions::{interrupts::without_interrupts, port::{Port, PortGeneric, ReadWriteAccess}};
+use x86_64::instructions::port::{Port, PortGeneric, ReadWriteAccess};
+use crate::sync::{IrqSafeMutex, LEVEL_SCREEN};
 
 #[macro_export]
 macro_rules! print
@@ -20,18 +20,18 @@ macro_rules! println
 pub fn _print(args: fmt::Arguments)
 {
     use core::fmt::Write;
-    without_interrupts(||{
-        SCREEN.lock().write_fmt(args).unwrap();
-    });
+    SCREEN.lock().write_fmt(args).unwrap();
 }
 
-const BUF_HEIGHT: u32 = 25;
-const BUF_WIDTH: u32 = 80;
+pub const BUF_HEIGHT: u32 = 25;
+pub const BUF_WIDTH: u32 = 80;
 const BUF_SIZE: usize = (BUF_HEIGHT * BUF_WIDTH * 2) as usize;
 
 lazy_static!
 {
-    pub static ref SCREEN: Mutex<Screen> = Mutex::new(
+    pub static ref SCREEN: IrqSafeMutex<Screen> = IrqSafeMutex::new(
+        "SCREEN",
+        LEVEL_SCREEN,
         {
             let mut screen = Screen
             {
@@ -130,6 +130,18 @@ impl Screen
         return buf;
     }
 
+    /// Puts a `get_buffer` snapshot back and moves the cursor to `line`, `col`.
+    pub fn restore(&mut self, buf: &[u8; (BUF_HEIGHT * BUF_WIDTH) as usize], line: u32, col: u32)
+    {
+        for i in 0..BUF_HEIGHT * BUF_WIDTH
+        {
+            self.write_char_byte(i, buf[i as usize]);
+        }
+        self.line = line;
+        self.col = col;
+        self.move_cursor();
+    }
+
     pub fn delete_last_char(&mut self, min_index:u32){
         if self.col > min_index{
             self.col -= 1;
@@ -138,6 +150,12 @@ impl Screen
         self.move_cursor();
     }
 
+    /// Puts a character at a fixed position without moving the cursor.
+    pub fn write_at(&mut self, line: u32, col: u32, char_byte: u8)
+    {
+        self.write_char_byte(line * BUF_WIDTH + col, char_byte);
+    }
+
     pub fn set_cursor_position(&mut self, position: u16)
     {
         unsafe{
@@ -0,0 +1,24 @@
+#![no_std]
+#![no_main]
+
+use unios_user::{println, SIGTERM};
+
+unios_user::entry!(main);
+
+extern "C" fn on_signal(signal: i32)
+{
+    println!("caught signal {}", signal);
+}
+
+// Sends itself SIGTERM, which the handler catches instead of dying
+fn main(_argv: &[&str]) -> i32
+{
+    if unios_user::catch(SIGTERM, on_signal) < 0
+    {
+        println!("catch failed");
+        return 1;
+    }
+    unios_user::kill(unios_user::getpid(), SIGTERM);
+    println!("still running after SIGTERM");
+    return 0;
+}
@@ -0,0 +1,16 @@
+#![no_std]
+#![no_main]
+
+use unios_user::println;
+
+unios_user::entry!(main);
+
+fn main(argv: &[&str]) -> i32
+{
+    println!("Hello from a Rust program in ring 3!");
+    for (i, arg) in argv.iter().enumerate()
+    {
+        println!("argv[{}] = {}", i, arg);
+    }
+    return 0;
+}
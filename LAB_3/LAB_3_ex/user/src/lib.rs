@@ -0,0 +1,189 @@
+#![no_std]
+
+use core::arch::{asm, global_asm};
+use core::fmt;
+use core::panic::PanicInfo;
+
+pub const SYS_EXIT: u64 = 0;
+pub const SYS_WRITE: u64 = 1;
+pub const SYS_GETPID: u64 = 2;
+pub const SYS_KILL: u64 = 3;
+pub const SYS_SIGNAL: u64 = 4;
+pub const SYS_SIGRETURN: u64 = 5;
+
+pub const SIGINT: i32 = 2;
+pub const SIGKILL: i32 = 9;
+pub const SIGSEGV: i32 = 11;
+pub const SIGTERM: i32 = 15;
+pub const SIGCHLD: i32 = 17;
+
+pub const SIG_DFL: u64 = 0;
+pub const SIG_IGN: u64 = 1;
+
+const MAX_ARGS: usize = 8;
+
+pub fn exit(status: i32) -> !
+{
+    unsafe {
+        asm!("int 0x80", in("rax") SYS_EXIT, in("rdi") status as i64 as u64, options(noreturn));
+    }
+}
+
+/// Writes `bytes` to the screen. Returns the number of bytes written or -1.
+pub fn write(bytes: &[u8]) -> isize
+{
+    let result: u64;
+    unsafe {
+        asm!(
+            "int 0x80",
+            inlateout("rax") SYS_WRITE => result,
+            in("rdi") bytes.as_ptr(),
+            in("rsi") bytes.len(),
+        );
+    }
+    result as isize
+}
+
+fn syscall2(number: u64, first: u64, second: u64) -> u64
+{
+    let result: u64;
+    unsafe {
+        asm!("int 0x80", inlateout("rax") number => result, in("rdi") first, in("rsi") second);
+    }
+    result
+}
+
+pub fn getpid() -> usize
+{
+    syscall2(SYS_GETPID, 0, 0) as usize
+}
+
+/// Sends `signal` to the process `pid`. Returns 0 or -1.
+pub fn kill(pid: usize, signal: i32) -> isize
+{
+    syscall2(SYS_KILL, pid as u64, signal as u64) as isize
+}
+
+/// Sets `signal` to SIG_DFL or SIG_IGN. Returns the previous action or -1.
+pub fn signal(signal: i32, action: u64) -> isize
+{
+    syscall2(SYS_SIGNAL, signal as u64, action) as isize
+}
+
+/// Calls `handler` with the signal number when `signal` arrives, once the
+/// program is back from its next system call. Returns the previous action
+/// or -1.
+pub fn catch(signal: i32, handler: extern "C" fn(i32)) -> isize
+{
+    let result: u64;
+    unsafe {
+        asm!(
+            "int 0x80",
+            inlateout("rax") SYS_SIGNAL => result,
+            in("rdi") signal as u64,
+            in("rsi") handler as usize,
+            in("rdx") unios_sigreturn as *const () as u64,
+        );
+    }
+    result as isize
+}
+
+extern "C"
+{
+    fn unios_sigreturn();
+}
+
+// Handlers return here, the kernel then gives back the registers they
+// interrupted
+global_asm!(
+    ".global unios_sigreturn",
+    "unios_sigreturn:",
+    "mov rax, 5",
+    "int 0x80",
+    "ud2",
+);
+
+struct Screen;
+
+impl fmt::Write for Screen
+{
+    fn write_str(&mut self, s: &str) -> fmt::Result
+    {
+        write(s.as_bytes());
+        Ok(())
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments)
+{
+    use core::fmt::Write;
+    Screen.write_fmt(args).unwrap();
+}
+
+#[macro_export]
+macro_rules! print
+{
+    ($($arg:tt)*) => ($crate::_print(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! println
+{
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
+
+/// Declares the program's main function: `fn(argv: &[&str]) -> i32`.
+#[macro_export]
+macro_rules! entry
+{
+    ($path:path) => {
+        #[no_mangle]
+        pub fn __unios_user_main(argv: &[&str]) -> i32
+        {
+            let main: fn(&[&str]) -> i32 = $path;
+            main(argv)
+        }
+    };
+}
+
+extern "Rust"
+{
+    fn __unios_user_main(argv: &[&str]) -> i32;
+}
+
+// The kernel starts us with rsp pointing at argc, followed by the argv pointers
+global_asm!(
+    ".global _start",
+    "_start:",
+    "mov rdi, rsp",
+    "call unios_user_start",
+    "ud2",
+);
+
+#[no_mangle]
+extern "C" fn unios_user_start(stack: *const u64) -> !
+{
+    let argc = (unsafe { *stack } as usize).min(MAX_ARGS);
+    let mut argv = [""; MAX_ARGS];
+    for i in 0..argc
+    {
+        let pointer = unsafe { *stack.add(1 + i) } as *const u8;
+        let mut len = 0;
+        while unsafe { *pointer.add(len) } != 0
+        {
+            len += 1;
+        }
+        let bytes = unsafe { core::slice::from_raw_parts(pointer, len) };
+        argv[i] = core::str::from_utf8(bytes).unwrap_or("");
+    }
+    exit(unsafe { __unios_user_main(&argv[..argc]) });
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> !
+{
+    println!("{}", info);
+    exit(101);
+}